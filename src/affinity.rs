@@ -0,0 +1,80 @@
+use std::io;
+
+/// Returns the cores this process may run on, in order.
+#[cfg(target_os = "linux")]
+pub fn cores() -> io::Result<Vec<usize>> {
+    // SAFETY: cpu_set_t is plain data, and sched_getaffinity writes no more
+    // than the size it is given.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|core| libc::CPU_ISSET(*core, &set))
+            .collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn cores() -> io::Result<Vec<usize>> {
+    Err(unsupported())
+}
+
+/// Returns the cores on NUMA node `node`, from sysfs.
+#[cfg(target_os = "linux")]
+pub fn node_cores(node: usize) -> io::Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+    let list = std::fs::read_to_string(&path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    parse_list(list.trim())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: bad list", path)))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn node_cores(_node: usize) -> io::Result<Vec<usize>> {
+    Err(unsupported())
+}
+
+// parses a kernel CPU list such as `0-3,8-11`
+#[cfg(target_os = "linux")]
+fn parse_list(list: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cores.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cores.push(range.parse().ok()?),
+        }
+    }
+
+    Some(cores)
+}
+
+/// Pins the calling thread to `core`, so the scheduler doesn't move it.
+#[cfg(target_os = "linux")]
+pub fn pin(core: usize) -> io::Result<()> {
+    // SAFETY: as in cores; CPU_SET ignores cores beyond the end of the set.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin(_core: usize) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads to cores is only supported on Linux",
+    )
+}
@@ -0,0 +1,148 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    process::Command,
+};
+
+use argh::FromArgs;
+use tracing::{error, info, warn, Level};
+
+use nsbench::json::{self, Value};
+
+use crate::{controller::Controller, logging, RunArguments};
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "agent",
+    description = "wait for runs sent by `nsbench controller`, one at a time"
+)]
+pub struct AgentArguments {
+    #[argh(
+        option,
+        description = "address to listen for the controller on (default 0.0.0.0:7353)",
+        default = "SocketAddr::from(([0, 0, 0, 0], crate::controller::DEFAULT_PORT))"
+    )]
+    listen: SocketAddr,
+
+    #[argh(
+        option,
+        description = "address of a controller to take runs from; repeat for each, at least one is required"
+    )]
+    allow: Vec<IpAddr>,
+}
+
+pub fn main(args: AgentArguments) -> ! {
+    logging::init(Level::INFO);
+
+    if args.allow.is_empty() {
+        error!("at least one --allow is required");
+        std::process::exit(1);
+    }
+
+    let listener = TcpListener::bind(args.listen).unwrap_or_else(|e| {
+        error!("could not listen on {}: {}", args.listen, e);
+        std::process::exit(1);
+    });
+    info!("Listening for runs on {}", args.listen);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        if !args.allow.contains(&peer.ip()) {
+            warn!("refused a connection from {}", peer);
+            continue;
+        }
+
+        let reply = match serve(&stream, peer) {
+            Ok(results) => json::object(vec![("results", results)]),
+            Err(e) => {
+                warn!("run for {} failed: {}", peer, e);
+                json::object(vec![("error", e.into())])
+            }
+        };
+        if let Err(e) = (&stream).write_all(format!("{}\n", reply).as_bytes()) {
+            warn!("could not send the results to {}: {}", peer, e);
+        }
+    }
+
+    unreachable!("the listener stopped accepting connections")
+}
+
+// reads a run from `stream`, runs it and returns what it measured
+fn serve(stream: &TcpStream, peer: SocketAddr) -> Result<Value, String> {
+    let mut request = String::new();
+    BufReader::new(stream)
+        .read_line(&mut request)
+        .map_err(|e| format!("could not read the run: {}", e))?;
+
+    let args: Vec<String> = json::parse(&request)?
+        .get("args")
+        .and_then(Value::as_array)
+        .and_then(|args| {
+            args.iter()
+                .map(|arg| arg.as_str().map(str::to_string))
+                .collect()
+        })
+        .ok_or("no args in the run")?;
+
+    let argv: Vec<&str> = args.iter().map(String::as_str).collect();
+    let run = RunArguments::from_args(&["nsbench run"], &argv)
+        .map_err(|exit| exit.output.trim_end().to_string())?;
+    if let Some(option) = Controller::unsupported(&run).or_else(|| writes(&run)) {
+        return Err(format!("{} cannot be used with an agent", option));
+    }
+
+    info!("Running for {}: {}", peer, args.join(" "));
+    let path = std::env::temp_dir().join(format!("nsbench-agent-{}.json", std::process::id()));
+    let results = run_to(&path, &args);
+    let _ = std::fs::remove_file(&path);
+    let results = results?;
+    info!("Finished the run for {}", peer);
+
+    Ok(results)
+}
+
+// runs `nsbench run` with `args`, keeping the results in `path`
+fn run_to(path: &Path, args: &[String]) -> Result<Value, String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let status = Command::new(exe)
+        .arg("run")
+        .args(args)
+        .arg("--results-file")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("could not start the run: {}", e))?;
+    if !status.success() {
+        return Err(format!("the run failed ({})", status));
+    }
+
+    let results = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    json::parse(&results)
+}
+
+// returns the option given in `args` that would write to the agent's disk
+fn writes(args: &RunArguments) -> Option<&'static str> {
+    [
+        (args.out.is_some(), "--out"),
+        (args.csv.is_some(), "--csv"),
+        (args.report.is_some(), "--report"),
+        (args.junit.is_some(), "--junit"),
+        (args.save_baseline.is_some(), "--save-baseline"),
+        (args.checkpoint.is_some(), "--checkpoint"),
+    ]
+    .iter()
+    .find(|(given, _)| *given)
+    .map(|(_, option)| *option)
+}
@@ -0,0 +1,405 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use argh::FromArgs;
+use tracing::{error, info, warn, Level};
+
+use nsbench::json::{self, Value};
+
+use crate::{logging, RunArguments};
+
+// how long a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the largest request body accepted
+const MAX_BODY: usize = 1 << 20;
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "api",
+    description = "start, watch, steer and stop runs over HTTP, one at a time"
+)]
+pub struct ApiArguments {
+    #[argh(
+        option,
+        description = "address to serve the API on (default 127.0.0.1:8053)",
+        default = "SocketAddr::from(([127, 0, 0, 1], 8053))"
+    )]
+    listen: SocketAddr,
+
+    #[argh(
+        option,
+        description = "address allowed to use the API besides localhost; repeat for each"
+    )]
+    allow: Vec<IpAddr>,
+}
+
+/// State is how the latest run is going.
+enum State {
+    Running(Child),
+    Finished(Value),
+    Failed(String),
+}
+
+/// Run is the latest run started through the API.
+struct Run {
+    id: u64,
+    args: Vec<String>,
+    state: State,
+    // the --control socket of the run, and the file it writes its summary to
+    control: PathBuf,
+    out: PathBuf,
+}
+
+impl Run {
+    fn to_json(&self, stats: Option<Value>) -> Value {
+        let mut fields = vec![
+            ("id", self.id.into()),
+            (
+                "args",
+                Value::Array(self.args.iter().map(|arg| arg.as_str().into()).collect()),
+            ),
+        ];
+        match &self.state {
+            State::Running(_) => {
+                fields.push(("state", "running".into()));
+                fields.push(("stats", stats.into()));
+            }
+            State::Finished(summary) => {
+                fields.push(("state", "finished".into()));
+                fields.push(("summary", summary.clone()));
+            }
+            State::Failed(e) => {
+                fields.push(("state", "failed".into()));
+                fields.push(("error", e.as_str().into()));
+            }
+        }
+
+        json::object(fields)
+    }
+}
+
+// an HTTP response: the status line's code and reason, and a JSON body
+type Response = (&'static str, Value);
+
+pub fn main(args: ApiArguments) -> ! {
+    logging::init(Level::INFO);
+
+    let listener = TcpListener::bind(args.listen).unwrap_or_else(|e| {
+        error!("could not listen on {}: {}", args.listen, e);
+        std::process::exit(1);
+    });
+    info!("Serving the API on http://{}", args.listen);
+
+    let run: Arc<Mutex<Option<Run>>> = Arc::new(Mutex::new(None));
+    for stream in listener.incoming().flatten() {
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        if !peer.ip().is_loopback() && !args.allow.contains(&peer.ip()) {
+            warn!("refused a connection from {}", peer);
+            continue;
+        }
+
+        let run = run.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &run) {
+                warn!("could not answer {}: {}", peer, e);
+            }
+        });
+    }
+
+    unreachable!("the listener stopped accepting connections")
+}
+
+// reads a request from `stream` and answers it
+fn serve(stream: TcpStream, run: &Arc<Mutex<Option<Run>>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    let mut length = 0;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+        line.clear();
+    }
+
+    let (status, body) = if length > MAX_BODY {
+        ("413 Payload Too Large", error("the body is too large"))
+    } else {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let mut parts = request.split_whitespace();
+        let body = String::from_utf8_lossy(&body);
+        route(
+            parts.next().unwrap_or(""),
+            parts.next().unwrap_or(""),
+            &body,
+            run,
+        )
+    };
+
+    let body = format!("{}\n", body);
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn route(method: &str, path: &str, body: &str, run: &Arc<Mutex<Option<Run>>>) -> Response {
+    match (method, path) {
+        ("POST", "/run") => start(body, run),
+        ("GET", "/run") => status(run),
+        ("POST", "/run/stop") => command(run, "stop"),
+        ("POST", "/run/qps") => {
+            let qps = json::parse(body)
+                .ok()
+                .and_then(|body| body.get("qps").and_then(Value::as_u64));
+            match qps {
+                Some(qps) => command(run, &format!("set qps {}", qps)),
+                None => ("400 Bad Request", error("expected {\"qps\": <n>}")),
+            }
+        }
+        (_, "/run") | (_, "/run/stop") | (_, "/run/qps") => {
+            ("405 Method Not Allowed", error("method not allowed"))
+        }
+        _ => ("404 Not Found", error("not found")),
+    }
+}
+
+// starts the run described by `body`, {"args": [...]}, with the arguments
+// of `nsbench run`
+fn start(body: &str, run: &Arc<Mutex<Option<Run>>>) -> Response {
+    let args: Option<Vec<String>> = json::parse(body).ok().and_then(|body| {
+        body.get("args")?
+            .as_array()?
+            .iter()
+            .map(|arg| arg.as_str().map(str::to_string))
+            .collect()
+    });
+    let args = match args {
+        Some(args) => args,
+        None => return ("400 Bad Request", error("expected {\"args\": [...]}")),
+    };
+
+    let argv: Vec<&str> = args.iter().map(String::as_str).collect();
+    let parsed = match RunArguments::from_args(&["nsbench run"], &argv) {
+        Ok(parsed) => parsed,
+        Err(exit) => return ("400 Bad Request", error(exit.output.trim_end())),
+    };
+    if let Some(option) = taken(&parsed, &args) {
+        return (
+            "400 Bad Request",
+            error(&format!("{} cannot be used with the API", option)),
+        );
+    }
+
+    let mut latest = run.lock().unwrap();
+    if let Some(Run {
+        state: State::Running(_),
+        ..
+    }) = &*latest
+    {
+        return ("409 Conflict", error("a run is already going"));
+    }
+
+    let id = latest.as_ref().map_or(1, |run| run.id + 1);
+    let prefix = format!("nsbench-api-{}-{}", std::process::id(), id);
+    let control = std::env::temp_dir().join(format!("{}.sock", prefix));
+    let out = std::env::temp_dir().join(format!("{}.json", prefix));
+
+    let child = std::env::current_exe().and_then(|exe| {
+        Command::new(exe)
+            .arg("run")
+            .args(&args)
+            .arg("--control")
+            .arg(&control)
+            .args(["--format", "json", "--out"])
+            .arg(&out)
+            .spawn()
+    });
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            return (
+                "500 Internal Server Error",
+                error(&format!("could not start the run: {}", e)),
+            )
+        }
+    };
+    info!("Started run {}: {}", id, args.join(" "));
+
+    let started = Run {
+        id,
+        args,
+        state: State::Running(child),
+        control,
+        out,
+    };
+    let answer = started.to_json(None);
+    *latest = Some(started);
+    drop(latest);
+
+    let run = run.clone();
+    thread::spawn(move || wait(id, &run));
+
+    ("202 Accepted", answer)
+}
+
+// waits for run number `id` to end, and keeps its summary
+fn wait(id: u64, run: &Arc<Mutex<Option<Run>>>) {
+    loop {
+        thread::sleep(Duration::from_millis(100));
+
+        let mut latest = run.lock().unwrap();
+        let latest = match &mut *latest {
+            Some(latest) if latest.id == id => latest,
+            _ => return,
+        };
+        let child = match &mut latest.state {
+            State::Running(child) => child,
+            _ => return,
+        };
+
+        let status = match child.try_wait() {
+            Ok(Some(status)) => status,
+            Ok(None) => continue,
+            Err(e) => {
+                latest.state = State::Failed(e.to_string());
+                return;
+            }
+        };
+
+        // assertions that fail exit with status 2, having written the summary
+        let summary = if status.success() || status.code() == Some(2) {
+            summary(&latest.out)
+        } else {
+            Err(format!("the run failed ({})", status))
+        };
+        let _ = std::fs::remove_file(&latest.out);
+        latest.state = match summary {
+            Ok(summary) => {
+                info!("Run {} finished", id);
+                State::Finished(summary)
+            }
+            Err(e) => {
+                warn!("run {} failed: {}", id, e);
+                State::Failed(e)
+            }
+        };
+        return;
+    }
+}
+
+fn summary(path: &Path) -> Result<Value, String> {
+    let summary = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    json::parse(&summary)
+}
+
+// reports on the latest run, with its totals so far if it is still going
+fn status(run: &Arc<Mutex<Option<Run>>>) -> Response {
+    let control = match &*run.lock().unwrap() {
+        None => return ("404 Not Found", error("no run has been started")),
+        Some(Run {
+            state: State::Running(_),
+            control,
+            ..
+        }) => Some(control.clone()),
+        Some(_) => None,
+    };
+
+    // the lock isn't held while the run answers
+    let stats = control
+        .and_then(|control| ask(&control, "stats").ok())
+        .and_then(|stats| json::parse(&stats).ok());
+    match &*run.lock().unwrap() {
+        Some(latest) => ("200 OK", latest.to_json(stats)),
+        None => ("404 Not Found", error("no run has been started")),
+    }
+}
+
+// passes `command` on to the run in progress, over its control socket
+fn command(run: &Arc<Mutex<Option<Run>>>, command: &str) -> Response {
+    let control = match &*run.lock().unwrap() {
+        Some(Run {
+            state: State::Running(_),
+            control,
+            ..
+        }) => control.clone(),
+        _ => return ("409 Conflict", error("no run is going")),
+    };
+
+    match ask(&control, command) {
+        Ok(answer) => match answer.strip_prefix("error: ") {
+            Some(e) => ("409 Conflict", error(e)),
+            None => ("200 OK", json::object(vec![("ok", true.into())])),
+        },
+        // e.g. before the run has set up its control socket
+        Err(e) => (
+            "503 Service Unavailable",
+            error(&format!("could not reach the run: {}", e)),
+        ),
+    }
+}
+
+// sends `command` to the control socket at `path` and returns the answer
+#[cfg(unix)]
+fn ask(path: &Path, command: &str) -> io::Result<String> {
+    let stream = std::os::unix::net::UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    writeln!(&stream, "{}", command)?;
+
+    let mut answer = String::new();
+    BufReader::new(&stream).read_line(&mut answer)?;
+    Ok(answer.trim_end().to_string())
+}
+
+#[cfg(not(unix))]
+fn ask(_path: &Path, _command: &str) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "steering runs is only supported on Unix",
+    ))
+}
+
+// the option given in `args`, parsed as `parsed`, that the API sets itself
+fn taken(parsed: &RunArguments, args: &[String]) -> Option<&'static str> {
+    [
+        (parsed.control.is_some(), "--control"),
+        (parsed.out.is_some(), "--out"),
+        // its default can't be told apart from being given
+        (args.iter().any(|arg| arg == "--format"), "--format"),
+        (parsed.tui, "--tui"),
+        (parsed.results_file.is_some(), "--results-file"),
+    ]
+    .iter()
+    .find(|(given, _)| *given)
+    .map(|(_, option)| *option)
+}
+
+fn error(e: &str) -> Value {
+    json::object(vec![("error", e.into())])
+}
@@ -0,0 +1,400 @@
+use std::{str::FromStr, time::Duration};
+
+use nsbench::{per_second, RunDetails};
+
+/// Metric is a figure from the summary that can be checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// A latency percentile, e.g. p99.
+    Percentile(f64),
+    MeanLatency,
+    MinLatency,
+    MaxLatency,
+    /// The share of queries that succeeded, as a percentage.
+    SuccessRate,
+    /// The share of queries that failed, as a percentage.
+    FailureRate,
+    /// Successful queries per second.
+    Qps,
+    Successes,
+    Failures,
+}
+
+impl Metric {
+    /// Returns the metric for a run that took `runtime`. Latencies are in
+    /// nanoseconds.
+    pub fn value(&self, details: &RunDetails, runtime: Duration) -> f64 {
+        let queries = (details.successes + details.failures) as f64;
+
+        match self {
+            Metric::Percentile(percentile) => details.latencies.percentile(*percentile) as f64,
+            Metric::MeanLatency => details.mean_latency().as_nanos() as f64,
+            Metric::MinLatency => details.latencies.min() as f64,
+            Metric::MaxLatency => details.latencies.max() as f64,
+            Metric::SuccessRate => details.successes as f64 / queries * 100.0,
+            Metric::FailureRate => details.failures as f64 / queries * 100.0,
+            Metric::Qps => per_second(details.successes, runtime) as f64,
+            Metric::Successes => details.successes as f64,
+            Metric::Failures => details.failures as f64,
+        }
+    }
+
+    fn is_latency(&self) -> bool {
+        matches!(
+            self,
+            Metric::Percentile(_) | Metric::MeanLatency | Metric::MinLatency | Metric::MaxLatency
+        )
+    }
+
+    /// Formats a value of this metric the way the summary does.
+    pub fn format(&self, value: f64) -> String {
+        match self {
+            _ if self.is_latency() => format!("{:.2?}", Duration::from_nanos(value as u64)),
+            Metric::SuccessRate | Metric::FailureRate => format!("{:.2}%", value),
+            _ => format!("{}", value),
+        }
+    }
+
+    // parses a threshold for this metric: a duration for latencies, and a
+    // number (with an optional % for rates) for everything else
+    fn threshold(&self, s: &str) -> Result<f64, String> {
+        if self.is_latency() {
+            return crate::parse_duration(s).map(|duration| duration.as_nanos() as f64);
+        }
+
+        let number = match self {
+            Metric::SuccessRate | Metric::FailureRate => s.strip_suffix('%').unwrap_or(s),
+            _ => s,
+        };
+        number
+            .parse::<f64>()
+            .map_err(|_| format!("invalid threshold '{}'", s))
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Metric::MeanLatency),
+            "min" => Ok(Metric::MinLatency),
+            "max" => Ok(Metric::MaxLatency),
+            "success_rate" => Ok(Metric::SuccessRate),
+            "failure_rate" => Ok(Metric::FailureRate),
+            "qps" => Ok(Metric::Qps),
+            "successes" => Ok(Metric::Successes),
+            "failures" => Ok(Metric::Failures),
+            _ => match s.strip_prefix('p').map(str::parse::<f64>) {
+                Some(Ok(percentile)) if percentile > 0.0 && percentile <= 100.0 => {
+                    Ok(Metric::Percentile(percentile))
+                }
+                _ => Err(format!(
+                    "unknown metric '{}': expected a percentile such as p99, mean, min, max, success_rate, failure_rate, qps, successes or failures",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Less => value < threshold,
+            Comparison::LessOrEqual => value <= threshold,
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterOrEqual => value >= threshold,
+        }
+    }
+}
+
+/// Assertion is a check on the final results, given on the command line as
+/// `<metric><comparison><threshold>`, e.g. `p99<5ms` or `success_rate>=99.9`.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    text: String,
+    metric: Metric,
+    comparison: Comparison,
+    threshold: f64,
+}
+
+impl FromStr for Assertion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let at = s.find(['<', '>']).ok_or_else(|| {
+            format!(
+                "invalid assertion '{}': expected <metric><comparison><threshold>, e.g. p99<5ms",
+                s
+            )
+        })?;
+
+        let (metric, rest) = s.split_at(at);
+        let (comparison, threshold) = match (&rest[..1], rest[1..].strip_prefix('=')) {
+            ("<", Some(threshold)) => (Comparison::LessOrEqual, threshold),
+            ("<", None) => (Comparison::Less, &rest[1..]),
+            (_, Some(threshold)) => (Comparison::GreaterOrEqual, threshold),
+            (_, None) => (Comparison::Greater, &rest[1..]),
+        };
+
+        let metric = metric.trim().parse::<Metric>()?;
+        let threshold = metric
+            .threshold(threshold.trim())
+            .map_err(|e| format!("invalid assertion '{}': {}", s, e))?;
+
+        Ok(Self {
+            text: s.to_string(),
+            metric,
+            comparison,
+            threshold,
+        })
+    }
+}
+
+impl Assertion {
+    /// Reports whether the assertion holds for a run that took `runtime`.
+    pub fn holds(&self, details: &RunDetails, runtime: Duration) -> bool {
+        self.comparison
+            .holds(self.metric.value(details, runtime), self.threshold)
+    }
+}
+
+/// Verdict is how an assertion fared against a run.
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    pub assertion: String,
+    pub actual: String,
+    pub passed: bool,
+}
+
+/// Checks each assertion against the totals for a run that took `runtime`.
+pub fn check(assertions: &[Assertion], details: &RunDetails, runtime: Duration) -> Vec<Verdict> {
+    assertions
+        .iter()
+        .map(|assertion| {
+            let value = assertion.metric.value(details, runtime);
+
+            Verdict {
+                assertion: assertion.text.clone(),
+                actual: assertion.metric.format(value),
+                // NaN, e.g. a success rate with no queries, never passes
+                passed: assertion.comparison.holds(value, assertion.threshold),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    // 99 successes taking 1ms to 99ms and one failure that took 100ms
+    fn details() -> RunDetails {
+        let mut details = RunDetails::default();
+        details.successes = 99;
+        details.failures = 1;
+        for ms in 1..=100 {
+            details.latencies.record(ms * MS);
+            details.total_latency += (ms * MS) as u128;
+            details.latency_count += 1;
+        }
+        details
+    }
+
+    #[test]
+    fn parses_metrics() {
+        let cases = [
+            ("p50", Metric::Percentile(50.0)),
+            ("p99.9", Metric::Percentile(99.9)),
+            ("p100", Metric::Percentile(100.0)),
+            ("mean", Metric::MeanLatency),
+            ("min", Metric::MinLatency),
+            ("max", Metric::MaxLatency),
+            ("success_rate", Metric::SuccessRate),
+            ("failure_rate", Metric::FailureRate),
+            ("qps", Metric::Qps),
+            ("successes", Metric::Successes),
+            ("failures", Metric::Failures),
+        ];
+
+        for (s, metric) in cases {
+            assert_eq!(s.parse(), Ok(metric), "{}", s);
+        }
+
+        for s in ["p0", "p101", "p-1", "px", "p", "P99", "latency", ""] {
+            assert!(s
+                .parse::<Metric>()
+                .unwrap_err()
+                .starts_with(&format!("unknown metric '{}'", s)));
+        }
+    }
+
+    #[test]
+    fn parses_assertions() {
+        let cases = [
+            ("p99<5ms", Metric::Percentile(99.0), Comparison::Less, 5e6),
+            (
+                "p99 <= 5ms",
+                Metric::Percentile(99.0),
+                Comparison::LessOrEqual,
+                5e6,
+            ),
+            ("mean>1s", Metric::MeanLatency, Comparison::Greater, 1e9),
+            (
+                "success_rate>=99.9",
+                Metric::SuccessRate,
+                Comparison::GreaterOrEqual,
+                99.9,
+            ),
+            (
+                "failure_rate<0.1%",
+                Metric::FailureRate,
+                Comparison::Less,
+                0.1,
+            ),
+            ("qps>=1000", Metric::Qps, Comparison::GreaterOrEqual, 1000.0),
+            (
+                "failures<=0",
+                Metric::Failures,
+                Comparison::LessOrEqual,
+                0.0,
+            ),
+        ];
+
+        for (s, metric, comparison, threshold) in cases {
+            let assertion: Assertion = s.parse().unwrap();
+            assert_eq!(
+                (assertion.metric, assertion.comparison, assertion.threshold),
+                (metric, comparison, threshold),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_assertions() {
+        let cases = [
+            ("p99", "invalid assertion 'p99': expected <metric><comparison><threshold>, e.g. p99<5ms"),
+            ("p99=5ms", "invalid assertion 'p99=5ms': expected <metric><comparison><threshold>, e.g. p99<5ms"),
+            ("qps>fast", "invalid assertion 'qps>fast': invalid threshold 'fast'"),
+            ("qps>10%", "invalid assertion 'qps>10%': invalid threshold '10%'"),
+            ("successes>=", "invalid assertion 'successes>=': invalid threshold ''"),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(s.parse::<Assertion>().unwrap_err(), err, "{}", s);
+        }
+
+        for s in ["p99<5", "mean>=fast", "max<"] {
+            let err = s.parse::<Assertion>().unwrap_err();
+            assert!(
+                err.starts_with(&format!("invalid assertion '{}': ", s)),
+                "{}",
+                err
+            );
+        }
+
+        let err = "latency<5ms".parse::<Assertion>().unwrap_err();
+        assert!(err.starts_with("unknown metric 'latency'"), "{}", err);
+    }
+
+    #[test]
+    fn compares_at_the_threshold() {
+        let cases = [
+            ("successes>=99", true),
+            ("successes>99", false),
+            ("successes<=99", true),
+            ("successes<99", false),
+            ("failures<1", false),
+            ("failures<=1", true),
+            ("success_rate>=99%", true),
+            ("success_rate>99", false),
+            ("failure_rate<=1%", true),
+            ("failure_rate<1%", false),
+            ("qps>=99", true),
+            ("qps>99", false),
+            ("min>=1ms", true),
+            ("min>1ms", false),
+            ("max<=100ms", true),
+            ("max<100ms", false),
+            ("mean<=50.5ms", true),
+            ("mean<50.5ms", false),
+        ];
+
+        let details = details();
+        for (s, holds) in cases {
+            let assertion: Assertion = s.parse().unwrap();
+            assert_eq!(
+                assertion.holds(&details, Duration::from_secs(1)),
+                holds,
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn checks_percentiles_to_bucket_precision() {
+        let details = details();
+        let runtime = Duration::from_secs(1);
+
+        // buckets are within 1% of the values in them
+        for (s, holds) in [("p50<=50.5ms", true), ("p50<49.5ms", false)] {
+            let assertion: Assertion = s.parse().unwrap();
+            assert_eq!(assertion.holds(&details, runtime), holds, "{}", s);
+        }
+    }
+
+    #[test]
+    fn never_passes_without_queries() {
+        let assertions: Vec<Assertion> = ["success_rate>=0", "failure_rate<=100"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        for verdict in check(&assertions, &RunDetails::default(), Duration::from_secs(1)) {
+            assert!(!verdict.passed, "{}", verdict.assertion);
+            assert_eq!(verdict.actual, "NaN%");
+        }
+    }
+
+    #[test]
+    fn formats_verdicts() {
+        let assertions: Vec<Assertion> = ["max<100ms", "success_rate>=99.5", "failures<=1"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        let verdicts = check(&assertions, &details(), Duration::from_secs(1));
+        let verdicts: Vec<_> = verdicts
+            .iter()
+            .map(|verdict| {
+                (
+                    verdict.assertion.as_str(),
+                    verdict.actual.as_str(),
+                    verdict.passed,
+                )
+            })
+            .collect();
+        assert_eq!(
+            verdicts,
+            [
+                ("max<100ms", "100.00ms", false),
+                ("success_rate>=99.5", "99.00%", false),
+                ("failures<=1", "1", true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use nsbench::RunDetails;
+
+use crate::{
+    assertions::{Metric, Verdict},
+    diff::Run,
+};
+
+// where baselines are kept, relative to the working directory, so that a CI
+// job can commit or cache them alongside the code they measure
+const DIRECTORY: &str = ".nsbench/baselines";
+
+// the tail latencies held to the baseline
+const TAIL: &[(&str, f64)] = &[("p99", 99.0), ("p99.9", 99.9)];
+
+/// Tolerance is how far a run may fall behind its baseline before it counts
+/// as a regression, as a percentage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance(f64);
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self(5.0)
+    }
+}
+
+impl FromStr for Tolerance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%').unwrap_or(s).parse::<f64>() {
+            Ok(percent) if percent >= 0.0 && percent.is_finite() => Ok(Self(percent)),
+            _ => Err(format!(
+                "invalid tolerance '{}': expected a percentage, e.g. 5%",
+                s
+            )),
+        }
+    }
+}
+
+/// Returns the file the baseline called `name` lives in.
+pub fn path(name: &str) -> Result<PathBuf, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        || name.starts_with('.')
+    {
+        return Err(format!(
+            "invalid baseline name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        ));
+    }
+
+    Ok(Path::new(DIRECTORY).join(format!("{}.json", name)))
+}
+
+/// Stores `summary`, the JSON summary of a run, as the baseline called
+/// `name`, replacing any baseline already saved under that name.
+pub fn save(name: &str, summary: &str) -> Result<PathBuf, String> {
+    let path = path(name)?;
+    std::fs::create_dir_all(DIRECTORY)
+        .and_then(|_| std::fs::write(&path, format!("{}\n", summary)))
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+/// Baseline is a saved run that later runs are held to.
+pub struct Baseline {
+    name: String,
+    run: Run,
+}
+
+impl Baseline {
+    pub fn load(name: &str) -> Result<Self, String> {
+        let path = path(name)?;
+        let run = Run::load(&path).map_err(|e| {
+            format!(
+                "could not read baseline '{}' from {}: {}",
+                name,
+                path.display(),
+                e
+            )
+        })?;
+
+        Ok(Self {
+            name: name.to_string(),
+            run,
+        })
+    }
+
+    /// Checks the totals for a run that took `runtime` against the baseline:
+    /// the request rate may not drop, nor the tail latencies rise, by more
+    /// than `tolerance`.
+    pub fn check(
+        &self,
+        tolerance: Tolerance,
+        details: &RunDetails,
+        runtime: Duration,
+    ) -> Vec<Verdict> {
+        let mut verdicts = Vec::new();
+
+        let floor = self.run.requests_per_second * (1.0 - tolerance.0 / 100.0);
+        let qps = Metric::Qps.value(details, runtime);
+        verdicts.push(Verdict {
+            assertion: format!(
+                "qps>={} (baseline {} less {}%)",
+                floor.round(),
+                self.name,
+                tolerance.0
+            ),
+            actual: Metric::Qps.format(qps),
+            passed: qps >= floor,
+        });
+
+        for (label, percentile) in TAIL {
+            let before = match self.run.latencies.iter().find(|(other, _)| other == label) {
+                Some((_, before)) => before,
+                None => continue,
+            };
+
+            let metric = Metric::Percentile(*percentile);
+            let ceiling = before * (1.0 + tolerance.0 / 100.0);
+            let value = metric.value(details, runtime);
+            verdicts.push(Verdict {
+                assertion: format!(
+                    "{}<={} (baseline {} plus {}%)",
+                    label,
+                    metric.format(ceiling),
+                    self.name,
+                    tolerance.0
+                ),
+                actual: metric.format(value),
+                passed: value <= ceiling,
+            });
+        }
+
+        verdicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a baseline of 1000 queries per second with a p99 of 2000ns
+    fn baseline(latencies: &str) -> Baseline {
+        let summary = format!(
+            r#"{{"totals": {{
+                "successes": 1000,
+                "failures": 0,
+                "requests_per_second": 1000,
+                "latency": {{"mean_ns": 1000, "stddev_ns": 10, {}}}
+            }}}}"#,
+            latencies
+        );
+
+        Baseline {
+            name: "main".to_string(),
+            run: Run::parse(&summary).unwrap(),
+        }
+    }
+
+    // a run of `successes` queries in a second, all of which took `latency`
+    // nanoseconds
+    fn details(successes: u64, latency: u64) -> RunDetails {
+        let mut details = RunDetails::default();
+        details.successes = successes;
+        for _ in 0..successes {
+            details.latencies.record(latency);
+        }
+        details
+    }
+
+    #[test]
+    fn parses_tolerances() {
+        let cases = [
+            ("5%", 5.0),
+            ("5", 5.0),
+            ("0", 0.0),
+            ("12.5%", 12.5),
+            ("150%", 150.0),
+        ];
+
+        for (s, percent) in cases {
+            assert_eq!(s.parse(), Ok(Tolerance(percent)), "{}", s);
+        }
+
+        for s in ["-1%", "abc", "", "%", "5%%", "inf", "NaN"] {
+            assert_eq!(
+                s.parse::<Tolerance>(),
+                Err(format!(
+                    "invalid tolerance '{}': expected a percentage, e.g. 5%",
+                    s
+                )),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn checks_names() {
+        for name in ["main", "v1.2", "pr-123_udp"] {
+            assert_eq!(
+                path(name),
+                Ok(Path::new(DIRECTORY).join(format!("{}.json", name)))
+            );
+        }
+
+        for name in ["", ".hidden", "..", "a/b", "../main", "a b", "ü"] {
+            assert!(path(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn holds_runs_to_the_tolerance() {
+        let baseline = baseline(r#""p50_ns": 1000, "p99_ns": 2000"#);
+        let runtime = Duration::from_secs(1);
+
+        // the request rate may drop to 950 and the p99 rise to 2100ns
+        let cases = [
+            (Tolerance(5.0), 950, 2100, true, true),
+            (Tolerance(5.0), 949, 2101, false, false),
+            (Tolerance(5.0), 2000, 100, true, true),
+            (Tolerance(0.0), 1000, 2000, true, true),
+            (Tolerance(0.0), 999, 2001, false, false),
+        ];
+
+        for (tolerance, qps, p99, qps_passes, p99_passes) in cases {
+            let verdicts = baseline.check(tolerance, &details(qps, p99), runtime);
+            let passed: Vec<_> = verdicts.iter().map(|verdict| verdict.passed).collect();
+            assert_eq!(
+                passed,
+                [qps_passes, p99_passes],
+                "{:?} {} {}",
+                tolerance,
+                qps,
+                p99
+            );
+        }
+
+        let verdicts = baseline.check(Tolerance(5.0), &details(950, 2100), runtime);
+        let assertions: Vec<_> = verdicts
+            .iter()
+            .map(|verdict| verdict.assertion.as_str())
+            .collect();
+        assert_eq!(
+            assertions,
+            [
+                "qps>=950 (baseline main less 5%)",
+                "p99<=2.10µs (baseline main plus 5%)",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_the_tails_the_baseline_has() {
+        let runtime = Duration::from_secs(1);
+
+        let verdicts = baseline(r#""p50_ns": 1000"#).check(
+            Tolerance::default(),
+            &details(1000, 1000),
+            runtime,
+        );
+        assert_eq!(verdicts.len(), 1);
+
+        let baseline = baseline(r#""p99_ns": 2000, "p99.9_ns": 3000"#);
+        let verdicts = baseline.check(Tolerance::default(), &details(1000, 3100), runtime);
+        let passed: Vec<_> = verdicts.iter().map(|verdict| verdict.passed).collect();
+        assert_eq!(passed, [true, false, true]);
+    }
+}
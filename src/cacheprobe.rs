@@ -0,0 +1,398 @@
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use argh::FromArgs;
+use tracing::{error, info, warn, Level};
+use trust_dns_resolver::proto::{
+    op::{Message, ResponseCode},
+    rr::{Name, RecordType},
+};
+
+use nsbench::{
+    extended_error, histogram::Histogram, parse_duration, parse_record_type, rcode_name, wire,
+    Transport,
+};
+
+use crate::{logging, NameserverAddr};
+
+// the UDP payload size advertised, so that resolvers can attach extended DNS
+// errors
+const EDNS_PAYLOAD: u16 = 1232;
+
+// the extended DNS errors (RFC 8914) for a stale answer and a stale NXDOMAIN
+const STALE_ANSWER: u16 = 3;
+const STALE_NXDOMAIN: u16 = 19;
+
+// the TTL RFC 8767 suggests stale answers are served with
+const STALE_TTL: u32 = 30;
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "cache-probe",
+    description = "ask a resolver for one name now and then, and tell cache hits from upstream fetches by whether the TTL counts down",
+    example = "nsbench cache-probe 192.0.2.53 example.com -n 30 --interval 2s"
+)]
+pub struct CacheProbeArguments {
+    #[argh(
+        option,
+        short = 'n',
+        description = "how many queries to send (default 10)",
+        default = "10"
+    )]
+    count: u32,
+
+    #[argh(
+        option,
+        description = "time between queries, e.g. 500ms; at least a second tells a cached TTL from a fixed one (default 2s)",
+        from_str_fn(parse_duration),
+        default = "Duration::from_secs(2)"
+    )]
+    interval: Duration,
+
+    #[argh(
+        option,
+        description = "record type to query (default A)",
+        from_str_fn(parse_record_type),
+        default = "RecordType::A"
+    )]
+    qtype: RecordType,
+
+    #[argh(
+        switch,
+        description = "prime the name, wait for the answer to expire while the resolver is cut off from the authoritative servers, then check whether it serves the stale answer (RFC 8767)"
+    )]
+    stale: bool,
+
+    #[argh(
+        option,
+        description = "duration to wait (in ns) before considering a request failed (default 1s, or 5s with --stale)"
+    )]
+    timeout: Option<u32>,
+
+    #[argh(
+        option,
+        short = 'p',
+        description = "port to contact the nameserver on (default 53)"
+    )]
+    port: Option<u16>,
+
+    #[argh(positional, description = "nameserver to probe, as ip or ip:port")]
+    nameserver: NameserverAddr,
+
+    #[argh(positional, description = "hostname to query")]
+    host: Name,
+}
+
+/// Verdict is what a response says about the resolver's cache, judged by its
+/// TTL against that of the response before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    /// The TTL counted down: the answer came from the cache.
+    Hit,
+    /// The TTL went back up, or held still for over a second: the answer was
+    /// fetched again.
+    Miss,
+    /// Too little time passed for a cached TTL to have moved.
+    Unknown,
+}
+
+impl Verdict {
+    fn judge(previous: u32, ttl: u32, elapsed: Duration) -> Self {
+        if ttl < previous {
+            Verdict::Hit
+        } else if ttl > previous || elapsed >= Duration::from_secs(1) {
+            Verdict::Miss
+        } else {
+            Verdict::Unknown
+        }
+    }
+}
+
+/// Stale is what a resolver cut off from the authoritative servers made of a
+/// query for a name whose answer had expired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stale {
+    /// The expired answer, as marked by an extended DNS error or its TTL.
+    Served,
+    /// An answer with a full TTL: the authoritative servers were reachable
+    /// after all.
+    Fresh,
+    /// An error or no response, with what it was.
+    Unanswered(String),
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Verdict::Hit => "cache hit",
+            Verdict::Miss => "upstream fetch",
+            Verdict::Unknown => "too soon to tell",
+        })
+    }
+}
+
+pub fn main(args: CacheProbeArguments) -> ! {
+    logging::init(Level::INFO);
+
+    let nameserver = args.nameserver.socket_addr(args.port).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    if args.count < 2 && !args.stale {
+        error!("-n must be at least 2, as each TTL is judged against the one before it");
+        std::process::exit(1);
+    }
+
+    // stale answers may only come once the resolver gives up on the
+    // authoritative servers, 1.8s in by RFC 8767's suggestion
+    let timeout = match (args.timeout, args.stale) {
+        (Some(timeout), _) => Duration::new(0, timeout),
+        (None, false) => Duration::from_secs(1),
+        (None, true) => Duration::from_secs(5),
+    };
+    let mut client = wire::Client::new(
+        nameserver,
+        wire::ClientOptions {
+            transport: Transport::Udp,
+            timeout,
+            retries: 0,
+            tcp_fallback: true,
+            bind: None,
+            ip_ttl: None,
+            dscp: None,
+            proxy: None,
+            edns_payload: Some(EDNS_PAYLOAD),
+            dnssec: false,
+            ecs: None,
+        },
+    );
+
+    if args.stale {
+        probe_stale(&args, nameserver, client);
+    }
+
+    info!(
+        "Probing {} for {} {} every {:?}",
+        nameserver, args.host, args.qtype, args.interval
+    );
+
+    let mut hits = Histogram::default();
+    let mut misses = Histogram::default();
+    let mut unknown = 0;
+    let mut failures = 0;
+    // the TTL of the last answer, and when it came
+    let mut previous: Option<(u32, Instant)> = None;
+
+    for i in 1..=args.count {
+        if i > 1 {
+            thread::sleep(args.interval);
+        }
+
+        let sent = Instant::now();
+        let res = client.query(args.host.clone(), args.qtype);
+        let latency = sent.elapsed();
+
+        let message = match res {
+            Ok(exchange) => exchange.message,
+            Err(e) => {
+                warn!("#{}: {}", i, e);
+                failures += 1;
+                continue;
+            }
+        };
+        let ttl = match (message.response_code(), answer_ttl(&message, args.qtype)) {
+            (ResponseCode::NoError, Some(ttl)) => ttl,
+            (ResponseCode::NoError, None) => {
+                warn!("#{}: NOERROR with no {} answers", i, args.qtype);
+                failures += 1;
+                continue;
+            }
+            (rcode, _) => {
+                warn!("#{}: {}", i, rcode_name(rcode.into()));
+                failures += 1;
+                continue;
+            }
+        };
+
+        match previous {
+            Some((previous_ttl, at)) => {
+                let verdict = Verdict::judge(previous_ttl, ttl, at.elapsed());
+                info!(
+                    "#{}: TTL {} ({:+}), {} in {:?}",
+                    i,
+                    ttl,
+                    ttl as i64 - previous_ttl as i64,
+                    verdict,
+                    latency
+                );
+                match verdict {
+                    Verdict::Hit => hits.record(latency.as_nanos() as u64),
+                    Verdict::Miss => misses.record(latency.as_nanos() as u64),
+                    Verdict::Unknown => unknown += 1,
+                }
+            }
+            None => info!("#{}: TTL {} in {:?}", i, ttl, latency),
+        }
+        previous = Some((ttl, Instant::now()));
+    }
+
+    println!("Nameserver: {}", nameserver);
+    println!("Query: {} {}", args.host, args.qtype);
+    println!("Queries: {}", args.count);
+    println!("Cache Hits: {}", hits.count());
+    println!("Upstream Fetches: {}", misses.count());
+    if unknown > 0 {
+        println!("Too Soon To Tell: {}", unknown);
+    }
+    if failures > 0 {
+        println!("Failures: {}", failures);
+    }
+    for (label, latencies) in [("Cache Hit", &hits), ("Upstream Fetch", &misses)] {
+        if latencies.count() > 0 {
+            println!(
+                "{} Latency: p50 {:?}, min {:?}, max {:?}",
+                label,
+                Duration::from_nanos(latencies.percentile(50.0)),
+                Duration::from_nanos(latencies.min()),
+                Duration::from_nanos(latencies.max())
+            );
+        }
+    }
+    match (hits.count(), misses.count()) {
+        (0, 0) => println!("Caching: not enough answers to tell"),
+        (0, _) => println!("Caching: none seen, every answer was fetched again"),
+        (_, 0) => println!("Caching: every answer came from the cache"),
+        _ => println!(
+            "Caching: a hit saves {:?} at the median",
+            Duration::from_nanos(misses.percentile(50.0))
+                .saturating_sub(Duration::from_nanos(hits.percentile(50.0)))
+        ),
+    }
+
+    std::process::exit(0);
+}
+
+// prints what a resolver made of the name once its answer expired
+fn probe_stale(args: &CacheProbeArguments, nameserver: SocketAddr, mut client: wire::Client) -> ! {
+    let sent = Instant::now();
+    let res = client.query(args.host.clone(), args.qtype);
+    let latency = sent.elapsed();
+    let primed = match res.map(|exchange| exchange.message) {
+        Ok(message) if message.response_code() == ResponseCode::NoError => {
+            answer_ttl(&message, args.qtype)
+        }
+        Ok(message) => {
+            error!(
+                "could not prime {} {}: {}",
+                args.host,
+                args.qtype,
+                rcode_name(message.response_code().into())
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!("could not prime {} {}: {}", args.host, args.qtype, e);
+            std::process::exit(1);
+        }
+    };
+    let primed = primed.unwrap_or_else(|| {
+        error!(
+            "could not prime {} {}: NOERROR with no answers",
+            args.host, args.qtype
+        );
+        std::process::exit(1);
+    });
+
+    info!(
+        "Primed {} {} with TTL {} in {:?}; cut {} off from the authoritative servers now, as the answer expires in {}s",
+        args.host, args.qtype, primed, latency, nameserver, primed
+    );
+    thread::sleep(Duration::from_secs(primed as u64 + 1));
+
+    let mut served = Histogram::default();
+    let mut fresh = 0;
+    let mut unanswered = 0;
+
+    for i in 1..=args.count {
+        if i > 1 {
+            thread::sleep(args.interval);
+        }
+
+        let sent = Instant::now();
+        let res = client.query(args.host.clone(), args.qtype);
+        let latency = sent.elapsed();
+
+        let stale = match res {
+            Ok(exchange) => judge_stale(&exchange.message, args.qtype, primed),
+            Err(e) => Stale::Unanswered(e.to_string()),
+        };
+        match &stale {
+            Stale::Served => {
+                info!("#{}: stale answer in {:?}", i, latency);
+                served.record(latency.as_nanos() as u64);
+            }
+            Stale::Fresh => {
+                info!("#{}: fresh answer in {:?}", i, latency);
+                fresh += 1;
+            }
+            Stale::Unanswered(e) => {
+                info!("#{}: {} after {:?}", i, e, latency);
+                unanswered += 1;
+            }
+        }
+    }
+
+    println!("Nameserver: {}", nameserver);
+    println!("Query: {} {}", args.host, args.qtype);
+    println!("Queries: {}", args.count);
+    println!("Stale Answers: {}", served.count());
+    println!("Fresh Answers: {}", fresh);
+    println!("Unanswered: {}", unanswered);
+    if served.count() > 0 {
+        println!(
+            "Stale Answer Latency: p50 {:?}, min {:?}, max {:?}",
+            Duration::from_nanos(served.percentile(50.0)),
+            Duration::from_nanos(served.min()),
+            Duration::from_nanos(served.max())
+        );
+    }
+    match (served.count(), fresh) {
+        (0, 0) => println!("Serve-stale: not seen, the expired answer went unanswered"),
+        (0, _) => println!("Serve-stale: can't tell, the authoritative servers still answered"),
+        _ => println!("Serve-stale: seen"),
+    }
+
+    std::process::exit(0);
+}
+
+// judges a response to a query for a name that was primed with a `primed` TTL
+// and has since expired
+fn judge_stale(message: &Message, record_type: RecordType, primed: u32) -> Stale {
+    let marked = matches!(
+        extended_error(message),
+        Some(STALE_ANSWER) | Some(STALE_NXDOMAIN)
+    );
+    match (message.response_code(), answer_ttl(message, record_type)) {
+        _ if marked => Stale::Served,
+        (ResponseCode::NoError, Some(ttl)) if ttl <= STALE_TTL && primed > STALE_TTL => {
+            Stale::Served
+        }
+        (ResponseCode::NoError, Some(_)) => Stale::Fresh,
+        (ResponseCode::NoError, None) => Stale::Unanswered("NOERROR with no answers".to_string()),
+        (rcode, _) => Stale::Unanswered(rcode_name(rcode.into())),
+    }
+}
+
+// the lowest TTL of the `record_type` records in the answer section
+fn answer_ttl(message: &Message, record_type: RecordType) -> Option<u32> {
+    message
+        .answers()
+        .iter()
+        .filter(|record| record.record_type() == record_type)
+        .map(|record| record.ttl())
+        .min()
+}
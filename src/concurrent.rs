@@ -0,0 +1,260 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use trust_dns_resolver::proto::{
+    op::{Message, MessageType},
+    rr::{Name, RecordType},
+};
+
+use tracing::error;
+
+use crate::{
+    failurelog,
+    mismatch::{self, TimedOut},
+    mmsg,
+    reporter::Reporter,
+    trace, wire, QueryConfig,
+};
+
+// how long to block on the socket when the window is full (or the rate
+// limiter is empty) before checking for expired queries again.
+const RECV_TICK: Duration = Duration::from_millis(1);
+
+/// Keeps up to `window` queries in flight, sending a new one as each is
+/// answered or times out. This gives many outstanding queries per thread,
+/// where the closed loop has exactly one. Queries are sent and answers read in
+/// batches where the platform allows (sendmmsg and recvmmsg on Linux), to save
+/// on system calls, and take turns between the worker's UDP sockets.
+///
+/// Only UDP is supported; truncated responses are counted but not retried.
+pub fn perform_queries(qc: &mut QueryConfig, reporter: &mut Reporter, window: usize) {
+    let mut sockets = Vec::with_capacity(qc.sockets);
+    for _ in 0..qc.sockets {
+        match wire::udp_socket(qc.nameserver, &qc.client_options) {
+            Ok(socket) => {
+                socket.set_read_timeout(Some(RECV_TICK)).unwrap();
+                sockets.push(socket);
+            }
+            Err(e) => {
+                error!("could not open socket to {}: {}", qc.nameserver, e);
+                return;
+            }
+        }
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut trace = qc
+        .trace
+        .as_ref()
+        .map(|trace| trace::Buffer::new(trace.clone(), qc.nameserver));
+    let mut bufs = vec![vec![0u8; wire::MAX_UDP_SIZE]; mmsg::BATCH];
+    // query id -> when it was sent, and its name and type
+    let mut outstanding: HashMap<u16, (Instant, Name, RecordType)> = HashMap::with_capacity(window);
+    let mut timed_out = TimedOut::new();
+    let mut next_id: u16 = 0;
+    let mut next_socket = 0;
+    let mut sending = true;
+
+    while sending || !outstanding.is_empty() {
+        reporter.tick();
+        if qc.finished.load(Ordering::Relaxed) {
+            sending = false;
+        }
+
+        // fill the window, sending the new queries for each socket together
+        let mut batches = vec![Vec::new(); sockets.len()];
+        let mut packets = vec![Vec::new(); sockets.len()];
+        let mut queued = HashSet::new();
+        while sending && outstanding.len() + queued.len() < window {
+            // skip ids that are still waiting on an answer, or about to be
+            // sent; should every one be taken, wait for some to free up
+            let mut tries = 0;
+            let free = loop {
+                next_id = next_id.wrapping_add(1);
+                if !outstanding.contains_key(&next_id) && !queued.contains(&next_id) {
+                    break true;
+                }
+                tries += 1;
+                if tries > u16::MAX as u32 {
+                    break false;
+                }
+            };
+            if !free {
+                break;
+            }
+
+            if let Some(limiter) = &qc.limiter {
+                if !limiter.try_acquire() {
+                    break;
+                }
+            }
+
+            if let Some(budget) = &qc.budget {
+                if !budget.take() {
+                    sending = false;
+                    break;
+                }
+            }
+
+            let (name, record_type) = qc.names.next(&mut rng);
+            let record_type = record_type.unwrap_or_else(|| qc.qtypes.sample(&mut rng));
+
+            *reporter
+                .details()
+                .queries_by_type
+                .entry(record_type)
+                .or_default() += 1;
+
+            match wire::build_query(next_id, name.clone(), record_type, &qc.client_options) {
+                Ok(packet) => {
+                    batches[next_socket].push((next_id, name, record_type));
+                    packets[next_socket].push(packet);
+                    next_socket = (next_socket + 1) % sockets.len();
+                    queued.insert(next_id);
+                }
+                Err(e) => failed(qc, reporter, &mut trace, &name, record_type, e.into()),
+            }
+        }
+
+        for ((socket, batch), packets) in sockets.iter().zip(batches).zip(packets) {
+            if batch.is_empty() {
+                continue;
+            }
+
+            let results = mmsg::send(socket, &packets);
+            let now = Instant::now();
+            for ((id, name, record_type), sent) in batch.into_iter().zip(results) {
+                match sent {
+                    Ok(len) => {
+                        reporter.details().bytes_sent += len as u64;
+                        outstanding.insert(id, (now, name, record_type));
+                    }
+                    Err(e) => failed(qc, reporter, &mut trace, &name, record_type, e.into()),
+                }
+            }
+        }
+
+        let mut received = Vec::new();
+        for i in mmsg::wait(&sockets, RECV_TICK).unwrap_or_default() {
+            let lengths = mmsg::recv(&sockets[i], &mut bufs[received.len()..]).unwrap_or_default();
+            received.extend(lengths);
+            if received.len() == bufs.len() {
+                break;
+            }
+        }
+        let now = Instant::now();
+        for (buf, size) in bufs.iter().zip(received) {
+            reporter.details().bytes_received += size as u64;
+
+            let message = match Message::from_vec(&buf[..size]) {
+                Ok(message) if message.message_type() == MessageType::Response => message,
+                _ => {
+                    reporter.details().mismatches.malformed += 1;
+                    continue;
+                }
+            };
+
+            let answered = mismatch::take(
+                &mut outstanding,
+                &mut timed_out,
+                &message,
+                &mut reporter.details().mismatches,
+            );
+            if let Some((sent, name, record_type)) = answered {
+                let exchange = wire::Exchange {
+                    truncated: message.truncated(),
+                    message,
+                    size,
+                    attempts: 1,
+                    connect_time: None,
+                    tcp_fallback: false,
+                };
+                let latency = now.duration_since(sent);
+                let res = Ok(exchange);
+                if let Some(trace) = &mut trace {
+                    trace.record(sent, &name, record_type, &res, latency);
+                }
+                let success = reporter.details().record(
+                    record_type,
+                    &res,
+                    latency.as_nanos(),
+                    qc.expect_nxdomain,
+                    qc.expected.as_deref(),
+                );
+                if !success {
+                    qc.failure_log
+                        .failed(qc.nameserver, &name, record_type, latency, || {
+                            failurelog::cause(&res)
+                        });
+                }
+
+                if let Some(budget) = &qc.budget {
+                    budget.complete(1);
+                }
+            }
+        }
+
+        // anything that has waited longer than the timeout has failed
+        let now = Instant::now();
+        let timeout = qc.client_options.timeout;
+        let mut expired = 0;
+        outstanding.retain(|id, (sent, name, record_type)| {
+            if now.duration_since(*sent) < timeout {
+                return true;
+            }
+
+            timed_out.insert(*id);
+            reporter.details().timed_out(*record_type);
+            if let Some(trace) = &mut trace {
+                trace.timed_out(*sent, name, *record_type);
+            }
+            qc.failure_log.failed(
+                qc.nameserver,
+                name,
+                *record_type,
+                now.duration_since(*sent),
+                || wire::QueryError::Timeout.to_string(),
+            );
+            expired += 1;
+            false
+        });
+
+        if expired > 0 {
+            if let Some(budget) = &qc.budget {
+                budget.complete(expired);
+            }
+        }
+    }
+}
+
+// accounts for a query that could not be sent
+fn failed(
+    qc: &QueryConfig,
+    reporter: &mut Reporter,
+    trace: &mut Option<trace::Buffer>,
+    name: &Name,
+    record_type: RecordType,
+    error: wire::QueryError,
+) {
+    let res = Err(error);
+    reporter.details().record(
+        record_type,
+        &res,
+        0,
+        qc.expect_nxdomain,
+        qc.expected.as_deref(),
+    );
+    if let Some(trace) = trace {
+        trace.record(Instant::now(), name, record_type, &res, Duration::ZERO);
+    }
+    qc.failure_log
+        .failed(qc.nameserver, name, record_type, Duration::ZERO, || {
+            failurelog::cause(&res)
+        });
+    if let Some(budget) = &qc.budget {
+        budget.complete(1);
+    }
+}
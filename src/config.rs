@@ -0,0 +1,487 @@
+use std::path::Path;
+
+// the settings that fill in `nsbench run`'s positional arguments, in order
+const POSITIONALS: &[&str] = &["nameserver", "host"];
+
+/// Value is what a setting in a config file holds: a switch to turn on or
+/// off, or the values of an option, one for each time it is given.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Switch(bool),
+    Option(Vec<String>),
+}
+
+/// Config is a run described in a TOML file, such as:
+///
+/// ```text
+/// [targets]
+/// nameserver = ["10.0.0.1", "10.0.0.2:5353"]
+/// protocol = "udp"
+///
+/// [workload]
+/// query-file = "names.txt"
+/// qtype-mix = "A:60,AAAA:40"
+///
+/// [rate]
+/// ramp = "1000:20000:60"
+/// time-secs = 300
+///
+/// [output]
+/// format = "json"
+/// out = "run.json"
+/// assert = ["p99<5ms", "success_rate>99.9"]
+/// ```
+///
+/// Every key is the long name of a `nsbench run` option, with `_` allowed
+/// for `-`, or `nameserver` or `host`. Tables only group the keys, so their
+/// names are up to the file. Switches take `true` or `false`, options that can
+/// be repeated take an array, and several nameservers can be an array too.
+/// Dotted keys, inline tables, multi-line strings and `\u` escapes are not
+/// supported, nor are counted switches: `verbose = 2` becomes `--verbose 2`.
+#[derive(Debug, Default)]
+pub struct Config {
+    settings: Vec<(String, Value)>,
+}
+
+impl Config {
+    /// Reads the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        Self::parse(&contents)
+            .map_err(|(lineno, msg)| format!("{}:{}: {}", path.display(), lineno, msg))
+    }
+
+    /// Returns the command line `args` with the config's settings added in
+    /// front, leaving out those the command line already sets. `given` is
+    /// `args` as redacted by argh, so that it names each option, whichever
+    /// way it was spelled, and each positional argument.
+    pub fn merge(&self, args: &[String], given: &[String]) -> Vec<String> {
+        let positionals = given
+            .iter()
+            .filter(|arg| POSITIONALS.contains(&arg.as_str()))
+            .count();
+
+        let mut merged = Vec::new();
+        let mut trailing = Vec::new();
+        for (key, value) in &self.settings {
+            if let Some(position) = POSITIONALS.iter().position(|name| name == key) {
+                if position >= positionals {
+                    if let Value::Option(values) = value {
+                        trailing.push((position, values.join(",")));
+                    }
+                }
+                continue;
+            }
+
+            let flag = format!("--{}", key);
+            if given.contains(&flag) {
+                continue;
+            }
+
+            match value {
+                Value::Switch(true) => merged.push(flag),
+                Value::Switch(false) => {}
+                Value::Option(values) => {
+                    for value in values {
+                        merged.push(flag.clone());
+                        merged.push(value.clone());
+                    }
+                }
+            }
+        }
+
+        // positional arguments go after the command line's own, in order
+        trailing.sort();
+        merged.extend(args.iter().cloned());
+        merged.extend(trailing.into_iter().map(|(_, value)| value));
+        merged
+    }
+
+    // parses the TOML in `contents`, failing with the line and what is wrong
+    // with it
+    fn parse(contents: &str) -> Result<Self, (usize, String)> {
+        let mut config = Self::default();
+        let mut cursor = Cursor {
+            rest: contents,
+            lineno: 1,
+        };
+
+        loop {
+            cursor.skip_blank();
+            let lineno = cursor.lineno;
+            if cursor.rest.is_empty() {
+                break;
+            }
+
+            if let Some(rest) = cursor.rest.strip_prefix('[') {
+                let end = rest
+                    .find([']', '\n'])
+                    .filter(|end| rest[*end..].starts_with(']'))
+                    .ok_or((lineno, "unterminated table header".to_string()))?;
+                cursor.rest = &rest[end + 1..];
+                cursor.end_line()?;
+                continue;
+            }
+
+            let end = cursor
+                .rest
+                .find('=')
+                .filter(|end| !cursor.rest[..*end].contains('\n'))
+                .ok_or((lineno, "expected key = value".to_string()))?;
+            let key = cursor.rest[..end]
+                .trim()
+                .trim_matches('"')
+                .replace('_', "-");
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err((lineno, format!("invalid key '{}'", key)));
+            }
+            if key == "config" {
+                return Err((lineno, "config files cannot include others".to_string()));
+            }
+            if config.settings.iter().any(|(seen, _)| *seen == key) {
+                return Err((lineno, format!("{} is set twice", key)));
+            }
+            cursor.rest = &cursor.rest[end + 1..];
+
+            cursor.skip_spaces();
+            let value = if cursor.rest.starts_with('[') {
+                cursor.rest = &cursor.rest[1..];
+                let mut values = Vec::new();
+                loop {
+                    cursor.skip_blank();
+                    if let Some(rest) = cursor.rest.strip_prefix(']') {
+                        cursor.rest = rest;
+                        break;
+                    }
+
+                    match cursor.scalar()? {
+                        Value::Option(value) => values.extend(value),
+                        Value::Switch(_) => {
+                            return Err((
+                                cursor.lineno,
+                                "arrays of booleans are not supported".to_string(),
+                            ))
+                        }
+                    }
+
+                    cursor.skip_blank();
+                    if let Some(rest) = cursor.rest.strip_prefix(',') {
+                        cursor.rest = rest;
+                    } else if !cursor.rest.starts_with(']') {
+                        return Err((cursor.lineno, "expected , or ] in array".to_string()));
+                    }
+                }
+                Value::Option(values)
+            } else {
+                cursor.scalar()?
+            };
+            cursor.end_line()?;
+
+            if POSITIONALS.contains(&key.as_str()) && matches!(value, Value::Switch(_)) {
+                return Err((lineno, format!("{} must be a string", key)));
+            }
+            config.settings.push((key, value));
+        }
+
+        Ok(config)
+    }
+}
+
+// a position in a config file, and the line it is on
+struct Cursor<'a> {
+    rest: &'a str,
+    lineno: usize,
+}
+
+impl Cursor<'_> {
+    fn advance(&mut self, len: usize) {
+        self.lineno += self.rest[..len].matches('\n').count();
+        self.rest = &self.rest[len..];
+    }
+
+    // skips spaces and tabs, but not the end of the line
+    fn skip_spaces(&mut self) {
+        let len = self.rest.len() - self.rest.trim_start_matches([' ', '\t']).len();
+        self.advance(len);
+    }
+
+    // skips whitespace, newlines and comments
+    fn skip_blank(&mut self) {
+        loop {
+            let len = self.rest.len() - self.rest.trim_start().len();
+            self.advance(len);
+            if !self.rest.starts_with('#') {
+                return;
+            }
+            self.advance(self.rest.find('\n').unwrap_or(self.rest.len()));
+        }
+    }
+
+    // makes sure nothing but a comment follows on the line
+    fn end_line(&mut self) -> Result<(), (usize, String)> {
+        self.skip_spaces();
+        if self.rest.starts_with('#') {
+            self.advance(self.rest.find('\n').unwrap_or(self.rest.len()));
+        }
+        if !self.rest.is_empty() && !self.rest.starts_with(['\n', '\r']) {
+            return Err((self.lineno, "expected the end of the line".to_string()));
+        }
+
+        Ok(())
+    }
+
+    // reads a string, number or boolean
+    fn scalar(&mut self) -> Result<Value, (usize, String)> {
+        let lineno = self.lineno;
+        let unterminated = || (lineno, "unterminated string".to_string());
+
+        if let Some(rest) = self.rest.strip_prefix('\'') {
+            let end = rest
+                .find(['\'', '\n'])
+                .filter(|end| rest[*end..].starts_with('\''))
+                .ok_or_else(unterminated)?;
+            let value = rest[..end].to_string();
+            self.advance(end + 2);
+            return Ok(Value::Option(vec![value]));
+        }
+
+        if let Some(rest) = self.rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = rest.char_indices();
+            loop {
+                match chars.next().ok_or_else(unterminated)? {
+                    (_, '\n') => return Err(unterminated()),
+                    (end, '"') => {
+                        self.advance(end + 2);
+                        return Ok(Value::Option(vec![value]));
+                    }
+                    (_, '\\') => value.push(match chars.next().ok_or_else(unterminated)?.1 {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        c @ ('"' | '\\') => c,
+                        c => return Err((lineno, format!("unsupported escape \\{}", c))),
+                    }),
+                    (_, c) => value.push(c),
+                }
+            }
+        }
+
+        let len = self
+            .rest
+            .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+            .unwrap_or(self.rest.len());
+        let token = &self.rest[..len];
+        let value = match token {
+            "true" => Value::Switch(true),
+            "false" => Value::Switch(false),
+            _ => {
+                let number = token.replace('_', "");
+                if number.parse::<f64>().is_err() {
+                    return Err((lineno, format!("invalid value '{}': quote strings", token)));
+                }
+                Value::Option(vec![number])
+            }
+        };
+        self.advance(len);
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let cases = [
+            ("qps = 100\n[rate\n", 2, "unterminated table header"),
+            (
+                "# comment\n\nhost = \"example.com\n",
+                3,
+                "unterminated string",
+            ),
+            ("qps = 100\nqps = 200\n", 2, "qps is set twice"),
+            ("[a]\nrate.qps = 100\n", 2, "invalid key 'rate.qps'"),
+            ("protocol = udp\n", 1, "invalid value 'udp': quote strings"),
+            ("x = {a = 1}\n", 1, "invalid value '{a': quote strings"),
+            ("host = \"\\u0041\"\n", 1, "unsupported escape \\u"),
+            (
+                "assert = [\n  \"p99<5ms\"\n  \"p50<1ms\"\n]\n",
+                3,
+                "expected , or ] in array",
+            ),
+            ("qps = 100 200\n", 1, "expected the end of the line"),
+            (
+                "host = \"\"\"\nexample.com\"\"\"\n",
+                1,
+                "expected the end of the line",
+            ),
+            ("nameserver = true\n", 1, "nameserver must be a string"),
+            (
+                "config = \"other.toml\"\n",
+                1,
+                "config files cannot include others",
+            ),
+        ];
+
+        for (contents, lineno, msg) in cases {
+            assert_eq!(
+                Config::parse(contents).unwrap_err(),
+                (lineno, msg.to_string()),
+                "{:?}",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn parses_values() {
+        let config = Config::parse(
+            r#"
+            [output]
+            time_secs = 1_000 # a comment
+            dnssec = true
+            shuffle = false
+            out = 'C:\runs\out.json'
+            name-template = "a\tb\"c\""
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.settings,
+            vec![
+                ("time-secs".to_string(), Value::Option(strings(&["1000"]))),
+                ("dnssec".to_string(), Value::Switch(true)),
+                ("shuffle".to_string(), Value::Switch(false)),
+                (
+                    "out".to_string(),
+                    Value::Option(strings(&["C:\\runs\\out.json"]))
+                ),
+                (
+                    "name-template".to_string(),
+                    Value::Option(strings(&["a\tb\"c\""]))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_arrays() {
+        let config = Config::parse(
+            r#"
+            nameserver = ["10.0.0.1", "10.0.0.2:5353"]
+            assert = [
+                "p99<5ms", # the tail
+                "success_rate>99.9",
+            ]
+            qtype = []
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.settings,
+            vec![
+                (
+                    "nameserver".to_string(),
+                    Value::Option(strings(&["10.0.0.1", "10.0.0.2:5353"]))
+                ),
+                (
+                    "assert".to_string(),
+                    Value::Option(strings(&["p99<5ms", "success_rate>99.9"]))
+                ),
+                ("qtype".to_string(), Value::Option(Vec::new())),
+            ]
+        );
+
+        assert_eq!(
+            Config::parse("x = [true]\n").unwrap_err(),
+            (1, "arrays of booleans are not supported".to_string())
+        );
+        assert_eq!(
+            config.merge(&strings(&["-t", "5"]), &strings(&["--time-secs"])),
+            strings(&[
+                "--assert",
+                "p99<5ms",
+                "--assert",
+                "success_rate>99.9",
+                "-t",
+                "5",
+                "10.0.0.1,10.0.0.2:5353",
+            ])
+        );
+    }
+
+    #[test]
+    fn merges_positionals_after_the_command_line() {
+        let config = Config::parse(
+            r#"
+            host = "example.com"
+            nameserver = "10.0.0.1"
+            qps = 100
+            "#,
+        )
+        .unwrap();
+
+        // both come from the file, in order, whichever order it has them in
+        assert_eq!(
+            config.merge(&[], &[]),
+            strings(&["--qps", "100", "10.0.0.1", "example.com"])
+        );
+
+        // the command line's nameserver comes first, then the file's host
+        assert_eq!(
+            config.merge(&strings(&["192.0.2.1"]), &strings(&["nameserver"])),
+            strings(&["--qps", "100", "192.0.2.1", "example.com"])
+        );
+
+        // and with both on the command line, the file's are left out
+        assert_eq!(
+            config.merge(
+                &strings(&["192.0.2.1", "example.org"]),
+                &strings(&["nameserver", "host"])
+            ),
+            strings(&["--qps", "100", "192.0.2.1", "example.org"])
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let config = Config::parse(
+            r#"
+            nameserver = "10.0.0.1"
+            qps = 100
+            time_secs = 60
+            tui = true
+            assert = ["p99<5ms"]
+            "#,
+        )
+        .unwrap();
+
+        // -t arrives here as --time-secs, as with_config names it
+        let merged = config.merge(
+            &strings(&["--qps", "200", "-t", "10", "--assert", "p50<1ms"]),
+            &strings(&["--qps", "--time-secs", "--assert"]),
+        );
+        assert_eq!(
+            merged,
+            strings(&["--tui", "--qps", "200", "-t", "10", "--assert", "p50<1ms", "10.0.0.1",])
+        );
+    }
+
+    #[test]
+    fn counted_switches_become_options() {
+        // documented as unsupported: argh rejects the value it is given
+        let config = Config::parse("verbose = 2\n").unwrap();
+        assert_eq!(config.merge(&[], &[]), strings(&["--verbose", "2"]));
+    }
+}
@@ -0,0 +1,109 @@
+//! Asking several nameservers the same questions and finding where their
+//! answers differ, as when checking a new resolver against the one it
+//! replaces.
+
+use std::net::SocketAddr;
+
+use trust_dns_resolver::proto::rr::{Name, RecordType};
+
+use crate::{rcode_name, wire};
+
+/// Outcome is what a nameserver made of a query: its response code and
+/// answer section, or why no response came back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The response code, and the answers as sorted `<name> <type> <data>`
+    /// lines, leaving out TTLs, which count down in caches.
+    Answered(String, Vec<String>),
+    Failed(String),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Answered(rcode, answers) if answers.is_empty() => {
+                write!(f, "{} with no answers", rcode)
+            }
+            Outcome::Answered(rcode, answers) => write!(f, "{} {}", rcode, answers.join(", ")),
+            Outcome::Failed(e) => f.write_str(e),
+        }
+    }
+}
+
+/// Disagreement is a query the nameservers didn't answer alike, with what
+/// each of them made of it, in order.
+#[derive(Debug, Clone)]
+pub struct Disagreement {
+    pub name: Name,
+    pub record_type: RecordType,
+    pub outcomes: Vec<Outcome>,
+}
+
+/// Report is how a consistency check went.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// How many queries every nameserver was asked.
+    pub checked: usize,
+    pub disagreements: Vec<Disagreement>,
+}
+
+/// Asks every one of `nameservers` each of `queries`, one at a time, and
+/// returns those they answered differently: with another response code, or
+/// another set of records in the answer section, in any order.
+pub fn check(
+    nameservers: &[SocketAddr],
+    options: wire::ClientOptions,
+    queries: impl IntoIterator<Item = (Name, RecordType)>,
+) -> Report {
+    let mut clients: Vec<_> = nameservers
+        .iter()
+        .map(|nameserver| wire::Client::new(*nameserver, options))
+        .collect();
+
+    let mut report = Report::default();
+    for (name, record_type) in queries {
+        let outcomes: Vec<_> = clients
+            .iter_mut()
+            .map(|client| outcome(client.query(name.clone(), record_type)))
+            .collect();
+
+        report.checked += 1;
+        if outcomes.iter().any(|outcome| *outcome != outcomes[0]) {
+            report.disagreements.push(Disagreement {
+                name,
+                record_type,
+                outcomes,
+            });
+        }
+    }
+
+    report
+}
+
+fn outcome(res: Result<wire::Exchange, wire::QueryError>) -> Outcome {
+    let exchange = match res {
+        Ok(exchange) => exchange,
+        Err(e) => return Outcome::Failed(e.to_string()),
+    };
+
+    let message = &exchange.message;
+    let mut answers: Vec<String> = message
+        .answers()
+        .iter()
+        .map(|record| {
+            let data = record
+                .data()
+                .map(|data| data.to_string())
+                .unwrap_or_default();
+            format!(
+                "{} {} {}",
+                record.name().to_lowercase(),
+                record.record_type(),
+                data
+            )
+        })
+        .collect();
+    answers.sort();
+
+    Outcome::Answered(rcode_name(message.response_code().into()), answers)
+}
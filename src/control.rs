@@ -0,0 +1,256 @@
+//! A socket to steer a run while it goes on. Each line sent to it is a
+//! command, answered with a line of its own: `ok`, or `error: ` and why.
+//!
+//! - `set qps <n>` changes the rate each nameserver is sent, for the rest of
+//!   the stage.
+//! - `summary` logs a summary of the run so far, as SIGUSR1 does.
+//! - `stop` ends the run early, as Ctrl-C does.
+//! - `stats` answers with the totals so far, as a line of JSON, instead of
+//!   `ok`.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    interrupt,
+    json::{self, Value},
+    per_second,
+    rate::RateLimiter,
+    rcode_name, Failures, RunDetails, LATENCY_PERCENTILES,
+};
+
+/// Addr is where the control socket listens: a TCP address, or the path of
+/// a Unix socket.
+#[derive(Debug, Clone)]
+pub enum Addr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for Addr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') {
+            return Ok(Addr::Unix(PathBuf::from(s)));
+        }
+
+        // unlike the metrics, a control socket only listens on loopback
+        // unless told otherwise
+        let addr = match s.strip_prefix(':') {
+            Some(port) => format!("127.0.0.1:{}", port),
+            None => s.to_string(),
+        };
+        addr.parse().map(Addr::Tcp).map_err(|_| {
+            format!(
+                "invalid control socket '{}': expected [<ip>]:<port> or a path",
+                s
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for Addr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Addr::Tcp(addr) => write!(f, "{}", addr),
+            Addr::Unix(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Control holds what the commands act on: the rate limiters of the stage in
+/// progress, if its rate can be changed, and the totals so far.
+#[derive(Debug, Default)]
+pub struct Control {
+    limiters: Mutex<Vec<Arc<RateLimiter>>>,
+    // and how far into the run they go
+    totals: Mutex<(RunDetails, Duration)>,
+}
+
+impl Control {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hands over the rate limiters of a stage as it starts; none if its rate
+    /// is not a fixed --qps.
+    pub(crate) fn stage_started(&self, limiters: Vec<Arc<RateLimiter>>) {
+        *self.limiters.lock().unwrap() = limiters;
+    }
+
+    /// Adds a worker's report to the totals.
+    pub(crate) fn add(&self, details: RunDetails) {
+        self.totals.lock().unwrap().0 += details;
+    }
+
+    /// Notes that every worker has reported on the run up to `elapsed`.
+    pub(crate) fn reported_until(&self, elapsed: Duration) {
+        self.totals.lock().unwrap().1 = elapsed;
+    }
+
+    // the totals so far, for the stats command
+    fn stats(&self) -> Value {
+        let (totals, elapsed) = &*self.totals.lock().unwrap();
+        let elapsed = *elapsed;
+
+        let mut latencies = vec![
+            ("mean", (totals.mean_latency().as_nanos() as u64).into()),
+            ("min", totals.latencies.min().into()),
+        ];
+        latencies.extend(
+            LATENCY_PERCENTILES.iter().map(|(label, percentile)| {
+                (*label, totals.latencies.percentile(*percentile).into())
+            }),
+        );
+        latencies.push(("max", totals.latencies.max().into()));
+
+        json::object(vec![
+            ("elapsed_ns", (elapsed.as_nanos() as u64).into()),
+            ("successes", totals.successes.into()),
+            ("failures", totals.failures.into()),
+            ("failure_causes", failures(&totals.failure_causes)),
+            (
+                "requests_per_second",
+                per_second(totals.successes, elapsed).into(),
+            ),
+            ("latency_ns", json::object(latencies)),
+            (
+                "rcodes",
+                Value::Object(
+                    totals
+                        .rcodes
+                        .iter()
+                        .map(|(rcode, count)| (rcode_name(*rcode), (*count).into()))
+                        .collect(),
+                ),
+            ),
+            ("bytes_sent", totals.bytes_sent.into()),
+            ("bytes_received", totals.bytes_received.into()),
+        ])
+    }
+
+    // carries out a command, returning its answer or what went wrong if it
+    // could not be
+    fn execute(&self, command: &str) -> Result<String, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["set", "qps", qps] => {
+                let qps: u64 = qps
+                    .parse()
+                    .ok()
+                    .filter(|qps| *qps > 0)
+                    .ok_or_else(|| format!("invalid rate '{}'", qps))?;
+
+                let limiters = self.limiters.lock().unwrap();
+                if limiters.is_empty() {
+                    return Err(
+                        "the rate can only be changed in a closed-loop stage with a fixed --qps"
+                            .to_string(),
+                    );
+                }
+                for limiter in limiters.iter() {
+                    limiter.set_rate(qps as f64);
+                }
+                info!("Rate changed to {}/s per nameserver", qps);
+                Ok("ok".to_string())
+            }
+            ["summary"] => {
+                interrupt::want_summary();
+                Ok("ok".to_string())
+            }
+            ["stop"] => {
+                info!("Stopping the run");
+                interrupt::stop();
+                Ok("ok".to_string())
+            }
+            ["stats"] => Ok(self.stats().to_string()),
+            _ => Err(format!(
+                "unknown command '{}': expected set qps <n>, summary, stats or stop",
+                command.trim()
+            )),
+        }
+    }
+}
+
+/// Listens for commands on `addr` from a background thread for the rest of
+/// the process's life, each connection on a thread of its own.
+pub fn serve(addr: &Addr, control: Arc<Control>) -> io::Result<()> {
+    match addr {
+        Addr::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let control = control.clone();
+                    thread::spawn(move || {
+                        let reader = BufReader::new(stream.try_clone()?);
+                        converse(reader, stream, &control)
+                    });
+                }
+            });
+        }
+        #[cfg(unix)]
+        Addr::Unix(path) => {
+            let listener = std::os::unix::net::UnixListener::bind(path)?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let control = control.clone();
+                    thread::spawn(move || {
+                        let reader = BufReader::new(stream.try_clone()?);
+                        converse(reader, stream, &control)
+                    });
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        Addr::Unix(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix control sockets are not supported on this platform",
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+fn failures(failures: &Failures) -> Value {
+    json::object(vec![
+        ("timeout", failures.timeout.into()),
+        ("servfail", failures.servfail.into()),
+        ("refused", failures.refused.into()),
+        ("nxdomain", failures.nxdomain.into()),
+        ("network", failures.network.into()),
+        ("wrong_answer", failures.wrong_answer.into()),
+        ("wrong_flags", failures.wrong_flags.into()),
+        ("other", failures.other.into()),
+    ])
+}
+
+// answers the commands on a connection until it is closed
+fn converse(reader: impl BufRead, mut writer: impl Write, control: &Control) -> io::Result<()> {
+    for command in reader.lines() {
+        let command = command?;
+        if command.trim().is_empty() {
+            continue;
+        }
+
+        match control.execute(&command) {
+            Ok(answer) => writeln!(writer, "{}", answer)?,
+            Err(e) => {
+                warn!("control command '{}' failed: {}", command.trim(), e);
+                writeln!(writer, "error: {}", e)?
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    thread,
+};
+
+use argh::FromArgs;
+use tracing::info;
+
+use nsbench::{
+    json::{self, Value},
+    remote, stages, Results,
+};
+
+use crate::RunArguments;
+
+/// The port agents listen on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 7353;
+
+// the options of `nsbench run` that only shape what the controller prints or
+// writes once the agents are done, and whether each takes a value; they are
+// kept from the agents
+const LOCAL_OPTIONS: &[(&str, bool)] = &[
+    ("--config", true),
+    ("--consistency", true),
+    ("--qname-min", false),
+    ("--timeseries", false),
+    ("--format", true),
+    ("--out", true),
+    ("--csv", true),
+    ("--report", true),
+    ("--junit", true),
+    ("--assert", true),
+    ("--save-baseline", true),
+    ("--check-baseline", true),
+    ("--tolerance", true),
+    ("--checkpoint", true),
+    ("--checkpoint-interval", true),
+];
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "controller",
+    description = "run a benchmark from several agents at once, and sum up their results",
+    example = "nsbench controller --agent 10.0.0.5 --agent 10.0.0.6 -- 192.0.2.53 example.com -t 60"
+)]
+pub struct ControllerArguments {
+    #[argh(
+        option,
+        description = "host or host:port of an `nsbench agent` to run on (port 7353 if not given); repeat for each"
+    )]
+    pub agent: Vec<String>,
+
+    #[argh(
+        positional,
+        greedy,
+        description = "the arguments of `nsbench run`, after --, which every agent runs in full"
+    )]
+    pub args: Vec<String>,
+}
+
+/// Controller sends a run to its agents and collects what they measured.
+#[derive(Debug)]
+pub struct Controller {
+    agents: Vec<String>,
+    args: Vec<String>,
+}
+
+impl Controller {
+    /// Returns a controller for the agents in `controller`, to run the
+    /// `nsbench run` arguments `args`, with any config file already merged.
+    pub fn new(controller: &ControllerArguments, args: &[String]) -> Self {
+        Self {
+            agents: controller.agent.clone(),
+            args: forwarded(args),
+        }
+    }
+
+    /// Returns the option given in `args` that can't be used with a
+    /// controller, as it reports while the run goes on, where only the agents
+    /// see it.
+    pub fn unsupported(args: &RunArguments) -> Option<&'static str> {
+        [
+            (args.tui, "--tui"),
+            (args.ndjson.is_some(), "--ndjson"),
+            (args.trace.is_some(), "--trace"),
+            (args.prometheus.is_some(), "--prometheus"),
+            (args.statsd.is_some(), "--statsd"),
+            (args.influx.is_some(), "--influx"),
+            (args.otlp.is_some(), "--otlp"),
+            (args.control.is_some(), "--control"),
+            (args.find_max.is_some(), "--find-max"),
+            (args.script.is_some(), "--script"),
+            (args.results_file.is_some(), "--results-file"),
+        ]
+        .iter()
+        .find(|(given, _)| *given)
+        .map(|(_, option)| *option)
+    }
+
+    /// Runs `stages` on every agent at once and returns their results added
+    /// up, or what went wrong with the first agent that failed.
+    pub fn run(&self, stages: &[stages::Stage]) -> Result<Results, String> {
+        if self.agents.is_empty() {
+            return Err("at least one --agent is required".to_string());
+        }
+
+        info!(
+            "Running on {} agent{}",
+            self.agents.len(),
+            if self.agents.len() == 1 { "" } else { "s" }
+        );
+        let request = json::object(vec![(
+            "args",
+            Value::Array(self.args.iter().map(|arg| arg.as_str().into()).collect()),
+        )])
+        .to_string();
+
+        let handles: Vec<_> = self
+            .agents
+            .iter()
+            .cloned()
+            .map(|agent| {
+                let request = request.clone();
+                thread::spawn(move || {
+                    let reply = ask(&agent, &request);
+                    (agent, reply)
+                })
+            })
+            .collect();
+
+        let mut shares = Vec::new();
+        for handle in handles {
+            let (agent, reply) = handle.join().unwrap();
+            let results = reply.and_then(|results| remote::decode(&results, stages));
+            shares.push(results.map_err(|e| format!("agent {}: {}", agent, e))?);
+            info!("Agent {} finished", agent);
+        }
+
+        Ok(remote::merge(shares).unwrap())
+    }
+}
+
+// sends `request` to `agent` and waits for the results of its run
+fn ask(agent: &str, request: &str) -> Result<Value, String> {
+    let addr = resolve(agent)?;
+    let stream = TcpStream::connect(addr).map_err(|e| format!("could not connect: {}", e))?;
+    (&stream)
+        .write_all(format!("{}\n", request).as_bytes())
+        .map_err(|e| format!("could not send the run: {}", e))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut reply)
+        .map_err(|e| format!("could not read the results: {}", e))?;
+    if reply.is_empty() {
+        return Err("closed the connection before the run finished".to_string());
+    }
+
+    let reply = json::parse(&reply)?;
+    if let Some(e) = reply.get("error").and_then(Value::as_str) {
+        return Err(e.to_string());
+    }
+    reply
+        .get("results")
+        .cloned()
+        .ok_or_else(|| "replied without results".to_string())
+}
+
+// the address of an agent given as host or host:port
+fn resolve(agent: &str) -> Result<SocketAddr, String> {
+    agent
+        .to_socket_addrs()
+        .or_else(|_| (agent, DEFAULT_PORT).to_socket_addrs())
+        .map_err(|e| format!("invalid address: {}", e))?
+        .next()
+        .ok_or_else(|| "no address found".to_string())
+}
+
+// `args` without the options that are left to the controller
+fn forwarded(args: &[String]) -> Vec<String> {
+    let mut forwarded = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match LOCAL_OPTIONS.iter().find(|(option, _)| option == arg) {
+            Some((_, true)) => {
+                args.next();
+            }
+            Some((_, false)) => {}
+            None => forwarded.push(arg.clone()),
+        }
+    }
+    forwarded
+}
@@ -0,0 +1,392 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use argh::FromArgs;
+use tracing::{error, Level};
+
+use nsbench::json::{self, Value};
+
+use crate::logging;
+
+// how far apart two figures must be, in standard errors, to be called
+// significant: about 95% confidence
+const SIGNIFICANT: f64 = 1.96;
+
+#[derive(FromArgs, Debug)]
+#[argh(
+    subcommand,
+    name = "diff",
+    description = "compare two runs saved with --format json"
+)]
+pub struct DiffArguments {
+    #[argh(positional, description = "the earlier run, e.g. before a change")]
+    before: PathBuf,
+
+    #[argh(positional, description = "the later run")]
+    after: PathBuf,
+}
+
+/// Run is the part of a saved summary that gets compared.
+pub struct Run {
+    successes: f64,
+    failures: f64,
+    pub requests_per_second: f64,
+    mean_latency: f64,
+    latency_stddev: f64,
+    /// (label, nanoseconds) for each latency figure after the mean.
+    pub latencies: Vec<(String, f64)>,
+    failure_causes: Vec<(String, f64)>,
+    // the request rate of each interval, if the run kept them
+    rates: Vec<f64>,
+}
+
+impl Run {
+    /// Reads a summary written by `--format json`.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    /// Reads a summary from the text of one.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document = json::parse(text)?;
+
+        let totals = document
+            .get("totals")
+            .ok_or("not a summary from --format json: no totals")?;
+        let number = |value: &Value, key: &str| {
+            value
+                .get(key)
+                .and_then(Value::as_f64)
+                .ok_or_else(|| format!("no {} in the totals", key))
+        };
+
+        let latency = totals.get("latency").ok_or("no latency in the totals")?;
+        let latencies = latency
+            .as_object()
+            .unwrap_or_default()
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "mean_ns" | "stddev_ns"))
+            .filter_map(|(key, value)| {
+                Some((key.strip_suffix("_ns")?.to_string(), value.as_f64()?))
+            })
+            .collect();
+        let failure_causes = totals
+            .get("failure_causes")
+            .and_then(Value::as_object)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.as_f64()?)))
+            .collect();
+        let rates = document
+            .get("intervals")
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|interval| interval.get("requests_per_second")?.as_f64())
+            .collect();
+
+        Ok(Self {
+            successes: number(totals, "successes")?,
+            failures: number(totals, "failures")?,
+            requests_per_second: number(totals, "requests_per_second")?,
+            mean_latency: number(latency, "mean_ns")?,
+            latency_stddev: number(latency, "stddev_ns")?,
+            latencies,
+            failure_causes,
+            rates,
+        })
+    }
+
+    fn queries(&self) -> f64 {
+        self.successes + self.failures
+    }
+}
+
+/// Runs `nsbench diff`, and exits.
+pub fn main(args: DiffArguments) -> ! {
+    logging::init(Level::INFO);
+
+    let load = |path: &Path| {
+        Run::load(path).unwrap_or_else(|e| {
+            error!("could not read {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    };
+    let before = load(&args.before);
+    let after = load(&args.after);
+
+    println!("Before: {}", args.before.display());
+    println!("After: {}", args.after.display());
+    println!();
+    println!(
+        "{:<20} {:>14} {:>14} {:>10}  Significance",
+        "Metric", "Before", "After", "Change"
+    );
+
+    row(
+        "Requests/s",
+        format!("{}", before.requests_per_second),
+        format!("{}", after.requests_per_second),
+        change(before.requests_per_second, after.requests_per_second),
+        welch(&before.rates, &after.rates),
+    );
+
+    let rate = |run: &Run| run.successes / run.queries() * 100.0;
+    row(
+        "Success rate",
+        format!("{:.2}%", rate(&before)),
+        format!("{:.2}%", rate(&after)),
+        format!("{:+.2} pts", rate(&after) - rate(&before)),
+        proportions(
+            before.successes,
+            before.queries(),
+            after.successes,
+            after.queries(),
+        ),
+    );
+
+    row(
+        "Latency mean",
+        latency(before.mean_latency),
+        latency(after.mean_latency),
+        change(before.mean_latency, after.mean_latency),
+        significance(
+            after.mean_latency - before.mean_latency,
+            (before.latency_stddev.powi(2) / before.successes
+                + after.latency_stddev.powi(2) / after.successes)
+                .sqrt(),
+        ),
+    );
+    for (label, value) in &before.latencies {
+        if let Some((_, other)) = after.latencies.iter().find(|(other, _)| other == label) {
+            row(
+                &format!("Latency {}", label),
+                latency(*value),
+                latency(*other),
+                change(*value, *other),
+                "-".to_string(),
+            );
+        }
+    }
+
+    for (cause, count) in &before.failure_causes {
+        let other = after
+            .failure_causes
+            .iter()
+            .find(|(other, _)| other == cause)
+            .map_or(0.0, |(_, count)| *count);
+        if *count == 0.0 && other == 0.0 {
+            continue;
+        }
+
+        row(
+            &format!("Failures: {}", cause),
+            format!("{:.2}%", count / before.queries() * 100.0),
+            format!("{:.2}%", other / after.queries() * 100.0),
+            format!(
+                "{:+.2} pts",
+                (other / after.queries() - count / before.queries()) * 100.0
+            ),
+            proportions(*count, before.queries(), other, after.queries()),
+        );
+    }
+
+    std::process::exit(0);
+}
+
+fn row(metric: &str, before: String, after: String, change: String, significance: String) {
+    println!(
+        "{:<20} {:>14} {:>14} {:>10}  {}",
+        metric, before, after, change, significance
+    );
+}
+
+fn latency(nanos: f64) -> String {
+    format!("{:.2?}", Duration::from_nanos(nanos as u64))
+}
+
+// the relative change from `before` to `after`
+fn change(before: f64, after: f64) -> String {
+    if before == after {
+        return "0.00%".to_string();
+    }
+    if before == 0.0 {
+        return "n/a".to_string();
+    }
+
+    format!("{:+.2}%", (after - before) / before * 100.0)
+}
+
+// whether a difference of `delta` stands out from noise with a standard error
+// of `error`
+fn significance(delta: f64, error: f64) -> String {
+    if !error.is_finite() || error <= 0.0 {
+        return "-".to_string();
+    }
+
+    let z = delta / error;
+    if z.abs() >= SIGNIFICANT {
+        format!("significant (z = {:.1})", z)
+    } else {
+        format!("within noise (z = {:.1})", z)
+    }
+}
+
+// compares the means of two samples with Welch's t-test, which for runs of
+// more than a few seconds is close enough to a z-test
+fn welch(before: &[f64], after: &[f64]) -> String {
+    if before.len() < 2 || after.len() < 2 {
+        return "-".to_string();
+    }
+
+    let stats = |sample: &[f64]| {
+        let n = sample.len() as f64;
+        let mean = sample.iter().sum::<f64>() / n;
+        let variance = sample.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        (mean, variance / n)
+    };
+    let (before_mean, before_error) = stats(before);
+    let (after_mean, after_error) = stats(after);
+
+    significance(
+        after_mean - before_mean,
+        (before_error + after_error).sqrt(),
+    )
+}
+
+// compares two proportions, `a` out of `n` and `b` out of `m`, with a
+// two-proportion z-test
+fn proportions(a: f64, n: f64, b: f64, m: f64) -> String {
+    let pooled = (a + b) / (n + m);
+    let error = (pooled * (1.0 - pooled) * (1.0 / n + 1.0 / m)).sqrt();
+
+    significance(b / m - a / n, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUMMARY: &str = r#"{
+        "totals": {
+            "successes": 99,
+            "failures": 1,
+            "requests_per_second": 1000,
+            "latency": {"mean_ns": 1000, "stddev_ns": 10, "p50_ns": 900, "p99_ns": 2000},
+            "failure_causes": {"timeout": 1, "servfail": 0}
+        },
+        "intervals": [{"requests_per_second": 990}, {"requests_per_second": 1010}]
+    }"#;
+
+    #[test]
+    fn reads_summaries() {
+        let run = Run::parse(SUMMARY).unwrap();
+        assert_eq!(
+            (run.successes, run.failures, run.queries()),
+            (99.0, 1.0, 100.0)
+        );
+        assert_eq!(run.requests_per_second, 1000.0);
+        assert_eq!((run.mean_latency, run.latency_stddev), (1000.0, 10.0));
+        assert_eq!(
+            run.latencies,
+            [("p50".to_string(), 900.0), ("p99".to_string(), 2000.0)]
+        );
+        assert_eq!(
+            run.failure_causes,
+            [("timeout".to_string(), 1.0), ("servfail".to_string(), 0.0)]
+        );
+        assert_eq!(run.rates, [990.0, 1010.0]);
+
+        // a run saved without --interval has no rates to compare
+        let run = Run::parse(&SUMMARY.replace("intervals", "other")).unwrap();
+        assert!(run.rates.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        let cases = [
+            ("{}", "not a summary from --format json: no totals"),
+            (r#"{"totals": {}}"#, "no latency in the totals"),
+            (
+                r#"{"totals": {"latency": {"stddev_ns": 1}}}"#,
+                "no successes in the totals",
+            ),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(Run::parse(s).err().unwrap(), err, "{}", s);
+        }
+
+        let err = Run::parse(&SUMMARY.replace("\"mean_ns\"", "\"median_ns\""));
+        assert_eq!(err.err().unwrap(), "no mean_ns in the totals");
+        assert!(Run::parse("{\"totals\": ").is_err());
+    }
+
+    #[test]
+    fn formats_changes() {
+        let cases = [
+            (100.0, 110.0, "+10.00%"),
+            (100.0, 90.0, "-10.00%"),
+            (5.0, 5.0, "0.00%"),
+            (0.0, 0.0, "0.00%"),
+            (0.0, 5.0, "n/a"),
+        ];
+
+        for (before, after, expected) in cases {
+            assert_eq!(change(before, after), expected, "{} {}", before, after);
+        }
+    }
+
+    #[test]
+    fn calls_significance() {
+        let cases = [
+            (2.0, 1.0, "significant (z = 2.0)"),
+            (-1.96, 1.0, "significant (z = -2.0)"),
+            (1.0, 1.0, "within noise (z = 1.0)"),
+            (1.0, 0.0, "-"),
+            (1.0, f64::NAN, "-"),
+        ];
+
+        for (delta, error, expected) in cases {
+            assert_eq!(significance(delta, error), expected, "{} {}", delta, error);
+        }
+    }
+
+    #[test]
+    fn compares_rates() {
+        let cases: [(&[f64], &[f64], &str); 4] = [
+            (
+                &[9.0, 10.0, 11.0],
+                &[19.0, 20.0, 21.0],
+                "significant (z = 12.2)",
+            ),
+            (
+                &[9.0, 10.0, 11.0],
+                &[9.0, 10.0, 11.5],
+                "within noise (z = 0.2)",
+            ),
+            (&[10.0, 10.0], &[10.0, 10.0], "-"),
+            (&[10.0], &[10.0, 20.0], "-"),
+        ];
+
+        for (before, after, expected) in cases {
+            assert_eq!(welch(before, after), expected, "{:?} {:?}", before, after);
+        }
+    }
+
+    #[test]
+    fn compares_proportions() {
+        let cases = [
+            ((900.0, 1000.0), (950.0, 1000.0), "significant (z = 4.2)"),
+            ((99.0, 100.0), (99.0, 100.0), "within noise (z = 0.0)"),
+            ((100.0, 100.0), (100.0, 100.0), "-"),
+        ];
+
+        for ((a, n), (b, m), expected) in cases {
+            assert_eq!(proportions(a, n, b, m), expected, "{}/{} {}/{}", a, n, b, m);
+        }
+    }
+}
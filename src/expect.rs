@@ -0,0 +1,410 @@
+//! The answers a run expects back, so that a nameserver that answers quickly
+//! but wrongly doesn't pass for a healthy one.
+
+use std::{collections::HashMap, net::IpAddr, path::Path, str::FromStr};
+
+use trust_dns_resolver::proto::{op::Message, rr::RecordType};
+
+// the header flags that can be expected, by name, with how to read them
+#[allow(clippy::type_complexity)]
+const FLAGS: &[(&str, fn(&Message) -> bool)] = &[
+    ("aa", Message::authoritative),
+    ("tc", Message::truncated),
+    ("rd", Message::recursion_desired),
+    ("ra", Message::recursion_available),
+    ("ad", Message::authentic_data),
+    ("cd", Message::checking_disabled),
+];
+
+/// Flags are the header flags a response must have set, and those it must
+/// have clear, e.g. AA from an authoritative server and RA from a recursive
+/// one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Flags {
+    set: Vec<&'static str>,
+    clear: Vec<&'static str>,
+}
+
+impl Flags {
+    /// Returns the flags `message` has that it shouldn't, and those it
+    /// lacks that it should, e.g. `["aa", "!ra"]`, or none if it has the
+    /// flags expected.
+    pub fn violations(&self, message: &Message) -> Vec<String> {
+        let mut violations = Vec::new();
+        for (name, read) in FLAGS {
+            if self.set.contains(name) && !read(message) {
+                violations.push(name.to_string());
+            }
+            if self.clear.contains(name) && read(message) {
+                violations.push(format!("!{}", name));
+            }
+        }
+        violations
+    }
+}
+
+impl FromStr for Flags {
+    type Err = String;
+
+    /// Parses a comma-separated list of flags to expect set, each prefixed
+    /// with `!` to expect it clear instead, e.g. `aa,!ra`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = Self::default();
+        for flag in s.split(',') {
+            let flag = flag.trim().to_ascii_lowercase();
+            let (want, name) = match flag.strip_prefix('!') {
+                Some(name) => (&mut flags.clear, name),
+                None => (&mut flags.set, flag.as_str()),
+            };
+            match FLAGS.iter().find(|(known, _)| *known == name) {
+                Some((known, _)) => want.push(known),
+                None => {
+                    return Err(format!(
+                        "unknown flag '{}': expected aa, tc, rd, ra, ad or cd, prefixed with ! to expect it clear",
+                        flag
+                    ))
+                }
+            }
+        }
+
+        if let Some(flag) = flags.set.iter().find(|flag| flags.clear.contains(flag)) {
+            return Err(format!("flag '{}' expected both set and clear", flag));
+        }
+        Ok(flags)
+    }
+}
+
+impl std::fmt::Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags: Vec<String> = self
+            .set
+            .iter()
+            .map(|flag| flag.to_string())
+            .chain(self.clear.iter().map(|flag| format!("!{}", flag)))
+            .collect();
+        f.write_str(&flags.join(","))
+    }
+}
+
+/// Returns the names of the header flags `message` has set, e.g. `rd ra`.
+pub(crate) fn flags_set(message: &Message) -> String {
+    FLAGS
+        .iter()
+        .filter(|(_, read)| read(message))
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Expectations are the answers that count as right: for the names listed in
+/// an expectations file their own, and for every other name those given
+/// for all of them, if any; and how many of them there must be at least.
+/// Apart from those, they hold the header flags responses must have.
+#[derive(Debug, Clone, Default)]
+pub struct Expectations {
+    everywhere: Vec<String>,
+    by_name: HashMap<String, Vec<String>>,
+    min_answers: usize,
+    flags: Flags,
+}
+
+impl Expectations {
+    /// Returns expectations that every name answers with one of `values`.
+    pub fn new(values: &[String]) -> Self {
+        Self {
+            everywhere: values.iter().map(|value| normalize(value)).collect(),
+            by_name: HashMap::new(),
+            min_answers: 0,
+            flags: Flags::default(),
+        }
+    }
+
+    /// Makes responses with fewer than `count` records of the type asked for
+    /// fall short, as a load balancer with no healthy backends may answer
+    /// NOERROR with fewer records than it should.
+    pub fn with_min_answers(mut self, count: usize) -> Self {
+        self.min_answers = count;
+        self
+    }
+
+    /// Makes responses without the header `flags` expected fall short.
+    pub fn with_flags(mut self, flags: Flags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Reports whether `message` has the header flags expected.
+    pub fn flags_match(&self, message: &Message) -> bool {
+        self.flags.violations(message).is_empty()
+    }
+
+    /// Reads the answers expected for particular names: one name per line,
+    /// followed by the answers it may be given, separated by whitespace.
+    /// Blank lines and `#` comments are skipped.
+    ///
+    /// ```text
+    /// example.com 93.184.216.34 2606:2800:220:1:248:1893:25c8:1946
+    /// www.example.com example.com.
+    /// ```
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        self.parse(&contents)
+            .map_err(|(lineno, msg)| format!("{}:{}: {}", path.display(), lineno, msg))
+    }
+
+    // adds the answers expected in `contents`, failing with the line and what
+    // is wrong with it
+    fn parse(&mut self, contents: &str) -> Result<(), (usize, String)> {
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let name = normalize(words.next().unwrap());
+            let values: Vec<String> = words.map(normalize).collect();
+            if values.is_empty() {
+                return Err((
+                    lineno + 1,
+                    "expected a name and the answers it may be given".into(),
+                ));
+            }
+            self.by_name.entry(name).or_default().extend(values);
+        }
+
+        Ok(())
+    }
+
+    /// Reports whether a response to a `record_type` query has the answers
+    /// expected: at least the minimum number of records of that type, and
+    /// if any answers are expected for the name asked about, at least one
+    /// record, each of them one of those answers.
+    pub fn matches(&self, message: &Message, record_type: RecordType) -> bool {
+        let name = match message.queries().first() {
+            Some(query) => normalize(&query.name().to_string()),
+            None => return false,
+        };
+
+        let answers: Vec<_> = message
+            .answers()
+            .iter()
+            .filter(|record| record.record_type() == record_type)
+            .collect();
+        if answers.len() < self.min_answers {
+            return false;
+        }
+
+        let expected = match self.by_name.get(&name) {
+            Some(expected) => expected,
+            None if self.everywhere.is_empty() => return true,
+            None => &self.everywhere,
+        };
+        !answers.is_empty()
+            && answers.iter().all(|record| {
+                record
+                    .data()
+                    .is_some_and(|data| expected.contains(&normalize(&data.to_string())))
+            })
+    }
+}
+
+// puts an answer or name in the form they are compared in: addresses as
+// printed by the standard library, and anything else lowercase without a
+// trailing dot
+fn normalize(s: &str) -> String {
+    match s.parse::<IpAddr>() {
+        Ok(addr) => addr.to_string(),
+        Err(_) => s.trim_end_matches('.').to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_resolver::proto::{
+        op::Query,
+        rr::{Name, RData, Record},
+    };
+
+    // a response to a `record_type` query for `name` answering with `answers`
+    fn response(name: &str, record_type: RecordType, answers: &[&str]) -> Message {
+        let name = Name::from_ascii(name).unwrap();
+        let mut message = Message::new();
+        message.add_query(Query::query(name.clone(), record_type));
+        for answer in answers {
+            let data = match answer.parse::<IpAddr>() {
+                Ok(IpAddr::V4(addr)) => RData::A(addr),
+                Ok(IpAddr::V6(addr)) => RData::AAAA(addr),
+                Err(_) => RData::CNAME(Name::from_ascii(answer).unwrap()),
+            };
+            message.add_answer(Record::from_rdata(name.clone(), 300, data));
+        }
+        message
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_expected_answers() {
+        let expectations = Expectations::new(&strings(&[
+            "192.0.2.1",
+            "192.0.2.2",
+            "2001:DB8:0:0::1",
+            "Target.Example.COM.",
+        ]));
+
+        let cases = [
+            (&["192.0.2.1"][..], RecordType::A, true),
+            (&["192.0.2.1", "192.0.2.2"], RecordType::A, true),
+            (&["192.0.2.1", "192.0.2.3"], RecordType::A, false),
+            (&["192.0.2.3"], RecordType::A, false),
+            (&[], RecordType::A, false),
+            // the same addresses and names, written differently
+            (&["2001:db8::1"], RecordType::AAAA, true),
+            (&["target.example.com."], RecordType::CNAME, true),
+            // only records of the type asked for are checked
+            (&["target.example.com.", "192.0.2.1"], RecordType::A, true),
+            (
+                &["192.0.2.3", "target.example.com."],
+                RecordType::CNAME,
+                true,
+            ),
+        ];
+
+        for (answers, record_type, matches) in cases {
+            let message = response("www.example.com.", record_type, answers);
+            assert_eq!(
+                expectations.matches(&message, record_type),
+                matches,
+                "{:?} {}",
+                answers,
+                record_type
+            );
+        }
+
+        // a response without the question can't be for the name asked about
+        let mut message = Message::new();
+        message.add_answers(
+            response("www.example.com.", RecordType::A, &["192.0.2.1"]).take_answers(),
+        );
+        assert!(!expectations.matches(&message, RecordType::A));
+    }
+
+    #[test]
+    fn matches_answers_by_name() {
+        let mut expectations = Expectations::default();
+        expectations
+            .parse(
+                "# per name\n\nWWW.example.com. 192.0.2.1 # the web\nwww.example.com 192.0.2.2\n",
+            )
+            .unwrap();
+
+        let cases = [
+            ("www.example.com.", "192.0.2.1", true),
+            ("www.example.com.", "192.0.2.2", true),
+            ("www.example.com.", "192.0.2.3", false),
+            // names without expectations of their own take any answer
+            ("mail.example.com.", "192.0.2.3", true),
+        ];
+
+        for (name, answer, matches) in cases {
+            let message = response(name, RecordType::A, &[answer]);
+            assert_eq!(
+                expectations.matches(&message, RecordType::A),
+                matches,
+                "{} {}",
+                name,
+                answer
+            );
+        }
+
+        // and with answers for every name, those
+        let mut expectations = Expectations::new(&strings(&["192.0.2.9"]));
+        expectations.parse("www.example.com 192.0.2.1\n").unwrap();
+        let message = response("www.example.com.", RecordType::A, &["192.0.2.9"]);
+        assert!(!expectations.matches(&message, RecordType::A));
+        let message = response("mail.example.com.", RecordType::A, &["192.0.2.9"]);
+        assert!(expectations.matches(&message, RecordType::A));
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let cases = [
+            ("www.example.com\n", 1),
+            ("# comment\n\nwww.example.com # 192.0.2.1\n", 3),
+        ];
+
+        for (contents, lineno) in cases {
+            assert_eq!(
+                Expectations::default().parse(contents).unwrap_err(),
+                (
+                    lineno,
+                    "expected a name and the answers it may be given".to_string()
+                ),
+                "{:?}",
+                contents
+            );
+        }
+    }
+
+    #[test]
+    fn parses_flags() {
+        let cases = [
+            ("aa", "aa"),
+            ("aa,!ra", "aa,!ra"),
+            (" AA , !RA ", "aa,!ra"),
+            ("!tc,rd,ad,!cd", "rd,ad,!tc,!cd"),
+        ];
+
+        for (s, expected) in cases {
+            assert_eq!(s.parse::<Flags>().unwrap().to_string(), expected, "{}", s);
+        }
+
+        let cases = [
+            ("xx", "unknown flag 'xx': expected aa, tc, rd, ra, ad or cd, prefixed with ! to expect it clear"),
+            ("aa,", "unknown flag '': expected aa, tc, rd, ra, ad or cd, prefixed with ! to expect it clear"),
+            ("!!aa", "unknown flag '!!aa': expected aa, tc, rd, ra, ad or cd, prefixed with ! to expect it clear"),
+            ("aa,!AA", "flag 'aa' expected both set and clear"),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(s.parse::<Flags>(), Err(err.to_string()), "{}", s);
+        }
+    }
+
+    #[test]
+    fn finds_flag_violations() {
+        let mut message = Message::new();
+        message.set_authoritative(true);
+        message.set_recursion_desired(true);
+        assert_eq!(flags_set(&message), "aa rd");
+
+        let cases = [
+            ("aa", vec![]),
+            ("aa,!ra", vec![]),
+            ("ra", vec!["ra"]),
+            ("!aa", vec!["!aa"]),
+            ("ra,ad,!aa,!tc", vec!["!aa", "ra", "ad"]),
+        ];
+
+        for (s, violations) in cases {
+            let flags: Flags = s.parse().unwrap();
+            assert_eq!(flags.violations(&message), violations, "{}", s);
+
+            let expectations = Expectations::default().with_flags(flags);
+            assert_eq!(
+                expectations.flags_match(&message),
+                violations.is_empty(),
+                "{}",
+                s
+            );
+        }
+
+        assert!(Expectations::default().flags_match(&message));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info};
+use trust_dns_resolver::proto::{
+    op::ResponseCode,
+    rr::{Name, RecordType},
+};
+
+use crate::{expect, rcode_name, wire};
+
+/// FailureLog logs a handful of failed queries every second, so that a run
+/// full of failures says what went wrong without drowning in them. With
+/// debugging on, every failure is logged.
+#[derive(Debug)]
+pub struct FailureLog {
+    per_second: u32,
+    started: Instant,
+    // the second of the run in the upper 32 bits, and how many have been
+    // logged in it in the lower 32, so that workers failing at once don't
+    // contend on a lock
+    logged: AtomicU64,
+}
+
+impl FailureLog {
+    pub fn new(per_second: u32) -> Self {
+        Self {
+            per_second,
+            started: Instant::now(),
+            logged: AtomicU64::new(0),
+        }
+    }
+
+    /// Logs a `record_type` query for `name` to `nameserver` that failed
+    /// after `elapsed` because of `cause`, which is only worked out if the
+    /// failure is logged.
+    pub fn failed(
+        &self,
+        nameserver: SocketAddr,
+        name: &Name,
+        record_type: RecordType,
+        elapsed: Duration,
+        cause: impl FnOnce() -> String,
+    ) {
+        if self.sample() {
+            info!(
+                "failed query: {} {} to {}: {} after {:.2?}",
+                name,
+                record_type,
+                nameserver,
+                cause(),
+                elapsed
+            );
+        } else if tracing::enabled!(tracing::Level::DEBUG) {
+            debug!(
+                "failed query: {} {} to {}: {} after {:.2?}",
+                name,
+                record_type,
+                nameserver,
+                cause(),
+                elapsed
+            );
+        }
+    }
+
+    // whether this second still has room for another failure
+    fn sample(&self) -> bool {
+        if self.per_second == 0 {
+            return false;
+        }
+
+        let second = self.started.elapsed().as_secs() & u64::from(u32::MAX);
+        let mut logged = self.logged.load(Ordering::Relaxed);
+        loop {
+            // a full second is turned down without writing, so it stays cheap
+            let count = if logged >> 32 != second {
+                0
+            } else if (logged as u32) < self.per_second {
+                logged as u32
+            } else {
+                return false;
+            };
+
+            match self.logged.compare_exchange_weak(
+                logged,
+                second << 32 | u64::from(count + 1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => logged = current,
+            }
+        }
+    }
+}
+
+/// Describes why the outcome of a query counted as a failure.
+pub fn cause(res: &Result<wire::Exchange, wire::QueryError>) -> String {
+    match res {
+        Ok(exchange) => match exchange.message.response_code() {
+            ResponseCode::NoError if exchange.message.answer_count() == 0 => {
+                "NOERROR with no answers".to_string()
+            }
+            ResponseCode::NoError => format!(
+                "NOERROR answering {} with flags {}",
+                exchange
+                    .message
+                    .answers()
+                    .iter()
+                    .filter_map(|record| record.data())
+                    .map(|data| data.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                expect::flags_set(&exchange.message)
+            ),
+            rcode => rcode_name(rcode.into()),
+        },
+        Err(e) => e.to_string(),
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::{
+    interrupt, per_second,
+    stages::{Length, Stage},
+    RunDetails,
+};
+
+// stop searching once the bounds are within this fraction of each other
+const TOLERANCE: f64 = 0.02;
+
+// a probe that achieves less than this fraction of its target rate has
+// saturated the server (or the client) even if latency looks fine
+const ACHIEVED_RATIO: f64 = 0.95;
+
+const MAX_PROBES: usize = 12;
+
+/// Searches for the highest query rate at which p99 latency stays within
+/// `slo`. `run` runs one probe stage and returns its results and how long it
+/// took.
+///
+/// The first probe runs as fast as possible to find a ceiling; if that already
+/// meets the SLO it is the answer. Otherwise the rate is bisected between zero
+/// and the ceiling until the bounds are within a few percent. Returns None if
+/// no probed rate met the SLO. An interrupted search returns the best rate
+/// found before it was.
+pub fn search<F>(slo: Duration, probe: Duration, mut run: F) -> Option<u64>
+where
+    F: FnMut(Stage) -> (RunDetails, Duration),
+{
+    let stage = |qps| Stage {
+        length: Length::Time(probe),
+        qps,
+        profile: None,
+        qtypes: None,
+    };
+
+    let (details, elapsed) = run(stage(None));
+    // a probe cut short by Ctrl-C says nothing about its rate
+    if interrupt::interrupted() {
+        return None;
+    }
+    let ceiling = per_second(details.successes, elapsed);
+    let passed = meets(&details, slo);
+    report(None, ceiling, &details, passed);
+
+    if passed {
+        return Some(ceiling);
+    }
+
+    let mut low = 0;
+    let mut high = ceiling;
+    let mut best = None;
+
+    for _ in 1..MAX_PROBES {
+        if (high - low) as f64 <= high as f64 * TOLERANCE {
+            break;
+        }
+
+        let target = low + (high - low) / 2;
+        if target == 0 {
+            break;
+        }
+
+        let (details, elapsed) = run(stage(Some(target)));
+        if interrupt::interrupted() {
+            break;
+        }
+        let achieved = per_second(details.successes, elapsed);
+        let passed = meets(&details, slo) && achieved as f64 >= target as f64 * ACHIEVED_RATIO;
+        report(Some(target), achieved, &details, passed);
+
+        if passed {
+            low = target;
+            best = Some(target);
+        } else {
+            high = target;
+        }
+    }
+
+    best
+}
+
+fn meets(details: &RunDetails, slo: Duration) -> bool {
+    details.successes > 0 && details.latencies.percentile(99.0) as u128 <= slo.as_nanos()
+}
+
+fn report(target: Option<u64>, achieved: u64, details: &RunDetails, passed: bool) {
+    let target = match target {
+        Some(qps) => format!("{}/s", qps),
+        None => "unlimited".to_string(),
+    };
+
+    info!(
+        "Probe {}: {}/s achieved, p99 {:?} ({})",
+        target,
+        achieved,
+        Duration::from_nanos(details.latencies.percentile(99.0)),
+        if passed { "pass" } else { "fail" }
+    );
+}
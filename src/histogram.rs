@@ -0,0 +1,371 @@
+use crate::json::{self, Value};
+
+// each power of two is split into this many linear buckets, which keeps every
+// recorded value within 1% of its bucket's midpoint.
+const SUB_BUCKET_BITS: u32 = 7;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Histogram counts latencies (or any other u64) in log-linear buckets, in the
+/// style of HdrHistogram: small enough to merge every second, precise enough
+/// for tail percentiles.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let index = index(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+
+        self.counts[index] += 1;
+        self.min = if self.total == 0 {
+            value
+        } else {
+            self.min.min(value)
+        };
+        self.total += 1;
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        if other.total > 0 {
+            self.min = if self.total == 0 {
+                other.min
+            } else {
+                self.min.min(other.min)
+            };
+        }
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+        self.min = 0;
+        self.max = 0;
+    }
+
+    /// Returns the histogram as JSON, with only the buckets that hold values,
+    /// as `[index, count]` pairs, for from_json to read back exactly.
+    pub fn to_json(&self) -> Value {
+        json::object(vec![
+            (
+                "counts",
+                Value::Array(
+                    self.counts
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, count)| **count > 0)
+                        .map(|(index, count)| Value::Array(vec![index.into(), (*count).into()]))
+                        .collect(),
+                ),
+            ),
+            ("total", self.total.into()),
+            ("min", self.min.into()),
+            ("max", self.max.into()),
+        ])
+    }
+
+    /// Reads a histogram written by to_json.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let mut histogram = Self {
+            counts: Vec::new(),
+            total: value.get("total")?.as_u64()?,
+            min: value.get("min")?.as_u64()?,
+            max: value.get("max")?.as_u64()?,
+        };
+
+        for bucket in value.get("counts")?.as_array()? {
+            let (index, count) = match bucket.as_array()? {
+                [index, count] => (index.as_u64()? as usize, count.as_u64()?),
+                _ => return None,
+            };
+            // no bucket lies beyond the one for u64::MAX
+            if index > self::index(u64::MAX) {
+                return None;
+            }
+            if index >= histogram.counts.len() {
+                histogram.counts.resize(index + 1, 0);
+            }
+            histogram.counts[index] = count;
+        }
+
+        if histogram.counts.iter().sum::<u64>() != histogram.total {
+            return None;
+        }
+
+        Some(histogram)
+    }
+
+    /// Returns how many values have been recorded.
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns how many of the recorded values were no more than `value`, to
+    /// the precision of the buckets.
+    pub fn count_at_most(&self, value: u64) -> u64 {
+        self.counts
+            .iter()
+            .enumerate()
+            .take_while(|(index, _)| value_at(*index) <= value)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Returns each bucket that holds any values, as the value it stands for
+    /// and how many it holds, from the smallest up.
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(move |(index, count)| (value_at(index).clamp(self.min, self.max), *count))
+    }
+
+    /// Splits the range from the smallest value recorded to the largest into
+    /// `bins` spaced logarithmically, and returns the top of each bin and
+    /// how many values fell in it, from the smallest up; none if `bins` is
+    /// zero.
+    pub fn log_bins(&self, bins: usize) -> Vec<(u64, u64)> {
+        if bins == 0 {
+            return Vec::new();
+        }
+
+        let min = self.min.max(1) as f64;
+        let max = self.max.max(1) as f64;
+        let span = (max / min).ln().max(f64::EPSILON);
+
+        let mut counts = vec![0; bins];
+        for (value, count) in self.buckets() {
+            let position = ((value.max(1) as f64 / min).ln() / span * bins as f64) as usize;
+            counts[position.min(bins - 1)] += count;
+        }
+
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let top = min * (span * (i + 1) as f64 / bins as f64).exp();
+                (top.round() as u64, count)
+            })
+            .collect()
+    }
+
+    /// Returns the smallest value recorded, exactly rather than to the
+    /// precision of its bucket, or 0 if nothing has been recorded.
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    /// Returns the largest value recorded, exactly rather than to the
+    /// precision of its bucket.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns the value below which `percentile` percent of the recorded
+    /// values fall, or 0 if nothing has been recorded.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+
+        let rank = ((percentile / 100.0 * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return value_at(index).clamp(self.min, self.max);
+            }
+        }
+
+        unreachable!("histogram total does not match its buckets")
+    }
+}
+
+fn index(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+
+    let shift = (63 - value.leading_zeros()) - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) - SUB_BUCKETS;
+    ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+// the midpoint of the values that land in bucket `index`
+fn value_at(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+
+    let shift = index / SUB_BUCKETS - 1;
+    let low = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    low + (1 << shift) / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_bucket_edges() {
+        let cases = [
+            (0, 0, 0),
+            (1, 1, 1),
+            (127, 127, 127),
+            (128, 128, 128),
+            (255, 255, 255),
+            (256, 256, 257),
+            (257, 256, 257),
+            (258, 257, 259),
+            (511, 383, 511),
+            (512, 384, 514),
+            (u64::MAX, 7423, (255 << 56) + (1 << 55)),
+        ];
+
+        for (value, expected_index, expected_value) in cases {
+            assert_eq!(index(value), expected_index, "{}", value);
+            assert_eq!(value_at(index(value)), expected_value, "{}", value);
+        }
+    }
+
+    #[test]
+    fn buckets_stay_within_a_percent() {
+        let mut values = vec![];
+        for shift in 0..64 {
+            let power = 1u64 << shift;
+            values.extend([power - 1, power, power + 1, power + power / 3]);
+        }
+        values.push(u64::MAX);
+
+        for value in values {
+            let midpoint = value_at(index(value));
+            let error = (midpoint as f64 - value as f64).abs() / value.max(1) as f64;
+            assert!(error <= 0.01, "{} is in the bucket for {}", value, midpoint);
+            assert!(index(value) <= index(value.saturating_add(1)), "{}", value);
+        }
+    }
+
+    #[test]
+    fn ranks_percentiles() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+
+        let cases = [
+            (0.0, 1),
+            (1.0, 1),
+            (50.0, 50),
+            (50.5, 51),
+            (99.0, 99),
+            (99.9, 100),
+            (100.0, 100),
+        ];
+
+        for (percentile, expected) in cases {
+            assert_eq!(histogram.percentile(percentile), expected, "{}", percentile);
+        }
+        assert_eq!(histogram.count_at_most(10), 10);
+    }
+
+    #[test]
+    fn clamps_percentiles_to_what_was_recorded() {
+        // 256 lands in the bucket whose midpoint is 257
+        let mut histogram = Histogram::default();
+        histogram.record(256);
+        assert_eq!(histogram.percentile(0.0), 256);
+        assert_eq!(histogram.percentile(100.0), 256);
+
+        histogram.record(100_000);
+        assert_ne!(value_at(index(100_000)), 100_000);
+        assert_eq!(histogram.percentile(0.0), 257);
+        assert_eq!(histogram.percentile(100.0), 100_000);
+    }
+
+    #[test]
+    fn merges_min_and_max() {
+        let mut histogram = Histogram::default();
+        histogram.record(10);
+        histogram.record(20);
+
+        // an empty histogram's min of 0 is not a value
+        histogram.merge(&Histogram::default());
+        assert_eq!((histogram.min(), histogram.max()), (10, 20));
+
+        let mut merged = Histogram::default();
+        merged.merge(&histogram);
+        assert_eq!((merged.min(), merged.max(), merged.count()), (10, 20, 2));
+
+        let mut other = Histogram::default();
+        other.record(5);
+        other.record(1000);
+        merged.merge(&other);
+        assert_eq!((merged.min(), merged.max(), merged.count()), (5, 1000, 4));
+        assert_eq!(merged.percentile(50.0), 10);
+    }
+
+    #[test]
+    fn round_trips_json() {
+        let mut histogram = Histogram::default();
+        for value in [0, 1, 127, 128, 1_000, 1_000, 123_456_789, u64::MAX] {
+            histogram.record(value);
+        }
+
+        let value = json::parse(&histogram.to_json().to_string()).unwrap();
+        let read = Histogram::from_json(&value).unwrap();
+        assert_eq!(read.counts, histogram.counts);
+        assert_eq!(
+            (read.count(), read.min(), read.max()),
+            (histogram.count(), histogram.min(), histogram.max())
+        );
+
+        let cases = [
+            r#"{"counts":[[1,2]],"total":1,"min":1,"max":1}"#,
+            r#"{"counts":[[7424,1]],"total":1,"min":1,"max":1}"#,
+            r#"{"counts":[[1]],"total":1,"min":1,"max":1}"#,
+            r#"{"counts":[],"total":0,"min":0}"#,
+        ];
+
+        for s in cases {
+            assert!(
+                Histogram::from_json(&json::parse(s).unwrap()).is_none(),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn splits_into_log_bins() {
+        let mut histogram = Histogram::default();
+        for value in [1, 10, 100, 1000] {
+            histogram.record(value);
+        }
+
+        assert!(histogram.log_bins(0).is_empty());
+        let bins = histogram.log_bins(3);
+        assert_eq!(bins.iter().map(|(_, count)| count).sum::<u64>(), 4);
+        assert_eq!(bins.last().unwrap().0, 1000);
+    }
+}
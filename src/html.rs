@@ -0,0 +1,338 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    time::Duration,
+};
+
+use nsbench::{
+    failure_causes, mbit_per_second, per_second, rcode_name, workload::QtypeMix, Interval,
+    RunDetails, LATENCY_PERCENTILES,
+};
+
+use crate::{report::Results, RunArguments};
+
+// the size of each chart, in pixels
+const WIDTH: f64 = 760.0;
+const HEIGHT: f64 = 220.0;
+// room left around the plot for the axis labels
+const MARGIN: f64 = 50.0;
+
+// how many bars the latency distribution is drawn with
+const DISTRIBUTION_BARS: usize = 40;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+th,td{padding:.25em .75em;text-align:right;border-bottom:1px solid #ddd}\
+th:first-child,td:first-child{text-align:left}\
+svg{display:block;margin-bottom:1.5em}\
+.axis{stroke:#888}.line{fill:none;stroke:#1f77b4;stroke-width:1.5}\
+.bar{fill:#1f77b4}text{font-size:11px;fill:#555}";
+
+/// Writes the summary to `w` as a single HTML page, with the time series and
+/// the latency distribution drawn as inline SVG, so that it needs nothing
+/// else to be read.
+pub fn html<W: Write>(
+    mut w: W,
+    args: &RunArguments,
+    qtypes: &QtypeMix,
+    results: &Results,
+) -> io::Result<()> {
+    let overall = results.overall;
+    let mut page = String::new();
+
+    writeln!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>nsbench report</title><style>{}</style></head><body>\n<h1>nsbench report</h1>",
+        STYLE
+    )
+    .unwrap();
+
+    let nameservers = results
+        .nameservers
+        .iter()
+        .map(|nameserver| nameserver.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut config = vec![
+        ("Nameserver", nameservers),
+        ("Protocol", args.protocol.to_string()),
+        ("Query types", qtypes.to_string()),
+        ("Runtime", format!("{:.2?}", results.runtime)),
+    ];
+    if let Some(max_rate) = results.max_rate {
+        config.push(("Highest rate within SLO", format!("{}/s", max_rate)));
+    }
+    table(
+        &mut page,
+        "Run",
+        &["", ""],
+        config.into_iter().map(|(k, v)| vec![k.to_string(), v]),
+    );
+
+    let mut totals = vec![
+        ("Successes", overall.successes.to_string()),
+        (
+            "Failures",
+            format!("{}{}", overall.failures, failure_causes(overall)),
+        ),
+        ("Success rate", format!("{:.2}%", overall.success_rate())),
+        (
+            "Requests",
+            format!("{}/s", per_second(overall.successes, results.runtime)),
+        ),
+        ("Average latency", format!("{:.2?}", overall.mean_latency())),
+        ("Latency min", latency(overall.latencies.min())),
+    ];
+    for (label, percentile) in LATENCY_PERCENTILES {
+        totals.push((label, latency(overall.latencies.percentile(*percentile))));
+    }
+    totals.push(("Latency max", latency(overall.latencies.max())));
+    totals.push((
+        "Throughput",
+        format!(
+            "{:.2} Mbit/s out, {:.2} Mbit/s in",
+            mbit_per_second(overall.bytes_sent, results.runtime),
+            mbit_per_second(overall.bytes_received, results.runtime)
+        ),
+    ));
+    table(
+        &mut page,
+        "Totals",
+        &["", ""],
+        totals.into_iter().map(|(k, v)| vec![k.to_string(), v]),
+    );
+
+    if !results.timeseries.is_empty() {
+        page.push_str("<h2>Over time</h2>\n");
+        let points = |value: &dyn Fn(&Interval) -> f64| {
+            results
+                .timeseries
+                .iter()
+                .map(|interval| (interval.offset.as_secs_f64(), value(interval)))
+                .collect::<Vec<(f64, f64)>>()
+        };
+
+        line_chart(
+            &mut page,
+            "Requests/s",
+            &points(&|interval| per_second(interval.successes, interval.length) as f64),
+            |rate| format!("{:.0}", rate),
+        );
+        line_chart(
+            &mut page,
+            "Average latency",
+            &points(&|interval| interval.mean_latency.as_secs_f64()),
+            |secs| format!("{:.2?}", Duration::from_secs_f64(secs)),
+        );
+        line_chart(
+            &mut page,
+            "p99 latency",
+            &points(&|interval| interval.p99_latency.as_secs_f64()),
+            |secs| format!("{:.2?}", Duration::from_secs_f64(secs)),
+        );
+        line_chart(
+            &mut page,
+            "Failures/s",
+            &points(&|interval| per_second(interval.failures, interval.length) as f64),
+            |rate| format!("{:.0}", rate),
+        );
+    }
+
+    if overall.latencies.count() > 0 {
+        page.push_str("<h2>Latency distribution</h2>\n");
+        distribution(&mut page, overall);
+    }
+
+    if results.nameservers.len() > 1 {
+        let mut header = vec![
+            "Nameserver",
+            "Successes",
+            "Failures",
+            "Success rate",
+            "Avg latency",
+        ];
+        header.extend(LATENCY_PERCENTILES.iter().map(|(label, _)| *label));
+        table(
+            &mut page,
+            "Nameservers",
+            &header,
+            results
+                .nameservers
+                .iter()
+                .zip(results.per_target)
+                .map(|(nameserver, details)| {
+                    let mut row = vec![
+                        nameserver.to_string(),
+                        details.successes.to_string(),
+                        format!("{}{}", details.failures, failure_causes(details)),
+                        format!("{:.2}%", details.success_rate()),
+                        format!("{:.2?}", details.mean_latency()),
+                    ];
+                    row.extend(
+                        LATENCY_PERCENTILES.iter().map(|(_, percentile)| {
+                            latency(details.latencies.percentile(*percentile))
+                        }),
+                    );
+                    row
+                }),
+        );
+    }
+
+    let responses: u64 = overall.rcodes.values().sum();
+    table(
+        &mut page,
+        "Response codes",
+        &["RCODE", "Responses", "Share"],
+        overall.rcodes.iter().map(|(rcode, count)| {
+            vec![
+                rcode_name(*rcode),
+                count.to_string(),
+                format!("{:.2}%", *count as f64 / responses as f64 * 100.0),
+            ]
+        }),
+    );
+
+    page.push_str("</body></html>\n");
+    w.write_all(page.as_bytes())?;
+    w.flush()
+}
+
+fn latency(nanos: u64) -> String {
+    format!("{:.2?}", Duration::from_nanos(nanos))
+}
+
+fn table(page: &mut String, title: &str, header: &[&str], rows: impl Iterator<Item = Vec<String>>) {
+    write!(page, "<h2>{}</h2>\n<table>\n<tr>", escape(title)).unwrap();
+    for column in header {
+        write!(page, "<th>{}</th>", escape(column)).unwrap();
+    }
+    page.push_str("</tr>\n");
+
+    for row in rows {
+        page.push_str("<tr>");
+        for cell in row {
+            write!(page, "<td>{}</td>", escape(&cell)).unwrap();
+        }
+        page.push_str("</tr>\n");
+    }
+    page.push_str("</table>\n");
+}
+
+// draws `points`, (seconds into the run, value) pairs, as a line from zero up
+// to the largest value, which is labelled with `label`
+fn line_chart(
+    page: &mut String,
+    title: &str,
+    points: &[(f64, f64)],
+    label: impl Fn(f64) -> String,
+) {
+    let max_x = points.iter().map(|(x, _)| *x).fold(1.0, f64::max);
+    let max_y = points.iter().map(|(_, y)| *y).fold(0.0, f64::max);
+
+    let x = |x: f64| MARGIN + x / max_x * (WIDTH - 2.0 * MARGIN);
+    let y = |y: f64| {
+        if max_y <= 0.0 {
+            HEIGHT - MARGIN
+        } else {
+            HEIGHT - MARGIN - y / max_y * (HEIGHT - 2.0 * MARGIN)
+        }
+    };
+
+    svg_start(page, title);
+    let path = points
+        .iter()
+        .map(|(px, py)| format!("{:.1},{:.1}", x(*px), y(*py)))
+        .collect::<Vec<String>>()
+        .join(" ");
+    writeln!(
+        page,
+        "<polyline class=\"line\" points=\"{}\"/>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">0</text>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{:.0}s</text>",
+        path,
+        MARGIN - 4.0,
+        MARGIN + 4.0,
+        escape(&label(max_y)),
+        MARGIN - 4.0,
+        HEIGHT - MARGIN,
+        WIDTH - MARGIN,
+        HEIGHT - MARGIN + 14.0,
+        max_x,
+    )
+    .unwrap();
+    page.push_str("</svg>\n");
+}
+
+// draws how many successful queries took each latency, in bars spaced
+// logarithmically from the fastest to the slowest
+fn distribution(page: &mut String, details: &RunDetails) {
+    let latencies = &details.latencies;
+    let bars = latencies.log_bins(DISTRIBUTION_BARS);
+    let tallest = bars
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    svg_start(page, "Queries by latency");
+    let width = (WIDTH - 2.0 * MARGIN) / DISTRIBUTION_BARS as f64;
+    for (i, (_, count)) in bars.iter().enumerate() {
+        let height = *count as f64 / tallest as f64 * (HEIGHT - 2.0 * MARGIN);
+        writeln!(
+            page,
+            "<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\"><title>{}</title></rect>",
+            MARGIN + i as f64 * width,
+            HEIGHT - MARGIN - height,
+            (width - 1.0).max(1.0),
+            height,
+            count
+        )
+        .unwrap();
+    }
+    writeln!(
+        page,
+        "<text x=\"{}\" y=\"{}\">{}</text>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>",
+        MARGIN,
+        HEIGHT - MARGIN + 14.0,
+        latency(latencies.min()),
+        WIDTH - MARGIN,
+        HEIGHT - MARGIN + 14.0,
+        latency(latencies.max()),
+        MARGIN - 4.0,
+        MARGIN + 4.0,
+        tallest
+    )
+    .unwrap();
+    page.push_str("</svg>\n");
+}
+
+// opens a chart, with its title and axes
+fn svg_start(page: &mut String, title: &str) {
+    writeln!(
+        page,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n<text x=\"{m}\" y=\"{t}\">{title}</text>\n<line class=\"axis\" x1=\"{m}\" y1=\"{m}\" x2=\"{m}\" y2=\"{b}\"/>\n<line class=\"axis\" x1=\"{m}\" y1=\"{b}\" x2=\"{r}\" y2=\"{b}\"/>",
+        w = WIDTH,
+        h = HEIGHT,
+        m = MARGIN,
+        t = MARGIN - 16.0,
+        b = HEIGHT - MARGIN,
+        r = WIDTH - MARGIN,
+        title = escape(title)
+    )
+    .unwrap();
+}
+
+/// Escapes `s` for use in HTML or XML text and attribute values.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
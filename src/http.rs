@@ -0,0 +1,83 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+// how long a request may take before it's given up on
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Endpoint is an `http://` URL that results are POSTed to. There's no TLS, so
+/// anything further away than a local collector needs a proxy in front.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub host: String,
+    pub path: String,
+}
+
+impl Endpoint {
+    /// Parses `url`, which must start with `http://`. A URL without a path is
+    /// given `default_path`.
+    pub fn parse(url: &str, default_path: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("invalid URL '{}': only http:// URLs are supported", url))?;
+
+        let (host, path) = match rest.find('/') {
+            Some(slash) if slash + 1 < rest.len() => (&rest[..slash], &rest[slash..]),
+            Some(slash) => (&rest[..slash], default_path),
+            None => (rest, default_path),
+        };
+        if host.is_empty() {
+            return Err(format!("invalid URL '{}': no host", url));
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// POSTs `body` with the extra `headers`, and checks that it was accepted.
+    pub fn post(
+        &self,
+        content_type: &str,
+        headers: &[(String, String)],
+        body: &str,
+    ) -> io::Result<()> {
+        let addr = self
+            .host
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses for host"))?;
+
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            content_type,
+            body.len()
+        );
+        for (name, value) in headers {
+            write!(request, "{}: {}\r\n", name, value).unwrap();
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes())?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!(
+                "server answered '{}'",
+                status.trim()
+            ))),
+        }
+    }
+}
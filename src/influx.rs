@@ -0,0 +1,114 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Write},
+    net::SocketAddr,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use crate::{http::Endpoint, Interval};
+
+enum Sink {
+    File(File),
+    Http {
+        endpoint: Endpoint,
+        token: Option<String>,
+    },
+}
+
+/// Writer sends each second's results in InfluxDB line protocol, either to a
+/// server's HTTP write endpoint or to a file for loading later.
+pub struct Writer {
+    sink: Mutex<Sink>,
+    tags: String,
+}
+
+impl Writer {
+    /// Opens `target`, which is either an `http://` URL to POST to, such as
+    /// `http://localhost:8086/api/v2/write?org=ops&bucket=dns`, or the path of
+    /// a file to create. `token` is sent as the API token, if there is one.
+    pub fn new(
+        target: &str,
+        token: Option<String>,
+        protocol: &str,
+        run_id: &str,
+    ) -> io::Result<Self> {
+        let sink = if target.contains("://") {
+            Sink::Http {
+                endpoint: Endpoint::parse(target, "/write")
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+                token,
+            }
+        } else {
+            Sink::File(File::create(target)?)
+        };
+
+        Ok(Self {
+            sink: Mutex::new(sink),
+            tags: format!("protocol={},run_id={}", escape(protocol), escape(run_id)),
+        })
+    }
+
+    /// Writes an interval's results for each nameserver as a point in the
+    /// `nsbench` measurement, timestamped when the interval ended.
+    pub fn write(&self, nameservers: &[SocketAddr], intervals: &[Interval]) -> io::Result<()> {
+        let mut lines = String::new();
+        for (nameserver, interval) in nameservers.iter().zip(intervals) {
+            let causes = &interval.failure_causes;
+            writeln!(
+                lines,
+                "nsbench,nameserver={},{} successes={}i,failures={}i,timeout={}i,servfail={}i,refused={}i,nxdomain={}i,network={}i,wrong_answer={}i,wrong_flags={}i,other={}i,mean_latency_ns={}i,p99_latency_ns={}i,bytes_sent={}i,bytes_received={}i {}",
+                escape(&nameserver.to_string()),
+                self.tags,
+                interval.successes,
+                interval.failures,
+                causes.timeout,
+                causes.servfail,
+                causes.refused,
+                causes.nxdomain,
+                causes.network,
+                causes.wrong_answer,
+                causes.wrong_flags,
+                causes.other,
+                interval.mean_latency.as_nanos(),
+                interval.p99_latency.as_nanos(),
+                interval.bytes_sent,
+                interval.bytes_received,
+                interval
+                    .ended
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            )
+            .unwrap();
+        }
+
+        match &mut *self.sink.lock().unwrap() {
+            Sink::File(file) => {
+                file.write_all(lines.as_bytes())?;
+                file.flush()
+            }
+            Sink::Http { endpoint, token } => {
+                let headers: Vec<(String, String)> = token
+                    .iter()
+                    .map(|token| ("Authorization".to_string(), format!("Token {}", token)))
+                    .collect();
+                endpoint.post("text/plain; charset=utf-8", &headers, &lines)
+            }
+        }
+    }
+}
+
+// escapes a tag value, in which commas, spaces and equals signs are special
+fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
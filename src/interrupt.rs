@@ -0,0 +1,71 @@
+//! Signals that act on a run while it goes on: Ctrl-C to stop it early,
+//! keeping what it measured so far, and SIGUSR1 to see how it is going.
+
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static SUMMARY_WANTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes SIGINT end the run early: the stage in progress stops as if its time
+/// were up, no further stages or probes start, and run_benchmark returns the
+/// results of the part that ran. A second SIGINT exits straight away. Also
+/// makes SIGUSR1 log a summary of the run so far without stopping it.
+#[cfg(target_os = "linux")]
+pub fn install() -> io::Result<()> {
+    extern "C" fn on_sigint(_: libc::c_int) {
+        // only async-signal-safe calls here
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            // SAFETY: _exit is async-signal-safe
+            unsafe { libc::_exit(130) }
+        }
+    }
+
+    extern "C" fn on_sigusr1(_: libc::c_int) {
+        SUMMARY_WANTED.store(true, Ordering::SeqCst);
+    }
+
+    for (signal, handler) in [
+        (libc::SIGINT, on_sigint as *const ()),
+        (libc::SIGUSR1, on_sigusr1 as *const ()),
+    ] {
+        // SAFETY: the handlers do nothing that isn't safe in a signal handler
+        let previous = unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn install() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Ctrl-C and SIGUSR1 are only handled on Linux",
+    ))
+}
+
+/// Ends the run early, as SIGINT does.
+pub fn stop() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Asks for a summary of the run so far, as SIGUSR1 does.
+pub fn want_summary() {
+    SUMMARY_WANTED.store(true, Ordering::SeqCst);
+}
+
+/// Reports whether the run was interrupted.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Reports whether a summary of the run so far was asked for with SIGUSR1
+/// since the last call.
+pub fn summary_wanted() -> bool {
+    SUMMARY_WANTED.swap(false, Ordering::Relaxed)
+}
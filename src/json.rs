@@ -0,0 +1,572 @@
+use std::fmt::{self, Write};
+
+/// Value is a JSON document, built up in memory or parsed from text, and
+/// written out compactly with Display. Objects keep their keys in the order
+/// they were given.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(u64),
+    /// An integer below zero, so that Int covers all of u64.
+    NegInt(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+/// Builds an object from `(key, value)` pairs.
+pub fn object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Int(n as u64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Value::Int(n as u64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::Array(values)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::NegInt(n) => write!(f, "{}", n),
+            // JSON has no NaN or infinity, e.g. for a rate over no time at all
+            Value::Float(n) if !n.is_finite() => f.write_str("null"),
+            // Debug keeps a fraction or exponent on whole numbers, so that
+            // they parse back as floats
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::String(s) => write_string(f, s),
+            Value::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl Value {
+    /// Returns the field `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::NegInt(n) => Some(*n as f64),
+            Value::Float(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Value)]> {
+        match self {
+            Value::Object(fields) => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a JSON document, such as a summary saved with `--format json`.
+pub fn parse(s: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: s.char_indices().peekable(),
+    };
+
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some((at, _)) => Err(format!("unexpected data at offset {}", at)),
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while let Some((_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((at, c)) => Err(format!(
+                "expected '{}' at offset {}, found '{}'",
+                expected, at, c
+            )),
+            None => Err(format!("expected '{}', found the end", expected)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+
+        match self.chars.peek().copied() {
+            Some((_, '{')) => self.object(),
+            Some((_, '[')) => self.array(),
+            Some((_, '"')) => self.string().map(Value::String),
+            Some((_, 't')) => self.literal("true", Value::Bool(true)),
+            Some((_, 'f')) => self.literal("false", Value::Bool(false)),
+            Some((_, 'n')) => self.literal("null", Value::Null),
+            Some((_, c)) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some((at, c)) => Err(format!("unexpected '{}' at offset {}", c, at)),
+            None => Err("unexpected end of the document".to_string()),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(value)
+    }
+
+    // reads a number as RFC 8259 has it: an optional minus, an integer part
+    // without leading zeros, then an optional fraction and exponent, each
+    // with at least one digit. Integers that fit are kept exact.
+    fn number(&mut self) -> Result<Value, String> {
+        let mut number = String::new();
+        let invalid = |number: &str| format!("invalid number '{}'", number);
+
+        self.take(&mut number, |c| c == '-');
+        if !self.take(&mut number, |c| c == '0') && self.digits(&mut number) == 0 {
+            return Err(invalid(&number));
+        }
+
+        let mut integer = true;
+        if self.take(&mut number, |c| c == '.') {
+            integer = false;
+            if self.digits(&mut number) == 0 {
+                return Err(invalid(&number));
+            }
+        }
+        if self.take(&mut number, |c| matches!(c, 'e' | 'E')) {
+            integer = false;
+            self.take(&mut number, |c| matches!(c, '+' | '-'));
+            if self.digits(&mut number) == 0 {
+                return Err(invalid(&number));
+            }
+        }
+
+        if integer {
+            if let Ok(n) = number.parse::<u64>() {
+                return Ok(Value::Int(n));
+            }
+            if let Ok(n) = number.parse::<i64>() {
+                // -0 is plain zero
+                return Ok(if n == 0 {
+                    Value::Int(0)
+                } else {
+                    Value::NegInt(n)
+                });
+            }
+        }
+        number
+            .parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| invalid(&number))
+    }
+
+    // moves the next character onto `number` if `accept` allows it, reporting
+    // whether it did
+    fn take(&mut self, number: &mut String, accept: impl Fn(char) -> bool) -> bool {
+        match self.chars.next_if(|(_, c)| accept(*c)) {
+            Some((_, c)) => {
+                number.push(c);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // moves a run of digits onto `number`, returning how many there were
+    fn digits(&mut self, number: &mut String) -> usize {
+        let mut count = 0;
+        while self.take(number, |c| c.is_ascii_digit()) {
+            count += 1;
+        }
+        count
+    }
+
+    // reads the four hex digits of a \u escape
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut hex = String::new();
+        for _ in 0..4 {
+            if !self.take(&mut hex, |c| c.is_ascii_hexdigit()) {
+                return Err(format!("invalid escape '\\u{}'", hex));
+            }
+        }
+
+        Ok(u32::from_str_radix(&hex, 16).unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(s),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 'r')) => s.push('\r'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, 'b')) => s.push('\u{8}'),
+                    Some((_, 'f')) => s.push('\u{c}'),
+                    Some((_, 'u')) => {
+                        let mut code = self.hex4()?;
+                        // a character outside the BMP is escaped as a UTF-16
+                        // surrogate pair, high then low
+                        if (0xd800..0xdc00).contains(&code) {
+                            let low = match (self.chars.next(), self.chars.next()) {
+                                (Some((_, '\\')), Some((_, 'u'))) => self.hex4()?,
+                                _ => 0,
+                            };
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(format!("unpaired surrogate '\\u{:04x}'", code));
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        s.push(
+                            char::from_u32(code)
+                                .ok_or_else(|| format!("unpaired surrogate '\\u{:04x}'", code))?,
+                        );
+                    }
+                    Some((_, c @ ('"' | '\\' | '/'))) => s.push(c),
+                    Some((at, c)) => {
+                        return Err(format!("invalid escape '\\{}' at offset {}", c, at))
+                    }
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some((_, c)) => s.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if let Some((_, ']')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, ']')) => return Ok(Value::Array(values)),
+                Some((at, c)) => {
+                    return Err(format!(
+                        "expected ',' or ']' at offset {}, found '{}'",
+                        at, c
+                    ))
+                }
+                None => return Err("unterminated array".to_string()),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if let Some((_, '}')) = self.chars.peek() {
+            self.chars.next();
+            return Ok(Value::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => continue,
+                Some((_, '}')) => return Ok(Value::Object(fields)),
+                Some((at, c)) => {
+                    return Err(format!(
+                        "expected ',' or '}}' at offset {}, found '{}'",
+                        at, c
+                    ))
+                }
+                None => return Err("unterminated object".to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let document = object(vec![
+            ("null", Value::Null),
+            ("bools", vec![true.into(), false.into()].into()),
+            ("max", u64::MAX.into()),
+            ("negative", Value::NegInt(i64::MIN)),
+            ("whole", 100.0.into()),
+            ("fraction", 0.1.into()),
+            ("tiny", 1e-300.into()),
+            ("huge", 1e300.into()),
+            ("negative_fraction", (-2.5).into()),
+            (
+                "escapes",
+                "quote \" backslash \\ \n\r\t \u{1} \u{1f}".into(),
+            ),
+            ("unicode", "é ☃ 😀".into()),
+            ("empty", object(Vec::new())),
+            (
+                "nested",
+                vec![Vec::new().into(), object(vec![("", "".into())])].into(),
+            ),
+        ]);
+
+        assert_eq!(parse(&document.to_string()), Ok(document.clone()));
+
+        // so does what is written out from what was parsed
+        let text = document.to_string();
+        assert_eq!(parse(&text).unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn non_finite_floats_are_null() {
+        assert_eq!(Value::Float(f64::NAN).to_string(), "null");
+        assert_eq!(Value::Float(f64::INFINITY).to_string(), "null");
+    }
+
+    #[test]
+    fn parses_numbers() {
+        let cases = [
+            ("0", Value::Int(0)),
+            ("-0", Value::Int(0)),
+            ("42", Value::Int(42)),
+            ("18446744073709551615", Value::Int(u64::MAX)),
+            ("-12", Value::NegInt(-12)),
+            ("-9223372036854775808", Value::NegInt(i64::MIN)),
+            // too big for either is kept as near as a float goes
+            ("18446744073709551616", Value::Float(18446744073709551616.0)),
+            ("-9223372036854775809", Value::Float(-9223372036854775809.0)),
+            ("1.5", Value::Float(1.5)),
+            ("-0.25", Value::Float(-0.25)),
+            ("1e3", Value::Float(1000.0)),
+            ("1E+3", Value::Float(1000.0)),
+            ("15e-1", Value::Float(1.5)),
+        ];
+
+        for (text, value) in cases {
+            assert_eq!(parse(text), Ok(value), "{}", text);
+        }
+
+        assert_eq!(parse("-12").unwrap().as_u64(), None);
+        assert_eq!(parse("-12").unwrap().as_f64(), Some(-12.0));
+    }
+
+    #[test]
+    fn rejects_malformed_numbers() {
+        let cases = [
+            ("1-2e", "unexpected data at offset 1"),
+            ("01", "unexpected data at offset 1"),
+            ("1.2.3", "unexpected data at offset 3"),
+            ("[1-2]", "expected ',' or ']' at offset 2, found '-'"),
+            ("-", "invalid number '-'"),
+            ("--1", "invalid number '-'"),
+            ("-a", "invalid number '-'"),
+            ("1.", "invalid number '1.'"),
+            ("1.e5", "invalid number '1.'"),
+            ("1e", "invalid number '1e'"),
+            ("1e+", "invalid number '1e+'"),
+            ("+1", "unexpected '+' at offset 0"),
+            (".5", "unexpected '.' at offset 0"),
+        ];
+
+        for (text, err) in cases {
+            assert_eq!(parse(text), Err(err.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_escapes() {
+        let cases = [
+            (r#""\"\\\/\b\f\n\r\t""#, "\"\\/\u{8}\u{c}\n\r\t"),
+            (r#""\u0041\u00e9\u2603""#, "Aé☃"),
+            (r#""\ud83d\ude00""#, "😀"),
+            (r#""a\uD834\uDD1Eb""#, "a𝄞b"),
+        ];
+
+        for (text, s) in cases {
+            assert_eq!(parse(text), Ok(Value::String(s.to_string())), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_escapes() {
+        let cases = [
+            (r#""\ud83d""#, "unpaired surrogate '\\ud83d'"),
+            (r#""\ud83dx""#, "unpaired surrogate '\\ud83d'"),
+            (r#""\ud83dA""#, "unpaired surrogate '\\ud83d'"),
+            (r#""\ud83d\u0041""#, "unpaired surrogate '\\ud83d'"),
+            (r#""\ude00""#, "unpaired surrogate '\\ude00'"),
+            (r#""\u12""#, "invalid escape '\\u12'"),
+            (r#""\u+123""#, "invalid escape '\\u'"),
+            (r#""\x""#, "invalid escape '\\x' at offset 2"),
+            (r#""abc"#, "unterminated string"),
+        ];
+
+        for (text, err) in cases {
+            assert_eq!(parse(text), Err(err.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        let cases = [
+            ("", "unexpected end of the document"),
+            ("[1,", "unexpected end of the document"),
+            ("[1 2]", "expected ',' or ']' at offset 3, found '2'"),
+            ("{\"a\" 1}", "expected ':' at offset 5, found '1'"),
+            ("{\"a\":1", "unterminated object"),
+            ("tru", "expected 'e', found the end"),
+            ("{} {}", "unexpected data at offset 3"),
+        ];
+
+        for (text, err) in cases {
+            assert_eq!(parse(text), Err(err.to_string()), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    time::Duration,
+};
+
+use nsbench::{per_second, stage_load, RunDetails, LATENCY_PERCENTILES};
+
+use crate::{html::escape, report::Results, RunArguments};
+
+/// Writes the results to `w` as a JUnit XML test report, for CI servers to
+/// show alongside their tests: the run (or each of its stages) is a test case
+/// that passes if any query succeeded, and so is each `--assert` and
+/// `--check-baseline` check. The totals are attached as properties.
+pub fn junit<W: Write>(mut w: W, args: &RunArguments, results: &Results) -> io::Result<()> {
+    let overall = results.overall;
+
+    // (name, time, why it failed)
+    let mut cases: Vec<(String, Duration, Option<String>)> = Vec::new();
+    if results.per_stage.len() > 1 {
+        for (i, (stage, details, elapsed)) in results.per_stage.iter().enumerate() {
+            cases.push((
+                format!("stage {}: {}", i + 1, stage_load(stage)),
+                *elapsed,
+                failed_run(details),
+            ));
+        }
+    } else {
+        cases.push(("run".to_string(), results.runtime, failed_run(overall)));
+    }
+    let runs = cases.len();
+    for verdict in results.verdicts {
+        cases.push((
+            verdict.assertion.clone(),
+            Duration::ZERO,
+            (!verdict.passed).then(|| format!("was {}", verdict.actual)),
+        ));
+    }
+    let failures = cases
+        .iter()
+        .filter(|(_, _, failure)| failure.is_some())
+        .count();
+
+    let nameservers = results
+        .nameservers
+        .iter()
+        .map(|nameserver| nameserver.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    let mut properties = vec![
+        ("nameserver".to_string(), nameservers.clone()),
+        ("protocol".to_string(), args.protocol.to_string()),
+        ("successes".to_string(), overall.successes.to_string()),
+        ("failures".to_string(), overall.failures.to_string()),
+        (
+            "success_rate".to_string(),
+            format!("{:.2}", overall.success_rate()),
+        ),
+        (
+            "requests_per_second".to_string(),
+            per_second(overall.successes, results.runtime).to_string(),
+        ),
+        (
+            "mean_latency_ns".to_string(),
+            overall.mean_latency().as_nanos().to_string(),
+        ),
+    ];
+    for (label, percentile) in LATENCY_PERCENTILES {
+        properties.push((
+            format!("{}_latency_ns", label),
+            overall.latencies.percentile(*percentile).to_string(),
+        ));
+    }
+    if let Some(max_rate) = results.max_rate {
+        properties.push(("max_rate".to_string(), max_rate.to_string()));
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuites name=\"nsbench\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+        cases.len(),
+        failures,
+        results.runtime.as_secs_f64()
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "  <testsuite name=\"nsbench {}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"0\" time=\"{:.3}\">",
+        escape(&nameservers),
+        cases.len(),
+        failures,
+        results.runtime.as_secs_f64()
+    )
+    .unwrap();
+
+    xml.push_str("    <properties>\n");
+    for (name, value) in properties {
+        writeln!(
+            xml,
+            "      <property name=\"{}\" value=\"{}\"/>",
+            escape(&name),
+            escape(&value)
+        )
+        .unwrap();
+    }
+    xml.push_str("    </properties>\n");
+
+    for (i, (name, time, failure)) in cases.iter().enumerate() {
+        let classname = if i < runs {
+            "nsbench.run"
+        } else {
+            "nsbench.checks"
+        };
+        write!(
+            xml,
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            classname,
+            escape(name),
+            time.as_secs_f64()
+        )
+        .unwrap();
+        match failure {
+            Some(message) => writeln!(
+                xml,
+                ">\n      <failure message=\"{}\" type=\"{}\"/>\n    </testcase>",
+                escape(message),
+                if i < runs { "run" } else { "check" }
+            )
+            .unwrap(),
+            None => xml.push_str("/>\n"),
+        }
+    }
+
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    w.write_all(xml.as_bytes())?;
+    w.flush()
+}
+
+// why a run counts as failed: it got no answers that counted as successes
+fn failed_run(details: &RunDetails) -> Option<String> {
+    if details.successes > 0 {
+        return None;
+    }
+
+    Some(format!(
+        "no queries succeeded ({} failed)",
+        details.failures
+    ))
+}
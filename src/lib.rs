@@ -0,0 +1,1923 @@
+//! nsbench floods nameservers with DNS queries and measures how they cope:
+//! the request rate they sustain, the latency of their answers and why the
+//! queries that failed did. The `nsbench` command is a front end to this
+//! library, which can also be embedded in a test harness:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use nsbench::{stages, wire, workload, Config, Transport};
+//!
+//! let options = wire::ClientOptions {
+//!     transport: Transport::Udp,
+//!     timeout: Duration::from_secs(1),
+//!     retries: 0,
+//!     tcp_fallback: true,
+//!     bind: None,
+//!     ip_ttl: None,
+//!     dscp: None,
+//!     proxy: None,
+//!     edns_payload: None,
+//!     dnssec: false,
+//!     ecs: None,
+//! };
+//! let names = workload::Names::Single("example.com.".parse().unwrap());
+//! let stage = stages::Stage {
+//!     length: stages::Length::Time(Duration::from_secs(10)),
+//!     qps: Some(1000),
+//!     profile: None,
+//!     qtypes: None,
+//! };
+//!
+//! let results = nsbench::run_benchmark(Config::new(
+//!     vec!["127.0.0.1:53".parse().unwrap()],
+//!     options,
+//!     names,
+//!     vec![stage],
+//! ));
+//! println!("p99: {}ns", results.overall.latencies.percentile(99.0));
+//! ```
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::SocketAddr,
+    ops::AddAssign,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        mpsc::{channel, sync_channel, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use tracing::{info, warn};
+use trust_dns_resolver::proto::{
+    op::{Message, ResponseCode},
+    rr::{
+        rdata::opt::{EdnsCode, EdnsOption},
+        RecordType,
+    },
+};
+
+pub mod affinity;
+mod concurrent;
+pub mod consistency;
+pub mod control;
+pub mod expect;
+pub mod failurelog;
+mod findmax;
+pub mod histogram;
+mod http;
+pub mod influx;
+pub mod interrupt;
+pub mod json;
+mod mismatch;
+mod mmsg;
+mod openloop;
+pub mod otlp;
+mod pcap;
+pub mod prometheus;
+pub mod qnamemin;
+pub mod rate;
+pub mod remote;
+mod reporter;
+pub mod socks;
+pub mod source;
+pub mod stages;
+pub mod statsd;
+mod tasks;
+pub mod trace;
+pub mod tui;
+pub mod wire;
+pub mod workload;
+mod zone;
+
+/// The latency percentiles shown in the summary.
+pub const LATENCY_PERCENTILES: &[(&str, f64)] =
+    &[("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p99.9", 99.9)];
+
+/// The TTL distribution in the summary: each range starts at its lower bound
+/// and runs to the next one.
+pub const TTL_RANGES: [(u32, &str); 5] = [
+    (0, "0"),
+    (1, "1-59"),
+    (60, "60-299"),
+    (300, "300-3599"),
+    (3600, "3600+"),
+];
+
+/// Transport is how queries travel to the nameserver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "udp" => Ok(Transport::Udp),
+            "tcp" => Ok(Transport::Tcp),
+            _ => Err(format!("invalid protocol '{}': expected udp or tcp", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "udp"),
+            Transport::Tcp => write!(f, "tcp"),
+        }
+    }
+}
+
+/// Parses a record type such as `aaaa` or `MX`, in any case.
+pub fn parse_record_type(s: &str) -> Result<RecordType, String> {
+    RecordType::from_str(&s.to_uppercase()).map_err(|e| e.to_string())
+}
+
+/// Parses a DiffServ codepoint, as a number from 0 to 63 or by name: `ef`,
+/// `cs0` to `cs7`, `af11` to `af43`, `va` or `le` (lower effort, the
+/// scavenger class).
+pub fn parse_dscp(s: &str) -> Result<u8, String> {
+    let invalid = || {
+        format!(
+            "invalid DSCP '{}': expected 0 to 63, ef, cs0-cs7, af11-af43, va or le",
+            s
+        )
+    };
+    let name = s.to_ascii_lowercase();
+    let digits = |prefix: &str| -> Option<Vec<u8>> {
+        let rest = name.strip_prefix(prefix)?;
+        rest.chars()
+            .map(|c| c.to_digit(10).map(|d| d as u8))
+            .collect()
+    };
+
+    let dscp = match name.as_str() {
+        "ef" => 46,
+        "va" => 44,
+        "le" => 1,
+        _ => match (digits("cs").as_deref(), digits("af").as_deref()) {
+            (Some([class @ 0..=7]), _) => class * 8,
+            (_, Some([class @ 1..=4, drop @ 1..=3])) => class * 8 + drop * 2,
+            _ => s.parse().map_err(|_| invalid())?,
+        },
+    };
+    if dscp > 63 {
+        return Err(invalid());
+    }
+
+    Ok(dscp)
+}
+
+/// Parses a duration with a unit: 250us, 20ms, 10s, 5m.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .ok_or_else(|| format!("invalid duration '{}': missing unit (ns, us, ms, s, m)", s))?;
+    let (value, unit) = s.split_at(split);
+
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    let secs = match unit {
+        "ns" => value / 1e9,
+        "us" => value / 1e6,
+        "ms" => value / 1e3,
+        "s" => value,
+        "m" => value * 60.0,
+        _ => return Err(format!("invalid duration '{}': unknown unit '{}'", s, unit)),
+    };
+
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid duration '{}'", s))
+}
+
+#[derive(Debug)]
+struct QueryConfig {
+    init_done: SyncSender<()>,
+    // (target, interval, details) for each interval of each worker
+    informer_sender: Sender<(usize, u32, RunDetails)>,
+    finished: Arc<AtomicBool>,
+    target: usize,
+    nameserver: SocketAddr,
+    client_options: wire::ClientOptions,
+    // how many sockets (or TCP connections) the worker takes turns between
+    sockets: usize,
+    names: workload::NameGenerator,
+    pacing: Option<workload::Pacing>,
+    limiter: Option<Arc<rate::RateLimiter>>,
+    budget: Option<Arc<QueryBudget>>,
+    open_loop: Option<openloop::Schedule>,
+    concurrency: Option<usize>,
+    tasks: bool,
+    warmup: Option<Duration>,
+    expected_interval: Option<Duration>,
+    // the core this worker is pinned to
+    core: Option<usize>,
+    qtypes: workload::QtypeMix,
+    expect_nxdomain: bool,
+    expected: Option<Arc<expect::Expectations>>,
+    failure_log: Arc<failurelog::FailureLog>,
+    trace: Option<Arc<trace::Trace>>,
+    lock: Arc<Mutex<()>>,
+}
+
+/// RunDetails is how a set of queries fared: how many succeeded and why the
+/// rest failed, their latencies, and what came back.
+#[derive(Clone, Debug, Default)]
+pub struct RunDetails {
+    pub successes: u64,
+    pub failures: u64,
+    /// The sum of the latencies of every successful query, in nanoseconds.
+    pub total_latency: u128,
+    /// How many latencies are in total_latency.
+    pub latency_count: u64,
+    // running mean and sum of squared differences from it (Welford), for the
+    // standard deviation
+    latency_mean: f64,
+    latency_m2: f64,
+    pub failure_causes: Failures,
+    pub truncated: u64,
+    pub tcp_fallbacks: u64,
+    pub queries_by_type: BTreeMap<RecordType, u64>,
+    pub by_type: BTreeMap<RecordType, TypeDetails>,
+    /// Responses received, by numeric RCODE.
+    pub rcodes: BTreeMap<u16, u64>,
+    pub latencies: histogram::Histogram,
+    /// Wire sizes of the responses received, in bytes.
+    pub response_sizes: histogram::Histogram,
+    pub total_response_size: u64,
+    /// Everything sent and received, including retransmissions, TCP length
+    /// prefixes and responses that arrived too late to count.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// The lowest answer TTL of each successful response, how long a cache
+    /// could keep it, and how many fell into each of TTL_RANGES.
+    pub ttls: histogram::Histogram,
+    pub total_ttl: u64,
+    pub ttl_ranges: [u64; TTL_RANGES.len()],
+    /// Answered queries, by how many UDP transmissions they took.
+    pub attempts: BTreeMap<u32, u64>,
+    /// How long each new TCP connection took to open, in nanoseconds.
+    pub connect_times: histogram::Histogram,
+    pub total_connect_time: u128,
+    pub dnssec: Dnssec,
+    pub mismatches: Mismatches,
+    /// Responses carrying an EDNS Client Subnet option, by the scope prefix
+    /// length the nameserver said its answer holds for.
+    pub ecs_scopes: BTreeMap<u8, u64>,
+}
+
+impl RunDetails {
+    fn reset(&mut self) {
+        self.successes = 0;
+        self.failures = 0;
+        self.total_latency = 0;
+        self.latency_count = 0;
+        self.latency_mean = 0.0;
+        self.latency_m2 = 0.0;
+        self.failure_causes = Failures::default();
+        self.truncated = 0;
+        self.tcp_fallbacks = 0;
+        self.queries_by_type.clear();
+        self.by_type.clear();
+        self.rcodes.clear();
+        self.latencies.clear();
+        self.response_sizes.clear();
+        self.total_response_size = 0;
+        self.bytes_sent = 0;
+        self.bytes_received = 0;
+        self.ttls.clear();
+        self.total_ttl = 0;
+        self.ttl_ranges = Default::default();
+        self.attempts.clear();
+        self.connect_times.clear();
+        self.total_connect_time = 0;
+        self.dnssec = Dnssec::default();
+        self.mismatches = Mismatches::default();
+        self.ecs_scopes.clear();
+    }
+
+    /// Accounts for the outcome of one `record_type` query that took `latency`
+    /// nanoseconds, returning whether it succeeded. Any time spent opening a
+    /// connection is counted as connect time rather than latency, and an
+    /// answer other than the `expected` one, or one without the header
+    /// flags expected, as a failure.
+    fn record(
+        &mut self,
+        record_type: RecordType,
+        res: &Result<wire::Exchange, wire::QueryError>,
+        latency: u128,
+        expect_nxdomain: bool,
+        expected: Option<&expect::Expectations>,
+    ) -> bool {
+        let exchange = match res {
+            Ok(exchange) => exchange,
+            Err(e) => {
+                self.failures += 1;
+                self.by_type.entry(record_type).or_default().failures += 1;
+                match e {
+                    wire::QueryError::Timeout => self.failure_causes.timeout += 1,
+                    wire::QueryError::Io(_) => self.failure_causes.network += 1,
+                    wire::QueryError::Proto(_) => self.failure_causes.other += 1,
+                }
+                return false;
+            }
+        };
+
+        if exchange.truncated {
+            self.truncated += 1;
+        }
+        if exchange.tcp_fallback {
+            self.tcp_fallbacks += 1;
+        }
+
+        *self.attempts.entry(exchange.attempts).or_default() += 1;
+
+        let latency = match exchange.connect_time {
+            Some(connect_time) => {
+                let connect_time = connect_time.as_nanos();
+                self.connect_times.record(connect_time as u64);
+                self.total_connect_time += connect_time;
+                latency.saturating_sub(connect_time)
+            }
+            None => latency,
+        };
+
+        self.response_sizes.record(exchange.size as u64);
+        self.total_response_size += exchange.size as u64;
+
+        let message = &exchange.message;
+        *self
+            .rcodes
+            .entry(message.response_code().into())
+            .or_default() += 1;
+        if let Some(scope) = ecs_scope(message) {
+            *self.ecs_scopes.entry(scope).or_default() += 1;
+        }
+
+        let success = if expect_nxdomain {
+            message.response_code() == ResponseCode::NXDomain
+        } else {
+            message.response_code() == ResponseCode::NoError && message.answer_count() > 0
+        };
+        let wrong_answer =
+            success && expected.is_some_and(|expected| !expected.matches(message, record_type));
+        let wrong_flags = success
+            && !wrong_answer
+            && expected.is_some_and(|expected| !expected.flags_match(message));
+        let success = success && !wrong_answer && !wrong_flags;
+
+        if success {
+            self.successes += 1;
+            self.total_latency += latency;
+            self.latency_count += 1;
+
+            let delta = latency as f64 - self.latency_mean;
+            self.latency_mean += delta / self.latency_count as f64;
+            self.latency_m2 += delta * (latency as f64 - self.latency_mean);
+            self.latencies.record(latency as u64);
+
+            let by_type = self.by_type.entry(record_type).or_default();
+            by_type.successes += 1;
+            by_type.total_latency += latency;
+            by_type.latencies.record(latency as u64);
+
+            if dnssec_ok(message) {
+                if message.authentic_data() {
+                    self.dnssec.validated.record(latency as u64);
+                } else {
+                    self.dnssec.unvalidated.record(latency as u64);
+                }
+            }
+
+            if let Some(ttl) = message.answers().iter().map(|record| record.ttl()).min() {
+                self.ttls.record(ttl as u64);
+                self.total_ttl += ttl as u64;
+                let range = TTL_RANGES
+                    .iter()
+                    .rposition(|(lower, _)| ttl >= *lower)
+                    .unwrap();
+                self.ttl_ranges[range] += 1;
+            }
+        } else {
+            self.failures += 1;
+            self.by_type.entry(record_type).or_default().failures += 1;
+            match message.response_code() {
+                _ if wrong_answer => self.failure_causes.wrong_answer += 1,
+                _ if wrong_flags => self.failure_causes.wrong_flags += 1,
+                ResponseCode::ServFail => self.failure_causes.servfail += 1,
+                ResponseCode::Refused => self.failure_causes.refused += 1,
+                ResponseCode::NXDomain => self.failure_causes.nxdomain += 1,
+                _ => self.failure_causes.other += 1,
+            }
+        }
+
+        if dnssec_ok(message) && validation_failed(message) {
+            self.dnssec.bogus += 1;
+        }
+
+        success
+    }
+
+    /// Records the latencies of the queries a worker would have sent while it
+    /// waited `latency` nanoseconds on one, had it kept to one every
+    /// `interval`: each would have waited for the stalled query too, less the
+    /// time until its turn. This is HdrHistogram's correction for coordinated
+    /// omission, and only affects the percentiles and maximum.
+    fn backfill(&mut self, latency: u128, interval: Duration) {
+        let interval = interval.as_nanos();
+        let mut missed = latency.saturating_sub(interval);
+
+        while missed >= interval {
+            self.latencies.record(missed as u64);
+            missed -= interval;
+        }
+    }
+
+    /// Accounts for a `record_type` query that was never answered.
+    fn timed_out(&mut self, record_type: RecordType) {
+        self.failures += 1;
+        self.failure_causes.timeout += 1;
+        self.by_type.entry(record_type).or_default().failures += 1;
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        if self.latency_count == 0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_nanos((self.total_latency / self.latency_count as u128) as u64)
+    }
+
+    pub fn latency_stddev(&self) -> Duration {
+        if self.latency_count < 2 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_nanos((self.latency_m2 / (self.latency_count - 1) as f64).sqrt() as u64)
+    }
+
+    pub fn success_rate(&self) -> f64 {
+        (self.successes as f64 / (self.successes + self.failures) as f64) * 100.0
+    }
+}
+
+/// TypeDetails is how the queries for one record type fared.
+#[derive(Debug, Clone, Default)]
+pub struct TypeDetails {
+    pub successes: u64,
+    pub failures: u64,
+    pub total_latency: u128,
+    pub latencies: histogram::Histogram,
+}
+
+impl TypeDetails {
+    pub fn mean_latency(&self) -> Duration {
+        match self.latencies.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total_latency / count as u128) as u64),
+        }
+    }
+}
+
+impl AddAssign for TypeDetails {
+    fn add_assign(&mut self, rhs: TypeDetails) {
+        self.successes += rhs.successes;
+        self.failures += rhs.failures;
+        self.total_latency += rhs.total_latency;
+        self.latencies.merge(&rhs.latencies);
+    }
+}
+
+/// Dnssec is how the responses to queries with the DO bit set fared: the
+/// latencies of the successful ones the nameserver vouched for with the AD
+/// bit and of those it didn't, and how many said that validation failed.
+#[derive(Debug, Clone, Default)]
+pub struct Dnssec {
+    pub validated: histogram::Histogram,
+    pub unvalidated: histogram::Histogram,
+    /// Responses carrying an extended DNS error (RFC 8914) for a DNSSEC
+    /// failure, such as DNSSEC Bogus or Signature Expired.
+    pub bogus: u64,
+}
+
+impl AddAssign for Dnssec {
+    fn add_assign(&mut self, rhs: Dnssec) {
+        self.validated.merge(&rhs.validated);
+        self.unvalidated.merge(&rhs.unvalidated);
+        self.bogus += rhs.bogus;
+    }
+}
+
+// the EDNS option carrying extended DNS errors (RFC 8914), and the range of
+// its INFO-CODEs that report DNSSEC failures, from DNSSEC Indeterminate to
+// NSEC Missing
+const EXTENDED_DNS_ERROR: u16 = 15;
+const DNSSEC_ERRORS: std::ops::RangeInclusive<u16> = 5..=12;
+
+// whether a response is to a query that asked for DNSSEC records, as the DO
+// bit is copied into the response
+fn dnssec_ok(message: &Message) -> bool {
+    message
+        .extensions()
+        .as_ref()
+        .is_some_and(|edns| edns.dnssec_ok())
+}
+
+/// Returns the info code of the extended DNS error (RFC 8914) `message`
+/// carries, if any, e.g. 3 for a stale answer.
+pub fn extended_error(message: &Message) -> Option<u16> {
+    let option = message
+        .extensions()
+        .as_ref()
+        .and_then(|edns| edns.option(EdnsCode::Unknown(EXTENDED_DNS_ERROR)));
+    match option {
+        Some(EdnsOption::Unknown(_, data)) if data.len() >= 2 => {
+            Some(u16::from_be_bytes([data[0], data[1]]))
+        }
+        _ => None,
+    }
+}
+
+// the scope prefix length of the EDNS Client Subnet option a response
+// carries, if any
+fn ecs_scope(message: &Message) -> Option<u8> {
+    let option = message
+        .extensions()
+        .as_ref()
+        .and_then(|edns| edns.option(EdnsCode::Subnet));
+    match option {
+        Some(EdnsOption::Unknown(_, data)) if data.len() >= 4 => Some(data[3]),
+        _ => None,
+    }
+}
+
+// whether a response carries an extended DNS error saying validation failed
+fn validation_failed(message: &Message) -> bool {
+    extended_error(message).is_some_and(|code| DNSSEC_ERRORS.contains(&code))
+}
+
+/// Failures breaks failed queries down by cause. Responses that were neither
+/// the expected answer nor one of the listed error codes, such as an empty
+/// NOERROR, count as other; answers that differ from those given with
+/// `expected` count as wrong answers, and responses without the header
+/// flags expected as wrong flags.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Failures {
+    pub timeout: u64,
+    pub servfail: u64,
+    pub refused: u64,
+    pub nxdomain: u64,
+    pub network: u64,
+    pub wrong_answer: u64,
+    pub wrong_flags: u64,
+    pub other: u64,
+}
+
+impl AddAssign for Failures {
+    fn add_assign(&mut self, rhs: Failures) {
+        self.timeout += rhs.timeout;
+        self.servfail += rhs.servfail;
+        self.refused += rhs.refused;
+        self.nxdomain += rhs.nxdomain;
+        self.network += rhs.network;
+        self.wrong_answer += rhs.wrong_answer;
+        self.wrong_flags += rhs.wrong_flags;
+        self.other += rhs.other;
+    }
+}
+
+impl std::fmt::Display for Failures {
+    /// Lists the causes that occurred, e.g. `timeout 12, SERVFAIL 3`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let causes = [
+            ("timeout", self.timeout),
+            ("SERVFAIL", self.servfail),
+            ("REFUSED", self.refused),
+            ("NXDOMAIN", self.nxdomain),
+            ("network", self.network),
+            ("wrong answer", self.wrong_answer),
+            ("wrong flags", self.wrong_flags),
+            ("other", self.other),
+        ];
+
+        let mut first = true;
+        for (cause, count) in causes.iter().filter(|(_, count)| *count > 0) {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", cause, count)?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Mismatches counts the UDP packets that arrived without answering a query
+/// in flight, which are thrown away. Packets from addresses other than the
+/// nameserver's never get this far, as the sockets are connected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mismatches {
+    /// Answers to queries that had already timed out.
+    pub late: u64,
+    /// Responses with an id no query was sent with, or answered already.
+    pub unsolicited: u64,
+    /// Responses with the id of a query in flight but another question, as a
+    /// spoofed answer might have; the query goes on waiting for its own.
+    pub wrong_question: u64,
+    /// Packets that were not DNS responses at all.
+    pub malformed: u64,
+}
+
+impl Mismatches {
+    pub fn total(&self) -> u64 {
+        self.late + self.unsolicited + self.wrong_question + self.malformed
+    }
+}
+
+impl AddAssign for Mismatches {
+    fn add_assign(&mut self, rhs: Mismatches) {
+        self.late += rhs.late;
+        self.unsolicited += rhs.unsolicited;
+        self.wrong_question += rhs.wrong_question;
+        self.malformed += rhs.malformed;
+    }
+}
+
+impl std::fmt::Display for Mismatches {
+    /// Lists the kinds that occurred, e.g. `late 12, wrong question 3`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kinds = [
+            ("late", self.late),
+            ("unsolicited", self.unsolicited),
+            ("wrong question", self.wrong_question),
+            ("malformed", self.malformed),
+        ];
+
+        let mut first = true;
+        for (kind, count) in kinds.iter().filter(|(_, count)| *count > 0) {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", kind, count)?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Interval summarizes one reporting interval of a run, for the time series.
+#[derive(Debug, Clone)]
+pub struct Interval {
+    /// When the interval ended, from the start of the run and by the clock.
+    pub offset: Duration,
+    pub ended: SystemTime,
+    /// Usually a second, but the last interval of a stage may be cut short.
+    pub length: Duration,
+    pub successes: u64,
+    pub failures: u64,
+    pub failure_causes: Failures,
+    pub mean_latency: Duration,
+    pub p99_latency: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl Interval {
+    fn new(details: &RunDetails) -> Self {
+        Self {
+            offset: Duration::ZERO,
+            ended: SystemTime::UNIX_EPOCH,
+            length: Duration::ZERO,
+            successes: details.successes,
+            failures: details.failures,
+            failure_causes: details.failure_causes,
+            mean_latency: details.mean_latency(),
+            p99_latency: Duration::from_nanos(details.latencies.percentile(99.0)),
+            bytes_sent: details.bytes_sent,
+            bytes_received: details.bytes_received,
+        }
+    }
+}
+
+/// StageResults is what a stage produced: the totals, the totals for each
+/// nameserver, how long it ran and its time series.
+#[derive(Debug)]
+struct StageResults {
+    overall: RunDetails,
+    per_target: Vec<RunDetails>,
+    elapsed: Duration,
+    intervals: Vec<Interval>,
+}
+
+impl AddAssign<RunDetails> for RunDetails {
+    fn add_assign(&mut self, rhs: RunDetails) {
+        self.successes += rhs.successes;
+        self.total_latency += rhs.total_latency;
+
+        // combine the two variances (Chan et al.)
+        let count = self.latency_count + rhs.latency_count;
+        if count > 0 {
+            let delta = rhs.latency_mean - self.latency_mean;
+            let weight = rhs.latency_count as f64 / count as f64;
+            self.latency_m2 += rhs.latency_m2 + delta * delta * self.latency_count as f64 * weight;
+            self.latency_mean += delta * weight;
+        }
+        self.latency_count = count;
+        self.failures += rhs.failures;
+        self.failure_causes += rhs.failure_causes;
+        self.truncated += rhs.truncated;
+        self.tcp_fallbacks += rhs.tcp_fallbacks;
+        for (record_type, count) in rhs.queries_by_type {
+            *self.queries_by_type.entry(record_type).or_default() += count;
+        }
+        for (record_type, details) in rhs.by_type {
+            *self.by_type.entry(record_type).or_default() += details;
+        }
+        for (rcode, count) in rhs.rcodes {
+            *self.rcodes.entry(rcode).or_default() += count;
+        }
+        self.latencies.merge(&rhs.latencies);
+        self.response_sizes.merge(&rhs.response_sizes);
+        self.total_response_size += rhs.total_response_size;
+        self.bytes_sent += rhs.bytes_sent;
+        self.bytes_received += rhs.bytes_received;
+        self.ttls.merge(&rhs.ttls);
+        self.total_ttl += rhs.total_ttl;
+        for (count, other) in self.ttl_ranges.iter_mut().zip(rhs.ttl_ranges) {
+            *count += other;
+        }
+        for (attempts, count) in rhs.attempts {
+            *self.attempts.entry(attempts).or_default() += count;
+        }
+        self.connect_times.merge(&rhs.connect_times);
+        self.total_connect_time += rhs.total_connect_time;
+        self.dnssec += rhs.dnssec;
+        self.mismatches += rhs.mismatches;
+        for (scope, count) in rhs.ecs_scopes {
+            *self.ecs_scopes.entry(scope).or_default() += count;
+        }
+    }
+}
+
+/// QueryBudget hands out a fixed number of queries to a group of workers,
+/// for runs that stop after a count instead of a time.
+#[derive(Debug)]
+struct QueryBudget {
+    total: u64,
+    issued: AtomicU64,
+    completed: AtomicU64,
+}
+
+impl QueryBudget {
+    fn new(total: u64) -> Self {
+        Self {
+            total,
+            issued: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+        }
+    }
+
+    /// Claims one query, returning false once they have all been handed out.
+    fn take(&self) -> bool {
+        self.issued
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            < self.total
+    }
+
+    /// Marks `count` claimed queries as answered or failed.
+    fn complete(&self, count: u64) {
+        self.completed
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn done(&self) -> bool {
+        self.completed.load(std::sync::atomic::Ordering::Relaxed) >= self.total
+    }
+}
+
+// sleeps until `deadline`, waking up periodically to see if the run is over.
+// returns false if it is.
+fn wait_until(deadline: Instant, finished: &AtomicBool) -> bool {
+    let tick = Duration::from_millis(100);
+
+    loop {
+        if finished.load(std::sync::atomic::Ordering::Relaxed) {
+            return false;
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+
+        thread::sleep((deadline - now).min(tick));
+    }
+}
+
+fn perform_queries(mut qc: QueryConfig) {
+    if let Some(core) = qc.core {
+        if let Err(e) = affinity::pin(core) {
+            warn!("could not pin worker to core {}: {}", core, e);
+        }
+    }
+
+    let mut clients: Vec<wire::Client> = (0..qc.sockets)
+        .map(|_| wire::Client::new(qc.nameserver, qc.client_options))
+        .collect();
+
+    qc.init_done.send(()).unwrap();
+    drop(qc.lock.lock().unwrap());
+
+    let mut reporter = reporter::Reporter::new(&qc);
+    if let Some(schedule) = qc.open_loop {
+        openloop::perform_queries(&mut qc, reporter, schedule);
+    } else if let (Some(window), true) = (qc.concurrency, qc.tasks) {
+        tasks::perform_queries(&mut qc, &mut reporter, window);
+    } else if let Some(window) = qc.concurrency {
+        concurrent::perform_queries(&mut qc, &mut reporter, window);
+    } else {
+        closed_loop(&mut qc, &mut clients, &mut reporter);
+    }
+}
+
+// sends each query as soon as the previous one has been answered (or timed
+// out), from each of `clients` in turn
+fn closed_loop(
+    qc: &mut QueryConfig,
+    clients: &mut [wire::Client],
+    reporter: &mut reporter::Reporter,
+) {
+    let mut rng = rand::thread_rng();
+    let mut trace = qc
+        .trace
+        .as_ref()
+        .map(|trace| trace::Buffer::new(trace.clone(), qc.nameserver));
+
+    let start = Instant::now();
+    let mut next_client = 0;
+
+    while !qc.finished.load(std::sync::atomic::Ordering::Relaxed) {
+        if let Some(pacing) = &qc.pacing {
+            if !reporter.wait_until(start + pacing.due(qc.names.position()), &qc.finished) {
+                break;
+            }
+        }
+
+        if let Some(limiter) = &qc.limiter {
+            if !limiter.acquire(&qc.finished, || reporter.tick()) {
+                break;
+            }
+        }
+
+        if let Some(budget) = &qc.budget {
+            if !budget.take() {
+                break;
+            }
+        }
+
+        let (name, record_type) = qc.names.next(&mut rng);
+        let record_type = record_type.unwrap_or_else(|| qc.qtypes.sample(&mut rng));
+
+        let client = &mut clients[next_client % clients.len()];
+        next_client += 1;
+
+        let now = Instant::now();
+        let res = client.query(name.clone(), record_type);
+        let current = Instant::now().duration_since(now).as_nanos();
+        if let Some(trace) = &mut trace {
+            trace.record(
+                now,
+                &name,
+                record_type,
+                &res,
+                Duration::from_nanos(current as u64),
+            );
+        }
+
+        let details = reporter.details();
+        *details.queries_by_type.entry(record_type).or_default() += 1;
+        let success = details.record(
+            record_type,
+            &res,
+            current,
+            qc.expect_nxdomain,
+            qc.expected.as_deref(),
+        );
+        if !success {
+            qc.failure_log.failed(
+                qc.nameserver,
+                &name,
+                record_type,
+                Duration::from_nanos(current as u64),
+                || failurelog::cause(&res),
+            );
+        }
+        let (sent, received) = client.take_traffic();
+        details.bytes_sent += sent;
+        details.bytes_received += received;
+        details.mismatches += client.take_mismatches();
+        if let (true, Some(interval)) = (success, qc.expected_interval) {
+            details.backfill(current, interval);
+        }
+
+        if let Some(budget) = &qc.budget {
+            budget.complete(1);
+        }
+    }
+}
+
+/// IntervalHook is called with each interval of the time series as it ends.
+pub type IntervalHook = Arc<dyn Fn(&Interval) + Send + Sync>;
+
+/// CheckpointHook is called with the results of the run so far, every
+/// `checkpoint_interval`, so that they can be kept somewhere safe.
+pub type CheckpointHook = Arc<dyn Fn(&Results) + Send + Sync>;
+
+/// StageHook is called as each stage ends, with the stage, what it measured
+/// and how long it ran, and may change, add or drop the stages still to run.
+pub type StageHook =
+    Arc<dyn Fn(&stages::Stage, &RunDetails, Duration, &mut VecDeque<stages::Stage>) + Send + Sync>;
+
+/// Config is everything about a benchmark: where the queries go and what
+/// they ask, the stages that set the load, and where progress is reported as
+/// it runs. Config::new fills in what the `nsbench` command defaults to.
+pub struct Config {
+    pub nameservers: Vec<SocketAddr>,
+    /// How many workers query each nameserver.
+    pub cpus: usize,
+    /// The cores to pin the workers to, handed out in turn; when empty, the
+    /// workers go wherever the scheduler puts them.
+    pub cores: Vec<usize>,
+    /// The core kept from the workers to pin the informer to, if any.
+    pub informer_core: Option<usize>,
+    pub client_options: wire::ClientOptions,
+    /// How many sockets (or TCP connections), and so source ports, each
+    /// worker takes turns between.
+    pub sockets_per_worker: usize,
+    pub names: workload::Names,
+    pub pacing: Option<workload::Pacing>,
+    pub sampler: workload::Sampler,
+    pub repeat_ratio: f64,
+    /// The record types asked for, unless a stage has its own.
+    pub qtypes: workload::QtypeMix,
+    pub stages: Vec<stages::Stage>,
+    /// When set, the stages are ignored and the run searches for the highest
+    /// rate whose p99 latency stays within this, probing each rate for
+    /// `probe_time`.
+    pub find_max: Option<Duration>,
+    pub probe_time: Duration,
+    pub open_loop: bool,
+    pub arrival: rate::Arrival,
+    pub burst: Option<rate::Burst>,
+    pub concurrency: Option<usize>,
+    /// Whether the in-flight queries of `concurrency` are tokio tasks, each
+    /// with a socket of its own, rather than multiplexed over the workers'
+    /// sockets.
+    pub tasks: bool,
+    pub warmup: Option<Duration>,
+    pub expected_interval: Option<Duration>,
+    pub expect_nxdomain: bool,
+    /// When set, responses without the answers expected count as failures.
+    pub expected: Option<Arc<expect::Expectations>>,
+    pub failure_log: Arc<failurelog::FailureLog>,
+    pub trace: Option<Arc<trace::Trace>>,
+    pub on_interval: Option<IntervalHook>,
+    pub on_checkpoint: Option<CheckpointHook>,
+    pub checkpoint_interval: Duration,
+    /// Not called when searching with `find_max`.
+    pub on_stage: Option<StageHook>,
+    pub metrics: Option<Arc<prometheus::Metrics>>,
+    pub statsd: Option<Arc<statsd::Client>>,
+    pub influx: Option<Arc<influx::Writer>>,
+    pub otlp: Option<Arc<otlp::Exporter>>,
+    pub dashboard: Option<Arc<Mutex<tui::Dashboard>>>,
+    /// Where the control socket's commands take effect.
+    pub control: Option<Arc<control::Control>>,
+}
+
+impl Config {
+    /// Returns a closed-loop benchmark of one worker per nameserver asking
+    /// for A records, which runs the `stages` and reports on nothing but the
+    /// log.
+    pub fn new(
+        nameservers: Vec<SocketAddr>,
+        client_options: wire::ClientOptions,
+        names: workload::Names,
+        stages: Vec<stages::Stage>,
+    ) -> Self {
+        Self {
+            sampler: workload::NameDistribution::RoundRobin.sampler(names.len()),
+            nameservers,
+            cpus: 1,
+            cores: Vec::new(),
+            informer_core: None,
+            client_options,
+            sockets_per_worker: 1,
+            names,
+            pacing: None,
+            repeat_ratio: 0.0,
+            qtypes: workload::QtypeMix::single(RecordType::A),
+            stages,
+            find_max: None,
+            probe_time: Duration::from_secs(10),
+            open_loop: false,
+            arrival: rate::Arrival::Fixed,
+            burst: None,
+            concurrency: None,
+            tasks: false,
+            warmup: None,
+            expected_interval: None,
+            expect_nxdomain: false,
+            expected: None,
+            failure_log: Arc::new(failurelog::FailureLog::new(0)),
+            trace: None,
+            on_interval: None,
+            on_checkpoint: None,
+            checkpoint_interval: Duration::from_secs(60),
+            on_stage: None,
+            metrics: None,
+            statsd: None,
+            influx: None,
+            otlp: None,
+            dashboard: None,
+            control: None,
+        }
+    }
+}
+
+/// Results is everything a benchmark measured.
+#[derive(Debug, Clone)]
+pub struct Results {
+    pub nameservers: Vec<SocketAddr>,
+    pub overall: RunDetails,
+    /// The totals for each nameserver, in the order they were given.
+    pub per_target: Vec<RunDetails>,
+    /// Each stage (or probe, when searching for the highest rate) that ran,
+    /// with its totals and how long it took.
+    pub per_stage: Vec<(stages::Stage, RunDetails, Duration)>,
+    pub timeseries: Vec<Interval>,
+    pub runtime: Duration,
+    /// The highest rate that met the `find_max` SLO, if any did.
+    pub max_rate: Option<u64>,
+}
+
+/// Runs the benchmark `config` describes, one stage (or probe) after another,
+/// and returns what it measured.
+pub fn run_benchmark(config: Config) -> Results {
+    let mut overall = RunDetails::default();
+    let mut per_target = vec![RunDetails::default(); config.nameservers.len()];
+    let mut per_stage = Vec::new();
+    let mut timeseries = Vec::new();
+
+    let mut run = |stage: stages::Stage| {
+        let qtypes = stage.qtypes.as_ref().unwrap_or(&config.qtypes);
+        let stage_start: Duration = per_stage.iter().map(|(_, _, elapsed)| *elapsed).sum();
+        let before = Results {
+            nameservers: config.nameservers.clone(),
+            overall: overall.clone(),
+            per_target: per_target.clone(),
+            per_stage: per_stage.clone(),
+            timeseries: timeseries.clone(),
+            runtime: stage_start,
+            max_rate: None,
+        };
+        let results = run_stage(&config, &stage, qtypes, &before);
+
+        overall += results.overall.clone();
+        for (total, details) in per_target.iter_mut().zip(results.per_target) {
+            *total += details;
+        }
+
+        let elapsed = results.elapsed;
+        timeseries.extend(results.intervals.into_iter().map(|mut interval| {
+            interval.offset += stage_start;
+            interval
+        }));
+
+        per_stage.push((stage, results.overall.clone(), elapsed));
+        (results.overall, elapsed)
+    };
+
+    let max_rate = match config.find_max {
+        Some(slo) => findmax::search(slo, config.probe_time, &mut run),
+        None => {
+            let numbered = config.stages.len() > 1 || config.on_stage.is_some();
+            let mut remaining: VecDeque<_> = config.stages.iter().cloned().collect();
+            let mut i = 0;
+            while let Some(stage) = remaining.pop_front() {
+                if interrupt::interrupted() {
+                    break;
+                }
+                i += 1;
+                if numbered {
+                    info!("Stage {}: {}", i, stage_load(&stage));
+                }
+                let (details, elapsed) = run(stage.clone());
+                if let Some(on_stage) = &config.on_stage {
+                    on_stage(&stage, &details, elapsed, &mut remaining);
+                }
+            }
+            None
+        }
+    };
+
+    let runtime = per_stage.iter().map(|(_, _, elapsed)| *elapsed).sum();
+
+    Results {
+        nameservers: config.nameservers,
+        overall,
+        per_target,
+        per_stage,
+        timeseries,
+        runtime,
+        max_rate,
+    }
+}
+
+// returns the results of the run so far: those of the stages `before`, and
+// what the stage in progress has measured in its first `elapsed`
+fn so_far(
+    before: &Results,
+    stage: &stages::Stage,
+    totals: &RunDetails,
+    per_target: &[RunDetails],
+    intervals: &BTreeMap<u32, Interval>,
+    elapsed: Duration,
+) -> Results {
+    let mut results = before.clone();
+    results.overall += totals.clone();
+    for (total, details) in results.per_target.iter_mut().zip(per_target) {
+        *total += details.clone();
+    }
+    results
+        .per_stage
+        .push((stage.clone(), totals.clone(), elapsed));
+    results
+        .timeseries
+        .extend(intervals.values().cloned().map(|mut interval| {
+            interval.offset += before.runtime;
+            interval
+        }));
+    results.runtime += elapsed;
+    results
+}
+
+// sends an interval's results for each nameserver to statsd, warning rather
+// than stopping the run if that fails
+fn send_statsd(statsd: &statsd::Client, nameservers: &[SocketAddr], per_target: &[RunDetails]) {
+    for (nameserver, details) in nameservers.iter().zip(per_target) {
+        if let Err(e) = statsd.send(nameserver, details) {
+            warn!("could not send to statsd: {}", e);
+        }
+    }
+}
+
+// exports the totals, with an interval's results added, to the OpenTelemetry
+// collector, warning rather than stopping the run if that fails
+fn export_otlp(otlp: &otlp::Exporter, per_target: &[RunDetails]) {
+    if let Err(e) = otlp.export(per_target) {
+        warn!("could not export to OpenTelemetry: {}", e);
+    }
+}
+
+// prints the progress line for an interval of `length`, and one for each
+// nameserver if there are several
+fn print_interval(
+    interval: &RunDetails,
+    length: Duration,
+    nameservers: &[SocketAddr],
+    per_target: &[RunDetails],
+) {
+    info!(
+        "1s avg latency: {:?} | Successes: {} | Failures: {}{} | Total Req: {} | Mbit/s: {:.02} out, {:.02} in",
+        interval.mean_latency(),
+        interval.successes,
+        interval.failures,
+        failure_causes(interval),
+        interval.successes + interval.failures,
+        mbit_per_second(interval.bytes_sent, length),
+        mbit_per_second(interval.bytes_received, length),
+    );
+    if nameservers.len() > 1 {
+        for (nameserver, details) in nameservers.iter().zip(per_target) {
+            info!(
+                "  {}: avg latency: {:?} | Successes: {} | Failures: {}{} | Total Req: {}",
+                nameserver,
+                details.mean_latency(),
+                details.successes,
+                details.failures,
+                failure_causes(details),
+                details.successes + details.failures,
+            );
+        }
+    }
+}
+
+// prints a summary of the run so far, `elapsed` into it, as SIGUSR1 asks;
+// the nameservers' own lines cover only the stage in progress
+fn print_so_far(
+    totals: &RunDetails,
+    elapsed: Duration,
+    nameservers: &[SocketAddr],
+    per_target: &[RunDetails],
+) {
+    let percentiles: Vec<String> = LATENCY_PERCENTILES
+        .iter()
+        .map(|(label, percentile)| {
+            format!(
+                "{} {:.2?}",
+                label,
+                Duration::from_nanos(totals.latencies.percentile(*percentile))
+            )
+        })
+        .collect();
+    let rcodes: Vec<String> = totals
+        .rcodes
+        .iter()
+        .map(|(rcode, count)| format!("{} {}", rcode_name(*rcode), count))
+        .collect();
+
+    info!("So far, after {:.2?}:", elapsed);
+    info!(
+        "  Successes: {} | Failures: {}{} | Requests: {}/s | Mbit/s: {:.02} out, {:.02} in",
+        totals.successes,
+        totals.failures,
+        failure_causes(totals),
+        per_second(totals.successes, elapsed),
+        mbit_per_second(totals.bytes_sent, elapsed),
+        mbit_per_second(totals.bytes_received, elapsed),
+    );
+    info!(
+        "  Latency: avg {:.2?} | std dev {:.2?} | min {:.2?} | {} | max {:.2?}",
+        totals.mean_latency(),
+        totals.latency_stddev(),
+        Duration::from_nanos(totals.latencies.min()),
+        percentiles.join(" | "),
+        Duration::from_nanos(totals.latencies.max()),
+    );
+    if !rcodes.is_empty() {
+        info!("  RCODEs: {}", rcodes.join(", "));
+    }
+    if nameservers.len() > 1 {
+        for (nameserver, details) in nameservers.iter().zip(per_target) {
+            info!(
+                "  {}: Successes: {} | Failures: {}{} | avg latency: {:.2?} | p99: {:.2?}",
+                nameserver,
+                details.successes,
+                details.failures,
+                failure_causes(details),
+                details.mean_latency(),
+                Duration::from_nanos(details.latencies.percentile(99.0)),
+            );
+        }
+    }
+}
+
+/// The mnemonic for a response code, as dig prints it.
+pub fn rcode_name(rcode: u16) -> String {
+    let name = match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        6 => "YXDOMAIN",
+        7 => "YXRRSET",
+        8 => "NXRRSET",
+        9 => "NOTAUTH",
+        10 => "NOTZONE",
+        16 => "BADVERS",
+        23 => "BADCOOKIE",
+        _ => return format!("RCODE{}", rcode),
+    };
+
+    name.to_string()
+}
+
+/// The causes of `details`' failures in parentheses, or nothing if there were
+/// none.
+pub fn failure_causes(details: &RunDetails) -> String {
+    if details.failures == 0 {
+        return String::new();
+    }
+
+    format!(" ({})", details.failure_causes)
+}
+
+/// How many of `count` there were per second over `elapsed`.
+pub fn per_second(count: u64, elapsed: Duration) -> u64 {
+    (count as f64 / elapsed.as_secs_f64()) as u64
+}
+
+/// The rate `bytes` over `elapsed` make, in megabits per second.
+pub fn mbit_per_second(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 * 8.0 / 1_000_000.0 / elapsed.as_secs_f64()
+}
+
+/// Describes the load a stage offers, for the summary.
+pub fn stage_load(stage: &stages::Stage) -> String {
+    match (stage.qps, stage.profile) {
+        (Some(qps), _) => format!("{} at {}/s", stage.length, qps),
+        (None, Some(profile)) => profile.to_string(),
+        (None, None) => format!("{} unlimited", stage.length),
+    }
+}
+
+/// Runs `stage` against every nameserver. `before` is what the stages before
+/// it measured, for the summaries SIGUSR1 asks for and the checkpoints.
+fn run_stage(
+    config: &Config,
+    stage: &stages::Stage,
+    qtypes: &workload::QtypeMix,
+    before: &Results,
+) -> StageResults {
+    let cpus = config.cpus;
+    let workers = cpus * config.nameservers.len();
+
+    // each open-loop worker sends its share of the rate, staggered so the
+    // group's queries are evenly spaced.
+    let open_loop_interval = match stage.qps {
+        Some(qps) if config.open_loop => Some(Duration::from_secs_f64(cpus as f64 / qps as f64)),
+        _ => None,
+    };
+
+    let mut handles = Vec::new();
+    let (s, r) = sync_channel(1);
+    let (init_s, init_r) = sync_channel(workers);
+    let (inf_s, inf_r) = channel();
+    let finished = Arc::new(AtomicBool::new(false));
+    let lock = Arc::new(Mutex::new(()));
+
+    let mut limiters = Vec::new();
+    let mut budgets = Vec::new();
+
+    let mg = lock.lock().unwrap();
+
+    // each nameserver gets its own group of workers so they all see the same
+    // offered load.
+    for (target, nameserver) in config.nameservers.iter().enumerate() {
+        let limiter = match (stage.qps, stage.profile, config.burst) {
+            (_, _, Some(burst)) => Some(Arc::new(rate::RateLimiter::burst(burst))),
+            (Some(qps), None, None) if !config.open_loop => {
+                Some(Arc::new(rate::RateLimiter::new(qps as f64, config.arrival)))
+            }
+            (None, Some(profile), None) => Some(Arc::new(rate::RateLimiter::new(
+                profile.rate_at(Duration::ZERO),
+                config.arrival,
+            ))),
+            _ => None,
+        };
+        limiters.extend(limiter.clone());
+
+        let budget = match stage.length {
+            stages::Length::Queries(count) => Some(Arc::new(QueryBudget::new(count))),
+            stages::Length::Time(_) => None,
+        };
+        budgets.extend(budget.clone());
+
+        for worker in 0..cpus {
+            let qc = QueryConfig {
+                init_done: init_s.clone(),
+                informer_sender: inf_s.clone(),
+                finished: finished.clone(),
+                target,
+                nameserver: *nameserver,
+                client_options: config.client_options,
+                sockets: config.sockets_per_worker,
+                names: if config.pacing.is_some() {
+                    // paced workers split the capture between them so that
+                    // together they send it on its original schedule.
+                    config
+                        .names
+                        .generator(worker, worker, cpus, config.sampler.clone())
+                } else {
+                    // spread the workers out over the list so they don't all
+                    // ask for the same name at the same time.
+                    config.names.generator(
+                        worker,
+                        config.names.len() / cpus * worker,
+                        1,
+                        config.sampler.clone(),
+                    )
+                }
+                .with_repeat_ratio(config.repeat_ratio),
+                pacing: config.pacing.clone(),
+                limiter: limiter.clone(),
+                budget: budget.clone(),
+                open_loop: open_loop_interval.map(|interval| openloop::Schedule {
+                    interval,
+                    phase: interval / cpus as u32 * worker as u32,
+                    arrival: config.arrival,
+                }),
+                // the group's in-flight queries are shared out between the
+                // workers as evenly as they go
+                concurrency: config
+                    .concurrency
+                    .map(|total| total / cpus + usize::from(worker < total % cpus)),
+                tasks: config.tasks,
+                warmup: config.warmup,
+                expected_interval: config.expected_interval,
+                core: (!config.cores.is_empty())
+                    .then(|| config.cores[(target * cpus + worker) % config.cores.len()]),
+                qtypes: qtypes.clone(),
+                expect_nxdomain: config.expect_nxdomain,
+                expected: config.expected.clone(),
+                failure_log: config.failure_log.clone(),
+                trace: config.trace.clone(),
+                lock: lock.clone(),
+            };
+
+            handles.push(std::thread::spawn(move || perform_queries(qc)));
+        }
+    }
+
+    for _ in 0..workers {
+        init_r.recv().unwrap();
+    }
+
+    if let Some(control) = &config.control {
+        // a profile or burst sets the rate itself
+        let fixed = stage.profile.is_none() && config.burst.is_none();
+        control.stage_started(if fixed { limiters.clone() } else { Vec::new() });
+    }
+
+    // how often the workers report, as in reporter::Reporter. open-loop
+    // workers send and receive (a thread for each socket) on separate threads
+    // that report separately.
+    let tick = Duration::from_secs(1);
+    let reporters = if open_loop_interval.is_some() {
+        workers * (1 + config.sockets_per_worker)
+    } else {
+        workers
+    };
+    let nameservers = config.nameservers.clone();
+    let on_interval = config.on_interval.clone();
+    let metrics = config.metrics.clone();
+    let statsd = config.statsd.clone();
+    let influx = config.influx.clone();
+    let otlp = config.otlp.clone();
+    let dashboard = config.dashboard.clone();
+    let control = config.control.clone();
+    let duration = match stage.length {
+        stages::Length::Time(duration) => Some(duration),
+        stages::Length::Queries(_) => None,
+    };
+    let targets = nameservers.len();
+    let before = before.clone();
+    let before_elapsed = before.runtime;
+    let on_checkpoint = config.on_checkpoint.clone();
+    let checkpoint_interval = config.checkpoint_interval;
+    let this_stage = stage.clone();
+    let informer_core = config.informer_core;
+    let informer = thread::spawn(move || {
+        if let Some(core) = informer_core {
+            if let Err(e) = affinity::pin(core) {
+                warn!("could not pin the informer to core {}: {}", core, e);
+            }
+        }
+
+        let mut last_checkpoint = Instant::now();
+        let mut totals = RunDetails::default();
+        let mut per_target = vec![RunDetails::default(); targets];
+        // intervals that not every worker has reported on yet, how many have,
+        // and the interval for each target
+        let mut pending: BTreeMap<u32, (usize, RunDetails, Vec<RunDetails>)> = BTreeMap::new();
+        let mut intervals = BTreeMap::new();
+        while let Ok((target, seq, details)) = inf_r.recv() {
+            if let Some(metrics) = &metrics {
+                metrics.add(target, details.clone());
+            }
+            if let Some(control) = &control {
+                control.add(details.clone());
+            }
+            totals += details.clone();
+            per_target[target] += details.clone();
+
+            let (reported, interval, interval_per_target) =
+                pending.entry(seq).or_insert_with(|| {
+                    (
+                        0,
+                        RunDetails::default(),
+                        vec![RunDetails::default(); targets],
+                    )
+                });
+            *reported += 1;
+            *interval += details.clone();
+            interval_per_target[target] += details;
+
+            if *reported == reporters {
+                let (_, interval, interval_per_target) = pending.remove(&seq).unwrap();
+                if let Some(statsd) = &statsd {
+                    send_statsd(statsd, &nameservers, &interval_per_target);
+                }
+                if let Some(otlp) = &otlp {
+                    export_otlp(otlp, &interval_per_target);
+                }
+
+                // the stragglers answered after a timed stage ends
+                if duration.is_some_and(|duration| tick * seq >= duration) {
+                    intervals.insert(seq, Interval::new(&interval));
+                    continue;
+                }
+
+                if let Some(control) = &control {
+                    control.reported_until(before_elapsed + tick * (seq + 1));
+                }
+                if let Some(dashboard) = &dashboard {
+                    dashboard
+                        .lock()
+                        .unwrap()
+                        .draw(&interval, &totals, &per_target);
+                } else {
+                    print_interval(&interval, tick, &nameservers, &interval_per_target);
+                    if interrupt::summary_wanted() {
+                        let mut so_far = before.overall.clone();
+                        so_far += totals.clone();
+                        print_so_far(
+                            &so_far,
+                            before_elapsed + tick * (seq + 1),
+                            &nameservers,
+                            &per_target,
+                        );
+                    }
+                }
+
+                // the offset is from the start of the stage; the final one is
+                // worked out once the stage is over
+                let mut interval = Interval::new(&interval);
+                interval.offset = tick * (seq + 1);
+                interval.length = tick;
+                interval.ended = SystemTime::now();
+                if let Some(on_interval) = &on_interval {
+                    on_interval(&interval);
+                }
+                if let Some(influx) = &influx {
+                    let per_target: Vec<Interval> = interval_per_target
+                        .iter()
+                        .map(|details| Interval {
+                            ended: interval.ended,
+                            ..Interval::new(details)
+                        })
+                        .collect();
+                    if let Err(e) = influx.write(&nameservers, &per_target) {
+                        warn!("could not write to InfluxDB: {}", e);
+                    }
+                }
+                intervals.insert(seq, interval);
+
+                if let Some(on_checkpoint) = &on_checkpoint {
+                    if last_checkpoint.elapsed() >= checkpoint_interval {
+                        last_checkpoint = Instant::now();
+                        on_checkpoint(&so_far(
+                            &before,
+                            &this_stage,
+                            &totals,
+                            &per_target,
+                            &intervals,
+                            tick * (seq + 1),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // the final, partial intervals of workers that finished at
+        // different times
+        for (seq, (_, interval, interval_per_target)) in pending {
+            if let Some(statsd) = &statsd {
+                send_statsd(statsd, &nameservers, &interval_per_target);
+            }
+            if let Some(otlp) = &otlp {
+                export_otlp(otlp, &interval_per_target);
+            }
+            intervals.insert(seq, Interval::new(&interval));
+        }
+
+        s.send((totals, per_target, intervals)).unwrap()
+    });
+
+    drop(mg);
+
+    if let Some(warmup) = config.warmup {
+        info!("Warming up for {:?}", warmup);
+        std::thread::sleep(warmup);
+    }
+    let start = Instant::now();
+    let started = SystemTime::now();
+
+    if let Some(profile) = stage.profile {
+        let finished = finished.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            while !finished.load(std::sync::atomic::Ordering::Relaxed) {
+                let rate = profile.rate_at(start.elapsed());
+                for limiter in &limiters {
+                    limiter.set_rate(rate);
+                }
+
+                if profile
+                    .settles_after()
+                    .is_some_and(|settled| start.elapsed() >= settled)
+                {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        });
+    }
+
+    match stage.length {
+        stages::Length::Time(duration) => {
+            let deadline = start + duration;
+            while !interrupt::interrupted() {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                std::thread::sleep((deadline - now).min(Duration::from_millis(10)));
+            }
+        }
+        stages::Length::Queries(_) => {
+            while !budgets.iter().all(|budget| budget.done()) && !interrupt::interrupted() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+    finished.store(true, std::sync::atomic::Ordering::Release);
+
+    // timed stages report the time asked for rather than the few
+    // milliseconds either side of it spent starting and stopping, unless
+    // they were cut short
+    let elapsed = match stage.length {
+        stages::Length::Time(duration) if !interrupt::interrupted() => duration,
+        stages::Length::Time(duration) => start.elapsed().min(duration),
+        stages::Length::Queries(_) => start.elapsed(),
+    };
+
+    for handle in handles {
+        handle.join().unwrap()
+    }
+
+    drop(inf_s);
+    informer.join().unwrap();
+
+    let (overall, per_target, intervals) = r.recv().unwrap();
+
+    // the handful of queries answered after the stage ended are left out of
+    // the time series, though not the totals
+    let intervals = intervals
+        .into_iter()
+        .filter(|(seq, _)| tick * *seq < elapsed)
+        .map(|(seq, mut interval)| {
+            let begin = tick * seq;
+            interval.offset = (begin + tick).min(elapsed);
+            interval.ended = started + interval.offset;
+            interval.length = interval.offset - begin;
+            interval
+        })
+        .collect();
+
+    StageResults {
+        overall,
+        per_target,
+        elapsed,
+        intervals,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use trust_dns_resolver::proto::rr::{Name, RData, Record};
+
+    // an answer to an A query for example.com, as an exchange that took
+    // `connect_time` to open a connection for
+    fn answer(connect_time: Option<Duration>) -> Result<wire::Exchange, wire::QueryError> {
+        let mut message = Message::new();
+        message.add_answer(Record::from_rdata(
+            Name::from_ascii("example.com.").unwrap(),
+            300,
+            RData::A("192.0.2.1".parse().unwrap()),
+        ));
+        // as it would arrive, with the header's counts filled in
+        let wire = message.to_vec().unwrap();
+        Ok(wire::Exchange {
+            message: Message::from_vec(&wire).unwrap(),
+            size: wire.len(),
+            attempts: 1,
+            connect_time,
+            truncated: false,
+            tcp_fallback: false,
+        })
+    }
+
+    #[test]
+    fn connect_time_is_left_out_of_latency() {
+        let mut details = RunDetails::default();
+        assert!(details.record(RecordType::A, &answer(None), 1_000, false, None));
+        assert!(details.record(
+            RecordType::A,
+            &answer(Some(Duration::from_nanos(4_000))),
+            5_000,
+            false,
+            None
+        ));
+
+        assert_eq!(details.total_latency, 2_000);
+        assert_eq!(details.latencies.max(), 1_000);
+        assert_eq!(details.total_connect_time, 4_000);
+        assert_eq!(details.connect_times.count(), 1);
+    }
+
+    #[test]
+    fn parses_dscps() {
+        let cases = [
+            ("0", 0),
+            ("46", 46),
+            ("63", 63),
+            ("ef", 46),
+            ("EF", 46),
+            ("va", 44),
+            ("le", 1),
+            ("cs0", 0),
+            ("cs1", 8),
+            ("CS7", 56),
+            ("af11", 10),
+            ("af12", 12),
+            ("af13", 14),
+            ("af21", 18),
+            ("af41", 34),
+            ("af43", 38),
+        ];
+
+        for (s, dscp) in cases {
+            assert_eq!(parse_dscp(s), Ok(dscp), "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_dscps() {
+        for s in [
+            "64", "255", "256", "-1", "", "af44", "af10", "af51", "af1", "af111", "cs8", "cs",
+            "cs01", "be", "ef1",
+        ] {
+            assert_eq!(
+                parse_dscp(s),
+                Err(format!(
+                    "invalid DSCP '{}': expected 0 to 63, ef, cs0-cs7, af11-af43, va or le",
+                    s
+                )),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("250us", Duration::from_micros(250)),
+            ("20ms", Duration::from_millis(20)),
+            ("1.5ms", Duration::from_micros(1500)),
+            ("10s", Duration::from_secs(10)),
+            ("5m", Duration::from_secs(300)),
+            ("100ns", Duration::from_nanos(100)),
+            ("0s", Duration::ZERO),
+        ];
+
+        for (s, duration) in cases {
+            assert_eq!(parse_duration(s), Ok(duration), "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        let cases = [
+            (
+                "10",
+                "invalid duration '10': missing unit (ns, us, ms, s, m)",
+            ),
+            ("", "invalid duration '': missing unit (ns, us, ms, s, m)"),
+            ("10h", "invalid duration '10h': unknown unit 'h'"),
+            ("10 s", "invalid duration '10 s': unknown unit ' s'"),
+            ("ms", "invalid duration 'ms'"),
+            ("1.2.3s", "invalid duration '1.2.3s'"),
+            ("-1s", "invalid duration '-1s'"),
+            // too long to fit in a Duration
+            (
+                "99999999999999999999999s",
+                "invalid duration '99999999999999999999999s'",
+            ),
+            (
+                "999999999999999999999m",
+                "invalid duration '999999999999999999999m'",
+            ),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(parse_duration(s), Err(err.to_string()), "{}", s);
+        }
+    }
+
+    // the latencies answered in each of a few seconds of a run, of uneven
+    // sizes, including an idle second
+    fn seconds() -> Vec<Vec<u128>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        [1000, 1, 0, 250, 2]
+            .iter()
+            .map(|len| {
+                (0..*len)
+                    .map(|_| rng.gen_range(1_000..10_000_000))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // records `latencies`, with a timeout after every tenth
+    fn record_all(details: &mut RunDetails, latencies: &[u128]) {
+        for (i, latency) in latencies.iter().enumerate() {
+            details.record(RecordType::A, &answer(None), *latency, false, None);
+            if i % 10 == 9 {
+                details.record(
+                    RecordType::A,
+                    &Err(wire::QueryError::Timeout),
+                    *latency,
+                    false,
+                    None,
+                );
+            }
+        }
+    }
+
+    // the seconds recorded one by one and merged, and all of them recorded
+    // at once
+    fn merged_and_whole() -> (RunDetails, RunDetails) {
+        let mut merged = RunDetails::default();
+        let mut whole = RunDetails::default();
+        for latencies in seconds() {
+            let mut second = RunDetails::default();
+            record_all(&mut second, &latencies);
+            merged += second;
+            record_all(&mut whole, &latencies);
+        }
+        (merged, whole)
+    }
+
+    #[test]
+    fn merging_seconds_keeps_the_mean_and_counts() {
+        let (merged, whole) = merged_and_whole();
+
+        assert_eq!(whole.successes, 1253);
+        assert_eq!(
+            (merged.successes, merged.failures, merged.latency_count),
+            (whole.successes, whole.failures, whole.latency_count)
+        );
+        assert_eq!(merged.failure_causes.timeout, whole.failure_causes.timeout);
+        assert_eq!(merged.rcodes, whole.rcodes);
+        assert_eq!(merged.total_latency, whole.total_latency);
+        assert_eq!(merged.mean_latency(), whole.mean_latency());
+        assert!((merged.latency_mean - whole.latency_mean).abs() < 1e-6);
+        assert_eq!(merged.latencies.count(), whole.latencies.count());
+    }
+
+    #[test]
+    fn merging_seconds_keeps_the_spread() {
+        let (merged, whole) = merged_and_whole();
+
+        assert!(whole.latency_stddev() > Duration::ZERO);
+        let difference = merged.latency_stddev().abs_diff(whole.latency_stddev());
+        assert!(difference <= Duration::from_nanos(1), "{:?}", difference);
+        assert_eq!(
+            (merged.latencies.min(), merged.latencies.max()),
+            (whole.latencies.min(), whole.latencies.max())
+        );
+        for percentile in [50.0, 99.0, 100.0] {
+            assert_eq!(
+                merged.latencies.percentile(percentile),
+                whole.latencies.percentile(percentile)
+            );
+        }
+
+        // merging into, or with, nothing changes nothing
+        let mut empty = RunDetails::default();
+        empty += whole.clone();
+        let mut merged = whole.clone();
+        merged += RunDetails::default();
+        for details in [empty, merged] {
+            assert_eq!(details.latency_stddev(), whole.latency_stddev());
+            assert_eq!(details.latencies.min(), whole.latencies.min());
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    fmt::{self, Write as _},
+    io::{self, Write},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+
+/// Logger prints events to stderr: progress as plain lines, problems prefixed
+/// with how serious they are, and debugging output with where it came from.
+struct Logger {
+    level: Level,
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // the libraries' own events are only wanted when tracing everything
+        metadata.level() <= &self.level
+            && (metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+                || self.level == Level::TRACE)
+    }
+
+    fn new_span(&self, _: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut line = match *metadata.level() {
+            Level::ERROR => "error: ".to_string(),
+            Level::WARN => "warning: ".to_string(),
+            Level::INFO => String::new(),
+            level => format!("{} {}: ", level, metadata.target()),
+        };
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        line.push_str(&fields.message);
+        line.push_str(&fields.rest);
+        line.push('\n');
+
+        // one write, so that lines from different threads don't interleave
+        let _ = io::stderr().write_all(line.as_bytes());
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+// an event's message, and its other fields as ` name=value` pairs
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).unwrap();
+        } else {
+            write!(self.rest, " {}={:?}", field.name(), value).unwrap();
+        }
+    }
+}
+
+/// Sends events at `level` and above to stderr for the rest of the run.
+pub fn init(level: Level) {
+    tracing::subscriber::set_global_default(Logger { level }).expect("logging was set up twice");
+}
@@ -1,143 +1,202 @@
 use std::{
-    net::SocketAddr,
-    ops::AddAssign,
-    sync::{
-        atomic::AtomicBool,
-        mpsc::{channel, sync_channel, Sender, SyncSender},
-        Arc, Mutex,
-    },
-    thread,
-    time::{Duration, Instant},
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use trust_dns_resolver::{
-    config::{NameServerConfig, ResolverConfig, ResolverOpts},
-    Name, Resolver,
-};
+use trust_dns_resolver::{proto::rr::RecordType, Name};
 
 use argh::FromArgs;
+use tracing::{debug, error, info, warn, Level};
 
-#[derive(Debug, Clone)]
-struct QueryConfig {
-    init_done: SyncSender<()>,
-    informer_sender: Sender<RunDetails>,
-    finished: Arc<AtomicBool>,
-    nameserver: SocketAddr,
-    host: Name,
-    timeout: Duration,
-    lock: Arc<Mutex<()>>,
-}
+// how many of the queries the nameservers disagree on are logged
+const CONSISTENCY_SAMPLES: usize = 10;
 
-#[derive(Clone, Copy, Debug)]
-struct RunDetails {
-    successes: u64,
-    failures: u64,
-    duration: u128,
-}
+// the least time given a resolver to answer for the QNAME minimization test
+// name, which it likely has to resolve from the root down
+const QNAME_MIN_TIMEOUT: Duration = Duration::from_secs(5);
 
-impl RunDetails {
-    fn reset(&mut self) {
-        self.successes = 0;
-        self.failures = 0;
-        self.duration = 0;
-    }
+// EDNS payload size offered by default when ANY or DNSSEC queries are made
+const LARGE_EDNS_PAYLOAD: u16 = 4096;
+
+// the long names of `nsbench run`'s options that also have a short one, which
+// is how --config finds them on the command line
+const SHORT_OPTIONS: &[(&str, &str)] = &[
+    ("-t", "--time-secs"),
+    ("-n", "--num-queries"),
+    ("-l", "--cpus"),
+    ("-p", "--port"),
+    ("-v", "--verbose"),
+    ("-q", "--quiet"),
+];
+
+// the rows in the latency histogram in the summary, and the width of its
+// longest bar
+const HISTOGRAM_ROWS: usize = 20;
+const HISTOGRAM_WIDTH: usize = 40;
+
+use ipnet::IpNet;
+
+mod agent;
+mod api;
+mod assertions;
+mod baseline;
+mod cacheprobe;
+mod config;
+mod controller;
+mod diff;
+mod html;
+mod junit;
+mod logging;
+mod markdown;
+mod report;
+mod script;
+
+use nsbench::{
+    affinity, consistency, control, expect, failure_causes, failurelog, influx, interrupt,
+    mbit_per_second, otlp, parse_dscp, parse_duration, parse_record_type, per_second, prometheus,
+    qnamemin, rate, rcode_name, remote, socks, stage_load, stages, statsd, trace, tui, wire,
+    workload, CheckpointHook, Config, IntervalHook, RunDetails, StageHook, Transport,
+    LATENCY_PERCENTILES, TTL_RANGES,
+};
+
+/// Format is how the summary is printed: as text for people, as a JSON
+/// document for scripts, or as Markdown tables for pull requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Markdown,
 }
 
-impl Default for RunDetails {
-    fn default() -> Self {
-        Self {
-            successes: 0,
-            failures: 0,
-            duration: 0,
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err(format!(
+                "invalid format '{}': expected text, json or markdown",
+                s
+            )),
         }
     }
 }
 
-impl AddAssign<RunDetails> for RunDetails {
-    fn add_assign(&mut self, rhs: RunDetails) {
-        self.successes += rhs.successes;
-        if self.successes > 0 {
-            self.duration = (rhs.duration + self.duration) / self.successes as u128;
+#[derive(Debug, Clone, Copy)]
+struct NameserverAddr {
+    ip: IpAddr,
+    port: Option<u16>,
+}
+
+impl NameserverAddr {
+    fn socket_addr(&self, port: Option<u16>) -> Result<SocketAddr, String> {
+        match (self.port, port) {
+            (Some(ours), Some(theirs)) if ours != theirs => Err(format!(
+                "nameserver port {} conflicts with --port {}",
+                ours, theirs
+            )),
+            (Some(port), _) | (None, Some(port)) => Ok(SocketAddr::new(self.ip, port)),
+            (None, None) => Ok(SocketAddr::new(self.ip, 53)),
         }
-        self.failures += rhs.failures;
     }
 }
 
-fn perform_queries(qc: QueryConfig) {
-    let mut resolver_config = ResolverConfig::new();
-    resolver_config.add_name_server(NameServerConfig {
-        socket_addr: qc.nameserver,
-        protocol: trust_dns_resolver::config::Protocol::Udp,
-        tls_dns_name: None,
-        trust_nx_responses: true,
-        bind_addr: None,
-    });
+impl FromStr for NameserverAddr {
+    type Err = String;
 
-    let mut opts = ResolverOpts::default();
-    opts.rotate = false;
-    opts.cache_size = 0;
-    opts.timeout = qc.timeout;
-    opts.positive_min_ttl = Some(Duration::new(0, 0));
-    opts.positive_max_ttl = Some(Duration::new(0, 0));
-    opts.negative_min_ttl = Some(Duration::new(0, 0));
-    opts.negative_max_ttl = Some(Duration::new(0, 0));
-
-    let resolver = Resolver::new(resolver_config, opts).unwrap();
-
-    let ret = RunDetails::default();
-    let details = Arc::new(Mutex::new(ret));
-
-    let informer_details = details.clone();
-    let informer_finished_parent = Arc::new(AtomicBool::new(false));
-    let informer_finished = informer_finished_parent.clone();
-    let informer_sender = qc.informer_sender.clone();
-
-    let informer = thread::spawn(move || {
-        let tick = std::time::Duration::new(1, 0);
-        while !informer_finished.load(std::sync::atomic::Ordering::Relaxed) {
-            thread::sleep(tick);
-            let mut details = informer_details.lock().unwrap();
-            informer_sender.send(details.clone()).unwrap();
-            details.reset();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self {
+                ip: addr.ip(),
+                port: Some(addr.port()),
+            });
         }
-    });
-
-    qc.init_done.send(()).unwrap();
-    drop(qc.lock.lock().unwrap());
 
-    while !qc.finished.load(std::sync::atomic::Ordering::Relaxed) {
-        let now = Instant::now();
-        if resolver
-            .lookup(
-                qc.host.clone(),
-                trust_dns_resolver::proto::rr::RecordType::A,
-            )
-            .is_ok()
-        {
-            let mut writer = details.lock().unwrap();
-            writer.successes += 1;
-            let current = Instant::now().duration_since(now).as_nanos();
-            writer.duration += current;
-        } else {
-            let mut writer = details.lock().unwrap();
-            writer.failures += 1
+        match s.parse::<IpAddr>() {
+            Ok(ip) => Ok(Self { ip, port: None }),
+            Err(_) => Err(format!(
+                "invalid nameserver '{}': expected ip or ip:port",
+                s
+            )),
         }
     }
+}
+
+#[derive(Debug, Clone)]
+struct Nameservers(Vec<NameserverAddr>);
+
+impl Nameservers {
+    fn socket_addrs(&self, port: Option<u16>) -> Result<Vec<SocketAddr>, String> {
+        self.0.iter().map(|ns| ns.socket_addr(port)).collect()
+    }
+}
+
+impl FromStr for Nameservers {
+    type Err = String;
 
-    informer_finished_parent.store(true, std::sync::atomic::Ordering::Relaxed);
-    informer.join().unwrap();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(
+            s.split(',')
+                .map(|ns| ns.trim().parse())
+                .collect::<Result<_, _>>()?,
+        ))
+    }
 }
 
-#[derive(FromArgs, Clone, Debug)]
+#[derive(FromArgs, Debug)]
 #[argh(description = "Nameserver benchmarking/flooding tool")]
 struct CLIArguments {
+    #[argh(subcommand)]
+    command: Command,
+}
+
+// parsed once, so the size of the run arguments doesn't matter, and argh can't
+// parse into a Box
+#[allow(clippy::large_enum_variant)]
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum Command {
+    Run(RunArguments),
+    Replay(ReplayArguments),
+    Diff(diff::DiffArguments),
+    CacheProbe(cacheprobe::CacheProbeArguments),
+    Agent(agent::AgentArguments),
+    Controller(controller::ControllerArguments),
+    Api(api::ApiArguments),
+}
+
+#[derive(FromArgs, Clone, Debug)]
+#[argh(
+    subcommand,
+    name = "run",
+    description = "benchmark nameservers with generated queries"
+)]
+struct RunArguments {
+    #[argh(
+        option,
+        description = "TOML file of settings to run with, named after these options; options given here override it. Tables, strings, numbers, booleans and arrays are supported; dotted keys, inline tables, multi-line strings, \\u escapes and counts like verbose = 2 are not"
+    )]
+    config: Option<PathBuf>,
+
     #[argh(
         option,
         short = 't',
-        description = "time in seconds to run the test",
-        default = "60"
+        description = "time in seconds to run the test (default 60)"
+    )]
+    time_secs: Option<u64>,
+
+    #[argh(
+        option,
+        short = 'n',
+        description = "stop after this many queries per nameserver have been answered or timed out, instead of after -t"
     )]
-    time_secs: u64,
+    num_queries: Option<u64>,
 
     #[argh(
         option,
@@ -147,6 +206,89 @@ struct CLIArguments {
     )]
     cpus: usize,
 
+    #[argh(
+        switch,
+        description = "pin each worker thread to a core of its own, and the thread that collects their results to the first core when there are enough (Linux only)"
+    )]
+    pin_cores: bool,
+
+    #[argh(
+        option,
+        description = "pin the workers to the cores of this NUMA node, e.g. the one the network card is attached to (Linux only)"
+    )]
+    numa_node: Option<usize>,
+
+    #[argh(
+        option,
+        description = "hold the query rate to this many queries per second per nameserver, instead of as fast as possible"
+    )]
+    qps: Option<u64>,
+
+    #[argh(
+        switch,
+        description = "send at the --qps rate on a fixed schedule without waiting for answers, measuring latency from the scheduled send time"
+    )]
+    open_loop: bool,
+
+    #[argh(
+        option,
+        description = "how rate limited queries are spaced: fixed (evenly) or poisson (exponential gaps averaging the rate) (default fixed)",
+        default = "rate::Arrival::Fixed"
+    )]
+    arrival: rate::Arrival,
+
+    #[argh(
+        option,
+        description = "send <size> queries back to back at the start of every <interval>, e.g. 500:100ms, then sit idle"
+    )]
+    burst: Option<rate::Burst>,
+
+    #[argh(
+        option,
+        description = "increase the query rate linearly, as <start qps>:<end qps>:<seconds>, then hold at the end rate"
+    )]
+    ramp: Option<rate::Ramp>,
+
+    #[argh(
+        option,
+        description = "swing the query rate smoothly up and down, as <min qps>:<max qps>:<period seconds>"
+    )]
+    wave: Option<rate::Wave>,
+
+    #[argh(
+        option,
+        description = "send queries for this long before each stage without counting them, e.g. 10s",
+        from_str_fn(parse_duration)
+    )]
+    warmup: Option<Duration>,
+
+    #[argh(
+        option,
+        description = "how often each worker is expected to send, e.g. 1ms; answers slower than this also record the latencies the queries stuck behind them would have seen",
+        from_str_fn(parse_duration)
+    )]
+    expected_interval: Option<Duration>,
+
+    #[argh(
+        option,
+        description = "keep this many queries in flight per nameserver, spread over the workers, instead of one per worker (udp only, unless --async)"
+    )]
+    concurrency: Option<usize>,
+
+    #[argh(
+        switch,
+        long = "async",
+        description = "run the --concurrency queries as tokio tasks, each with its own socket or TCP connection, so that retries, tcp and the tcp fallback work"
+    )]
+    tasks: bool,
+
+    #[argh(
+        option,
+        description = "spread each worker's queries over this many sockets, and so source ports (default 1)",
+        default = "1"
+    )]
+    sockets_per_worker: usize,
+
     #[argh(
         option,
         description = "duration to wait (in ns) before considering a request failed",
@@ -154,94 +296,2142 @@ struct CLIArguments {
     )]
     timeout: u32,
 
+    #[argh(
+        option,
+        description = "send a UDP query that times out again up to this many times before counting it failed (default 0)",
+        default = "0"
+    )]
+    retries: u32,
+
+    #[argh(
+        option,
+        description = "transport to send queries over: udp or tcp (default udp)",
+        default = "Transport::Udp"
+    )]
+    protocol: Transport,
+
+    #[argh(switch, description = "do not retry truncated UDP responses over TCP")]
+    no_tcp_fallback: bool,
+
+    #[argh(
+        option,
+        short = 'p',
+        description = "port to contact the nameserver on (default 53)"
+    )]
+    port: Option<u16>,
+
+    #[argh(
+        option,
+        description = "local address to send queries from (default any)"
+    )]
+    bind: Option<IpAddr>,
+
+    #[argh(
+        option,
+        description = "TTL (hop limit over IPv6) of the packets sent, e.g. to have them die a few hops out in a lab (default the system's)"
+    )]
+    ip_ttl: Option<u32>,
+
+    #[argh(
+        option,
+        description = "mark the packets sent with this DiffServ codepoint, as 0-63 or a name such as ef, af41, cs1 or le (the scavenger class)",
+        from_str_fn(parse_dscp)
+    )]
+    dscp: Option<u8>,
+
+    #[argh(
+        option,
+        description = "socks5://host:port proxy to send TCP queries through"
+    )]
+    proxy: Option<socks::Proxy>,
+
+    #[argh(
+        option,
+        description = "record type to query: A, AAAA, MX, TXT, NS, SOA, SRV, PTR, CAA... (default A)",
+        from_str_fn(parse_record_type)
+    )]
+    qtype: Option<RecordType>,
+
+    #[argh(
+        option,
+        description = "weighted mix of record types to query, e.g. A:60,AAAA:30,HTTPS:10"
+    )]
+    qtype_mix: Option<workload::QtypeMix>,
+
     #[argh(
         positional,
-        description = "socketaddr (127.0.0.1:53) to contact for DNS queries"
+        description = "ip (127.0.0.1) or socketaddr (127.0.0.1:53) to contact for DNS queries; separate several with commas to compare them"
     )]
-    nameserver: SocketAddr,
+    nameserver: Nameservers,
 
-    #[argh(positional, description = "hostname to request A records for")]
-    host: Name,
-}
+    #[argh(
+        option,
+        description = "add EDNS to queries, advertising this UDP payload size (default off, 4096 for ANY and --dnssec)"
+    )]
+    edns_size: Option<u16>,
 
-fn main() {
-    let args: CLIArguments = argh::from_env();
-
-    let mut handles = Vec::new();
-    let (s, r) = sync_channel(args.cpus);
-    let (init_s, init_r) = sync_channel(args.cpus);
-    let (inf_s, inf_r) = channel();
-    let finished = Arc::new(AtomicBool::new(false));
-    let lock = Arc::new(Mutex::new(()));
-
-    let mg = lock.lock().unwrap();
-
-    for _ in 0..args.cpus {
-        let qc = QueryConfig {
-            init_done: init_s.clone(),
-            informer_sender: inf_s.clone(),
-            finished: finished.clone(),
-            nameserver: args.nameserver.clone(),
-            host: args.host.clone(),
-            timeout: Duration::new(0, args.timeout),
-            lock: lock.clone(),
-        };
+    #[argh(
+        switch,
+        description = "set the DO bit to ask for DNSSEC records, and report how many answers were validated (AD bit) or failed validation, and the latency of each"
+    )]
+    dnssec: bool,
 
-        handles.push(std::thread::spawn(move || perform_queries(qc)));
-    }
+    #[argh(
+        option,
+        description = "attach an EDNS Client Subnet option for this subnet to every query, e.g. 203.0.113.0/24, and report the scopes answers hold for"
+    )]
+    ecs: Option<IpNet>,
 
-    for _ in 0..args.cpus {
-        init_r.recv().unwrap();
-    }
+    #[argh(
+        switch,
+        description = "query a fresh random label under <host> every time, to defeat caching"
+    )]
+    random_prefix: bool,
 
-    let informer = thread::spawn(move || {
-        let mut totals = RunDetails::default();
-        let mut temp_total = RunDetails::default();
-        let mut start = Instant::now();
-        while let Ok(details) = inf_r.recv() {
-            totals += details;
-            temp_total += details;
+    #[argh(
+        switch,
+        description = "flood random nonexistent names under <host>, counting NXDOMAIN as success"
+    )]
+    nxdomain: bool,
 
-            if Instant::now().duration_since(start).as_secs() > 1 {
-                eprintln!(
-                    "1s avg latency: {:?} | Successes: {} | Failures: {} | Total Req: {}",
-                    Duration::from_nanos(temp_total.duration as u64),
-                    temp_total.successes,
-                    temp_total.failures,
-                    temp_total.successes + temp_total.failures,
-                );
+    #[argh(
+        option,
+        description = "an answer a response must carry to count as a success, e.g. an address; can be repeated, and any other answer is a wrong answer failure"
+    )]
+    expect: Vec<String>,
 
-                start = Instant::now();
-                temp_total = RunDetails::default();
-            }
-        }
+    #[argh(
+        option,
+        description = "file of names followed by the answers expected for each, which take the place of --expect for those names"
+    )]
+    expect_file: Option<PathBuf>,
 
-        s.send(totals).unwrap()
-    });
+    #[argh(
+        option,
+        description = "count responses with fewer than this many records of the type asked for as wrong answer failures"
+    )]
+    min_answers: Option<usize>,
 
-    drop(mg);
+    #[argh(
+        option,
+        description = "header flags a response must have to count as a success, e.g. aa,!tc; each prefixed with ! must be clear instead, and a response without them is a wrong flags failure"
+    )]
+    expect_flags: Option<expect::Flags>,
 
-    std::thread::sleep(Duration::new(args.time_secs, 0));
-    finished.store(true, std::sync::atomic::Ordering::Release);
+    #[argh(
+        option,
+        description = "file of hostnames to query, one per line, instead of <host>"
+    )]
+    query_file: Option<PathBuf>,
 
-    for handle in handles {
-        handle.join().unwrap()
-    }
+    #[argh(
+        option,
+        description = "dnsperf-style datafile of 'name type' lines to query, instead of <host>"
+    )]
+    datafile: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "BIND zone file whose owner names and types are queried, instead of <host>"
+    )]
+    zone_file: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "generate names from a template with {{seq}}, {{thread}} and {{rand:N}} placeholders, instead of <host>"
+    )]
+    name_template: Option<workload::NameTemplate>,
+
+    #[argh(
+        option,
+        description = "fraction of queries (0.0-1.0) that repeat a name already asked, to control the cache hit rate (default 0)",
+        default = "0.0"
+    )]
+    repeat_ratio: f64,
 
-    drop(inf_s);
-    informer.join().unwrap();
+    #[argh(
+        option,
+        description = "query PTR records for every address in a CIDR range, instead of <host>"
+    )]
+    ptr_range: Option<IpNet>,
 
-    let overall = r.recv().unwrap();
+    #[argh(
+        switch,
+        description = "shuffle the names from --query-file, --datafile or --zone-file before the run"
+    )]
+    shuffle: bool,
 
-    println!("Nameserver: {}", args.nameserver);
-    println!("Host: {}", args.host);
-    println!("CPUs Used: {}", args.cpus);
-    println!("Successes: {}", overall.successes);
-    println!("Failures: {}", overall.failures);
-    println!(
-        "Success Rate: {:.02}%",
-        (overall.successes as f64 / (overall.successes + overall.failures) as f64) * 100.0,
-    );
-    println!("Runtime: {}s", args.time_secs);
-    println!("Requests: {}/s", overall.successes / args.time_secs);
+    #[argh(
+        option,
+        description = "how names are picked from --query-file, --datafile or --zone-file: round-robin, uniform or zipf:<s> (default round-robin)",
+        default = "workload::NameDistribution::RoundRobin"
+    )]
+    distribution: workload::NameDistribution,
+
+    #[argh(
+        option,
+        description = "before the run, ask every nameserver the same first <n> queries of the workload and report any they answer differently (RCODE or records)"
+    )]
+    consistency: Option<usize>,
+
+    #[argh(
+        switch,
+        description = "before the run, ask every nameserver for internet.nl's QNAME minimization test name and report whether it minimizes the names it sends upstream"
+    )]
+    qname_min: bool,
+
+    #[argh(
+        option,
+        description = "file of load stages (duration, rate, record types) to run in order, instead of -t and --qps"
+    )]
+    stages: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "file of rules that change the rate and record types of the next stage, or repeat a stage or stop the run, by what each stage measured"
+    )]
+    script: Option<PathBuf>,
+
+    #[argh(
+        switch,
+        description = "print the results of every reporting interval at the end of the run"
+    )]
+    timeseries: bool,
+
+    #[argh(
+        switch,
+        description = "show a live dashboard of the rate, success rate and latency instead of a line every second (stdout and stderr must be a terminal)"
+    )]
+    tui: bool,
+
+    #[argh(
+        option,
+        description = "write the results of every reporting interval to this file as CSV"
+    )]
+    csv: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "write the results of every reporting interval to this file (or - for stdout) as a JSON line as soon as it is over"
+    )]
+    ndjson: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "keep the JSON summary of the run so far, with its full latency histogram, in this file, rewritten every --checkpoint-interval"
+    )]
+    checkpoint: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "how often to rewrite the --checkpoint file, e.g. 5m (default 60s)",
+        from_str_fn(parse_duration),
+        default = "Duration::from_secs(60)"
+    )]
+    checkpoint_interval: Duration,
+
+    #[argh(
+        option,
+        description = "write every query's send time, name, type, response code and latency to this CSV file, compressed with the gzip or zstd command, which must be installed, if it ends in .gz or .zst"
+    )]
+    trace: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "write the summary, with charts of the time series and latency distribution, to this HTML file"
+    )]
+    report: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "write the results and the outcome of every --assert and --check-baseline to this file as a JUnit XML test report"
+    )]
+    junit: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "serve live metrics for Prometheus at /metrics on this address while the run goes on, e.g. :9100"
+    )]
+    prometheus: Option<String>,
+
+    #[argh(
+        option,
+        description = "send each second's counters and latencies to this StatsD server, with DogStatsD tags, e.g. localhost:8125"
+    )]
+    statsd: Option<String>,
+
+    #[argh(
+        option,
+        description = "write each second's results in InfluxDB line protocol to this http:// write URL or file; set INFLUX_TOKEN to authenticate"
+    )]
+    influx: Option<String>,
+
+    #[argh(
+        option,
+        description = "tag the --influx points with this run ID instead of a random one"
+    )]
+    run_id: Option<String>,
+
+    #[argh(
+        option,
+        description = "export counters and latency histograms over OTLP/HTTP to this collector every second, e.g. http://localhost:4318; headers are taken from OTEL_EXPORTER_OTLP_HEADERS"
+    )]
+    otlp: Option<String>,
+
+    #[argh(
+        option,
+        description = "take commands to steer the run (set qps <n>, summary, stop) on this Unix socket path or TCP address, e.g. :7300"
+    )]
+    control: Option<control::Addr>,
+
+    #[argh(
+        option,
+        description = "how to print the summary: text, json or markdown (default text)",
+        default = "Format::Text"
+    )]
+    format: Format,
+
+    #[argh(
+        option,
+        description = "write the --format output to this file, and print the text summary as well"
+    )]
+    out: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "check the final results, e.g. p99<5ms or success_rate>99.9, and exit with status 2 if any check fails; can be repeated"
+    )]
+    assert: Vec<assertions::Assertion>,
+
+    #[argh(
+        option,
+        description = "save the results as the baseline with this name, for --check-baseline to compare later runs against"
+    )]
+    save_baseline: Option<String>,
+
+    #[argh(
+        option,
+        description = "compare the results with the baseline of this name, and exit with status 2 if the request rate or tail latency regressed"
+    )]
+    check_baseline: Option<String>,
+
+    #[argh(
+        option,
+        description = "how far --check-baseline lets the request rate drop or the tail latency rise before failing, e.g. 10% (default 5%)"
+    )]
+    tolerance: Option<baseline::Tolerance>,
+
+    #[argh(
+        option,
+        description = "search for the highest rate whose p99 latency stays within this SLO, e.g. 20ms",
+        from_str_fn(parse_duration)
+    )]
+    find_max: Option<Duration>,
+
+    #[argh(
+        option,
+        description = "length in seconds of each --find-max probe (default 10)",
+        default = "10"
+    )]
+    probe_time: u64,
+
+    #[argh(
+        option,
+        description = "log up to this many failed queries a second, with their name, cause and how long they took (default 5, 0 for none)",
+        default = "5"
+    )]
+    failure_samples: u32,
+
+    #[argh(
+        option,
+        hidden_help,
+        description = "write everything the run measured to this file, for the agent that started it"
+    )]
+    results_file: Option<PathBuf>,
+
+    #[argh(
+        switch,
+        short = 'v',
+        description = "log more: every failed query with -v, and the DNS library's own events as well with -v -v"
+    )]
+    verbose: u8,
+
+    #[argh(
+        switch,
+        short = 'q',
+        description = "log only warnings and errors, leaving out the per-second lines; the summary is still printed"
+    )]
+    quiet: bool,
+
+    #[argh(positional, description = "hostname to query")]
+    host: Option<Name>,
+}
+
+#[derive(FromArgs, Clone, Debug)]
+#[argh(
+    subcommand,
+    name = "replay",
+    description = "benchmark nameservers with the UDP DNS queries in a pcap capture, looped until the run ends"
+)]
+struct ReplayArguments {
+    #[argh(
+        option,
+        short = 't',
+        description = "time in seconds to run the test (default 60)"
+    )]
+    time_secs: Option<u64>,
+
+    #[argh(
+        option,
+        short = 'n',
+        description = "stop after this many queries per nameserver have been answered or timed out, instead of after -t"
+    )]
+    num_queries: Option<u64>,
+
+    #[argh(
+        option,
+        short = 'l',
+        description = "limit the number of CPUs (default off)",
+        default = "num_cpus::get()"
+    )]
+    cpus: usize,
+
+    #[argh(
+        option,
+        description = "hold the query rate to this many queries per second per nameserver, instead of as fast as possible"
+    )]
+    qps: Option<u64>,
+
+    #[argh(
+        option,
+        description = "send on the captured timing, scaled by this factor (2.0 is twice as fast); default is as fast as possible"
+    )]
+    speed: Option<f64>,
+
+    #[argh(
+        option,
+        description = "duration to wait (in ns) before considering a request failed",
+        default = "100000000"
+    )]
+    timeout: u32,
+
+    #[argh(
+        option,
+        short = 'p',
+        description = "port to contact the nameserver on (default 53)"
+    )]
+    port: Option<u16>,
+
+    #[argh(
+        option,
+        description = "how the captured queries are picked: round-robin, uniform or zipf:<s> (default round-robin)",
+        default = "workload::NameDistribution::RoundRobin"
+    )]
+    distribution: workload::NameDistribution,
+
+    #[argh(
+        option,
+        description = "how to print the summary: text, json or markdown (default text)",
+        default = "Format::Text"
+    )]
+    format: Format,
+
+    #[argh(
+        option,
+        description = "write the --format output to this file, and print the text summary as well"
+    )]
+    out: Option<PathBuf>,
+
+    #[argh(
+        switch,
+        short = 'v',
+        description = "log more: every failed query with -v, and the DNS library's own events as well with -v -v"
+    )]
+    verbose: u8,
+
+    #[argh(
+        switch,
+        short = 'q',
+        description = "log only warnings and errors, leaving out the per-second lines; the summary is still printed"
+    )]
+    quiet: bool,
+
+    #[argh(
+        positional,
+        description = "ip (127.0.0.1) or socketaddr (127.0.0.1:53) to contact for DNS queries; separate several with commas to compare them"
+    )]
+    nameserver: Nameservers,
+
+    #[argh(positional, description = "pcap file to replay")]
+    capture: PathBuf,
+}
+
+impl ReplayArguments {
+    // the run these arguments describe, with every option a replay doesn't
+    // take left at its default
+    fn run_arguments(&self) -> RunArguments {
+        // argh can only fill in defaults while parsing, so parse a command line
+        // that gives nothing else; the nameserver is replaced below
+        let mut args = RunArguments::from_args(&["nsbench run"], &["127.0.0.1"])
+            .expect("the defaults of nsbench run do not parse");
+
+        args.time_secs = self.time_secs;
+        args.num_queries = self.num_queries;
+        args.cpus = self.cpus;
+        args.qps = self.qps;
+        args.timeout = self.timeout;
+        args.port = self.port;
+        args.distribution = self.distribution;
+        args.format = self.format;
+        args.out = self.out.clone();
+        args.verbose = self.verbose;
+        args.quiet = self.quiet;
+        args.nameserver = self.nameserver.clone();
+        args
+    }
+}
+
+// prints how many successful queries took each latency as a bar chart, with
+// rows spaced logarithmically from the fastest to the slowest, so that a long
+// tail or a second peak stands out
+fn print_latency_histogram(details: &RunDetails) {
+    let bins = details.latencies.log_bins(HISTOGRAM_ROWS);
+    let tallest = bins
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    println!("{:<12} {:>12}", "Latency", "Queries");
+    for (top, count) in bins {
+        let mut width = (count as f64 / tallest as f64 * HISTOGRAM_WIDTH as f64).round() as usize;
+        if count > 0 {
+            // so that even a handful of outliers show up
+            width = width.max(1);
+        }
+
+        let row = format!(
+            "{:<12} {:>12} {}",
+            format!("<= {:.2?}", Duration::from_nanos(top)),
+            count,
+            "#".repeat(width)
+        );
+        println!("{}", row.trim_end());
+    }
+}
+
+// logs how each assertion fared, and exits with status 2 if any failed
+fn finish(verdicts: &[assertions::Verdict]) {
+    for verdict in verdicts {
+        if verdict.passed {
+            info!(
+                "assertion {} passed ({})",
+                verdict.assertion, verdict.actual
+            );
+        } else {
+            error!(
+                "assertion {} failed: was {}",
+                verdict.assertion, verdict.actual
+            );
+        }
+    }
+
+    if verdicts.iter().any(|verdict| !verdict.passed) {
+        std::process::exit(2);
+    }
+}
+
+// the share of responses that were truncated, in parentheses, or nothing if
+// none were
+fn truncation_rate(details: &RunDetails) -> String {
+    if details.truncated == 0 {
+        return String::new();
+    }
+
+    let responses: u64 = details.rcodes.values().sum();
+    format!(
+        " ({:.02}% of responses)",
+        details.truncated as f64 / responses as f64 * 100.0
+    )
+}
+
+fn main() {
+    let argv = with_config(std::env::args().collect());
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    // as argh::from_env does, which can't be used with the merged command line
+    let command = Path::new(argv[0])
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(argv[0]);
+    let args =
+        CLIArguments::from_args(&[command], &argv[1..]).unwrap_or_else(|exit| match exit.status {
+            Ok(()) => {
+                println!("{}", exit.output);
+                std::process::exit(0);
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {} --help for more information.",
+                    exit.output, command
+                );
+                std::process::exit(1);
+            }
+        });
+
+    match args.command {
+        Command::Run(args) => run(args, None, None),
+        Command::Replay(replay) => run(replay.run_arguments(), Some(&replay), None),
+        Command::Diff(args) => diff::main(args),
+        Command::CacheProbe(args) => cacheprobe::main(args),
+        Command::Agent(args) => agent::main(args),
+        Command::Api(args) => api::main(args),
+        Command::Controller(controller) => {
+            // the arguments for the agents are those of `nsbench run`, config
+            // file and all
+            let argv = with_config(
+                ["nsbench", "run"]
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .chain(controller.args.iter().cloned())
+                    .collect(),
+            );
+            let run_argv: Vec<&str> = argv[2..].iter().map(String::as_str).collect();
+            let args =
+                RunArguments::from_args(&["nsbench run"], &run_argv).unwrap_or_else(|exit| {
+                    eprintln!("{}", exit.output.trim_end());
+                    std::process::exit(if exit.status.is_ok() { 0 } else { 1 });
+                });
+            run(
+                args,
+                None,
+                Some(&controller::Controller::new(&controller, &argv[2..])),
+            )
+        }
+    }
+}
+
+// returns the command line with the settings from the file given to
+// `nsbench run --config`, if any, filled in beneath its own. This has to
+// happen before argh parses it, as the file may give the nameserver.
+fn with_config(argv: Vec<String>) -> Vec<String> {
+    if argv.get(1).map(String::as_str) != Some("run") {
+        return argv;
+    }
+    let path = match argv.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < argv.len() => PathBuf::from(&argv[i + 1]),
+        _ => return argv,
+    };
+
+    // logging isn't set up until the merged arguments say how much to log
+    let config = config::Config::load(&path).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+
+    // argh names the options given, however they were spelled, only once the
+    // arguments parse, which needs a nameserver; lend one if that's missing
+    let run: Vec<&str> = argv[2..].iter().map(String::as_str).collect();
+    let given = RunArguments::redact_arg_values(&["nsbench run"], &run).or_else(|_| {
+        let mut lent = run.clone();
+        lent.push("127.0.0.1");
+        RunArguments::redact_arg_values(&["nsbench run"], &lent).map(|mut given| {
+            given.retain(|arg| arg != "nameserver");
+            given
+        })
+    });
+    // leave whatever is wrong with the command line for argh to report
+    let given: Vec<String> = match given {
+        Ok(given) => given
+            .into_iter()
+            .map(
+                |arg| match SHORT_OPTIONS.iter().find(|(short, _)| *short == arg) {
+                    Some((_, long)) => long.to_string(),
+                    None => arg,
+                },
+            )
+            .collect(),
+        Err(_) => return argv,
+    };
+
+    let merged = config.merge(&argv[2..], &given);
+    let args: Vec<&str> = merged.iter().map(String::as_str).collect();
+    if let Err(exit) = RunArguments::redact_arg_values(&["nsbench run"], &args) {
+        eprintln!("error: {}: {}", path.display(), exit.output.trim_end());
+        std::process::exit(1);
+    }
+
+    argv[..2].iter().cloned().chain(merged).collect()
+}
+
+// runs the benchmark `args` describe, sending the queries captured in
+// `replay` if it is given, or has `controller`'s agents run it if that is
+// checks that the options given to `nsbench run` make sense together, before
+// anything is read or opened for the run
+fn validate(args: &RunArguments) -> Result<(), String> {
+    if args.quiet && args.verbose > 0 {
+        return Err("-q and -v cannot be used together".into());
+    }
+
+    if args.ip_ttl.is_some_and(|ttl| !(1..=255).contains(&ttl)) {
+        return Err("--ip-ttl must be between 1 and 255".into());
+    }
+
+    if args.proxy.is_some() && args.protocol != Transport::Tcp {
+        return Err("--proxy requires --protocol tcp".into());
+    }
+
+    if args.qtype.is_some() && args.qtype_mix.is_some() {
+        return Err("--qtype and --qtype-mix cannot be used together".into());
+    }
+
+    if args.ramp.is_some() && args.wave.is_some() {
+        return Err("--ramp and --wave cannot be used together".into());
+    }
+    let profile = args.ramp.is_some() || args.wave.is_some();
+
+    if args.open_loop {
+        if args.qps.is_none() && args.stages.is_none() {
+            return Err("--open-loop requires --qps".into());
+        }
+
+        if args.protocol != Transport::Udp {
+            return Err("--open-loop only supports --protocol udp".into());
+        }
+    }
+
+    if args.arrival != rate::Arrival::Fixed
+        && args.qps.is_none()
+        && !profile
+        && args.stages.is_none()
+        && args.find_max.is_none()
+    {
+        return Err("--arrival requires --qps, --ramp, --wave, --stages or --find-max".into());
+    }
+
+    if args.burst.is_some()
+        && (args.qps.is_some()
+            || profile
+            || args.stages.is_some()
+            || args.find_max.is_some()
+            || args.open_loop
+            || args.arrival != rate::Arrival::Fixed)
+    {
+        return Err("--burst cannot be used with --qps, --ramp, --wave, --stages, --find-max, --open-loop or --arrival".into());
+    }
+
+    if let Some(concurrency) = args.concurrency {
+        if concurrency < args.cpus {
+            return Err("--concurrency must be at least the number of CPUs (-l)".into());
+        }
+
+        // the queries a worker multiplexes over its sockets are told apart by
+        // their 16-bit ids
+        if !args.tasks && concurrency.div_ceil(args.cpus) > u16::MAX as usize {
+            return Err("--concurrency can keep at most 65535 queries in flight per worker without --async; raise -l".into());
+        }
+
+        if args.protocol != Transport::Udp && !args.tasks {
+            return Err("--concurrency only supports --protocol udp without --async".into());
+        }
+
+        if args.open_loop {
+            return Err("--concurrency cannot be used with --open-loop".into());
+        }
+    }
+
+    if args.tasks {
+        if args.concurrency.is_none() {
+            return Err("--async needs --concurrency".into());
+        }
+
+        if args.proxy.is_some() {
+            return Err("--async cannot be used with --proxy".into());
+        }
+
+        if args.sockets_per_worker > 1 {
+            return Err("--async cannot be used with --sockets-per-worker, as every query has a socket of its own".into());
+        }
+    }
+
+    if args.sockets_per_worker == 0 {
+        return Err("--sockets-per-worker must be above zero".into());
+    }
+
+    if args.sockets_per_worker > 1 && args.concurrency.is_some() && !cfg!(target_os = "linux") {
+        return Err("--sockets-per-worker with --concurrency is only supported on Linux".into());
+    }
+
+    if let Some(interval) = args.expected_interval {
+        if interval.is_zero() {
+            return Err("--expected-interval must be above zero".into());
+        }
+
+        if args.open_loop || args.concurrency.is_some() {
+            return Err(
+                "--expected-interval cannot be used with --open-loop or --concurrency".into(),
+            );
+        }
+    }
+
+    if args.retries > 0 && (args.open_loop || (args.concurrency.is_some() && !args.tasks)) {
+        return Err(
+            "--retries cannot be used with --open-loop, or --concurrency without --async".into(),
+        );
+    }
+
+    if profile {
+        if args.qps.is_some() {
+            return Err("--ramp and --wave cannot be used with --qps".into());
+        }
+
+        if args.open_loop {
+            return Err("--ramp and --wave cannot be used with --open-loop".into());
+        }
+    }
+
+    if !(0.0..=1.0).contains(&args.repeat_ratio) {
+        return Err("--repeat-ratio must be between 0.0 and 1.0".into());
+    }
+
+    if args.time_secs.is_some() && args.num_queries.is_some() {
+        return Err("-t and -n cannot be used together".into());
+    }
+
+    if args.nxdomain
+        && (!args.expect.is_empty() || args.expect_file.is_some() || args.min_answers.is_some())
+    {
+        return Err(
+            "--expect, --expect-file and --min-answers cannot be used with --nxdomain".into(),
+        );
+    }
+
+    if args.num_queries == Some(0) {
+        return Err("-n must be greater than zero".into());
+    }
+
+    if args.qps == Some(0) {
+        return Err("--qps must be greater than zero".into());
+    }
+
+    if args.find_max.is_some() {
+        if args.stages.is_some()
+            || args.qps.is_some()
+            || profile
+            || args.time_secs.is_some()
+            || args.num_queries.is_some()
+            || args.script.is_some()
+        {
+            return Err(
+                "--find-max cannot be used with -t, -n, --stages, --script, --qps, --ramp or --wave"
+                    .into(),
+            );
+        }
+
+        if args.probe_time == 0 {
+            return Err("--probe-time must be at least 1".into());
+        }
+    }
+
+    if args.stages.is_some()
+        && (args.qps.is_some() || profile || args.time_secs.is_some() || args.num_queries.is_some())
+    {
+        return Err("--stages cannot be used with -t, -n, --qps, --ramp or --wave".into());
+    }
+
+    if args.out.is_some() && args.format == Format::Text {
+        return Err(
+            "--out needs --format json or markdown; the text summary always goes to stdout".into(),
+        );
+    }
+
+    if let Some(name) = &args.save_baseline {
+        baseline::path(name)?;
+    }
+
+    if args.tolerance.is_some() && args.check_baseline.is_none() {
+        return Err("--tolerance only applies to --check-baseline".into());
+    }
+
+    Ok(())
+}
+
+fn run(
+    args: RunArguments,
+    replay: Option<&ReplayArguments>,
+    controller: Option<&controller::Controller>,
+) {
+    logging::init(match (args.quiet, args.verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    });
+    if let Err(e) = validate(&args) {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+
+    // the controller waits on its agents, which have Ctrl-C of their own
+    if controller.is_none() {
+        if let Err(e) = interrupt::install() {
+            debug!("Ctrl-C will end the run without a summary: {}", e);
+        }
+    }
+
+    if let Some(option) = controller.and_then(|_| controller::Controller::unsupported(&args)) {
+        error!("{} cannot be used with nsbench controller", option);
+        std::process::exit(1);
+    }
+
+    let nameservers = match args.nameserver.socket_addrs(args.port) {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(bind) = args.bind {
+        if let Some(ns) = nameservers.iter().find(|ns| ns.is_ipv4() != bind.is_ipv4()) {
+            error!(
+                "--bind {} and nameserver {} are different address families",
+                bind, ns
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let qtypes = match (args.qtype, args.qtype_mix.clone()) {
+        (Some(qtype), _) => workload::QtypeMix::single(qtype),
+        (None, Some(mix)) => mix,
+        (None, None) if args.ptr_range.is_some() => workload::QtypeMix::single(RecordType::PTR),
+        (None, None) => workload::QtypeMix::single(RecordType::A),
+    };
+
+    let profile = args
+        .ramp
+        .map(rate::Profile::Ramp)
+        .or(args.wave.map(rate::Profile::Wave));
+
+    let stages = match &args.stages {
+        Some(path) => match stages::parse(path) {
+            Ok(stages) => stages,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => vec![stages::Stage {
+            length: match args.num_queries {
+                Some(count) => stages::Length::Queries(count),
+                None => stages::Length::Time(Duration::from_secs(args.time_secs.unwrap_or(60))),
+            },
+            qps: args.qps,
+            profile,
+            qtypes: None,
+        }],
+    };
+
+    // ANY and signed responses are routinely larger than 512 bytes, so unless
+    // told otherwise offer a large EDNS buffer to get them over UDP.
+    let any_queries = qtypes.contains(RecordType::ANY)
+        || stages.iter().any(|stage| stage.contains(RecordType::ANY));
+    let edns_payload = match args.edns_size {
+        Some(size) => Some(size),
+        None if any_queries || args.dnssec || args.ecs.is_some() => Some(LARGE_EDNS_PAYLOAD),
+        None => None,
+    };
+
+    if any_queries {
+        warn!(
+            "ANY queries produce large responses and are the classic DNS amplification vector. Only run this against nameservers you operate, and expect rate limiting or minimal (RFC 8482) answers from many."
+        );
+    }
+
+    let client_options = wire::ClientOptions {
+        transport: args.protocol,
+        timeout: Duration::new(0, args.timeout),
+        retries: args.retries,
+        tcp_fallback: !args.no_tcp_fallback,
+        bind: args.bind,
+        ip_ttl: args.ip_ttl,
+        dscp: args.dscp,
+        proxy: args.proxy,
+        edns_payload,
+        dnssec: args.dnssec,
+        ecs: args.ecs,
+    };
+
+    let sources = [
+        args.host.is_some(),
+        args.query_file.is_some(),
+        args.datafile.is_some(),
+        args.ptr_range.is_some(),
+        args.zone_file.is_some(),
+        replay.is_some(),
+        args.name_template.is_some(),
+    ];
+    let mut offsets = None;
+
+    let names = if sources.iter().filter(|given| **given).count() != 1 {
+        Err("exactly one of <host>, --query-file, --datafile, --zone-file, --ptr-range or --name-template is required".into())
+    } else if let Some(host) = &args.host {
+        if args.random_prefix || args.nxdomain {
+            workload::Names::random_prefix(host.clone())
+        } else {
+            Ok(workload::Names::Single(host.clone()))
+        }
+    } else if args.random_prefix || args.nxdomain {
+        Err("--random-prefix and --nxdomain require <host>".into())
+    } else if let Some(path) = &args.query_file {
+        workload::Names::from_file(path, args.shuffle)
+    } else if let Some(path) = &args.datafile {
+        workload::Names::from_datafile(path, args.shuffle)
+    } else if let Some(path) = &args.zone_file {
+        workload::Names::from_zone_file(path, args.shuffle)
+    } else if let Some(template) = &args.name_template {
+        Ok(workload::Names::Template(Arc::new(template.clone())))
+    } else if let Some(replay) = replay {
+        workload::Names::from_pcap(&replay.capture).map(|(names, captured)| {
+            offsets = Some(captured);
+            names
+        })
+    } else {
+        Ok(workload::Names::PtrRange(args.ptr_range.unwrap()))
+    };
+    let names = match names {
+        Ok(names) => names,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.distribution != workload::NameDistribution::RoundRobin
+        && !matches!(names, workload::Names::List(_))
+    {
+        error!("--distribution requires --query-file, --datafile or --zone-file");
+        std::process::exit(1);
+    }
+
+    let pacing = match (replay.and_then(|replay| replay.speed), offsets) {
+        (Some(speed), _) if speed <= 0.0 => {
+            error!("--speed must be greater than zero");
+            std::process::exit(1);
+        }
+        (Some(speed), Some(offsets)) => {
+            if args.distribution != workload::NameDistribution::RoundRobin {
+                error!("--speed cannot be used with --distribution");
+                std::process::exit(1);
+            }
+            Some(workload::Pacing::new(offsets, speed))
+        }
+        _ => None,
+    };
+
+    if args.qps.is_some() && pacing.is_some() {
+        error!("--qps cannot be used with --speed");
+        std::process::exit(1);
+    }
+
+    if args.warmup.is_some()
+        && stages
+            .iter()
+            .any(|stage| matches!(stage.length, stages::Length::Queries(_)))
+    {
+        error!("--warmup cannot be used with -n or stages that set queries");
+        std::process::exit(1);
+    }
+
+    if args.find_max.is_some() && nameservers.len() > 1 {
+        error!("--find-max takes a single nameserver");
+        std::process::exit(1);
+    }
+
+    if args.open_loop && stages.iter().any(|stage| stage.qps.is_none()) {
+        error!("--open-loop requires every stage to set qps");
+        std::process::exit(1);
+    }
+
+    let (cores, informer_core) = if args.pin_cores || args.numa_node.is_some() {
+        let mut cores = match affinity::cores() {
+            Ok(cores) => cores,
+            Err(e) => {
+                error!("could not pin workers to cores: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Some(node) = args.numa_node {
+            match affinity::node_cores(node) {
+                Ok(local) => cores.retain(|core| local.contains(core)),
+                Err(e) => {
+                    error!("could not find the cores of NUMA node {}: {}", node, e);
+                    std::process::exit(1);
+                }
+            }
+            if cores.is_empty() {
+                error!("none of NUMA node {}'s cores are available", node);
+                std::process::exit(1);
+            }
+        }
+
+        // the informer gets a core to itself when there are enough
+        let workers = args.cpus * nameservers.len();
+        let informer_core = (cores.len() > workers).then(|| cores.remove(0));
+        cores.truncate(workers);
+        if cores.len() < workers {
+            warn!(
+                "there are more workers ({}) than cores to pin them to ({}), so some share a core",
+                workers,
+                cores.len()
+            );
+        }
+        (cores, informer_core)
+    } else {
+        (Vec::new(), None)
+    };
+
+    // opened before the run rather than after, so a bad path doesn't throw
+    // away a long one
+    let csv = args
+        .csv
+        .as_ref()
+        .map(|path| match std::fs::File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+
+    let out = args
+        .out
+        .as_ref()
+        .map(|path| match std::fs::File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+
+    let reference = args
+        .check_baseline
+        .as_ref()
+        .map(|name| match baseline::Baseline::load(name) {
+            Ok(reference) => reference,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        });
+
+    let junit = args
+        .junit
+        .as_ref()
+        .map(|path| match std::fs::File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+
+    let trace = args
+        .trace
+        .as_ref()
+        .map(|path| match trace::Trace::create(path) {
+            Ok(trace) => Arc::new(trace),
+            Err(e) => {
+                error!("could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+
+    let html = args
+        .report
+        .as_ref()
+        .map(|path| match std::fs::File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("could not create {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        });
+
+    let ndjson = args.ndjson.as_ref().map(|path| {
+        let stream: Box<dyn std::io::Write + Send> = if path.as_os_str() == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            match std::fs::File::create(path) {
+                Ok(file) => Box::new(file),
+                Err(e) => {
+                    error!("could not create {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        };
+
+        Arc::new(Mutex::new(stream))
+    });
+
+    let metrics = args.prometheus.as_deref().map(|addr| {
+        let addr = match prometheus::parse_addr(addr) {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+
+        let metrics = Arc::new(prometheus::Metrics::new(nameservers.clone()));
+        if let Err(e) = prometheus::serve(addr, metrics.clone()) {
+            error!("could not listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+
+        metrics
+    });
+
+    let statsd = args
+        .statsd
+        .as_deref()
+        .map(|addr| match statsd::Client::new(addr) {
+            Ok(client) => Arc::new(client),
+            Err(e) => {
+                error!("could not reach statsd at {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        });
+
+    let influx = args.influx.as_deref().map(|target| {
+        let run_id = args
+            .run_id
+            .clone()
+            .unwrap_or_else(|| format!("{:08x}", rand::random::<u32>()));
+        let token = std::env::var("INFLUX_TOKEN").ok();
+
+        match influx::Writer::new(target, token, &args.protocol.to_string(), &run_id) {
+            Ok(writer) => {
+                info!("Writing to InfluxDB as run {}", run_id);
+                Arc::new(writer)
+            }
+            Err(e) => {
+                error!("could not open {}: {}", target, e);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let otlp = args.otlp.as_deref().map(|url| {
+        let headers = match std::env::var("OTEL_EXPORTER_OTLP_HEADERS") {
+            Ok(headers) => otlp::parse_headers(&headers),
+            Err(_) => Ok(Vec::new()),
+        };
+
+        match headers.and_then(|headers| {
+            otlp::Exporter::new(url, headers, nameservers.clone(), args.protocol.to_string())
+        }) {
+            Ok(exporter) => Arc::new(exporter),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    });
+
+    let control = args.control.as_ref().map(|addr| {
+        let control = Arc::new(control::Control::new());
+        if let Err(e) = control::serve(addr, control.clone()) {
+            error!("could not listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+
+        control
+    });
+
+    let consistency = args.consistency.map(|count| {
+        if nameservers.len() < 2 {
+            error!("--consistency needs at least two nameservers");
+            std::process::exit(1);
+        }
+
+        info!(
+            "Checking that the nameservers agree on {} quer{}",
+            count,
+            if count == 1 { "y" } else { "ies" }
+        );
+        let mut rng = rand::thread_rng();
+        let mut generator = names.generator(0, 0, 1, args.distribution.sampler(names.len()));
+        let queries: Vec<_> = (0..count)
+            .map(|_| {
+                let (name, record_type) = generator.next(&mut rng);
+                (name, record_type.unwrap_or_else(|| qtypes.sample(&mut rng)))
+            })
+            .collect();
+
+        let report = consistency::check(&nameservers, client_options, queries);
+        for disagreement in report.disagreements.iter().take(CONSISTENCY_SAMPLES) {
+            warn!(
+                "nameservers disagree on {} {}: {}",
+                disagreement.name,
+                disagreement.record_type,
+                nameservers
+                    .iter()
+                    .zip(&disagreement.outcomes)
+                    .map(|(nameserver, outcome)| format!("{} said {}", nameserver, outcome))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+        }
+        if report.disagreements.len() > CONSISTENCY_SAMPLES {
+            warn!(
+                "and {} more disagreements",
+                report.disagreements.len() - CONSISTENCY_SAMPLES
+            );
+        }
+        report
+    });
+
+    let dashboard = if args.tui {
+        // the dashboard clears and redraws the screen with escape codes,
+        // which would only garble output that is going anywhere else
+        if !std::io::IsTerminal::is_terminal(&std::io::stdout())
+            || !std::io::IsTerminal::is_terminal(&std::io::stderr())
+        {
+            error!("--tui needs stdout and stderr to be a terminal");
+            std::process::exit(1);
+        }
+
+        Some(Arc::new(Mutex::new(tui::Dashboard::new(
+            nameservers.clone(),
+        ))))
+    } else {
+        None
+    };
+
+    // so that a bad path is caught now rather than a minute into the run
+    if let Some(path) = &args.checkpoint {
+        if let Err(e) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            error!("could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+    let expected = if args.expect.is_empty()
+        && args.expect_file.is_none()
+        && args.min_answers.is_none()
+        && args.expect_flags.is_none()
+    {
+        None
+    } else {
+        let mut expected = expect::Expectations::new(&args.expect)
+            .with_min_answers(args.min_answers.unwrap_or_default())
+            .with_flags(args.expect_flags.clone().unwrap_or_default());
+        if let Some(path) = &args.expect_file {
+            if let Err(e) = expected.load(path) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Arc::new(expected))
+    };
+
+    let qname_minimization = args.qname_min.then(|| {
+        let name = Name::from_ascii(qnamemin::TEST_NAME).unwrap();
+        let options = wire::ClientOptions {
+            timeout: client_options.timeout.max(QNAME_MIN_TIMEOUT),
+            ..client_options
+        };
+
+        nameservers
+            .iter()
+            .map(|nameserver| {
+                let minimization = qnamemin::detect(*nameserver, options, &name);
+                info!("QNAME minimization on {}: {}", nameserver, minimization);
+                minimization
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let on_stage = args.script.as_ref().map(|path| -> StageHook {
+        let script = script::Script::parse(path).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        let targets = nameservers.len();
+        Arc::new(move |stage, details, elapsed, remaining| {
+            script.apply(stage, details, elapsed, targets, remaining)
+        })
+    });
+
+    let on_checkpoint = args.checkpoint.clone().map(|path| -> CheckpointHook {
+        let args = args.clone();
+        let qtypes = qtypes.clone();
+        let consistency = consistency.clone();
+        let qname_minimization = qname_minimization.clone();
+        Arc::new(move |results| {
+            if let Err(e) = report::checkpoint(
+                &path,
+                &args,
+                &qtypes,
+                results,
+                consistency.as_ref(),
+                qname_minimization.as_deref(),
+            ) {
+                warn!("could not write checkpoint {}: {}", path.display(), e);
+            }
+        })
+    });
+
+    let config = Config {
+        nameservers: nameservers.clone(),
+        cpus: args.cpus,
+        cores: cores.clone(),
+        informer_core,
+        client_options,
+        names: names.clone(),
+        pacing,
+        sampler: args.distribution.sampler(names.len()),
+        repeat_ratio: args.repeat_ratio,
+        qtypes: qtypes.clone(),
+        stages,
+        find_max: args.find_max,
+        probe_time: Duration::from_secs(args.probe_time),
+        open_loop: args.open_loop,
+        arrival: args.arrival,
+        burst: args.burst,
+        concurrency: args.concurrency,
+        tasks: args.tasks,
+        sockets_per_worker: args.sockets_per_worker,
+        warmup: args.warmup,
+        expected_interval: args.expected_interval,
+        expect_nxdomain: args.nxdomain,
+        expected,
+        // failures would scroll the dashboard away
+        failure_log: Arc::new(failurelog::FailureLog::new(if args.tui {
+            0
+        } else {
+            args.failure_samples
+        })),
+        trace: trace.clone(),
+        on_interval: ndjson.map(|ndjson| -> IntervalHook {
+            Arc::new(move |interval| {
+                if let Err(e) = report::ndjson(&ndjson, interval) {
+                    warn!("could not write interval: {}", e);
+                }
+            })
+        }),
+        on_checkpoint,
+        checkpoint_interval: args.checkpoint_interval,
+        on_stage,
+        metrics,
+        statsd,
+        influx,
+        otlp,
+        dashboard,
+        control,
+    };
+
+    let results = match controller {
+        Some(controller) => controller.run(&config.stages).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        }),
+        None => nsbench::run_benchmark(config),
+    };
+
+    if let Some(control::Addr::Unix(path)) = &args.control {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // the last checkpoint covers the whole run
+    if let Some(path) = &args.checkpoint {
+        if let Err(e) = report::checkpoint(
+            path,
+            &args,
+            &qtypes,
+            &results,
+            consistency.as_ref(),
+            qname_minimization.as_deref(),
+        ) {
+            error!("could not write checkpoint {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = &args.results_file {
+        if let Err(e) = std::fs::write(path, remote::encode(&results).to_string()) {
+            error!("could not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    let nsbench::Results {
+        nameservers,
+        overall,
+        per_target,
+        per_stage,
+        timeseries,
+        runtime,
+        max_rate,
+    } = results;
+    if interrupt::interrupted() {
+        warn!(
+            "Interrupted: the results cover the first {:.2?} of the run",
+            runtime
+        );
+    }
+
+    if let (Some(path), Some(trace)) = (&args.trace, trace) {
+        if let Err(e) = trace.finish() {
+            error!("could not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(path), Some(file)) = (&args.csv, csv) {
+        if let Err(e) = report::csv(file, &timeseries) {
+            error!("could not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    let mut verdicts = assertions::check(&args.assert, &overall, runtime);
+    if let Some(reference) = &reference {
+        verdicts.extend(reference.check(args.tolerance.unwrap_or_default(), &overall, runtime));
+    }
+
+    let results = report::Results {
+        nameservers: &nameservers,
+        overall: &overall,
+        per_target: &per_target,
+        per_stage: &per_stage,
+        timeseries: &timeseries,
+        runtime,
+        max_rate,
+        verdicts: &verdicts,
+        consistency: consistency.as_ref(),
+        qname_minimization: qname_minimization.as_deref(),
+    };
+
+    if let (Some(path), Some(file)) = (&args.report, html) {
+        if let Err(e) = html::html(file, &args, &qtypes, &results) {
+            error!("could not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(path), Some(file)) = (&args.junit, junit) {
+        if let Err(e) = junit::junit(file, &args, &results) {
+            error!("could not write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(name) = &args.save_baseline {
+        match baseline::save(name, &report::json(&args, &qtypes, &results).to_string()) {
+            Ok(path) => info!("Saved baseline '{}' to {}", name, path.display()),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let summary = match args.format {
+        Format::Text => None,
+        Format::Json => Some(report::json(&args, &qtypes, &results).to_string()),
+        Format::Markdown => Some(markdown::markdown(&args, &qtypes, &results)),
+    };
+    if let Some(summary) = summary {
+        match (&args.out, out) {
+            (Some(path), Some(mut file)) => {
+                if let Err(e) = writeln!(file, "{}", summary) {
+                    error!("could not write {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+            _ => {
+                println!("{}", summary);
+                finish(&verdicts);
+                return;
+            }
+        }
+    }
+
+    if let Some(path) = &args.config {
+        println!("Config: {}", path.display());
+    }
+    println!(
+        "Nameserver: {}",
+        nameservers
+            .iter()
+            .map(|ns| ns.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+    println!("Protocol: {}", args.protocol);
+    if let Some(proxy) = args.proxy {
+        println!("Proxy: {}", proxy);
+    }
+    if let Some(ttl) = args.ip_ttl {
+        println!("IP TTL: {}", ttl);
+    }
+    if let Some(dscp) = args.dscp {
+        println!("DSCP: {}", dscp);
+    }
+    if let Some(host) = &args.host {
+        if args.random_prefix || args.nxdomain {
+            println!("Host: <random>.{}", host);
+        } else {
+            println!("Host: {}", host);
+        }
+    } else if let Some(path) = &args.query_file {
+        println!("Query File: {} ({} names)", path.display(), names.len());
+        println!("Distribution: {}", args.distribution);
+    } else if let Some(path) = &args.datafile {
+        println!("Datafile: {} ({} queries)", path.display(), names.len());
+        println!("Distribution: {}", args.distribution);
+    } else if let Some(path) = &args.zone_file {
+        println!("Zone File: {} ({} queries)", path.display(), names.len());
+        println!("Distribution: {}", args.distribution);
+    } else if let Some(replay) = replay {
+        println!(
+            "Replay: {} ({} queries)",
+            replay.capture.display(),
+            names.len()
+        );
+        match replay.speed {
+            Some(speed) => println!("Replay Speed: {}x", speed),
+            None => println!("Distribution: {}", args.distribution),
+        }
+    } else if let Some(range) = &args.ptr_range {
+        println!("PTR Range: {}", range);
+    } else if let Some(template) = &args.name_template {
+        println!("Name Template: {}", template);
+    }
+    if args.repeat_ratio > 0.0 {
+        println!("Repeat Ratio: {}", args.repeat_ratio);
+    }
+    println!("Query Type: {}", qtypes);
+    if let Some(payload) = edns_payload {
+        println!("EDNS Payload: {}", payload);
+    }
+    if args.dnssec {
+        println!("DO Bit: set");
+    }
+    if let Some(subnet) = &args.ecs {
+        println!("Client Subnet: {}", subnet);
+    }
+    if args.nxdomain {
+        println!("Expected Response: NXDOMAIN");
+    }
+    if !args.expect.is_empty() {
+        println!("Expected Answers: {}", args.expect.join(", "));
+    }
+    if let Some(path) = &args.expect_file {
+        println!("Expected Answers File: {}", path.display());
+    }
+    if let Some(count) = args.min_answers {
+        println!("Minimum Answers: {}", count);
+    }
+    if let Some(flags) = &args.expect_flags {
+        println!("Expected Flags: {}", flags);
+    }
+    println!("CPUs Used: {}", args.cpus);
+    if let Some(node) = args.numa_node {
+        println!("NUMA Node: {}", node);
+    }
+    if !cores.is_empty() {
+        println!(
+            "Pinned To Cores: {}",
+            cores
+                .iter()
+                .map(|core| core.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    if let Some(warmup) = args.warmup {
+        println!("Warm-up: {:?}", warmup);
+    }
+    if let Some(concurrency) = args.concurrency {
+        println!("Concurrency: {}", concurrency);
+        if args.tasks {
+            println!("Engine: async");
+        }
+    }
+    if args.sockets_per_worker > 1 {
+        println!("Sockets Per Worker: {}", args.sockets_per_worker);
+    }
+    if let Some(interval) = args.expected_interval {
+        println!("Expected Interval: {:?}", interval);
+    }
+    if let Some(slo) = args.find_max {
+        println!("SLO: p99 <= {:?}", slo);
+        match max_rate {
+            Some(qps) => println!("Max Rate: {}/s", qps),
+            None => println!("Max Rate: none (no probe met the SLO)"),
+        }
+    } else if let Some(path) = &args.stages {
+        println!("Stages: {} ({} stages)", path.display(), per_stage.len());
+    } else if let Some(qps) = args.qps {
+        println!("Target Rate: {}/s", qps);
+    } else if let Some(profile) = profile {
+        println!("Target Rate: {}", profile);
+    } else if let Some(burst) = args.burst {
+        println!("Burst: {}", burst);
+    }
+    if args.qps.is_some() || args.stages.is_some() || args.find_max.is_some() {
+        println!(
+            "Load: {}",
+            if args.open_loop {
+                "open loop"
+            } else {
+                "closed loop"
+            }
+        );
+    }
+    if args.arrival != rate::Arrival::Fixed {
+        println!("Arrival: {}", args.arrival);
+    }
+    println!("Successes: {}", overall.successes);
+    println!("Failures: {}{}", overall.failures, failure_causes(&overall));
+    println!(
+        "Truncated: {}{}",
+        overall.truncated,
+        truncation_rate(&overall)
+    );
+    println!("TCP Fallbacks: {}", overall.tcp_fallbacks);
+    if overall.mismatches.total() > 0 {
+        println!(
+            "Mismatched Responses: {} ({})",
+            overall.mismatches.total(),
+            overall.mismatches
+        );
+    }
+    println!("Success Rate: {:.02}%", overall.success_rate());
+    println!("Average Latency: {:.2?}", overall.mean_latency());
+    println!("Latency Std Dev: {:.2?}", overall.latency_stddev());
+    println!(
+        "Latency Min: {:.2?}",
+        Duration::from_nanos(overall.latencies.min())
+    );
+    for (label, percentile) in LATENCY_PERCENTILES {
+        println!(
+            "Latency {}: {:.2?}",
+            label,
+            Duration::from_nanos(overall.latencies.percentile(*percentile))
+        );
+    }
+    println!(
+        "Latency Max: {:.2?}",
+        Duration::from_nanos(overall.latencies.max())
+    );
+    let responses: u64 = overall.rcodes.values().sum();
+    if let Some(mean_size) = overall.total_response_size.checked_div(responses) {
+        println!(
+            "Response Size: min {} B, mean {} B, p95 {} B, max {} B",
+            overall.response_sizes.min(),
+            mean_size,
+            overall.response_sizes.percentile(95.0),
+            overall.response_sizes.max()
+        );
+    }
+    println!("Bytes Sent: {}", overall.bytes_sent);
+    println!("Bytes Received: {}", overall.bytes_received);
+    if let Some(mean_connect_time) = overall
+        .total_connect_time
+        .checked_div(overall.connect_times.count() as u128)
+    {
+        println!(
+            "Connections: {}, connect time mean {:.2?}, p99 {:.2?}, max {:.2?}",
+            overall.connect_times.count(),
+            Duration::from_nanos(mean_connect_time as u64),
+            Duration::from_nanos(overall.connect_times.percentile(99.0)),
+            Duration::from_nanos(overall.connect_times.max())
+        );
+    }
+    if let Some(mean_ttl) = overall.total_ttl.checked_div(overall.ttls.count()) {
+        println!(
+            "Answer TTL: min {}s, mean {}s, max {}s",
+            overall.ttls.min(),
+            mean_ttl,
+            overall.ttls.max()
+        );
+    }
+    let dnssec = &overall.dnssec;
+    if dnssec.validated.count() + dnssec.unvalidated.count() + dnssec.bogus > 0 {
+        println!(
+            "DNSSEC: {} validated (AD), {} not validated, {} failed validation",
+            dnssec.validated.count(),
+            dnssec.unvalidated.count(),
+            dnssec.bogus
+        );
+        for (label, latencies) in [
+            ("Validated", &dnssec.validated),
+            ("Not Validated", &dnssec.unvalidated),
+        ] {
+            if latencies.count() > 0 {
+                println!(
+                    "{} Latency: p50 {:.2?}, p99 {:.2?}",
+                    label,
+                    Duration::from_nanos(latencies.percentile(50.0)),
+                    Duration::from_nanos(latencies.percentile(99.0))
+                );
+            }
+        }
+    }
+    if interrupt::interrupted() {
+        println!("Runtime: {:.2?} (interrupted)", runtime);
+    } else {
+        println!("Runtime: {:.2?}", runtime);
+    }
+    println!("Requests: {}/s", per_second(overall.successes, runtime));
+    println!(
+        "Throughput: {:.02} Mbit/s out, {:.02} Mbit/s in",
+        mbit_per_second(overall.bytes_sent, runtime),
+        mbit_per_second(overall.bytes_received, runtime)
+    );
+    if let Some(consistency) = &consistency {
+        println!(
+            "Consistency: {} of {} queries answered differently",
+            consistency.disagreements.len(),
+            consistency.checked
+        );
+    }
+    if let Some(minimization) = &qname_minimization {
+        println!(
+            "QNAME Minimization: {}",
+            nameservers
+                .iter()
+                .zip(minimization)
+                .map(|(nameserver, minimization)| format!("{} {}", nameserver, minimization))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    if overall.queries_by_type.len() > 1 {
+        println!();
+        println!(
+            "{:<10} {:>12} {:>12} {:>12} {:>13} {:>12} {:>12}",
+            "Type", "Queries", "Successes", "Failures", "Success Rate", "Avg Latency", "p99"
+        );
+        for (record_type, count) in &overall.queries_by_type {
+            let details = overall
+                .by_type
+                .get(record_type)
+                .cloned()
+                .unwrap_or_default();
+
+            println!(
+                "{:<10} {:>12} {:>12} {:>12} {:>12.02}% {:>12} {:>12}",
+                record_type.to_string(),
+                count,
+                details.successes,
+                details.failures,
+                details.successes as f64 / (details.successes + details.failures) as f64 * 100.0,
+                format!("{:.2?}", details.mean_latency()),
+                format!(
+                    "{:.2?}",
+                    Duration::from_nanos(details.latencies.percentile(99.0))
+                ),
+            );
+        }
+    }
+
+    if overall.latencies.count() > 0 {
+        println!();
+        print_latency_histogram(&overall);
+    }
+
+    if overall.ttls.count() > 0 {
+        println!();
+        println!("{:<10} {:>12} {:>9}", "TTL", "Answers", "Share");
+        for ((_, label), count) in TTL_RANGES.iter().zip(overall.ttl_ranges) {
+            println!(
+                "{:<10} {:>12} {:>8.02}%",
+                label,
+                count,
+                count as f64 / overall.ttls.count() as f64 * 100.0
+            );
+        }
+    }
+
+    if args.retries > 0 && !overall.attempts.is_empty() {
+        let answered: u64 = overall.attempts.values().sum();
+
+        println!();
+        println!("{:<10} {:>12} {:>9}", "Attempts", "Answered", "Share");
+        for (attempts, count) in &overall.attempts {
+            println!(
+                "{:<10} {:>12} {:>8.02}%",
+                attempts,
+                count,
+                *count as f64 / answered as f64 * 100.0
+            );
+        }
+    }
+
+    if args.ecs.is_some() && responses > 0 {
+        let scoped: u64 = overall.ecs_scopes.values().sum();
+
+        println!();
+        println!("{:<10} {:>12} {:>9}", "ECS Scope", "Responses", "Share");
+        let rows = overall
+            .ecs_scopes
+            .iter()
+            .map(|(scope, count)| (format!("/{}", scope), *count))
+            .chain(Some(("no option".to_string(), responses - scoped)));
+        for (scope, count) in rows.filter(|(_, count)| *count > 0) {
+            println!(
+                "{:<10} {:>12} {:>8.02}%",
+                scope,
+                count,
+                count as f64 / responses as f64 * 100.0
+            );
+        }
+    }
+
+    if responses > 0 {
+        println!();
+        println!("{:<10} {:>12} {:>9}", "RCODE", "Responses", "Share");
+        for (rcode, count) in &overall.rcodes {
+            println!(
+                "{:<10} {:>12} {:>8.02}%",
+                rcode_name(*rcode),
+                count,
+                *count as f64 / responses as f64 * 100.0
+            );
+        }
+    }
+
+    if per_stage.len() > 1 {
+        println!();
+        println!(
+            "{:<6} {:<28} {:>12} {:>12} {:>13} {:>12} {:>12}",
+            "Stage", "Load", "Successes", "Failures", "Success Rate", "Requests/s", "p99"
+        );
+
+        for (i, (stage, details, elapsed)) in per_stage.iter().enumerate() {
+            println!(
+                "{:<6} {:<28} {:>12} {:>12} {:>12.02}% {:>12} {:>12}",
+                i + 1,
+                stage_load(stage),
+                details.successes,
+                details.failures,
+                details.success_rate(),
+                per_second(details.successes, *elapsed),
+                format!(
+                    "{:.2?}",
+                    Duration::from_nanos(details.latencies.percentile(99.0))
+                ),
+            );
+        }
+    }
+
+    if args.timeseries {
+        println!();
+        println!(
+            "{:>10} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}  Failure Causes",
+            "Time",
+            "Successes",
+            "Failures",
+            "Requests/s",
+            "Avg Latency",
+            "p99",
+            "Mbit/s Out",
+            "Mbit/s In"
+        );
+
+        for interval in &timeseries {
+            println!(
+                "{:>10} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12.02} {:>12.02}  {}",
+                format!("{:.1}s", interval.offset.as_secs_f64()),
+                interval.successes,
+                interval.failures,
+                per_second(interval.successes, interval.length),
+                format!("{:.2?}", interval.mean_latency),
+                format!("{:.2?}", interval.p99_latency),
+                mbit_per_second(interval.bytes_sent, interval.length),
+                mbit_per_second(interval.bytes_received, interval.length),
+                interval.failure_causes,
+            );
+        }
+    }
+
+    if nameservers.len() > 1 {
+        println!();
+        println!(
+            "{:<40} {:>12} {:>12} {:>13} {:>12} {:>12} {:>12}  Failure Causes",
+            "Nameserver",
+            "Successes",
+            "Failures",
+            "Success Rate",
+            "Requests/s",
+            "Avg Latency",
+            "p99"
+        );
+
+        for (nameserver, details) in nameservers.iter().zip(per_target) {
+            println!(
+                "{:<40} {:>12} {:>12} {:>12.02}% {:>12} {:>12} {:>12}  {}",
+                nameserver.to_string(),
+                details.successes,
+                details.failures,
+                details.success_rate(),
+                per_second(details.successes, runtime),
+                format!("{:.2?}", details.mean_latency()),
+                format!(
+                    "{:.2?}",
+                    Duration::from_nanos(details.latencies.percentile(99.0))
+                ),
+                details.failure_causes,
+            );
+        }
+    }
+
+    finish(&verdicts);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validated(argv: &[&str]) -> Result<(), String> {
+        let argv: Vec<&str> = ["127.0.0.1", "example.com"]
+            .iter()
+            .chain(argv)
+            .copied()
+            .collect();
+        validate(&RunArguments::from_args(&["nsbench run"], &argv).unwrap())
+    }
+
+    #[test]
+    fn accepts_sensible_runs() {
+        let cases: &[&[&str]] = &[
+            &[],
+            &["-t", "10", "--qps", "1000"],
+            &["--ramp", "1:1000:10", "--arrival", "poisson"],
+            &["--concurrency", "65535", "-l", "1"],
+            &["--concurrency", "70000", "-l", "2"],
+            &["--concurrency", "70000", "--async", "-l", "1"],
+            &[
+                "--concurrency",
+                "100",
+                "--async",
+                "--protocol",
+                "tcp",
+                "--retries",
+                "2",
+                "-l",
+                "1",
+            ],
+            &["--open-loop", "--qps", "1000"],
+            &["--burst", "500:100ms"],
+            &["--find-max", "10ms"],
+            &["--format", "json", "--out", "summary.json"],
+            &["--check-baseline", "main", "--tolerance", "10%"],
+        ];
+
+        for argv in cases {
+            assert_eq!(validated(argv), Ok(()), "{:?}", argv);
+        }
+    }
+
+    #[test]
+    fn rejects_conflicting_options() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["-q", "-v"], "-q and -v cannot be used together"),
+            (&["--ip-ttl", "0"], "--ip-ttl must be between 1 and 255"),
+            (&["--proxy", "socks5://127.0.0.1:1080"], "--proxy requires --protocol tcp"),
+            (
+                &["--qtype", "A", "--qtype-mix", "A:1"],
+                "--qtype and --qtype-mix cannot be used together",
+            ),
+            (
+                &["--ramp", "1:2:3", "--wave", "1:2:3"],
+                "--ramp and --wave cannot be used together",
+            ),
+            (&["--open-loop"], "--open-loop requires --qps"),
+            (
+                &["--arrival", "poisson"],
+                "--arrival requires --qps, --ramp, --wave, --stages or --find-max",
+            ),
+            (
+                &["--burst", "500:100ms", "--qps", "10"],
+                "--burst cannot be used with --qps, --ramp, --wave, --stages, --find-max, --open-loop or --arrival",
+            ),
+            (
+                &["--concurrency", "1", "-l", "2"],
+                "--concurrency must be at least the number of CPUs (-l)",
+            ),
+            (
+                &["--concurrency", "65536", "-l", "1"],
+                "--concurrency can keep at most 65535 queries in flight per worker without --async; raise -l",
+            ),
+            (
+                &["--concurrency", "10", "--protocol", "tcp", "-l", "1"],
+                "--concurrency only supports --protocol udp without --async",
+            ),
+            (&["--async"], "--async needs --concurrency"),
+            (
+                &["--async", "--concurrency", "10", "--sockets-per-worker", "2", "-l", "1"],
+                "--async cannot be used with --sockets-per-worker, as every query has a socket of its own",
+            ),
+            (
+                &["--sockets-per-worker", "0"],
+                "--sockets-per-worker must be above zero",
+            ),
+            (
+                &["--retries", "1", "--concurrency", "10", "-l", "1"],
+                "--retries cannot be used with --open-loop, or --concurrency without --async",
+            ),
+            (
+                &["--ramp", "1:2:3", "--qps", "10"],
+                "--ramp and --wave cannot be used with --qps",
+            ),
+            (
+                &["--repeat-ratio", "1.5"],
+                "--repeat-ratio must be between 0.0 and 1.0",
+            ),
+            (&["-t", "1", "-n", "1"], "-t and -n cannot be used together"),
+            (
+                &["--nxdomain", "--expect", "192.0.2.1"],
+                "--expect, --expect-file and --min-answers cannot be used with --nxdomain",
+            ),
+            (&["-n", "0"], "-n must be greater than zero"),
+            (&["--qps", "0"], "--qps must be greater than zero"),
+            (
+                &["--find-max", "10ms", "-t", "10"],
+                "--find-max cannot be used with -t, -n, --stages, --script, --qps, --ramp or --wave",
+            ),
+            (
+                &["--stages", "stages.txt", "--qps", "10"],
+                "--stages cannot be used with -t, -n, --qps, --ramp or --wave",
+            ),
+            (
+                &["--out", "summary.txt"],
+                "--out needs --format json or markdown; the text summary always goes to stdout",
+            ),
+            (
+                &["--save-baseline", "../main"],
+                "invalid baseline name '../main': use letters, digits, '-', '_' and '.'",
+            ),
+            (
+                &["--tolerance", "10%"],
+                "--tolerance only applies to --check-baseline",
+            ),
+        ];
+
+        for (argv, err) in cases {
+            assert_eq!(validated(argv), Err(err.to_string()), "{:?}", argv);
+        }
+    }
 }
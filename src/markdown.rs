@@ -0,0 +1,178 @@
+use std::{fmt::Write as _, time::Duration};
+
+use nsbench::{per_second, stage_load, workload::QtypeMix, RunDetails, LATENCY_PERCENTILES};
+
+use crate::{report::Results, RunArguments};
+
+/// Returns the summary as Markdown tables, to paste into a pull request or
+/// have a bot post as a comment: how the run was set up, the totals with
+/// their latency percentiles, and what the failures were.
+pub fn markdown(args: &RunArguments, qtypes: &QtypeMix, results: &Results) -> String {
+    let overall = results.overall;
+    let mut page = String::from("## nsbench results\n");
+
+    let nameservers = results
+        .nameservers
+        .iter()
+        .map(|nameserver| nameserver.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let mut config = vec![
+        vec!["Nameserver".to_string(), nameservers],
+        vec!["Protocol".to_string(), args.protocol.to_string()],
+        vec!["Query types".to_string(), qtypes.to_string()],
+        vec!["CPUs".to_string(), args.cpus.to_string()],
+        vec!["Runtime".to_string(), format!("{:.2?}", results.runtime)],
+    ];
+    if let Some(qps) = args.qps {
+        config.push(vec!["Rate limit".to_string(), format!("{}/s", qps)]);
+    }
+    if let Some(max_rate) = results.max_rate {
+        config.push(vec![
+            "Highest rate within SLO".to_string(),
+            format!("{}/s", max_rate),
+        ]);
+    }
+    table(&mut page, None, &["Setting", "Value"], config);
+
+    let mut header = vec![
+        "Nameserver",
+        "Successes",
+        "Failures",
+        "Success rate",
+        "Requests/s",
+        "Avg latency",
+    ];
+    header.extend(LATENCY_PERCENTILES.iter().map(|(label, _)| *label));
+    header.push("Max");
+    let mut rows = vec![totals("all", overall, results.runtime)];
+    if results.nameservers.len() > 1 {
+        for (nameserver, details) in results.nameservers.iter().zip(results.per_target) {
+            rows.push(totals(&nameserver.to_string(), details, results.runtime));
+        }
+    }
+    table(&mut page, Some("Results"), &header, rows);
+
+    if overall.failures > 0 {
+        let causes = &overall.failure_causes;
+        let queries = overall.successes + overall.failures;
+        table(
+            &mut page,
+            Some("Failures"),
+            &["Cause", "Queries", "Share of queries"],
+            [
+                ("timeout", causes.timeout),
+                ("SERVFAIL", causes.servfail),
+                ("REFUSED", causes.refused),
+                ("NXDOMAIN", causes.nxdomain),
+                ("network", causes.network),
+                ("wrong answer", causes.wrong_answer),
+                ("wrong flags", causes.wrong_flags),
+                ("other", causes.other),
+            ]
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(cause, count)| {
+                vec![
+                    cause.to_string(),
+                    count.to_string(),
+                    format!("{:.2}%", *count as f64 / queries as f64 * 100.0),
+                ]
+            })
+            .collect(),
+        );
+    }
+
+    if results.per_stage.len() > 1 {
+        table(
+            &mut page,
+            Some("Stages"),
+            &[
+                "Stage",
+                "Load",
+                "Successes",
+                "Failures",
+                "Success rate",
+                "Requests/s",
+                "p99",
+            ],
+            results
+                .per_stage
+                .iter()
+                .enumerate()
+                .map(|(i, (stage, details, elapsed))| {
+                    vec![
+                        (i + 1).to_string(),
+                        stage_load(stage),
+                        details.successes.to_string(),
+                        details.failures.to_string(),
+                        format!("{:.2}%", details.success_rate()),
+                        per_second(details.successes, *elapsed).to_string(),
+                        latency(details.latencies.percentile(99.0)),
+                    ]
+                })
+                .collect(),
+        );
+    }
+
+    if !results.verdicts.is_empty() {
+        table(
+            &mut page,
+            Some("Checks"),
+            &["Check", "Actual", "Result"],
+            results
+                .verdicts
+                .iter()
+                .map(|verdict| {
+                    vec![
+                        verdict.assertion.clone(),
+                        verdict.actual.clone(),
+                        if verdict.passed { "pass" } else { "**fail**" }.to_string(),
+                    ]
+                })
+                .collect(),
+        );
+    }
+
+    page.trim_end().to_string()
+}
+
+// a row of the results table
+fn totals(label: &str, details: &RunDetails, runtime: Duration) -> Vec<String> {
+    let mut row = vec![
+        label.to_string(),
+        details.successes.to_string(),
+        details.failures.to_string(),
+        format!("{:.2}%", details.success_rate()),
+        per_second(details.successes, runtime).to_string(),
+        format!("{:.2?}", details.mean_latency()),
+    ];
+    row.extend(
+        LATENCY_PERCENTILES
+            .iter()
+            .map(|(_, percentile)| latency(details.latencies.percentile(*percentile))),
+    );
+    row.push(latency(details.latencies.max()));
+    row
+}
+
+fn latency(nanos: u64) -> String {
+    format!("{:.2?}", Duration::from_nanos(nanos))
+}
+
+fn table(page: &mut String, title: Option<&str>, header: &[&str], rows: Vec<Vec<String>>) {
+    page.push('\n');
+    if let Some(title) = title {
+        writeln!(page, "### {}\n", title).unwrap();
+    }
+
+    writeln!(page, "| {} |", header.join(" | ")).unwrap();
+    writeln!(page, "|{}", "---|".repeat(header.len())).unwrap();
+    for row in rows {
+        let cells = row
+            .iter()
+            .map(|cell| cell.replace('|', "\\|"))
+            .collect::<Vec<String>>();
+        writeln!(page, "| {} |", cells.join(" | ")).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use trust_dns_resolver::proto::{
+    op::Message,
+    rr::{Name, RecordType},
+};
+
+use crate::Mismatches;
+
+// how many of the latest timed out queries are remembered
+const REMEMBERED: usize = 4096;
+
+/// TimedOut remembers the ids of the latest queries to time out, so that an
+/// answer that turns up for one afterwards counts as late rather than
+/// unsolicited.
+#[derive(Debug, Default)]
+pub struct TimedOut {
+    ids: HashSet<u16>,
+    order: VecDeque<u16>,
+}
+
+impl TimedOut {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes that the query with `id` timed out.
+    pub fn insert(&mut self, id: u16) {
+        if !self.ids.insert(id) {
+            return;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > REMEMBERED {
+            let oldest = self.order.pop_front().unwrap();
+            self.ids.remove(&oldest);
+        }
+    }
+
+    /// Reports whether `id` is that of a query that timed out, forgetting it
+    /// so that a second answer counts as unsolicited.
+    pub fn take(&mut self, id: u16) -> bool {
+        // the id stays in the order until it is pushed out
+        self.ids.remove(&id)
+    }
+}
+
+/// Reports whether `message` answers a `record_type` query for `name`: its
+/// question, if it has one, is the one asked. Names are compared without
+/// regard to case, as nameservers may echo them back in a different one.
+pub fn answers(message: &Message, name: &Name, record_type: RecordType) -> bool {
+    message
+        .queries()
+        .first()
+        .is_none_or(|query| query.name() == name && query.query_type() == record_type)
+}
+
+/// Takes the query in `outstanding`, by id, that `message` answers, or
+/// accounts for `message` in `mismatches` if it answers none.
+pub fn take<T>(
+    outstanding: &mut HashMap<u16, (T, Name, RecordType)>,
+    timed_out: &mut TimedOut,
+    message: &Message,
+    mismatches: &mut Mismatches,
+) -> Option<(T, Name, RecordType)> {
+    match outstanding.get(&message.id()) {
+        None if timed_out.take(message.id()) => mismatches.late += 1,
+        None => mismatches.unsolicited += 1,
+        Some((_, name, record_type)) if !answers(message, name, *record_type) => {
+            mismatches.wrong_question += 1
+        }
+        Some(_) => return outstanding.remove(&message.id()),
+    }
+
+    None
+}
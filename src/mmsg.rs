@@ -0,0 +1,168 @@
+use std::{io, net::UdpSocket, time::Duration};
+
+/// The most datagrams sent or received in one system call.
+pub const BATCH: usize = 32;
+
+/// Sends each of `packets` on the connected `socket`, returning how many bytes
+/// of each were sent or why it wasn't. On Linux they go out `BATCH` at a time
+/// with sendmmsg, elsewhere one at a time.
+#[cfg(target_os = "linux")]
+pub fn send(socket: &UdpSocket, packets: &[Vec<u8>]) -> Vec<io::Result<usize>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut results = Vec::with_capacity(packets.len());
+    for chunk in packets.chunks(BATCH) {
+        let mut iovecs: Vec<libc::iovec> = chunk
+            .iter()
+            .map(|packet| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+        let mut messages: Vec<libc::mmsghdr> = iovecs.iter_mut().map(header).collect();
+
+        // a datagram that can't be sent stops the call short, and is then the
+        // first of the rest to fail
+        let mut done = 0;
+        while done < messages.len() {
+            let rest = &mut messages[done..];
+            // SAFETY: each header points at one iovec, which points at a packet,
+            // and both outlive the call.
+            let sent = unsafe {
+                libc::sendmmsg(
+                    socket.as_raw_fd(),
+                    rest.as_mut_ptr(),
+                    rest.len() as libc::c_uint,
+                    0,
+                )
+            };
+
+            match sent {
+                n if n < 0 => {
+                    results.push(Err(io::Error::last_os_error()));
+                    done += 1;
+                }
+                0 => {
+                    results.push(Err(io::ErrorKind::WriteZero.into()));
+                    done += 1;
+                }
+                n => {
+                    let n = n as usize;
+                    results.extend(rest[..n].iter().map(|message| Ok(message.msg_len as usize)));
+                    done += n;
+                }
+            }
+        }
+    }
+
+    results
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send(socket: &UdpSocket, packets: &[Vec<u8>]) -> Vec<io::Result<usize>> {
+    packets.iter().map(|packet| socket.send(packet)).collect()
+}
+
+/// Receives a datagram into each of `bufs`, or as many as are waiting, after
+/// waiting for the first as long as the socket's read timeout. Returns the
+/// length of each. On Linux they are read in one recvmmsg call, elsewhere only
+/// one is read.
+#[cfg(target_os = "linux")]
+pub fn recv(socket: &UdpSocket, bufs: &mut [Vec<u8>]) -> io::Result<Vec<usize>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut iovecs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        })
+        .collect();
+    let mut messages: Vec<libc::mmsghdr> = iovecs.iter_mut().map(header).collect();
+
+    // SAFETY: each header points at one iovec, which points at a buffer, and
+    // both outlive the call.
+    let received = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            messages.as_mut_ptr(),
+            messages.len() as libc::c_uint,
+            libc::MSG_WAITFORONE as _,
+            std::ptr::null_mut(),
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(messages[..received as usize]
+        .iter()
+        .map(|message| message.msg_len as usize)
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn recv(socket: &UdpSocket, bufs: &mut [Vec<u8>]) -> io::Result<Vec<usize>> {
+    socket.recv(&mut bufs[0]).map(|len| vec![len])
+}
+
+/// Waits up to `timeout` for any of `sockets` to have a datagram waiting, and
+/// returns which do. A single socket is returned straight away, for `recv` to
+/// wait on with its read timeout; waiting on several needs Linux.
+#[cfg(target_os = "linux")]
+pub fn wait(sockets: &[UdpSocket], timeout: Duration) -> io::Result<Vec<usize>> {
+    use std::os::unix::io::AsRawFd;
+
+    if sockets.len() == 1 {
+        return Ok(vec![0]);
+    }
+
+    let mut fds: Vec<libc::pollfd> = sockets
+        .iter()
+        .map(|socket| libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // SAFETY: the pollfds are plain data that outlive the call.
+    let ready = unsafe {
+        libc::poll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            timeout.as_millis().max(1) as libc::c_int,
+        )
+    };
+    if ready < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(fds
+        .iter()
+        .enumerate()
+        .filter(|(_, fd)| fd.revents != 0)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn wait(sockets: &[UdpSocket], _timeout: Duration) -> io::Result<Vec<usize>> {
+    if sockets.len() == 1 {
+        return Ok(vec![0]);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "waiting on several sockets is only supported on Linux",
+    ))
+}
+
+// a header for a datagram in `iovec`, to or from the connected peer
+#[cfg(target_os = "linux")]
+fn header(iovec: &mut libc::iovec) -> libc::mmsghdr {
+    // SAFETY: mmsghdr is plain data, for which all zeroes is a valid value.
+    let mut message: libc::mmsghdr = unsafe { std::mem::zeroed() };
+    message.msg_hdr.msg_iov = iovec;
+    message.msg_hdr.msg_iovlen = 1;
+    message
+}
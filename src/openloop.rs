@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use trust_dns_resolver::proto::{
+    op::{Message, MessageType},
+    rr::{Name, RecordType},
+};
+
+use tracing::error;
+
+use crate::{
+    failurelog,
+    mismatch::{self, TimedOut},
+    mmsg,
+    rate::Arrival,
+    reporter::Reporter,
+    trace, wire, QueryConfig,
+};
+
+// how long the receiver blocks on the socket before checking for expired
+// queries and whether the run is over.
+const RECV_TICK: Duration = Duration::from_millis(10);
+
+// query id -> when it was meant to be sent, and its name and type
+type Outstanding = HashMap<u16, (Instant, Name, RecordType)>;
+
+/// Schedule is when an open-loop worker sends: on average one query every
+/// `interval`, spaced according to `arrival`, starting `phase` after the run
+/// begins.
+#[derive(Debug, Clone, Copy)]
+pub struct Schedule {
+    pub interval: Duration,
+    pub phase: Duration,
+    pub arrival: Arrival,
+}
+
+/// Sends queries on a fixed schedule whether or not earlier queries have been
+/// answered, from each of the worker's UDP sockets in turn.
+/// Responses are collected, in batches where the platform allows, by a
+/// receiver thread for each socket and their latency is measured from when the
+/// query was *supposed* to be sent, so a server that slows down shows up as
+/// latency instead of as a lower request rate.
+///
+/// The receivers report on their own, with reporters split from `reporter`.
+///
+/// Only UDP is supported; truncated responses are counted but not retried.
+pub fn perform_queries(qc: &mut QueryConfig, mut reporter: Reporter, schedule: Schedule) {
+    let mut sockets = Vec::with_capacity(qc.sockets);
+    for _ in 0..qc.sockets {
+        match wire::udp_socket(qc.nameserver, &qc.client_options) {
+            Ok(socket) => {
+                socket.set_read_timeout(Some(RECV_TICK)).unwrap();
+                sockets.push(socket);
+            }
+            Err(e) => {
+                error!("could not open socket to {}: {}", qc.nameserver, e);
+                return;
+            }
+        }
+    }
+
+    let outstanding: Arc<Mutex<Outstanding>> = Arc::new(Mutex::new(HashMap::new()));
+    let timed_out = Arc::new(Mutex::new(TimedOut::new()));
+    let sending = Arc::new(AtomicBool::new(true));
+
+    let receivers: Vec<_> = sockets
+        .iter()
+        .map(|socket| {
+            let socket = socket.try_clone().unwrap();
+            let outstanding = outstanding.clone();
+            let timed_out = timed_out.clone();
+            let sending = sending.clone();
+            let mut reporter = reporter.split();
+            let timeout = qc.client_options.timeout;
+            let expect_nxdomain = qc.expect_nxdomain;
+            let expected = qc.expected.clone();
+            let budget = qc.budget.clone();
+            let nameserver = qc.nameserver;
+            let failure_log = qc.failure_log.clone();
+            let mut trace = qc
+                .trace
+                .as_ref()
+                .map(|trace| trace::Buffer::new(trace.clone(), nameserver));
+
+            thread::spawn(move || {
+                let mut bufs = vec![vec![0u8; wire::MAX_UDP_SIZE]; mmsg::BATCH];
+
+                loop {
+                    reporter.tick();
+                    let received = mmsg::recv(&socket, &mut bufs).unwrap_or_default();
+                    let now = Instant::now();
+                    for (buf, len) in bufs.iter().zip(received) {
+                        reporter.details().bytes_received += len as u64;
+                        let message = match Message::from_vec(&buf[..len]) {
+                            Ok(message) if message.message_type() == MessageType::Response => {
+                                message
+                            }
+                            _ => {
+                                reporter.details().mismatches.malformed += 1;
+                                continue;
+                            }
+                        };
+
+                        let intended = mismatch::take(
+                            &mut outstanding.lock().unwrap(),
+                            &mut timed_out.lock().unwrap(),
+                            &message,
+                            &mut reporter.details().mismatches,
+                        );
+                        if let Some((intended, name, record_type)) = intended {
+                            let exchange = wire::Exchange {
+                                truncated: message.truncated(),
+                                message,
+                                size: len,
+                                attempts: 1,
+                                connect_time: None,
+                                tcp_fallback: false,
+                            };
+                            let latency = now.duration_since(intended);
+                            let res = Ok(exchange);
+                            if let Some(trace) = &mut trace {
+                                trace.record(intended, &name, record_type, &res, latency);
+                            }
+                            let success = reporter.details().record(
+                                record_type,
+                                &res,
+                                latency.as_nanos(),
+                                expect_nxdomain,
+                                expected.as_deref(),
+                            );
+                            if !success {
+                                failure_log.failed(nameserver, &name, record_type, latency, || {
+                                    failurelog::cause(&res)
+                                });
+                            }
+
+                            if let Some(budget) = &budget {
+                                budget.complete(1);
+                            }
+                        }
+                    }
+
+                    // anything that has waited longer than the timeout has failed
+                    let now = Instant::now();
+                    let mut outstanding = outstanding.lock().unwrap();
+                    let mut expired = 0;
+                    let mut timed_out = timed_out.lock().unwrap();
+                    outstanding.retain(|id, (intended, name, record_type)| {
+                        if now.duration_since(*intended) < timeout {
+                            return true;
+                        }
+
+                        timed_out.insert(*id);
+                        reporter.details().timed_out(*record_type);
+                        if let Some(trace) = &mut trace {
+                            trace.timed_out(*intended, name, *record_type);
+                        }
+                        failure_log.failed(
+                            nameserver,
+                            name,
+                            *record_type,
+                            now.duration_since(*intended),
+                            || wire::QueryError::Timeout.to_string(),
+                        );
+                        expired += 1;
+                        false
+                    });
+
+                    if expired > 0 {
+                        if let Some(budget) = &budget {
+                            budget.complete(expired);
+                        }
+                    }
+
+                    if !sending.load(Ordering::Relaxed) && outstanding.is_empty() {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    let mut trace = qc
+        .trace
+        .as_ref()
+        .map(|trace| trace::Buffer::new(trace.clone(), qc.nameserver));
+    let mut next_id: u16 = 0;
+    let mut next_socket = 0;
+    let mut next = Instant::now() + schedule.phase;
+
+    loop {
+        let intended = next;
+        if !reporter.wait_until(intended, &qc.finished) {
+            break;
+        }
+        next += schedule.arrival.gap(&mut rng, schedule.interval);
+
+        if let Some(budget) = &qc.budget {
+            if !budget.take() {
+                break;
+            }
+        }
+
+        let (name, record_type) = qc.names.next(&mut rng);
+        let record_type = record_type.unwrap_or_else(|| qc.qtypes.sample(&mut rng));
+
+        // skip ids that are still waiting on an answer
+        let id = {
+            let mut outstanding = outstanding.lock().unwrap();
+            let mut tries = 0;
+            loop {
+                next_id = next_id.wrapping_add(1);
+                tries += 1;
+                if !outstanding.contains_key(&next_id) || tries > u16::MAX as u32 {
+                    break;
+                }
+            }
+
+            outstanding.insert(next_id, (intended, name.clone(), record_type));
+            next_id
+        };
+
+        *reporter
+            .details()
+            .queries_by_type
+            .entry(record_type)
+            .or_default() += 1;
+
+        let socket = &sockets[next_socket];
+        next_socket = (next_socket + 1) % sockets.len();
+        let sent = wire::build_query(id, name.clone(), record_type, &qc.client_options)
+            .map_err(wire::QueryError::from)
+            .and_then(|packet| socket.send(&packet).map_err(wire::QueryError::from));
+
+        match sent {
+            Ok(len) => reporter.details().bytes_sent += len as u64,
+            Err(e) => {
+                outstanding.lock().unwrap().remove(&id);
+                let res = Err(e);
+                reporter.details().record(
+                    record_type,
+                    &res,
+                    0,
+                    qc.expect_nxdomain,
+                    qc.expected.as_deref(),
+                );
+                if let Some(trace) = &mut trace {
+                    trace.record(intended, &name, record_type, &res, Duration::ZERO);
+                }
+                qc.failure_log
+                    .failed(qc.nameserver, &name, record_type, Duration::ZERO, || {
+                        failurelog::cause(&res)
+                    });
+
+                if let Some(budget) = &qc.budget {
+                    budget.complete(1);
+                }
+            }
+        }
+    }
+
+    sending.store(false, Ordering::Relaxed);
+    for receiver in receivers {
+        receiver.join().unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+use std::{io, net::SocketAddr, sync::Mutex, time::SystemTime};
+
+use crate::{
+    http::Endpoint,
+    json::{object, Value},
+    prometheus::LATENCY_BUCKETS,
+    rcode_name, RunDetails,
+};
+
+// OTLP's AGGREGATION_TEMPORALITY_CUMULATIVE: every export carries the totals
+// since the start of the run
+const CUMULATIVE: u64 = 2;
+
+// a data point's own attributes, and its value fields
+type Point = (Vec<Value>, Vec<(&'static str, Value)>);
+
+/// Exporter pushes the running totals for each nameserver to an OpenTelemetry
+/// collector over OTLP/HTTP, in its JSON encoding, after every interval.
+pub struct Exporter {
+    endpoint: Endpoint,
+    headers: Vec<(String, String)>,
+    nameservers: Vec<SocketAddr>,
+    transport: String,
+    started: SystemTime,
+    totals: Mutex<Vec<RunDetails>>,
+}
+
+impl Exporter {
+    /// Returns an exporter for the collector at `url`, sending `headers` with
+    /// every request. A URL without a path has `/v1/metrics` added, as the
+    /// OTLP exporters do.
+    pub fn new(
+        url: &str,
+        headers: Vec<(String, String)>,
+        nameservers: Vec<SocketAddr>,
+        transport: String,
+    ) -> Result<Self, String> {
+        let totals = vec![RunDetails::default(); nameservers.len()];
+
+        Ok(Self {
+            endpoint: Endpoint::parse(url, "/v1/metrics")?,
+            headers,
+            nameservers,
+            transport,
+            started: SystemTime::now(),
+            totals: Mutex::new(totals),
+        })
+    }
+
+    /// Adds an interval's results for each nameserver to the totals, and
+    /// exports them.
+    pub fn export(&self, per_target: &[RunDetails]) -> io::Result<()> {
+        let body = {
+            let mut totals = self.totals.lock().unwrap();
+            for (total, details) in totals.iter_mut().zip(per_target) {
+                *total += details.clone();
+            }
+
+            self.request(&totals).to_string()
+        };
+
+        self.endpoint.post("application/json", &self.headers, &body)
+    }
+
+    // an ExportMetricsServiceRequest for `totals`
+    fn request(&self, totals: &[RunDetails]) -> Value {
+        let now = nanos(SystemTime::now());
+        let started = nanos(self.started);
+
+        // a data point for each nameserver, with any extra attributes
+        let points = |point: &dyn Fn(&RunDetails) -> Vec<Point>| {
+            let mut points = Vec::new();
+            for (nameserver, details) in self.nameservers.iter().zip(totals) {
+                for (mut attributes, fields) in point(details) {
+                    attributes.extend(vec![
+                        attribute("server.address", nameserver.ip().to_string()),
+                        object(vec![
+                            ("key", "server.port".into()),
+                            (
+                                "value",
+                                object(vec![("intValue", nameserver.port().to_string().into())]),
+                            ),
+                        ]),
+                        attribute("network.transport", self.transport.clone()),
+                    ]);
+
+                    let mut fields = fields;
+                    fields.insert(0, ("attributes", attributes.into()));
+                    fields.push(("startTimeUnixNano", started.clone()));
+                    fields.push(("timeUnixNano", now.clone()));
+                    points.push(object(fields));
+                }
+            }
+
+            Value::from(points)
+        };
+
+        let counter =
+            |name: &str, unit: &str, description: &str, value: &dyn Fn(&RunDetails) -> u64| {
+                sum(
+                    name,
+                    unit,
+                    description,
+                    points(&|details| {
+                        vec![(vec![], vec![("asInt", value(details).to_string().into())])]
+                    }),
+                )
+            };
+
+        let metrics = vec![
+            counter(
+                "nsbench.query.successes",
+                "{query}",
+                "Queries answered as expected.",
+                &|details| details.successes,
+            ),
+            sum(
+                "nsbench.query.failures",
+                "{query}",
+                "Failed queries, by cause.",
+                points(&|details| {
+                    let causes = &details.failure_causes;
+                    [
+                        ("timeout", causes.timeout),
+                        ("servfail", causes.servfail),
+                        ("refused", causes.refused),
+                        ("nxdomain", causes.nxdomain),
+                        ("network", causes.network),
+                        ("wrong_answer", causes.wrong_answer),
+                        ("wrong_flags", causes.wrong_flags),
+                        ("other", causes.other),
+                    ]
+                    .iter()
+                    .map(|(cause, count)| {
+                        (
+                            vec![attribute("error.type", cause.to_string())],
+                            vec![("asInt", count.to_string().into())],
+                        )
+                    })
+                    .collect()
+                }),
+            ),
+            sum(
+                "nsbench.responses",
+                "{response}",
+                "Responses, by RCODE.",
+                points(&|details| {
+                    details
+                        .rcodes
+                        .iter()
+                        .map(|(rcode, count)| {
+                            (
+                                vec![attribute("dns.response_code", rcode_name(*rcode))],
+                                vec![("asInt", count.to_string().into())],
+                            )
+                        })
+                        .collect()
+                }),
+            ),
+            counter(
+                "nsbench.truncated",
+                "{response}",
+                "Responses with the TC bit set.",
+                &|details| details.truncated,
+            ),
+            counter(
+                "nsbench.tcp_fallbacks",
+                "{query}",
+                "Queries retried over TCP.",
+                &|details| details.tcp_fallbacks,
+            ),
+            counter(
+                "nsbench.bytes_sent",
+                "By",
+                "Bytes sent to the nameserver.",
+                &|details| details.bytes_sent,
+            ),
+            counter(
+                "nsbench.bytes_received",
+                "By",
+                "Bytes received from the nameserver.",
+                &|details| details.bytes_received,
+            ),
+            object(vec![
+                ("name", "nsbench.query.duration".into()),
+                ("unit", "s".into()),
+                ("description", "Latency of successful queries.".into()),
+                (
+                    "histogram",
+                    object(vec![
+                        ("aggregationTemporality", CUMULATIVE.into()),
+                        (
+                            "dataPoints",
+                            points(&|details| vec![(vec![], histogram(details))]),
+                        ),
+                    ]),
+                ),
+            ]),
+        ];
+
+        object(vec![(
+            "resourceMetrics",
+            vec![object(vec![
+                (
+                    "resource",
+                    object(vec![(
+                        "attributes",
+                        vec![attribute("service.name", "nsbench".to_string())].into(),
+                    )]),
+                ),
+                (
+                    "scopeMetrics",
+                    vec![object(vec![
+                        (
+                            "scope",
+                            object(vec![
+                                ("name", "nsbench".into()),
+                                ("version", env!("CARGO_PKG_VERSION").into()),
+                            ]),
+                        ),
+                        ("metrics", metrics.into()),
+                    ])]
+                    .into(),
+                ),
+            ])]
+            .into(),
+        )])
+    }
+}
+
+// a monotonic, cumulative sum made of `points`
+fn sum(name: &str, unit: &str, description: &str, points: Value) -> Value {
+    object(vec![
+        ("name", name.into()),
+        ("unit", unit.into()),
+        ("description", description.into()),
+        (
+            "sum",
+            object(vec![
+                ("aggregationTemporality", CUMULATIVE.into()),
+                ("isMonotonic", true.into()),
+                ("dataPoints", points),
+            ]),
+        ),
+    ])
+}
+
+// the fields of a histogram data point for the latencies in `details`; each
+// bucket counts the latencies above the previous bound, up to its own
+fn histogram(details: &RunDetails) -> Vec<(&'static str, Value)> {
+    let latencies = &details.latencies;
+
+    let mut counts = Vec::new();
+    let mut below = 0;
+    for bound in LATENCY_BUCKETS {
+        let at_most = latencies.count_at_most((bound * 1_000_000_000.0) as u64);
+        counts.push(Value::from((at_most - below).to_string()));
+        below = at_most;
+    }
+    counts.push((latencies.count() - below).to_string().into());
+
+    let mut fields = vec![
+        ("count", latencies.count().to_string().into()),
+        (
+            "sum",
+            (details.total_latency as f64 / 1_000_000_000.0).into(),
+        ),
+        ("bucketCounts", counts.into()),
+        (
+            "explicitBounds",
+            LATENCY_BUCKETS
+                .iter()
+                .map(|bound| Value::from(*bound))
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+    ];
+    if latencies.count() > 0 {
+        fields.push(("min", (latencies.min() as f64 / 1_000_000_000.0).into()));
+        fields.push(("max", (latencies.max() as f64 / 1_000_000_000.0).into()));
+    }
+
+    fields
+}
+
+fn attribute(key: &str, value: String) -> Value {
+    object(vec![
+        ("key", key.into()),
+        ("value", object(vec![("stringValue", value.into())])),
+    ])
+}
+
+// a timestamp as OTLP's JSON encoding has it: nanoseconds since the epoch, as
+// a string, since it doesn't fit in a double
+fn nanos(time: SystemTime) -> Value {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+        .into()
+}
+
+/// Parses OTEL_EXPORTER_OTLP_HEADERS, a comma separated list of
+/// `<name>=<value>` pairs to send with every export.
+pub fn parse_headers(s: &str) -> Result<Vec<(String, String)>, String> {
+    s.split(',')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| {
+            header
+                .split_once('=')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .ok_or_else(|| format!("invalid header '{}': expected <name>=<value>", header))
+        })
+        .collect()
+}
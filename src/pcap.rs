@@ -0,0 +1,387 @@
+use std::{convert::TryInto, path::Path, time::Duration};
+
+use trust_dns_resolver::{
+    proto::{
+        op::{Message, MessageType, OpCode},
+        rr::RecordType,
+    },
+    Name,
+};
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// A DNS query found in a capture, with its time relative to the first one.
+#[derive(Debug, Clone)]
+pub struct CapturedQuery {
+    pub offset: Duration,
+    pub name: Name,
+    pub record_type: RecordType,
+}
+
+/// Reads the DNS queries out of a classic (not pcapng) capture file. Only
+/// queries carried over UDP are found; TCP streams are not reassembled.
+/// Anything that isn't a well-formed single-question query is skipped.
+pub fn read_queries(path: &Path) -> Result<Vec<CapturedQuery>, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+    parse_queries(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// reads the queries out of the capture in `data`
+fn parse_queries(data: &[u8]) -> Result<Vec<CapturedQuery>, String> {
+    if data.len() < 24 {
+        return Err("too short to be a pcap file".to_string());
+    }
+
+    let magic = u32::from_le_bytes(data[0..4].try_into().unwrap());
+    let (big_endian, nanos) = match magic {
+        MAGIC_MICROS => (false, false),
+        MAGIC_NANOS => (false, true),
+        _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+        _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+        _ => return Err("not a pcap file (pcapng is not supported)".to_string()),
+    };
+
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+
+    let linktype = read_u32(&data[20..24]) & 0x0fff_ffff;
+    let mut queries = Vec::new();
+    let mut first: Option<Duration> = None;
+    let mut pos = 24;
+
+    while pos + 16 <= data.len() {
+        let record = pos;
+        let secs = read_u32(&data[pos..pos + 4]) as u64;
+        let frac = read_u32(&data[pos + 4..pos + 8]);
+        let caplen = read_u32(&data[pos + 8..pos + 12]) as usize;
+        pos += 16;
+
+        if pos + caplen > data.len() {
+            break;
+        }
+        let packet = &data[pos..pos + caplen];
+        pos += caplen;
+
+        // the fraction of a second is in micro- or nanoseconds, and a
+        // whole second or more of it means the capture is corrupt
+        let timestamp = match (nanos, frac) {
+            (true, 0..=999_999_999) => Duration::new(secs, frac),
+            (false, 0..=999_999) => Duration::new(secs, frac * 1000),
+            _ => {
+                return Err(format!(
+                    "the packet at offset {} has an invalid timestamp",
+                    record
+                ))
+            }
+        };
+
+        let payload = match link_payload(linktype, packet).and_then(udp_payload) {
+            Some(payload) => payload,
+            None => continue,
+        };
+
+        if let Some((name, record_type)) = dns_query(payload) {
+            let first = *first.get_or_insert(timestamp);
+            queries.push(CapturedQuery {
+                offset: timestamp.saturating_sub(first),
+                name,
+                record_type,
+            });
+        }
+    }
+
+    Ok(queries)
+}
+
+/// Strips the link layer header, returning the IP packet.
+fn link_payload(linktype: u32, packet: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(packet),
+        LINKTYPE_NULL => packet.get(4..),
+        LINKTYPE_LINUX_SLL => packet.get(16..),
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes(packet.get(12..14)?.try_into().ok()?);
+            let mut offset = 14;
+
+            if ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes(packet.get(16..18)?.try_into().ok()?);
+                offset = 18;
+            }
+
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => packet.get(offset..),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the payload of a UDP datagram inside an IPv4 or IPv6 packet.
+/// Fragments and IPv6 extension headers are not handled.
+fn udp_payload(ip: &[u8]) -> Option<&[u8]> {
+    let udp = match ip.first()? >> 4 {
+        4 => {
+            let header_len = ((ip[0] & 0x0f) as usize) * 4;
+            if *ip.get(9)? != IPPROTO_UDP {
+                return None;
+            }
+            ip.get(header_len..)?
+        }
+        6 => {
+            if *ip.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+            ip.get(40..)?
+        }
+        _ => return None,
+    };
+
+    udp.get(8..)
+}
+
+fn dns_query(payload: &[u8]) -> Option<(Name, RecordType)> {
+    let message = Message::from_vec(payload).ok()?;
+
+    if message.message_type() != MessageType::Query
+        || message.op_code() != OpCode::Query
+        || message.queries().len() != 1
+    {
+        return None;
+    }
+
+    let query = &message.queries()[0];
+    Some((query.name().clone(), query.query_type()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use trust_dns_resolver::proto::op::Query;
+
+    // a capture built up in memory, one packet at a time
+    struct Capture {
+        data: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl Capture {
+        fn new(magic: u32, linktype: u32, big_endian: bool) -> Self {
+            let mut capture = Self {
+                data: Vec::new(),
+                big_endian,
+            };
+            capture.u32(magic);
+            // version 2.4, then the time zone, accuracy and snapshot length
+            capture.u16(2);
+            capture.u16(4);
+            capture.u32(0);
+            capture.u32(0);
+            capture.u32(65535);
+            capture.u32(linktype);
+            capture
+        }
+
+        fn u16(&mut self, n: u16) {
+            self.data.extend(if self.big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            });
+        }
+
+        fn u32(&mut self, n: u32) {
+            self.data.extend(if self.big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            });
+        }
+
+        fn packet(mut self, secs: u32, frac: u32, packet: &[u8]) -> Self {
+            self.u32(secs);
+            self.u32(frac);
+            self.u32(packet.len() as u32);
+            self.u32(packet.len() as u32);
+            self.data.extend(packet);
+            self
+        }
+    }
+
+    fn message(name: &str, record_type: RecordType, message_type: MessageType) -> Vec<u8> {
+        let mut message = Message::new();
+        message
+            .set_id(1)
+            .set_message_type(message_type)
+            .set_op_code(OpCode::Query)
+            .add_query(Query::query(Name::from_ascii(name).unwrap(), record_type));
+        message.to_vec().unwrap()
+    }
+
+    fn query(name: &str, record_type: RecordType) -> Vec<u8> {
+        message(name, record_type, MessageType::Query)
+    }
+
+    // `payload` in a datagram to port 53, in an IPv4 packet of `protocol`
+    // in an Ethernet frame
+    fn ethernet_ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend(ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend([0x45, 0, 0, 0, 0, 0, 0, 0, 64, protocol, 0, 0]);
+        frame.extend([192, 0, 2, 1, 192, 0, 2, 53]);
+        frame.extend([0xc0, 0x00, 0, 53, 0, 0, 0, 0]);
+        frame.extend(payload);
+        frame
+    }
+
+    // `payload` in a UDP datagram in a bare IPv6 packet
+    fn ipv6(payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0, 0, 0, IPPROTO_UDP, 64];
+        packet.extend([0u8; 32]);
+        packet.extend([0xc0, 0x00, 0, 53, 0, 0, 0, 0]);
+        packet.extend(payload);
+        packet
+    }
+
+    fn found(queries: &[CapturedQuery]) -> Vec<(Duration, String, RecordType)> {
+        queries
+            .iter()
+            .map(|query| (query.offset, query.name.to_string(), query.record_type))
+            .collect()
+    }
+
+    #[test]
+    fn reads_queries_with_their_offsets() {
+        let capture = Capture::new(MAGIC_MICROS, LINKTYPE_ETHERNET, false)
+            .packet(
+                100,
+                500_000,
+                &ethernet_ipv4(IPPROTO_UDP, &query("a.example.", RecordType::A)),
+            )
+            // answers, other protocols and anything that isn't DNS are skipped
+            .packet(
+                100,
+                600_000,
+                &ethernet_ipv4(
+                    IPPROTO_UDP,
+                    &message("a.example.", RecordType::A, MessageType::Response),
+                ),
+            )
+            .packet(
+                100,
+                700_000,
+                &ethernet_ipv4(6, &query("b.example.", RecordType::A)),
+            )
+            .packet(100, 800_000, &ethernet_ipv4(IPPROTO_UDP, b"not dns"))
+            .packet(
+                101,
+                750_000,
+                &ethernet_ipv4(IPPROTO_UDP, &query("c.example.", RecordType::AAAA)),
+            );
+
+        assert_eq!(
+            found(&parse_queries(&capture.data).unwrap()),
+            vec![
+                (Duration::ZERO, "a.example.".to_string(), RecordType::A),
+                (
+                    Duration::from_millis(1250),
+                    "c.example.".to_string(),
+                    RecordType::AAAA
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_big_endian_nanosecond_captures() {
+        let capture = Capture::new(MAGIC_NANOS, LINKTYPE_RAW, true)
+            .packet(7, 999_999_999, &ipv6(&query("a.example.", RecordType::MX)))
+            .packet(8, 1, &ipv6(&query("b.example.", RecordType::TXT)));
+
+        assert_eq!(
+            found(&parse_queries(&capture.data).unwrap()),
+            vec![
+                (Duration::ZERO, "a.example.".to_string(), RecordType::MX),
+                (
+                    Duration::from_nanos(2),
+                    "b.example.".to_string(),
+                    RecordType::TXT
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_fraction_of_a_second_or_more() {
+        let packet = ipv6(&query("a.example.", RecordType::A));
+        let cases = [
+            (MAGIC_MICROS, 1_000_000),
+            // too big to scale to nanoseconds in a u32
+            (MAGIC_MICROS, 4_294_968),
+            (MAGIC_MICROS, u32::MAX),
+            (MAGIC_NANOS, 1_000_000_000),
+        ];
+
+        for (magic, frac) in cases {
+            let capture = Capture::new(magic, LINKTYPE_RAW, false)
+                .packet(1, 0, &packet)
+                .packet(2, frac, &packet);
+            let record = 24 + 16 + packet.len();
+            assert_eq!(
+                parse_queries(&capture.data).unwrap_err(),
+                format!("the packet at offset {} has an invalid timestamp", record),
+                "{:x} {}",
+                magic,
+                frac
+            );
+        }
+    }
+
+    #[test]
+    fn stops_at_a_truncated_packet() {
+        let packet = ipv6(&query("a.example.", RecordType::A));
+        let mut capture = Capture::new(MAGIC_MICROS, LINKTYPE_RAW, false)
+            .packet(1, 0, &packet)
+            .packet(2, 0, &packet);
+        capture.data.truncate(capture.data.len() - 1);
+
+        assert_eq!(parse_queries(&capture.data).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_capture() {
+        assert_eq!(
+            parse_queries(&[0u8; 10]).unwrap_err(),
+            "too short to be a pcap file"
+        );
+
+        // a pcapng section header block
+        let capture = Capture::new(0x0a0d_0d0a, LINKTYPE_ETHERNET, false);
+        assert_eq!(
+            parse_queries(&capture.data).unwrap_err(),
+            "not a pcap file (pcapng is not supported)"
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{rcode_name, RunDetails};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+// how long a scraper gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Metrics holds the running totals for each nameserver, from the start of
+/// the run, for scraping.
+pub struct Metrics {
+    nameservers: Vec<SocketAddr>,
+    totals: Mutex<Vec<RunDetails>>,
+}
+
+impl Metrics {
+    pub fn new(nameservers: Vec<SocketAddr>) -> Self {
+        let totals = vec![RunDetails::default(); nameservers.len()];
+        Self {
+            nameservers,
+            totals: Mutex::new(totals),
+        }
+    }
+
+    /// Adds a worker's report for nameserver number `target`.
+    pub fn add(&self, target: usize, details: RunDetails) {
+        self.totals.lock().unwrap()[target] += details;
+    }
+
+    /// Renders the totals in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let totals = self.totals.lock().unwrap();
+        let mut out = String::new();
+
+        let mut counter = |name: &str, help: &str, value: &dyn Fn(&RunDetails) -> u64| {
+            writeln!(out, "# HELP nsbench_{} {}", name, help).unwrap();
+            writeln!(out, "# TYPE nsbench_{} counter", name).unwrap();
+            for (nameserver, details) in self.nameservers.iter().zip(totals.iter()) {
+                writeln!(
+                    out,
+                    "nsbench_{}{{nameserver=\"{}\"}} {}",
+                    name,
+                    nameserver,
+                    value(details)
+                )
+                .unwrap();
+            }
+        };
+
+        counter("successes_total", "Queries answered as expected.", &|d| {
+            d.successes
+        });
+        counter("truncated_total", "Responses with the TC bit set.", &|d| {
+            d.truncated
+        });
+        counter("tcp_fallbacks_total", "Queries retried over TCP.", &|d| {
+            d.tcp_fallbacks
+        });
+        counter("bytes_sent_total", "Bytes sent to the nameserver.", &|d| {
+            d.bytes_sent
+        });
+        counter(
+            "bytes_received_total",
+            "Bytes received from the nameserver.",
+            &|d| d.bytes_received,
+        );
+
+        writeln!(
+            out,
+            "# HELP nsbench_failures_total Failed queries, by cause."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE nsbench_failures_total counter").unwrap();
+        for (nameserver, details) in self.nameservers.iter().zip(totals.iter()) {
+            let causes = &details.failure_causes;
+            for (cause, count) in [
+                ("timeout", causes.timeout),
+                ("servfail", causes.servfail),
+                ("refused", causes.refused),
+                ("nxdomain", causes.nxdomain),
+                ("network", causes.network),
+                ("wrong_answer", causes.wrong_answer),
+                ("wrong_flags", causes.wrong_flags),
+                ("other", causes.other),
+            ] {
+                writeln!(
+                    out,
+                    "nsbench_failures_total{{nameserver=\"{}\",cause=\"{}\"}} {}",
+                    nameserver, cause, count
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(out, "# HELP nsbench_responses_total Responses, by RCODE.").unwrap();
+        writeln!(out, "# TYPE nsbench_responses_total counter").unwrap();
+        for (nameserver, details) in self.nameservers.iter().zip(totals.iter()) {
+            for (rcode, count) in &details.rcodes {
+                writeln!(
+                    out,
+                    "nsbench_responses_total{{nameserver=\"{}\",rcode=\"{}\"}} {}",
+                    nameserver,
+                    rcode_name(*rcode),
+                    count
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP nsbench_latency_seconds Latency of successful queries."
+        )
+        .unwrap();
+        writeln!(out, "# TYPE nsbench_latency_seconds histogram").unwrap();
+        for (nameserver, details) in self.nameservers.iter().zip(totals.iter()) {
+            for bound in LATENCY_BUCKETS {
+                writeln!(
+                    out,
+                    "nsbench_latency_seconds_bucket{{nameserver=\"{}\",le=\"{}\"}} {}",
+                    nameserver,
+                    bound,
+                    details
+                        .latencies
+                        .count_at_most((bound * 1_000_000_000.0) as u64)
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "nsbench_latency_seconds_bucket{{nameserver=\"{}\",le=\"+Inf\"}} {}",
+                nameserver,
+                details.latencies.count()
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "nsbench_latency_seconds_sum{{nameserver=\"{}\"}} {}",
+                nameserver,
+                details.total_latency as f64 / 1_000_000_000.0
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "nsbench_latency_seconds_count{{nameserver=\"{}\"}} {}",
+                nameserver,
+                details.latencies.count()
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+/// Parses the address to serve metrics on. A bare `:port` listens on every
+/// interface.
+pub fn parse_addr(s: &str) -> Result<SocketAddr, String> {
+    let addr = match s.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => s.to_string(),
+    };
+
+    addr.parse()
+        .map_err(|_| format!("invalid address '{}': expected [<ip>]:<port>", s))
+}
+
+/// Serves `metrics` at /metrics on `addr` from a background thread for the
+/// rest of the process's life.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &metrics) {
+                warn!("could not serve metrics: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    // the headers aren't needed, but have to be read before answering
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut stream = reader.into_inner();
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,65 @@
+//! Finding out whether a resolver minimizes the names it sends to
+//! authoritative servers (RFC 9156), which changes how many queries each one
+//! of a benchmark's costs upstream.
+
+use std::net::SocketAddr;
+
+use trust_dns_resolver::proto::rr::{Name, RData, RecordType};
+
+use crate::{rcode_name, wire};
+
+/// The test name internet.nl serves for this. Its zone is delegated so that
+/// a resolver that minimizes gets a TXT answer starting with HOORAY, and one
+/// that sends the full name to every server one starting with NO.
+pub const TEST_NAME: &str = "a.b.qnamemin-test.internet.nl.";
+
+/// Minimization is what a resolver's answer for the test name said of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Minimization {
+    Enabled,
+    Disabled,
+    /// The answer didn't tell, with what it was instead.
+    Unknown(String),
+}
+
+impl std::fmt::Display for Minimization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Minimization::Enabled => f.write_str("enabled"),
+            Minimization::Disabled => f.write_str("disabled"),
+            Minimization::Unknown(e) => write!(f, "unknown ({})", e),
+        }
+    }
+}
+
+/// Asks `nameserver` for the TXT record of `name`, a name in a zone set up
+/// like that of TEST_NAME, and infers from the answer whether it minimizes.
+pub fn detect(nameserver: SocketAddr, options: wire::ClientOptions, name: &Name) -> Minimization {
+    let mut client = wire::Client::new(nameserver, options);
+    let message = match client.query(name.clone(), RecordType::TXT) {
+        Ok(exchange) => exchange.message,
+        Err(e) => return Minimization::Unknown(e.to_string()),
+    };
+
+    let text = message
+        .answers()
+        .iter()
+        .find_map(|record| match record.data() {
+            Some(RData::TXT(txt)) => Some(
+                txt.txt_data()
+                    .iter()
+                    .map(|data| String::from_utf8_lossy(data))
+                    .collect::<String>(),
+            ),
+            _ => None,
+        });
+    match text {
+        Some(text) if text.starts_with("HOORAY") => Minimization::Enabled,
+        Some(text) if text.starts_with("NO") => Minimization::Disabled,
+        Some(text) => Minimization::Unknown(format!("answered {:?}", text)),
+        None => Minimization::Unknown(format!(
+            "{} with no TXT answer",
+            rcode_name(message.response_code().into())
+        )),
+    }
+}
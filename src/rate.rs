@@ -0,0 +1,657 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+// the bucket holds this much time's worth of tokens, so a worker that was
+// descheduled briefly can catch up without the offered load turning bursty.
+const BURST: Duration = Duration::from_millis(10);
+
+// longest a waiting worker sleeps before checking whether the run is over.
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Arrival is how queries are spread out within a second: evenly, or as a
+/// Poisson process, whose exponentially distributed gaps look more like the
+/// combined traffic of many independent clients.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arrival {
+    Fixed,
+    Poisson,
+}
+
+impl Arrival {
+    /// Returns the time until the next query for a stream with an average gap
+    /// of `interval`.
+    pub fn gap<R: Rng>(&self, rng: &mut R, interval: Duration) -> Duration {
+        match self {
+            Arrival::Fixed => interval,
+            Arrival::Poisson => interval.mul_f64(-(1.0 - rng.gen::<f64>()).ln()),
+        }
+    }
+}
+
+impl FromStr for Arrival {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Arrival::Fixed),
+            "poisson" => Ok(Arrival::Poisson),
+            _ => Err(format!(
+                "invalid arrival '{}': expected fixed or poisson",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Arrival {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arrival::Fixed => write!(f, "fixed"),
+            Arrival::Poisson => write!(f, "poisson"),
+        }
+    }
+}
+
+/// Wave is a rate that swings smoothly between `min` and `max` queries per
+/// second and back every `period`, starting at `min`.
+#[derive(Debug, Clone, Copy)]
+pub struct Wave {
+    pub min: f64,
+    pub max: f64,
+    pub period: Duration,
+}
+
+impl Wave {
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        let phase = elapsed.as_secs_f64() / self.period.as_secs_f64() * std::f64::consts::TAU;
+        self.min + (self.max - self.min) * (1.0 - phase.cos()) / 2.0
+    }
+}
+
+impl FromStr for Wave {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "invalid wave '{}': expected <min qps>:<max qps>:<period seconds>",
+                s
+            )
+        };
+
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 3 {
+            return Err(err());
+        }
+
+        let min = parts[0].parse::<u64>().map_err(|_| err())?;
+        let max = parts[1].parse::<u64>().map_err(|_| err())?;
+        let secs = parts[2].parse::<u64>().map_err(|_| err())?;
+
+        if secs == 0 {
+            return Err(format!("invalid wave '{}': period must be at least 1s", s));
+        }
+
+        Ok(Self {
+            min: min as f64,
+            max: max as f64,
+            period: Duration::from_secs(secs),
+        })
+    }
+}
+
+impl std::fmt::Display for Wave {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/s to {}/s every {}s",
+            self.min,
+            self.max,
+            self.period.as_secs()
+        )
+    }
+}
+
+/// Profile is a query rate that changes over the course of a run.
+#[derive(Debug, Clone, Copy)]
+pub enum Profile {
+    Ramp(Ramp),
+    Wave(Wave),
+}
+
+impl Profile {
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        match self {
+            Profile::Ramp(ramp) => ramp.rate_at(elapsed),
+            Profile::Wave(wave) => wave.rate_at(elapsed),
+        }
+    }
+
+    /// Returns how long until the rate stops changing, if it ever does.
+    pub fn settles_after(&self) -> Option<Duration> {
+        match self {
+            Profile::Ramp(ramp) => Some(ramp.duration),
+            Profile::Wave(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Profile::Ramp(ramp) => write!(f, "{}", ramp),
+            Profile::Wave(wave) => write!(f, "{}", wave),
+        }
+    }
+}
+
+/// Burst is `size` queries sent back to back at the start of every `interval`,
+/// given on the command line as `<size>:<interval>`, e.g. `500:100ms`.
+#[derive(Debug, Clone, Copy)]
+pub struct Burst {
+    pub size: u64,
+    pub interval: Duration,
+}
+
+impl FromStr for Burst {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (size, interval) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid burst '{}': expected <size>:<interval>", s))?;
+
+        let size = match size.parse::<u64>() {
+            Ok(size) if size > 0 => size,
+            _ => return Err(format!("invalid burst size '{}'", size)),
+        };
+        let interval = crate::parse_duration(interval)?;
+        if interval.is_zero() {
+            return Err(format!(
+                "invalid burst '{}': interval must be above zero",
+                s
+            ));
+        }
+
+        Ok(Self { size, interval })
+    }
+}
+
+impl std::fmt::Display for Burst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} queries every {:?}", self.size, self.interval)
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+    arrival: Arrival,
+    burst: Option<Burst>,
+    // when the next token (or burst of them) arrives, for Poisson arrivals and
+    // bursts
+    next: Instant,
+}
+
+impl Bucket {
+    fn capacity(&self) -> f64 {
+        (self.rate * BURST.as_secs_f64()).max(1.0)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+
+        if let Some(burst) = self.burst {
+            // a burst that isn't used up by the next one is lost, so bursts
+            // never pile on top of each other
+            if self.next <= now {
+                self.tokens = burst.size as f64;
+                while self.next <= now {
+                    self.next += burst.interval;
+                }
+            }
+
+            self.last = now;
+            return;
+        }
+
+        match self.arrival {
+            Arrival::Fixed => {
+                self.tokens = (self.tokens
+                    + now.duration_since(self.last).as_secs_f64() * self.rate)
+                    .min(self.capacity());
+            }
+            Arrival::Poisson => {
+                while self.next <= now {
+                    self.tokens = (self.tokens + 1.0).min(self.capacity());
+                    self.next += self.poisson_gap();
+                }
+            }
+        }
+
+        self.last = now;
+    }
+
+    fn poisson_gap(&self) -> Duration {
+        if self.rate <= 0.0 {
+            return MAX_WAIT;
+        }
+
+        Arrival::Poisson.gap(
+            &mut rand::thread_rng(),
+            Duration::from_secs_f64(1.0 / self.rate),
+        )
+    }
+}
+
+/// RateLimiter is a token bucket shared by a group of workers, holding their
+/// combined query rate to `rate` per second.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, arrival: Arrival) -> Self {
+        let mut bucket = Bucket {
+            rate,
+            tokens: 0.0,
+            last: Instant::now(),
+            arrival,
+            burst: None,
+            next: Instant::now(),
+        };
+        bucket.next += bucket.poisson_gap();
+
+        Self {
+            bucket: Mutex::new(bucket),
+        }
+    }
+
+    /// Returns a limiter that hands out `burst.size` queries at the start of
+    /// every `burst.interval`, to be sent as fast as the workers can.
+    pub fn burst(burst: Burst) -> Self {
+        Self {
+            bucket: Mutex::new(Bucket {
+                rate: burst.size as f64 / burst.interval.as_secs_f64(),
+                tokens: 0.0,
+                last: Instant::now(),
+                arrival: Arrival::Fixed,
+                burst: Some(burst),
+                next: Instant::now(),
+            }),
+        }
+    }
+
+    /// Changes the rate, keeping any tokens already in the bucket.
+    pub fn set_rate(&self, rate: f64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.rate = rate;
+
+        // the gaps are memoryless, so the next arrival can simply be redrawn
+        // at the new rate
+        bucket.next = Instant::now() + bucket.poisson_gap();
+    }
+
+    /// Takes a token if one is available, without waiting.
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+
+        false
+    }
+
+    /// Blocks until a query may be sent, calling `idle` at least every 100ms
+    /// while it waits. Returns false without taking a token if `finished` is
+    /// set while waiting.
+    pub fn acquire(&self, finished: &AtomicBool, mut idle: impl FnMut()) -> bool {
+        loop {
+            if finished.load(Ordering::Relaxed) {
+                return false;
+            }
+
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                bucket.refill();
+
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return true;
+                }
+
+                if bucket.rate <= 0.0 {
+                    MAX_WAIT
+                } else if bucket.arrival == Arrival::Poisson || bucket.burst.is_some() {
+                    bucket
+                        .next
+                        .saturating_duration_since(Instant::now())
+                        .min(MAX_WAIT)
+                } else {
+                    Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.rate).min(MAX_WAIT)
+                }
+            };
+
+            thread::sleep(wait);
+            idle();
+        }
+    }
+}
+
+/// Ramp is a linear change in rate from `start` to `end` queries per second
+/// over `duration`, after which the rate holds at `end`.
+#[derive(Debug, Clone, Copy)]
+pub struct Ramp {
+    pub start: f64,
+    pub end: f64,
+    pub duration: Duration,
+}
+
+impl Ramp {
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.duration {
+            return self.end;
+        }
+
+        let progress = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        self.start + (self.end - self.start) * progress
+    }
+}
+
+impl FromStr for Ramp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            format!(
+                "invalid ramp '{}': expected <start qps>:<end qps>:<seconds>",
+                s
+            )
+        };
+
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 3 {
+            return Err(err());
+        }
+
+        let start = parts[0].parse::<u64>().map_err(|_| err())?;
+        let end = parts[1].parse::<u64>().map_err(|_| err())?;
+        let secs = parts[2].parse::<u64>().map_err(|_| err())?;
+
+        if secs == 0 {
+            return Err(format!(
+                "invalid ramp '{}': duration must be at least 1s",
+                s
+            ));
+        }
+
+        Ok(Self {
+            start: start as f64,
+            end: end as f64,
+            duration: Duration::from_secs(secs),
+        })
+    }
+}
+
+impl std::fmt::Display for Ramp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/s to {}/s over {}s",
+            self.start,
+            self.end,
+            self.duration.as_secs()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a limiter whose bucket last filled `since` ago
+    fn limiter(rate: f64, since: Duration) -> RateLimiter {
+        let limiter = RateLimiter::new(rate, Arrival::Fixed);
+        limiter.bucket.lock().unwrap().last -= since;
+        limiter
+    }
+
+    fn tokens(limiter: &RateLimiter) -> f64 {
+        let mut bucket = limiter.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.tokens
+    }
+
+    #[test]
+    fn refills_at_the_rate() {
+        let cases = [
+            // half a token in 5ms at 100/s, give or take how long the test
+            // takes
+            (100.0, Duration::from_millis(5), 0.5, 1.0),
+            // the bucket holds 10ms worth
+            (1000.0, Duration::from_secs(1), 10.0, 10.0),
+            (100_000.0, Duration::from_secs(1), 1000.0, 1000.0),
+            // and at least one token, however slow the rate
+            (10.0, Duration::from_secs(1), 1.0, 1.0),
+        ];
+
+        for (rate, since, min, max) in cases {
+            let tokens = tokens(&limiter(rate, since));
+            assert!(
+                tokens >= min && tokens <= max,
+                "{} {:?}: {}",
+                rate,
+                since,
+                tokens
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_tokens_when_the_rate_changes() {
+        let limiter = limiter(1000.0, Duration::from_secs(1));
+        limiter.set_rate(100_000.0);
+        assert!((10.0..11.0).contains(&tokens(&limiter)));
+
+        limiter.bucket.lock().unwrap().last -= Duration::from_secs(1);
+        assert_eq!(tokens(&limiter), 1000.0);
+
+        for _ in 0..1000 {
+            assert!(limiter.try_acquire());
+        }
+    }
+
+    #[test]
+    fn a_zero_rate_sends_nothing() {
+        for arrival in [Arrival::Fixed, Arrival::Poisson] {
+            let limiter = RateLimiter::new(0.0, arrival);
+            limiter.bucket.lock().unwrap().last -= Duration::from_secs(1);
+            assert_eq!(tokens(&limiter), 0.0, "{}", arrival);
+            assert!(!limiter.try_acquire(), "{}", arrival);
+        }
+
+        // a worker waiting on it still notices the run ending
+        let limiter = RateLimiter::new(0.0, Arrival::Fixed);
+        let finished = AtomicBool::new(false);
+        assert!(!limiter.acquire(&finished, || finished.store(true, Ordering::Relaxed)));
+    }
+
+    #[test]
+    fn acquires_once_a_token_arrives() {
+        let limiter = RateLimiter::new(1000.0, Arrival::Fixed);
+        let finished = AtomicBool::new(false);
+        let start = Instant::now();
+        assert!(limiter.acquire(&finished, || {}));
+        assert!(start.elapsed() < MAX_WAIT);
+
+        finished.store(true, Ordering::Relaxed);
+        assert!(!limiter.acquire(&finished, || {}));
+    }
+
+    #[test]
+    fn parses_ramps() {
+        let ramp: Ramp = "1000:20000:60".parse().unwrap();
+        assert_eq!(
+            (ramp.start, ramp.end, ramp.duration),
+            (1000.0, 20000.0, Duration::from_secs(60))
+        );
+        assert_eq!(ramp.to_string(), "1000/s to 20000/s over 60s");
+
+        let expected = |s| {
+            format!(
+                "invalid ramp '{}': expected <start qps>:<end qps>:<seconds>",
+                s
+            )
+        };
+        for s in ["1000:20000", "1:2:3:4", "a:1:1", "-1:2:3", "1:2:1.5", ""] {
+            assert_eq!(s.parse::<Ramp>().unwrap_err(), expected(s), "{}", s);
+        }
+        assert_eq!(
+            "1:2:0".parse::<Ramp>().unwrap_err(),
+            "invalid ramp '1:2:0': duration must be at least 1s"
+        );
+    }
+
+    #[test]
+    fn ramps_linearly() {
+        let up: Ramp = "1000:20000:60".parse().unwrap();
+        let down: Ramp = "100:0:10".parse().unwrap();
+
+        let cases = [
+            (up, 0, 1000.0),
+            (up, 30, 10500.0),
+            (up, 60, 20000.0),
+            (up, 120, 20000.0),
+            (down, 5, 50.0),
+            (down, 10, 0.0),
+        ];
+
+        for (ramp, secs, rate) in cases {
+            assert_eq!(
+                ramp.rate_at(Duration::from_secs(secs)),
+                rate,
+                "{} {}",
+                ramp,
+                secs
+            );
+        }
+
+        assert_eq!(
+            Profile::Ramp(up).settles_after(),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn parses_bursts() {
+        let burst: Burst = "500:100ms".parse().unwrap();
+        assert_eq!(
+            (burst.size, burst.interval),
+            (500, Duration::from_millis(100))
+        );
+        assert_eq!(burst.to_string(), "500 queries every 100ms");
+
+        let cases = [
+            ("500", "invalid burst '500': expected <size>:<interval>"),
+            ("0:100ms", "invalid burst size '0'"),
+            ("x:100ms", "invalid burst size 'x'"),
+            ("-1:100ms", "invalid burst size '-1'"),
+            (
+                "500:0s",
+                "invalid burst '500:0s': interval must be above zero",
+            ),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(s.parse::<Burst>().unwrap_err(), err, "{}", s);
+        }
+        assert!("500:soon".parse::<Burst>().is_err());
+    }
+
+    #[test]
+    fn hands_out_bursts() {
+        let burst: Burst = "500:100ms".parse().unwrap();
+        let limiter = RateLimiter::burst(burst);
+
+        // the first burst is due at once, and nothing more until the next
+        for _ in 0..500 {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+
+        // bursts that were missed don't pile up
+        {
+            let mut bucket = limiter.bucket.lock().unwrap();
+            bucket.tokens = 10.0;
+            bucket.next -= Duration::from_millis(350);
+        }
+        assert_eq!(tokens(&limiter), 500.0);
+        assert!(limiter.bucket.lock().unwrap().next > Instant::now());
+    }
+
+    #[test]
+    fn parses_waves() {
+        let wave: Wave = "5000:20000:60".parse().unwrap();
+        assert_eq!(
+            (wave.min, wave.max, wave.period),
+            (5000.0, 20000.0, Duration::from_secs(60))
+        );
+        assert_eq!(wave.to_string(), "5000/s to 20000/s every 60s");
+
+        let expected = |s| {
+            format!(
+                "invalid wave '{}': expected <min qps>:<max qps>:<period seconds>",
+                s
+            )
+        };
+        for s in ["5000:20000", "1:2:3:4", "a:1:1", "1:-2:3", "1:2:0.5", ""] {
+            assert_eq!(s.parse::<Wave>().unwrap_err(), expected(s), "{}", s);
+        }
+        assert_eq!(
+            "1:2:0".parse::<Wave>().unwrap_err(),
+            "invalid wave '1:2:0': period must be at least 1s"
+        );
+    }
+
+    #[test]
+    fn waves_between_min_and_max() {
+        let wave: Wave = "5000:20000:60".parse().unwrap();
+
+        let cases = [
+            (0, 5000.0),
+            (15, 12500.0),
+            (30, 20000.0),
+            (45, 12500.0),
+            (60, 5000.0),
+            (90, 20000.0),
+        ];
+
+        for (secs, rate) in cases {
+            let at = wave.rate_at(Duration::from_secs(secs));
+            assert!((at - rate).abs() < 1e-6, "{}: {}", secs, at);
+        }
+
+        for millis in (0..120_000).step_by(250) {
+            let at = wave.rate_at(Duration::from_millis(millis));
+            assert!((5000.0..=20000.0).contains(&at), "{}: {}", millis, at);
+        }
+
+        assert_eq!(Profile::Wave(wave).settles_after(), None);
+    }
+}
@@ -0,0 +1,438 @@
+//! Results sent between machines: an agent runs its share of a distributed
+//! benchmark and sends back everything it measured, histograms and all, and
+//! the controller adds the shares up as if one machine had run them.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    time::{Duration, SystemTime},
+};
+
+use trust_dns_resolver::proto::rr::RecordType;
+
+use crate::{
+    histogram::Histogram,
+    json::{self, Value},
+    stages, Dnssec, Failures, Interval, Mismatches, Results, RunDetails, TypeDetails, TTL_RANGES,
+};
+
+/// Returns `results` as JSON, for decode to read back on another machine.
+/// The stages themselves are left out; both ends know what they were.
+pub fn encode(results: &Results) -> Value {
+    json::object(vec![
+        (
+            "nameservers",
+            Value::Array(
+                results
+                    .nameservers
+                    .iter()
+                    .map(|ns| ns.to_string().into())
+                    .collect(),
+            ),
+        ),
+        ("overall", encode_details(&results.overall)),
+        (
+            "per_target",
+            Value::Array(results.per_target.iter().map(encode_details).collect()),
+        ),
+        (
+            "per_stage",
+            Value::Array(
+                results
+                    .per_stage
+                    .iter()
+                    .map(|(_, details, length)| {
+                        Value::Array(vec![encode_details(details), nanos(*length)])
+                    })
+                    .collect(),
+            ),
+        ),
+        (
+            "timeseries",
+            Value::Array(results.timeseries.iter().map(encode_interval).collect()),
+        ),
+        ("runtime_ns", nanos(results.runtime)),
+        ("max_rate", results.max_rate.into()),
+    ])
+}
+
+/// Reads results written by encode, for a run of `stages`.
+pub fn decode(value: &Value, stages: &[stages::Stage]) -> Result<Results, String> {
+    let field = |key: &str| value.get(key).ok_or(format!("no {} in the results", key));
+    let invalid = |key: &str| format!("invalid {} in the results", key);
+
+    let nameservers = field("nameservers")?
+        .as_array()
+        .ok_or_else(|| invalid("nameservers"))?
+        .iter()
+        .map(|ns| ns.as_str()?.parse().ok())
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid("nameservers"))?;
+
+    let per_stage = field("per_stage")?
+        .as_array()
+        .ok_or_else(|| invalid("per_stage"))?
+        .iter()
+        .zip(stages)
+        .map(|(entry, stage)| match entry.as_array()? {
+            [details, length] => Some((
+                stage.clone(),
+                decode_details(details)?,
+                Duration::from_nanos(length.as_u64()?),
+            )),
+            _ => None,
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| invalid("per_stage"))?;
+
+    Ok(Results {
+        nameservers,
+        overall: decode_details(field("overall")?).ok_or_else(|| invalid("overall"))?,
+        per_target: field("per_target")?
+            .as_array()
+            .and_then(|targets| targets.iter().map(decode_details).collect())
+            .ok_or_else(|| invalid("per_target"))?,
+        per_stage,
+        timeseries: field("timeseries")?
+            .as_array()
+            .and_then(|intervals| intervals.iter().map(decode_interval).collect())
+            .ok_or_else(|| invalid("timeseries"))?,
+        runtime: Duration::from_nanos(
+            field("runtime_ns")?
+                .as_u64()
+                .ok_or_else(|| invalid("runtime_ns"))?,
+        ),
+        max_rate: field("max_rate")?.as_u64(),
+    })
+}
+
+/// Adds up the results of machines that ran the same stages against the same
+/// nameservers at the same time. The time series are added up second by
+/// second; each second's p99 latency is the highest of any machine's, as
+/// the intervals keep no histogram to combine.
+pub fn merge(shares: Vec<Results>) -> Option<Results> {
+    let mut shares = shares.into_iter();
+    let mut merged = shares.next()?;
+
+    for share in shares {
+        merged.overall += share.overall;
+        for (total, details) in merged.per_target.iter_mut().zip(share.per_target) {
+            *total += details;
+        }
+        for ((_, total, length), (_, details, other)) in
+            merged.per_stage.iter_mut().zip(share.per_stage)
+        {
+            *total += details;
+            *length = (*length).max(other);
+        }
+
+        for (i, interval) in share.timeseries.into_iter().enumerate() {
+            match merged.timeseries.get_mut(i) {
+                Some(total) => add_interval(total, interval),
+                None => merged.timeseries.push(interval),
+            }
+        }
+
+        merged.runtime = merged.runtime.max(share.runtime);
+        merged.max_rate = merged.max_rate.min(share.max_rate);
+    }
+
+    Some(merged)
+}
+
+fn add_interval(total: &mut Interval, interval: Interval) {
+    let successes = total.successes + interval.successes;
+    if successes > 0 {
+        total.mean_latency = (total.mean_latency * total.successes as u32
+            + interval.mean_latency * interval.successes as u32)
+            / successes as u32;
+    }
+    total.p99_latency = total.p99_latency.max(interval.p99_latency);
+    total.successes = successes;
+    total.failures += interval.failures;
+    total.failure_causes += interval.failure_causes;
+    total.bytes_sent += interval.bytes_sent;
+    total.bytes_received += interval.bytes_received;
+    total.offset = total.offset.max(interval.offset);
+    total.ended = total.ended.max(interval.ended);
+    total.length = total.length.max(interval.length);
+}
+
+fn nanos(duration: Duration) -> Value {
+    (duration.as_nanos() as u64).into()
+}
+
+// u128 totals go as strings, as JSON numbers are only exact to 2^53
+fn encode_u128(n: u128) -> Value {
+    n.to_string().into()
+}
+
+fn decode_u128(value: &Value) -> Option<u128> {
+    value.as_str()?.parse().ok()
+}
+
+fn encode_counts<K: Copy + Into<u64>>(counts: &BTreeMap<K, u64>) -> Value {
+    Value::Array(
+        counts
+            .iter()
+            .map(|(key, count)| Value::Array(vec![(*key).into().into(), (*count).into()]))
+            .collect(),
+    )
+}
+
+fn decode_counts<K: Ord>(
+    value: &Value,
+    key: impl Fn(u64) -> Option<K>,
+) -> Option<BTreeMap<K, u64>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|entry| match entry.as_array()? {
+            [k, count] => Some((key(k.as_u64()?)?, count.as_u64()?)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn encode_failures(failures: &Failures) -> Value {
+    Value::Array(
+        [
+            failures.timeout,
+            failures.servfail,
+            failures.refused,
+            failures.nxdomain,
+            failures.network,
+            failures.wrong_answer,
+            failures.wrong_flags,
+            failures.other,
+        ]
+        .iter()
+        .map(|count| (*count).into())
+        .collect(),
+    )
+}
+
+fn decode_failures(value: &Value) -> Option<Failures> {
+    match value.as_array()? {
+        [timeout, servfail, refused, nxdomain, network, wrong_answer, wrong_flags, other] => {
+            Some(Failures {
+                timeout: timeout.as_u64()?,
+                servfail: servfail.as_u64()?,
+                refused: refused.as_u64()?,
+                nxdomain: nxdomain.as_u64()?,
+                network: network.as_u64()?,
+                wrong_answer: wrong_answer.as_u64()?,
+                wrong_flags: wrong_flags.as_u64()?,
+                other: other.as_u64()?,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn encode_mismatches(mismatches: &Mismatches) -> Value {
+    Value::Array(
+        [
+            mismatches.late,
+            mismatches.unsolicited,
+            mismatches.wrong_question,
+            mismatches.malformed,
+        ]
+        .iter()
+        .map(|count| (*count).into())
+        .collect(),
+    )
+}
+
+fn decode_mismatches(value: &Value) -> Option<Mismatches> {
+    match value.as_array()? {
+        [late, unsolicited, wrong_question, malformed] => Some(Mismatches {
+            late: late.as_u64()?,
+            unsolicited: unsolicited.as_u64()?,
+            wrong_question: wrong_question.as_u64()?,
+            malformed: malformed.as_u64()?,
+        }),
+        _ => None,
+    }
+}
+
+fn encode_details(details: &RunDetails) -> Value {
+    json::object(vec![
+        ("successes", details.successes.into()),
+        ("failures", details.failures.into()),
+        ("total_latency", encode_u128(details.total_latency)),
+        ("latency_count", details.latency_count.into()),
+        ("latency_mean", details.latency_mean.into()),
+        ("latency_m2", details.latency_m2.into()),
+        ("failure_causes", encode_failures(&details.failure_causes)),
+        ("truncated", details.truncated.into()),
+        ("tcp_fallbacks", details.tcp_fallbacks.into()),
+        (
+            "queries_by_type",
+            encode_counts(
+                &details
+                    .queries_by_type
+                    .iter()
+                    .map(|(record_type, count)| (u16::from(*record_type), *count))
+                    .collect(),
+            ),
+        ),
+        (
+            "by_type",
+            Value::Array(
+                details
+                    .by_type
+                    .iter()
+                    .map(|(record_type, by_type)| {
+                        Value::Array(vec![
+                            u64::from(u16::from(*record_type)).into(),
+                            by_type.successes.into(),
+                            by_type.failures.into(),
+                            encode_u128(by_type.total_latency),
+                            by_type.latencies.to_json(),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ),
+        ("rcodes", encode_counts(&details.rcodes)),
+        ("latencies", details.latencies.to_json()),
+        ("response_sizes", details.response_sizes.to_json()),
+        ("total_response_size", details.total_response_size.into()),
+        ("bytes_sent", details.bytes_sent.into()),
+        ("bytes_received", details.bytes_received.into()),
+        ("ttls", details.ttls.to_json()),
+        ("total_ttl", details.total_ttl.into()),
+        (
+            "ttl_ranges",
+            Value::Array(
+                details
+                    .ttl_ranges
+                    .iter()
+                    .map(|count| (*count).into())
+                    .collect(),
+            ),
+        ),
+        ("attempts", encode_counts(&details.attempts)),
+        ("ecs_scopes", encode_counts(&details.ecs_scopes)),
+        ("connect_times", details.connect_times.to_json()),
+        (
+            "total_connect_time",
+            encode_u128(details.total_connect_time),
+        ),
+        ("dnssec_validated", details.dnssec.validated.to_json()),
+        ("dnssec_unvalidated", details.dnssec.unvalidated.to_json()),
+        ("dnssec_bogus", details.dnssec.bogus.into()),
+        ("mismatches", encode_mismatches(&details.mismatches)),
+    ])
+}
+
+fn decode_details(value: &Value) -> Option<RunDetails> {
+    let u64_field = |key: &str| value.get(key)?.as_u64();
+    let histogram = |key: &str| Histogram::from_json(value.get(key)?);
+
+    let mut ttl_ranges = [0; TTL_RANGES.len()];
+    let ranges = value.get("ttl_ranges")?.as_array()?;
+    if ranges.len() != ttl_ranges.len() {
+        return None;
+    }
+    for (count, range) in ttl_ranges.iter_mut().zip(ranges) {
+        *count = range.as_u64()?;
+    }
+
+    let by_type = value
+        .get("by_type")?
+        .as_array()?
+        .iter()
+        .map(|entry| match entry.as_array()? {
+            [record_type, successes, failures, total_latency, latencies] => Some((
+                RecordType::from(u16::try_from(record_type.as_u64()?).ok()?),
+                TypeDetails {
+                    successes: successes.as_u64()?,
+                    failures: failures.as_u64()?,
+                    total_latency: decode_u128(total_latency)?,
+                    latencies: Histogram::from_json(latencies)?,
+                },
+            )),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    Some(RunDetails {
+        successes: u64_field("successes")?,
+        failures: u64_field("failures")?,
+        total_latency: decode_u128(value.get("total_latency")?)?,
+        latency_count: u64_field("latency_count")?,
+        latency_mean: value.get("latency_mean")?.as_f64()?,
+        latency_m2: value.get("latency_m2")?.as_f64()?,
+        failure_causes: decode_failures(value.get("failure_causes")?)?,
+        truncated: u64_field("truncated")?,
+        tcp_fallbacks: u64_field("tcp_fallbacks")?,
+        queries_by_type: decode_counts(value.get("queries_by_type")?, |record_type| {
+            Some(RecordType::from(u16::try_from(record_type).ok()?))
+        })?,
+        by_type,
+        rcodes: decode_counts(value.get("rcodes")?, |rcode| u16::try_from(rcode).ok())?,
+        latencies: histogram("latencies")?,
+        response_sizes: histogram("response_sizes")?,
+        total_response_size: u64_field("total_response_size")?,
+        bytes_sent: u64_field("bytes_sent")?,
+        bytes_received: u64_field("bytes_received")?,
+        ttls: histogram("ttls")?,
+        total_ttl: u64_field("total_ttl")?,
+        ttl_ranges,
+        attempts: decode_counts(value.get("attempts")?, |attempts| {
+            u32::try_from(attempts).ok()
+        })?,
+        ecs_scopes: decode_counts(value.get("ecs_scopes")?, |scope| u8::try_from(scope).ok())?,
+        connect_times: histogram("connect_times")?,
+        total_connect_time: decode_u128(value.get("total_connect_time")?)?,
+        dnssec: Dnssec {
+            validated: histogram("dnssec_validated")?,
+            unvalidated: histogram("dnssec_unvalidated")?,
+            bogus: u64_field("dnssec_bogus")?,
+        },
+        mismatches: decode_mismatches(value.get("mismatches")?)?,
+    })
+}
+
+fn encode_interval(interval: &Interval) -> Value {
+    json::object(vec![
+        ("offset_ns", nanos(interval.offset)),
+        (
+            "ended_ns",
+            nanos(
+                interval
+                    .ended
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ),
+        ),
+        ("length_ns", nanos(interval.length)),
+        ("successes", interval.successes.into()),
+        ("failures", interval.failures.into()),
+        ("failure_causes", encode_failures(&interval.failure_causes)),
+        ("mean_latency_ns", nanos(interval.mean_latency)),
+        ("p99_latency_ns", nanos(interval.p99_latency)),
+        ("bytes_sent", interval.bytes_sent.into()),
+        ("bytes_received", interval.bytes_received.into()),
+    ])
+}
+
+fn decode_interval(value: &Value) -> Option<Interval> {
+    let u64_field = |key: &str| value.get(key)?.as_u64();
+    let duration = |key: &str| u64_field(key).map(Duration::from_nanos);
+
+    Some(Interval {
+        offset: duration("offset_ns")?,
+        ended: SystemTime::UNIX_EPOCH + duration("ended_ns")?,
+        length: duration("length_ns")?,
+        successes: u64_field("successes")?,
+        failures: u64_field("failures")?,
+        failure_causes: decode_failures(value.get("failure_causes")?)?,
+        mean_latency: duration("mean_latency_ns")?,
+        p99_latency: duration("p99_latency_ns")?,
+        bytes_sent: u64_field("bytes_sent")?,
+        bytes_received: u64_field("bytes_received")?,
+    })
+}
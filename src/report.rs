@@ -0,0 +1,481 @@
+use std::{
+    io::{self, BufWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use nsbench::{
+    consistency,
+    histogram::Histogram,
+    interrupt,
+    json::{object, Value},
+    mbit_per_second, per_second, qnamemin, rcode_name, stage_load,
+    stages::Stage,
+    workload::QtypeMix,
+    Dnssec, Failures, Interval, Mismatches, RunDetails, LATENCY_PERCENTILES, TTL_RANGES,
+};
+
+use crate::{assertions::Verdict, RunArguments};
+
+/// Stream is where intervals are written as they finish, shared by every
+/// stage of a run.
+pub type Stream = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Results is everything a run produced, for the machine readable formats.
+pub struct Results<'a> {
+    pub nameservers: &'a [SocketAddr],
+    pub overall: &'a RunDetails,
+    pub per_target: &'a [RunDetails],
+    pub per_stage: &'a [(Stage, RunDetails, Duration)],
+    pub timeseries: &'a [Interval],
+    pub runtime: Duration,
+    pub max_rate: Option<u64>,
+    pub verdicts: &'a [Verdict],
+    pub consistency: Option<&'a consistency::Report>,
+    /// Whether each nameserver minimizes QNAMEs, if checked.
+    pub qname_minimization: Option<&'a [qnamemin::Minimization]>,
+}
+
+/// Returns the summary as a JSON document: how the run was set up, its
+/// totals, and the same breakdowns the text summary prints. Durations are in
+/// nanoseconds.
+pub fn json(args: &RunArguments, qtypes: &QtypeMix, results: &Results) -> Value {
+    let overall = results.overall;
+
+    object(vec![
+        ("config", config(args, qtypes, results)),
+        ("totals", details(overall, results.runtime)),
+        ("interrupted", interrupt::interrupted().into()),
+        (
+            "types",
+            overall
+                .queries_by_type
+                .iter()
+                .map(|(record_type, queries)| {
+                    let details = overall
+                        .by_type
+                        .get(record_type)
+                        .cloned()
+                        .unwrap_or_default();
+
+                    object(vec![
+                        ("type", record_type.to_string().into()),
+                        ("queries", (*queries).into()),
+                        ("successes", details.successes.into()),
+                        ("failures", details.failures.into()),
+                        ("mean_latency_ns", nanos(details.mean_latency())),
+                        ("p99_latency_ns", details.latencies.percentile(99.0).into()),
+                    ])
+                })
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        (
+            "rcodes",
+            overall
+                .rcodes
+                .iter()
+                .map(|(rcode, count)| {
+                    object(vec![
+                        ("rcode", rcode_name(*rcode).into()),
+                        ("responses", (*count).into()),
+                    ])
+                })
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        (
+            "stages",
+            results
+                .per_stage
+                .iter()
+                .map(|(stage, details, elapsed)| {
+                    object(vec![
+                        ("load", stage_load(stage).into()),
+                        ("results", self::details(details, *elapsed)),
+                    ])
+                })
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        (
+            "nameservers",
+            results
+                .nameservers
+                .iter()
+                .zip(results.per_target)
+                .map(|(nameserver, details)| {
+                    object(vec![
+                        ("nameserver", nameserver.to_string().into()),
+                        ("results", self::details(details, results.runtime)),
+                    ])
+                })
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        (
+            "assertions",
+            results
+                .verdicts
+                .iter()
+                .map(|verdict| {
+                    object(vec![
+                        ("assertion", verdict.assertion.as_str().into()),
+                        ("actual", verdict.actual.as_str().into()),
+                        ("passed", verdict.passed.into()),
+                    ])
+                })
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        (
+            "consistency",
+            results
+                .consistency
+                .map(|consistency| self::consistency(consistency, results.nameservers))
+                .into(),
+        ),
+        (
+            "qname_minimization",
+            results
+                .qname_minimization
+                .map(|minimization| {
+                    Value::Object(
+                        results
+                            .nameservers
+                            .iter()
+                            .zip(minimization)
+                            .map(|(nameserver, minimization)| {
+                                (nameserver.to_string(), minimization.to_string().into())
+                            })
+                            .collect(),
+                    )
+                })
+                .into(),
+        ),
+        (
+            "intervals",
+            results
+                .timeseries
+                .iter()
+                .map(interval)
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+    ])
+}
+
+fn config(args: &RunArguments, qtypes: &QtypeMix, results: &Results) -> Value {
+    object(vec![
+        (
+            "nameservers",
+            results
+                .nameservers
+                .iter()
+                .map(|nameserver| nameserver.to_string().into())
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+        ("protocol", args.protocol.to_string().into()),
+        ("qtypes", qtypes.to_string().into()),
+        ("cpus", args.cpus.into()),
+        ("timeout_ns", args.timeout.into()),
+        ("retries", args.retries.into()),
+        ("qps", args.qps.into()),
+        ("open_loop", args.open_loop.into()),
+        ("arrival", args.arrival.to_string().into()),
+        ("concurrency", args.concurrency.into()),
+        ("async", args.tasks.into()),
+        ("warmup_ns", args.warmup.map(nanos).into()),
+        (
+            "stages",
+            args.stages
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .into(),
+        ),
+        ("slo_p99_ns", args.find_max.map(nanos).into()),
+        ("max_rate", results.max_rate.into()),
+    ])
+}
+
+// the totals for a run, stage or nameserver that took `elapsed`
+fn details(details: &RunDetails, elapsed: Duration) -> Value {
+    let mut latency = vec![
+        ("mean_ns".to_string(), nanos(details.mean_latency())),
+        ("stddev_ns".to_string(), nanos(details.latency_stddev())),
+        ("min_ns".to_string(), details.latencies.min().into()),
+    ];
+    for (label, percentile) in LATENCY_PERCENTILES {
+        latency.push((
+            format!("{}_ns", label),
+            details.latencies.percentile(*percentile).into(),
+        ));
+    }
+    latency.push(("max_ns".to_string(), details.latencies.max().into()));
+
+    let responses: u64 = details.rcodes.values().sum();
+
+    object(vec![
+        ("successes", details.successes.into()),
+        ("failures", details.failures.into()),
+        ("failure_causes", failures(&details.failure_causes)),
+        ("truncated", details.truncated.into()),
+        ("tcp_fallbacks", details.tcp_fallbacks.into()),
+        ("mismatches", mismatches(&details.mismatches)),
+        ("success_rate", details.success_rate().into()),
+        ("runtime_ns", nanos(elapsed)),
+        (
+            "requests_per_second",
+            per_second(details.successes, elapsed).into(),
+        ),
+        ("latency", Value::Object(latency)),
+        (
+            "response_size",
+            object(vec![
+                ("min", details.response_sizes.min().into()),
+                (
+                    "mean",
+                    details.total_response_size.checked_div(responses).into(),
+                ),
+                ("p95", details.response_sizes.percentile(95.0).into()),
+                ("max", details.response_sizes.max().into()),
+            ]),
+        ),
+        ("bytes_sent", details.bytes_sent.into()),
+        ("bytes_received", details.bytes_received.into()),
+        (
+            "mbit_per_second_out",
+            mbit_per_second(details.bytes_sent, elapsed).into(),
+        ),
+        (
+            "mbit_per_second_in",
+            mbit_per_second(details.bytes_received, elapsed).into(),
+        ),
+        ("connections", details.connect_times.count().into()),
+        (
+            "ttl",
+            object(
+                TTL_RANGES
+                    .iter()
+                    .zip(details.ttl_ranges)
+                    .map(|((_, label), count)| (*label, count.into()))
+                    .collect(),
+            ),
+        ),
+        ("dnssec", dnssec(&details.dnssec)),
+        (
+            "attempts",
+            Value::Object(
+                details
+                    .attempts
+                    .iter()
+                    .map(|(attempts, count)| (attempts.to_string(), (*count).into()))
+                    .collect(),
+            ),
+        ),
+        (
+            "ecs_scopes",
+            Value::Object(
+                details
+                    .ecs_scopes
+                    .iter()
+                    .map(|(scope, count)| (scope.to_string(), (*count).into()))
+                    .collect(),
+            ),
+        ),
+    ])
+}
+
+fn consistency(report: &consistency::Report, nameservers: &[SocketAddr]) -> Value {
+    object(vec![
+        ("checked", report.checked.into()),
+        (
+            "disagreements",
+            report
+                .disagreements
+                .iter()
+                .map(|disagreement| {
+                    object(vec![
+                        ("name", disagreement.name.to_string().into()),
+                        ("type", disagreement.record_type.to_string().into()),
+                        (
+                            "answers",
+                            Value::Object(
+                                nameservers
+                                    .iter()
+                                    .zip(&disagreement.outcomes)
+                                    .map(|(nameserver, outcome)| {
+                                        (nameserver.to_string(), outcome.to_string().into())
+                                    })
+                                    .collect(),
+                            ),
+                        ),
+                    ])
+                })
+                .collect::<Vec<Value>>()
+                .into(),
+        ),
+    ])
+}
+
+fn dnssec(dnssec: &Dnssec) -> Value {
+    let latency = |latencies: &Histogram| {
+        object(vec![
+            ("p50_ns", latencies.percentile(50.0).into()),
+            ("p99_ns", latencies.percentile(99.0).into()),
+        ])
+    };
+
+    object(vec![
+        ("validated", dnssec.validated.count().into()),
+        ("unvalidated", dnssec.unvalidated.count().into()),
+        ("bogus", dnssec.bogus.into()),
+        ("validated_latency", latency(&dnssec.validated)),
+        ("unvalidated_latency", latency(&dnssec.unvalidated)),
+    ])
+}
+
+fn mismatches(mismatches: &Mismatches) -> Value {
+    object(vec![
+        ("late", mismatches.late.into()),
+        ("unsolicited", mismatches.unsolicited.into()),
+        ("wrong_question", mismatches.wrong_question.into()),
+        ("malformed", mismatches.malformed.into()),
+    ])
+}
+
+fn failures(failures: &Failures) -> Value {
+    object(vec![
+        ("timeout", failures.timeout.into()),
+        ("servfail", failures.servfail.into()),
+        ("refused", failures.refused.into()),
+        ("nxdomain", failures.nxdomain.into()),
+        ("network", failures.network.into()),
+        ("wrong_answer", failures.wrong_answer.into()),
+        ("wrong_flags", failures.wrong_flags.into()),
+        ("other", failures.other.into()),
+    ])
+}
+
+fn interval(interval: &Interval) -> Value {
+    object(vec![
+        ("timestamp", unix_time(interval.ended).into()),
+        ("time_s", interval.offset.as_secs_f64().into()),
+        ("length_s", interval.length.as_secs_f64().into()),
+        ("successes", interval.successes.into()),
+        ("failures", interval.failures.into()),
+        ("failure_causes", failures(&interval.failure_causes)),
+        (
+            "requests_per_second",
+            per_second(interval.successes, interval.length).into(),
+        ),
+        ("mean_latency_ns", nanos(interval.mean_latency)),
+        ("p99_latency_ns", nanos(interval.p99_latency)),
+        ("bytes_sent", interval.bytes_sent.into()),
+        ("bytes_received", interval.bytes_received.into()),
+    ])
+}
+
+/// Writes `interval` to `stream` as a line of JSON, flushing it so that it can
+/// be read while the run goes on.
+pub fn ndjson(stream: &Stream, interval: &Interval) -> io::Result<()> {
+    let mut stream = stream.lock().unwrap();
+    writeln!(stream, "{}", self::interval(interval))?;
+    stream.flush()
+}
+
+/// Replaces the checkpoint at `path` with the summary of the run so far, as
+/// `--format json` writes it, and the latency histogram in full. The file is
+/// written beside it and renamed over it, so that a crash leaves either the
+/// last checkpoint or this one.
+pub fn checkpoint(
+    path: &Path,
+    args: &RunArguments,
+    qtypes: &QtypeMix,
+    results: &nsbench::Results,
+    consistency: Option<&consistency::Report>,
+    qname_minimization: Option<&[qnamemin::Minimization]>,
+) -> io::Result<()> {
+    let summary = json(
+        args,
+        qtypes,
+        &Results {
+            nameservers: &results.nameservers,
+            overall: &results.overall,
+            per_target: &results.per_target,
+            per_stage: &results.per_stage,
+            timeseries: &results.timeseries,
+            runtime: results.runtime,
+            max_rate: results.max_rate,
+            verdicts: &[],
+            consistency,
+            qname_minimization,
+        },
+    );
+    let mut fields = match summary {
+        Value::Object(fields) => fields,
+        _ => unreachable!("the summary is an object"),
+    };
+    fields.push(("written".to_string(), unix_time(SystemTime::now()).into()));
+    fields.push((
+        "latency_histogram".to_string(),
+        results.overall.latencies.to_json(),
+    ));
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, format!("{}\n", Value::Object(fields)))?;
+    std::fs::rename(&partial, path)
+}
+
+/// Writes the time series as CSV, one row per interval. Latencies are in
+/// nanoseconds, as in the JSON summary.
+pub fn csv<W: Write>(w: W, timeseries: &[Interval]) -> io::Result<()> {
+    let mut w = BufWriter::new(w);
+
+    writeln!(
+        w,
+        "timestamp,time_s,successes,failures,timeout,servfail,refused,nxdomain,network,wrong_answer,wrong_flags,other,requests_per_second,mean_latency_ns,p99_latency_ns,bytes_sent,bytes_received"
+    )?;
+
+    for interval in timeseries {
+        let causes = &interval.failure_causes;
+        writeln!(
+            w,
+            "{:.3},{:.3},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            unix_time(interval.ended),
+            interval.offset.as_secs_f64(),
+            interval.successes,
+            interval.failures,
+            causes.timeout,
+            causes.servfail,
+            causes.refused,
+            causes.nxdomain,
+            causes.network,
+            causes.wrong_answer,
+            causes.wrong_flags,
+            causes.other,
+            per_second(interval.successes, interval.length),
+            interval.mean_latency.as_nanos(),
+            interval.p99_latency.as_nanos(),
+            interval.bytes_sent,
+            interval.bytes_received,
+        )?;
+    }
+
+    w.flush()
+}
+
+// seconds since the epoch, as a fraction
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn nanos(duration: Duration) -> Value {
+    (duration.as_nanos() as u64).into()
+}
@@ -0,0 +1,114 @@
+use std::{
+    sync::{atomic::AtomicBool, mpsc::Sender},
+    time::{Duration, Instant},
+};
+
+use crate::{wait_until, QueryConfig, RunDetails};
+
+// how often workers report to the stage's informer
+const TICK: Duration = Duration::from_secs(1);
+
+/// Reporter keeps a worker's results to itself, so that recording a query
+/// takes no lock, and sends them to the stage's informer an interval at a
+/// time. Every worker reports on the same one second boundaries, counted from
+/// when the run started, so their reports can be added up.
+///
+/// A worker reports as it records and while it waits, so one blocked on a
+/// slow answer sends the intervals that ended in the meantime once it has it.
+pub struct Reporter {
+    sender: Sender<(usize, u32, RunDetails)>,
+    target: usize,
+    // when the first interval starts, which is after any warm-up
+    epoch: Instant,
+    warming_up: bool,
+    seq: u32,
+    details: RunDetails,
+}
+
+impl Reporter {
+    pub fn new(qc: &QueryConfig) -> Self {
+        Self {
+            sender: qc.informer_sender.clone(),
+            target: qc.target,
+            epoch: Instant::now() + qc.warmup.unwrap_or_default(),
+            warming_up: qc.warmup.is_some(),
+            seq: 0,
+            details: RunDetails::default(),
+        }
+    }
+
+    /// Returns a reporter on the same boundaries, for a second thread of the
+    /// same worker. The informer has to expect its reports too.
+    pub fn split(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            target: self.target,
+            epoch: self.epoch,
+            warming_up: self.warming_up,
+            seq: self.seq,
+            details: RunDetails::default(),
+        }
+    }
+
+    /// Returns the results of the current interval, having sent any that have
+    /// ended.
+    pub fn details(&mut self) -> &mut RunDetails {
+        self.tick();
+        &mut self.details
+    }
+
+    /// Sends any intervals that have ended.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+
+        // whatever was recorded during the warm-up is thrown away
+        if self.warming_up {
+            if now < self.epoch {
+                return;
+            }
+            self.details.reset();
+            self.warming_up = false;
+        }
+
+        while now >= self.next() {
+            self.send();
+        }
+    }
+
+    /// Sleeps until `deadline` like wait_until, waking up to report any
+    /// intervals that end on the way.
+    pub fn wait_until(&mut self, deadline: Instant, finished: &AtomicBool) -> bool {
+        loop {
+            let next = self.next();
+            if deadline <= next {
+                return wait_until(deadline, finished);
+            }
+
+            if !wait_until(next, finished) {
+                return false;
+            }
+            self.tick();
+        }
+    }
+
+    // when the current interval ends
+    fn next(&self) -> Instant {
+        self.epoch + TICK * (self.seq + 1)
+    }
+
+    fn send(&mut self) {
+        let details = std::mem::take(&mut self.details);
+        self.sender.send((self.target, self.seq, details)).unwrap();
+        self.seq += 1;
+    }
+}
+
+impl Drop for Reporter {
+    // sends the last, partial interval
+    fn drop(&mut self) {
+        self.tick();
+        if !self.warming_up {
+            self.send();
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use tracing::info;
+
+use nsbench::{per_second, stage_load, stages::Stage, workload::QtypeMix, RunDetails};
+
+use crate::assertions::Assertion;
+
+// how many times in a row `repeat` runs a stage again unless told otherwise
+const DEFAULT_REPEATS: u32 = 10;
+
+/// Change is how a rule moves the rate of the next stage.
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    Scale(f64),
+    Add(f64),
+    Set(u64),
+}
+
+impl Change {
+    fn apply(&self, qps: f64) -> u64 {
+        let qps = match self {
+            Change::Scale(factor) => qps * factor,
+            Change::Add(amount) => qps + amount,
+            Change::Set(qps) => *qps as f64,
+        };
+        qps.round().max(1.0) as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Action {
+    Qps(Change),
+    Qtypes(QtypeMix),
+    /// Runs the stage that ended again, unless it already ran again this
+    /// many times in a row.
+    Repeat(u32),
+    Stop,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    text: String,
+    condition: Assertion,
+    actions: Vec<Action>,
+}
+
+/// Script is a set of rules that steer a staged run by what each stage
+/// measured.
+#[derive(Debug)]
+pub struct Script {
+    rules: Vec<Rule>,
+    // how many times in a row the stage now running was repeated
+    repeated: AtomicU32,
+}
+
+impl Script {
+    /// Reads a script: one rule per line, `if <condition> then <actions>`.
+    /// Blank lines and `#` comments are skipped.
+    ///
+    /// ```text
+    /// # back off while latency is high, push while it is low
+    /// if p99>10ms then qps*0.8 repeat
+    /// if p99<2ms then qps*1.2
+    /// if failure_rate>5% then stop
+    /// ```
+    ///
+    /// A condition is written as for --assert. The actions, separated by
+    /// whitespace, are `qps*<factor>`, `qps+<n>`, `qps-<n>` and `qps=<n>` to
+    /// change the rate, and `qtype=<type>` or `qtypes=<mix>` to change the
+    /// record types, all of the next stage; `repeat` to run the stage that
+    /// ended again before the rest, at most 10 times in a row or
+    /// `repeat:<n>` times, and `stop` to end the run. The nameservers queried
+    /// can't be changed.
+    ///
+    /// A run ends when its stages run out or a `stop` rule holds. As every
+    /// stage is only repeated so many times, it always ends.
+    pub fn parse(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        let rules = parse_rules(&contents)
+            .map_err(|(lineno, msg)| format!("{}:{}: {}", path.display(), lineno, msg))?;
+
+        if rules.is_empty() {
+            return Err(format!("{}: no rules found", path.display()));
+        }
+
+        Ok(Self {
+            rules,
+            repeated: AtomicU32::new(0),
+        })
+    }
+
+    /// Applies the rules that hold for `stage`, which measured `details` over
+    /// `elapsed` across `targets` nameservers, to the stages in `remaining`.
+    ///
+    /// A stage that holds no rate, or has a ramp or wave, has its rate
+    /// changed from what the stage that ended achieved per nameserver.
+    pub fn apply(
+        &self,
+        stage: &Stage,
+        details: &RunDetails,
+        elapsed: Duration,
+        targets: usize,
+        remaining: &mut VecDeque<Stage>,
+    ) {
+        let mut actions = Vec::new();
+        for rule in &self.rules {
+            if rule.condition.holds(details, elapsed) {
+                info!("Script: {} held", rule.text);
+                actions.extend(rule.actions.iter().cloned());
+            }
+        }
+
+        if actions.iter().any(|action| matches!(action, Action::Stop)) {
+            info!("Script: stopping the run");
+            remaining.clear();
+            return;
+        }
+
+        // the most repeats asked for, if any rule asked
+        let repeats = actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Repeat(repeats) => Some(*repeats),
+                _ => None,
+            })
+            .max();
+        let repeated = self.repeated.load(Ordering::Relaxed);
+        match repeats {
+            Some(repeats) if repeated < repeats => {
+                remaining.push_front(stage.clone());
+                self.repeated.store(repeated + 1, Ordering::Relaxed);
+            }
+            Some(repeats) => {
+                info!(
+                    "Script: the stage was already repeated {} time{}, moving on",
+                    repeats,
+                    if repeats == 1 { "" } else { "s" }
+                );
+                self.repeated.store(0, Ordering::Relaxed);
+            }
+            None => self.repeated.store(0, Ordering::Relaxed),
+        }
+        if actions.is_empty() {
+            return;
+        }
+
+        let next = match remaining.front_mut() {
+            Some(next) => next,
+            None => return,
+        };
+        let achieved = per_second(details.successes, elapsed) as f64 / targets.max(1) as f64;
+        let mut changed = false;
+        for action in actions {
+            match action {
+                Action::Qps(change) => {
+                    let qps = match (next.qps, &next.profile) {
+                        (Some(qps), None) => qps as f64,
+                        _ => achieved,
+                    };
+                    next.qps = Some(change.apply(qps));
+                    next.profile = None;
+                    changed = true;
+                }
+                Action::Qtypes(mix) => {
+                    next.qtypes = Some(mix);
+                    changed = true;
+                }
+                Action::Repeat(_) | Action::Stop => {}
+            }
+        }
+
+        if changed {
+            info!("Script: the next stage is now {}", stage_load(next));
+        }
+    }
+}
+
+// reads the rules in `contents`, failing with the line and what is wrong with
+// it
+fn parse_rules(contents: &str) -> Result<Vec<Rule>, (usize, String)> {
+    let mut rules = Vec::new();
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let err = |msg: String| (lineno + 1, msg);
+
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (text, actions) = match words.as_slice() {
+            ["if", condition, "then", actions @ ..] if !actions.is_empty() => (*condition, actions),
+            _ => {
+                return Err(err(
+                    "expected if <condition> then <actions>, e.g. if p99>10ms then qps*0.8".into(),
+                ))
+            }
+        };
+
+        rules.push(Rule {
+            text: text.to_string(),
+            condition: text.parse().map_err(err)?,
+            actions: actions
+                .iter()
+                .map(|action| parse_action(action))
+                .collect::<Result<_, _>>()
+                .map_err(err)?,
+        });
+    }
+
+    Ok(rules)
+}
+
+fn parse_action(s: &str) -> Result<Action, String> {
+    match s {
+        "repeat" => return Ok(Action::Repeat(DEFAULT_REPEATS)),
+        "stop" => return Ok(Action::Stop),
+        _ => {}
+    }
+
+    if let Some(repeats) = s.strip_prefix("repeat:") {
+        return match repeats.parse() {
+            Ok(repeats) if repeats > 0 => Ok(Action::Repeat(repeats)),
+            _ => Err(format!(
+                "invalid repeat count '{}': expected a number above zero",
+                repeats
+            )),
+        };
+    }
+
+    if let Some(qps) = s.strip_prefix("qps") {
+        let invalid = || format!("invalid rate change '{}'", s);
+        let mut chars = qps.chars();
+        let op = chars.next().ok_or_else(invalid)?;
+        let value: f64 = chars.as_str().parse().map_err(|_| invalid())?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(invalid());
+        }
+
+        return match op {
+            '*' if value > 0.0 => Ok(Action::Qps(Change::Scale(value))),
+            '+' => Ok(Action::Qps(Change::Add(value))),
+            '-' => Ok(Action::Qps(Change::Add(-value))),
+            '=' if value >= 1.0 => Ok(Action::Qps(Change::Set(value as u64))),
+            _ => Err(invalid()),
+        };
+    }
+
+    match s.split_once('=') {
+        Some(("qtype", value)) => Ok(Action::Qtypes(QtypeMix::single(
+            nsbench::parse_record_type(value)?,
+        ))),
+        Some(("qtypes", value)) => Ok(Action::Qtypes(value.parse()?)),
+        _ => Err(format!(
+            "unknown action '{}': expected qps*<factor>, qps+<n>, qps-<n>, qps=<n>, qtype=<type>, qtypes=<mix>, repeat, repeat:<n> or stop",
+            s
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nsbench::stages::Length;
+
+    fn script(contents: &str) -> Script {
+        Script {
+            rules: parse_rules(contents).unwrap(),
+            repeated: AtomicU32::new(0),
+        }
+    }
+
+    fn stage(qps: Option<u64>) -> Stage {
+        Stage {
+            length: Length::Queries(100),
+            qps,
+            profile: None,
+            qtypes: None,
+        }
+    }
+
+    // runs `stages` under `script`, returning the rate each stage that ran
+    // was given
+    fn run(script: &Script, stages: &[Option<u64>]) -> Vec<Option<u64>> {
+        let mut remaining: VecDeque<Stage> = stages.iter().map(|qps| stage(*qps)).collect();
+        let mut ran = Vec::new();
+        let mut details = RunDetails::default();
+        details.successes = 1000;
+
+        while let Some(stage) = remaining.pop_front() {
+            ran.push(stage.qps);
+            assert!(ran.len() < 100, "the run does not end");
+            script.apply(&stage, &details, Duration::from_secs(1), 2, &mut remaining);
+        }
+
+        ran
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rules = parse_rules(
+            "# back off\n\nif p99>10ms then qps*0.8 repeat # again\n  if failure_rate>5% then stop\n",
+        )
+        .unwrap();
+
+        let rules: Vec<_> = rules
+            .iter()
+            .map(|rule| (rule.text.as_str(), format!("{:?}", rule.actions)))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                ("p99>10ms", "[Qps(Scale(0.8)), Repeat(10)]".to_string()),
+                ("failure_rate>5%", "[Stop]".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let expected = "expected if <condition> then <actions>, e.g. if p99>10ms then qps*0.8";
+        let cases = [
+            ("if p99>10ms\n", 1, expected),
+            ("# comment\nwhen p99>10ms then stop\n", 2, expected),
+            ("if p99>10ms then\n", 1, expected),
+            ("if p99 > 10ms then stop\n", 1, expected),
+            (
+                "if p99>10ms then stop\nif qps<10 then qps*0\n",
+                2,
+                "invalid rate change 'qps*0'",
+            ),
+            (
+                "if qps<10 then repeat:0\n",
+                1,
+                "invalid repeat count '0': expected a number above zero",
+            ),
+            (
+                "if qps<10 then slower\n",
+                1,
+                "unknown action 'slower': expected qps*<factor>, qps+<n>, qps-<n>, qps=<n>, qtype=<type>, qtypes=<mix>, repeat, repeat:<n> or stop",
+            ),
+        ];
+
+        for (contents, lineno, msg) in cases {
+            assert_eq!(
+                parse_rules(contents).unwrap_err(),
+                (lineno, msg.to_string()),
+                "{:?}",
+                contents
+            );
+        }
+
+        let (lineno, msg) = parse_rules("if latency>1ms then stop\n").unwrap_err();
+        assert_eq!(lineno, 1);
+        assert!(msg.starts_with("unknown metric 'latency'"), "{}", msg);
+    }
+
+    #[test]
+    fn parses_actions() {
+        let cases = [
+            ("qps*1.5", "Qps(Scale(1.5))"),
+            ("qps+100", "Qps(Add(100.0))"),
+            ("qps-100", "Qps(Add(-100.0))"),
+            ("qps=500", "Qps(Set(500))"),
+            ("repeat", "Repeat(10)"),
+            ("repeat:3", "Repeat(3)"),
+            ("stop", "Stop"),
+        ];
+
+        for (s, expected) in cases {
+            assert_eq!(format!("{:?}", parse_action(s).unwrap()), expected, "{}", s);
+        }
+
+        assert!(matches!(parse_action("qtype=AAAA"), Ok(Action::Qtypes(_))));
+        assert!(matches!(
+            parse_action("qtypes=A:1,MX:1"),
+            Ok(Action::Qtypes(_))
+        ));
+
+        for s in [
+            "qps", "qps*", "qps*-1", "qps*inf", "qps/2", "qps=0", "qps=0.5", "qps+x",
+        ] {
+            assert_eq!(
+                parse_action(s).unwrap_err(),
+                format!("invalid rate change '{}'", s)
+            );
+        }
+
+        for s in ["repeat:", "repeat:-1", "repeat:x"] {
+            assert!(parse_action(s).is_err(), "{}", s);
+        }
+        assert!(parse_action("qtype=NOTATYPE").is_err());
+    }
+
+    #[test]
+    fn caps_repeats() {
+        let rules = script("if successes>=0 then repeat:2\n");
+        assert_eq!(
+            run(&rules, &[Some(10), Some(20)]),
+            [Some(10), Some(10), Some(10), Some(20), Some(20), Some(20)]
+        );
+
+        // a rule that always holds still lets the run end
+        let rules = script("if successes>=0 then repeat\n");
+        assert_eq!(run(&rules, &[Some(10)]).len(), 1 + DEFAULT_REPEATS as usize);
+    }
+
+    #[test]
+    fn changes_the_next_stage() {
+        let cases = [
+            ("if successes>=0 then qps*2\n", [Some(100), Some(200)]),
+            ("if successes>=0 then qps-1000\n", [Some(100), Some(1)]),
+            ("if successes>=0 then qps=300\n", [Some(100), Some(300)]),
+            ("if successes<0 then qps*2\n", [Some(100), Some(100)]),
+        ];
+
+        for (contents, expected) in cases {
+            assert_eq!(
+                run(&script(contents), &[Some(100), Some(100)]),
+                expected,
+                "{}",
+                contents
+            );
+        }
+
+        // without a rate of its own, the next stage starts from the 1000
+        // queries per second achieved, split over 2 nameservers
+        let rules = script("if successes>=0 then qps*2\n");
+        assert_eq!(run(&rules, &[Some(100), None]), [Some(100), Some(1000)]);
+    }
+
+    #[test]
+    fn stops() {
+        let rules = script("if successes>=0 then qps*2\nif failures<1 then stop\n");
+        assert_eq!(run(&rules, &[Some(10), Some(20), Some(30)]), [Some(10)]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, ToSocketAddrs},
+    str::FromStr,
+};
+
+const VERSION: u8 = 0x05;
+const NO_AUTH: u8 = 0x00;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Proxy is a SOCKS5 proxy given as `socks5://host:port`.
+#[derive(Debug, Clone, Copy)]
+pub struct Proxy(pub SocketAddr);
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addr = s
+            .strip_prefix("socks5://")
+            .ok_or_else(|| format!("invalid proxy '{}': expected socks5://host:port", s))?;
+
+        addr.to_socket_addrs()
+            .map_err(|e| format!("invalid proxy '{}': {}", s, e))?
+            .next()
+            .map(Proxy)
+            .ok_or_else(|| format!("invalid proxy '{}': host did not resolve", s))
+    }
+}
+
+impl std::fmt::Display for Proxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "socks5://{}", self.0)
+    }
+}
+
+/// Performs the SOCKS5 handshake on a stream already connected to the proxy,
+/// asking it to CONNECT to `target`. Only the no-authentication method is
+/// offered.
+pub fn handshake<S: Read + Write>(stream: &mut S, target: SocketAddr) -> io::Result<()> {
+    stream.write_all(&[VERSION, 1, NO_AUTH])?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [VERSION, NO_AUTH] {
+        return Err(io::Error::other("socks5 proxy requires authentication"));
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request)?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header)?;
+    if header[1] != 0 {
+        return Err(io::Error::other(format!(
+            "socks5 proxy refused connection (reply {})",
+            header[1]
+        )));
+    }
+
+    // drain the bound address the proxy reports; we have no use for it.
+    let len = match header[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        atyp => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("socks5 proxy sent unknown address type {}", atyp),
+            ))
+        }
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // a proxy that answers with `reply`, keeping what it is sent
+    struct Stream {
+        reply: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Stream {
+        fn new(reply: &[u8]) -> Self {
+            Self {
+                reply: Cursor::new(reply.to_vec()),
+                sent: Vec::new(),
+            }
+        }
+
+        // reports whether all of the reply was read
+        fn drained(&self) -> bool {
+            self.reply.position() as usize == self.reply.get_ref().len()
+        }
+    }
+
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reply.read(buf)
+        }
+    }
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const METHOD: [u8; 2] = [VERSION, NO_AUTH];
+
+    fn reply(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    #[test]
+    fn connects_over_ipv4() {
+        let mut stream = Stream::new(&reply(&[
+            &METHOD,
+            &[VERSION, 0, 0, ATYP_IPV4, 10, 0, 0, 1, 0x1f, 0x90],
+        ]));
+        handshake(&mut stream, "192.0.2.53:53".parse().unwrap()).unwrap();
+
+        assert_eq!(
+            stream.sent,
+            reply(&[
+                &[VERSION, 1, NO_AUTH],
+                &[VERSION, CONNECT, 0, ATYP_IPV4, 192, 0, 2, 53, 0, 53],
+            ])
+        );
+        assert!(stream.drained());
+    }
+
+    #[test]
+    fn connects_over_ipv6() {
+        let mut bound = vec![VERSION, 0, 0, ATYP_IPV6];
+        bound.extend([0u8; 16]);
+        bound.extend([0, 53]);
+        let mut stream = Stream::new(&reply(&[&METHOD, &bound]));
+        handshake(&mut stream, "[2001:db8::53]:5353".parse().unwrap()).unwrap();
+
+        let mut request = vec![VERSION, CONNECT, 0, ATYP_IPV6];
+        request.extend([0x20, 0x01, 0x0d, 0xb8]);
+        request.extend([0u8; 11]);
+        request.push(0x53);
+        request.extend(5353u16.to_be_bytes());
+        assert_eq!(stream.sent[3..], request[..]);
+        assert!(stream.drained());
+    }
+
+    #[test]
+    fn reads_past_a_bound_domain_name() {
+        let mut stream = Stream::new(&reply(&[
+            &METHOD,
+            &[VERSION, 0, 0, ATYP_DOMAIN, 5],
+            b"proxy",
+            &[0x1f, 0x90],
+        ]));
+        handshake(&mut stream, "192.0.2.53:53".parse().unwrap()).unwrap();
+
+        assert!(stream.drained());
+    }
+
+    #[test]
+    fn fails_when_authentication_is_required() {
+        let mut stream = Stream::new(&[VERSION, 0xff]);
+        let err = handshake(&mut stream, "192.0.2.53:53".parse().unwrap()).unwrap_err();
+
+        assert_eq!(err.to_string(), "socks5 proxy requires authentication");
+        // no CONNECT is sent
+        assert_eq!(stream.sent, [VERSION, 1, NO_AUTH]);
+    }
+
+    #[test]
+    fn fails_when_the_connection_is_refused() {
+        // 5 is "connection refused"
+        let mut stream = Stream::new(&reply(&[
+            &METHOD,
+            &[VERSION, 5, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0],
+        ]));
+        let err = handshake(&mut stream, "192.0.2.53:53".parse().unwrap()).unwrap_err();
+
+        assert_eq!(err.to_string(), "socks5 proxy refused connection (reply 5)");
+    }
+
+    #[test]
+    fn fails_on_an_unknown_address_type() {
+        let mut stream = Stream::new(&reply(&[&METHOD, &[VERSION, 0, 0, 0x02]]));
+        let err = handshake(&mut stream, "192.0.2.53:53".parse().unwrap()).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "socks5 proxy sent unknown address type 2");
+    }
+
+    #[test]
+    fn fails_on_a_short_reply() {
+        let cases: [&[u8]; 4] = [
+            &[VERSION],
+            &[VERSION, NO_AUTH, VERSION, 0],
+            &[VERSION, NO_AUTH, VERSION, 0, 0, ATYP_IPV4, 10, 0],
+            &[VERSION, NO_AUTH, VERSION, 0, 0, ATYP_DOMAIN, 5, b'p'],
+        ];
+
+        for reply in cases {
+            let mut stream = Stream::new(reply);
+            let err = handshake(&mut stream, "192.0.2.53:53".parse().unwrap()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof, "{:?}", reply);
+        }
+    }
+
+    #[test]
+    fn parses_proxies() {
+        let proxy: Proxy = "socks5://127.0.0.1:1080".parse().unwrap();
+        assert_eq!(proxy.0, "127.0.0.1:1080".parse().unwrap());
+        assert_eq!(proxy.to_string(), "socks5://127.0.0.1:1080");
+
+        assert_eq!(
+            "127.0.0.1:1080".parse::<Proxy>().unwrap_err(),
+            "invalid proxy '127.0.0.1:1080': expected socks5://host:port"
+        );
+        assert!("socks5://127.0.0.1".parse::<Proxy>().is_err());
+    }
+}
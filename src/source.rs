@@ -0,0 +1,162 @@
+use std::{fmt, sync::Arc};
+
+use ipnet::IpNet;
+use rand::RngCore;
+use trust_dns_resolver::{proto::rr::RecordType, Name};
+
+use crate::workload::{self, NameTemplate, Sampler};
+
+/// WorkloadSource produces the queries a single worker sends, one at a time.
+/// Each worker has a source of its own, so it can keep its place without
+/// locking. The worker loops only ever call `next_query`, so a new way of
+/// picking names needs a source and a Factory for it, not changes to them.
+pub trait WorkloadSource: Send {
+    /// Returns the next name to query, and its record type if the source
+    /// picks one; the run's qtype mix picks it otherwise.
+    fn next_query(&mut self, rng: &mut dyn RngCore) -> (Name, Option<RecordType>);
+}
+
+impl fmt::Debug for dyn WorkloadSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WorkloadSource")
+    }
+}
+
+/// Factory builds the source for each worker, given its number. Wrapped in
+/// workload::Names::Custom, it is how a workload of your own is plugged into
+/// a run.
+#[derive(Clone)]
+pub struct Factory(Arc<dyn Fn(usize) -> Box<dyn WorkloadSource> + Send + Sync>);
+
+impl Factory {
+    pub fn new<F>(build: F) -> Self
+    where
+        F: Fn(usize) -> Box<dyn WorkloadSource> + Send + Sync + 'static,
+    {
+        Self(Arc::new(build))
+    }
+
+    /// Returns the source for worker number `worker`.
+    pub fn build(&self, worker: usize) -> Box<dyn WorkloadSource> {
+        (self.0)(worker)
+    }
+}
+
+impl fmt::Debug for Factory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Factory")
+    }
+}
+
+/// Single asks for the same name every time.
+pub struct Single(pub Name);
+
+impl WorkloadSource for Single {
+    fn next_query(&mut self, _rng: &mut dyn RngCore) -> (Name, Option<RecordType>) {
+        (self.0.clone(), None)
+    }
+}
+
+/// List picks entries from a list loaded from a file, in order, uniformly at
+/// random or along a Zipf curve, as its Sampler says. In order, it starts
+/// `offset` entries in and moves `stride` entries at a time.
+pub struct List {
+    names: Arc<Vec<(Name, Option<RecordType>)>>,
+    sampler: Sampler,
+    seq: usize,
+    stride: usize,
+}
+
+impl List {
+    pub fn new(
+        names: Arc<Vec<(Name, Option<RecordType>)>>,
+        sampler: Sampler,
+        offset: usize,
+        stride: usize,
+    ) -> Self {
+        Self {
+            names,
+            sampler,
+            seq: offset,
+            stride,
+        }
+    }
+}
+
+impl WorkloadSource for List {
+    fn next_query(&mut self, rng: &mut dyn RngCore) -> (Name, Option<RecordType>) {
+        let index = self.sampler.index(rng, self.seq, self.names.len());
+        self.seq = self.seq.wrapping_add(self.stride);
+        self.names[index].clone()
+    }
+}
+
+/// RandomPrefix asks for `<random label>.<suffix>`, with a new label every
+/// time, so that no answer can come from cache.
+pub struct RandomPrefix(pub Name);
+
+impl WorkloadSource for RandomPrefix {
+    fn next_query(&mut self, rng: &mut dyn RngCore) -> (Name, Option<RecordType>) {
+        // the suffix was checked to have room for the label up front
+        (
+            workload::random_label(rng, workload::RANDOM_LABEL_LEN)
+                .append_domain(&self.0)
+                .unwrap(),
+            None,
+        )
+    }
+}
+
+/// PtrRange walks the reverse names of the addresses in a network, starting
+/// `offset` addresses in and moving `stride` at a time.
+pub struct PtrRange {
+    net: IpNet,
+    seq: usize,
+    stride: usize,
+}
+
+impl PtrRange {
+    pub fn new(net: IpNet, offset: usize, stride: usize) -> Self {
+        Self {
+            net,
+            seq: offset,
+            stride,
+        }
+    }
+}
+
+impl WorkloadSource for PtrRange {
+    fn next_query(&mut self, _rng: &mut dyn RngCore) -> (Name, Option<RecordType>) {
+        let n = self.seq as u128 % workload::net_size(&self.net);
+        self.seq = self.seq.wrapping_add(self.stride);
+        (Name::from(workload::nth_address(&self.net, n)), None)
+    }
+}
+
+/// Template renders a NameTemplate for every query, for worker number
+/// `worker`, counting `{seq}` from `offset` by `stride`.
+pub struct Template {
+    template: Arc<NameTemplate>,
+    worker: usize,
+    seq: usize,
+    stride: usize,
+}
+
+impl Template {
+    pub fn new(template: Arc<NameTemplate>, worker: usize, offset: usize, stride: usize) -> Self {
+        Self {
+            template,
+            worker,
+            seq: offset,
+            stride,
+        }
+    }
+}
+
+impl WorkloadSource for Template {
+    fn next_query(&mut self, rng: &mut dyn RngCore) -> (Name, Option<RecordType>) {
+        let name = self.template.render(rng, self.seq, self.worker);
+        self.seq = self.seq.wrapping_add(self.stride);
+        (name, None)
+    }
+}
@@ -0,0 +1,155 @@
+use std::{path::Path, time::Duration};
+
+use trust_dns_resolver::proto::rr::RecordType;
+
+use crate::{
+    rate::{Profile, Ramp, Wave},
+    workload::QtypeMix,
+};
+
+/// Length is how long a stage runs for: a fixed time, or until a number of
+/// queries have been answered (or timed out).
+#[derive(Debug, Clone, Copy)]
+pub enum Length {
+    Time(Duration),
+    Queries(u64),
+}
+
+impl std::fmt::Display for Length {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Length::Time(duration) => write!(f, "{}s", duration.as_secs()),
+            Length::Queries(queries) => write!(f, "{} queries", queries),
+        }
+    }
+}
+
+/// Stage is one period of a load profile, with its own length, offered load
+/// and record types.
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub length: Length,
+    pub qps: Option<u64>,
+    pub profile: Option<Profile>,
+    pub qtypes: Option<QtypeMix>,
+}
+
+impl Stage {
+    pub fn contains(&self, record_type: RecordType) -> bool {
+        self.qtypes
+            .as_ref()
+            .is_some_and(|qtypes| qtypes.contains(record_type))
+    }
+}
+
+/// Reads a load profile: one stage per line, as whitespace separated
+/// `key=value` settings, run in file order. Blank lines and `#` comments are
+/// skipped.
+///
+/// ```text
+/// # warm up, then push
+/// duration=30 qps=1000
+/// duration=60 ramp=1000:20000 qtypes=A:60,AAAA:40
+/// duration=120 qps=20000 qtype=AAAA
+/// duration=300 wave=5000:20000:60
+/// ```
+///
+/// Either `duration` (in seconds) or `queries` is required. `qps` holds the
+/// rate, `ramp` raises it linearly over the stage's duration and `wave`
+/// (`<min>:<max>:<period>`) swings it up and down; with none of them the stage
+/// runs as fast as possible. `qtype` or `qtypes` override the record types from the command
+/// line.
+pub fn parse(path: &Path) -> Result<Vec<Stage>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+    let mut stages = Vec::new();
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let err = |msg: String| format!("{}:{}: {}", path.display(), lineno + 1, msg);
+
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut duration = None;
+        let mut queries = None;
+        let mut qps = None;
+        let mut ramp = None;
+        let mut wave = None;
+        let mut qtypes = None;
+
+        for setting in line.split_whitespace() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| err(format!("expected key=value, got '{}'", setting)))?;
+
+            match key {
+                "duration" => match value.parse::<u64>() {
+                    Ok(secs) if secs > 0 => duration = Some(Duration::from_secs(secs)),
+                    _ => return Err(err(format!("invalid duration '{}'", value))),
+                },
+                "queries" => match value.parse::<u64>() {
+                    Ok(count) if count > 0 => queries = Some(count),
+                    _ => return Err(err(format!("invalid queries '{}'", value))),
+                },
+                "qps" => match value.parse::<u64>() {
+                    Ok(rate) if rate > 0 => qps = Some(rate),
+                    _ => return Err(err(format!("invalid qps '{}'", value))),
+                },
+                "ramp" => ramp = Some(value),
+                "wave" => wave = Some(value.parse::<Wave>().map_err(err)?),
+                "qtype" => {
+                    qtypes = Some(QtypeMix::single(
+                        crate::parse_record_type(value).map_err(err)?,
+                    ))
+                }
+                "qtypes" => qtypes = Some(value.parse::<QtypeMix>().map_err(err)?),
+                _ => return Err(err(format!("unknown setting '{}'", key))),
+            }
+        }
+
+        let length = match (duration, queries) {
+            (Some(duration), None) => Length::Time(duration),
+            (None, Some(queries)) => Length::Queries(queries),
+            (Some(_), Some(_)) => {
+                return Err(err("duration and queries cannot be used together".into()))
+            }
+            (None, None) => return Err(err("stage needs a duration or queries".into())),
+        };
+
+        if [qps.is_some(), ramp.is_some(), wave.is_some()]
+            .iter()
+            .filter(|given| **given)
+            .count()
+            > 1
+        {
+            return Err(err("only one of qps, ramp and wave may be given".into()));
+        }
+
+        // the ramp runs over the whole stage, so its length comes from there
+        let ramp = match (ramp, duration) {
+            (Some(_), None) => return Err(err("ramp requires a duration".into())),
+            (Some(ramp), Some(duration)) => Some(
+                format!("{}:{}", ramp, duration.as_secs())
+                    .parse::<Ramp>()
+                    .map_err(|_| err(format!("invalid ramp '{}': expected <start>:<end>", ramp)))?,
+            ),
+            (None, _) => None,
+        };
+
+        stages.push(Stage {
+            length,
+            qps,
+            profile: ramp.map(Profile::Ramp).or(wave.map(Profile::Wave)),
+            qtypes,
+        });
+    }
+
+    if stages.is_empty() {
+        return Err(format!("{}: no stages found", path.display()));
+    }
+
+    Ok(stages)
+}
@@ -0,0 +1,109 @@
+use std::{
+    fmt::Write as _,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use crate::{rcode_name, RunDetails};
+
+// metrics are packed into datagrams no larger than this, so that they fit in
+// an ethernet frame without fragmenting
+const MAX_DATAGRAM: usize = 1432;
+
+/// Client sends each interval's results to a StatsD server, with DogStatsD
+/// tags so that Datadog can break them down by nameserver.
+pub struct Client {
+    socket: UdpSocket,
+    addr: SocketAddr,
+}
+
+impl Client {
+    /// Resolves `addr`, given as `<host>:<port>`, and opens a socket to send
+    /// from.
+    pub fn new(addr: &str) -> io::Result<Self> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses for host"))?;
+
+        let bind: SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+
+        Ok(Self {
+            socket: UdpSocket::bind(bind)?,
+            addr,
+        })
+    }
+
+    /// Sends the counters and latencies of an interval's queries to
+    /// `nameserver`. Latencies go out as one timing per histogram bucket, with
+    /// a sample rate standing for how many queries landed in it.
+    pub fn send(&self, nameserver: &SocketAddr, details: &RunDetails) -> io::Result<()> {
+        let tags = format!("#nameserver:{}", nameserver);
+        let mut lines = vec![
+            format!("nsbench.successes:{}|c|{}", details.successes, tags),
+            format!("nsbench.truncated:{}|c|{}", details.truncated, tags),
+            format!("nsbench.tcp_fallbacks:{}|c|{}", details.tcp_fallbacks, tags),
+            format!("nsbench.bytes_sent:{}|c|{}", details.bytes_sent, tags),
+            format!(
+                "nsbench.bytes_received:{}|c|{}",
+                details.bytes_received, tags
+            ),
+        ];
+
+        let causes = &details.failure_causes;
+        for (cause, count) in [
+            ("timeout", causes.timeout),
+            ("servfail", causes.servfail),
+            ("refused", causes.refused),
+            ("nxdomain", causes.nxdomain),
+            ("network", causes.network),
+            ("wrong_answer", causes.wrong_answer),
+            ("wrong_flags", causes.wrong_flags),
+            ("other", causes.other),
+        ] {
+            if count > 0 {
+                lines.push(format!(
+                    "nsbench.failures:{}|c|{},cause:{}",
+                    count, tags, cause
+                ));
+            }
+        }
+
+        for (rcode, count) in &details.rcodes {
+            lines.push(format!(
+                "nsbench.responses:{}|c|{},rcode:{}",
+                count,
+                tags,
+                rcode_name(*rcode)
+            ));
+        }
+
+        for (latency, count) in details.latencies.buckets() {
+            let mut line = format!("nsbench.latency:{:.3}|ms", latency as f64 / 1_000_000.0);
+            if count > 1 {
+                write!(line, "|@{}", 1.0 / count as f64).unwrap();
+            }
+            write!(line, "|{}", tags).unwrap();
+            lines.push(line);
+        }
+
+        let mut datagram = String::new();
+        for line in lines {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.socket.send_to(datagram.as_bytes(), self.addr)?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        self.socket.send_to(datagram.as_bytes(), self.addr)?;
+
+        Ok(())
+    }
+}
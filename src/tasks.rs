@@ -0,0 +1,376 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream, UdpSocket},
+    runtime,
+    task::JoinSet,
+    time::timeout,
+};
+use trust_dns_resolver::proto::{
+    op::{Message, MessageType},
+    rr::{Name, RecordType},
+};
+
+use tracing::error;
+
+use crate::{
+    failurelog, mismatch, reporter::Reporter, trace, wire, Mismatches, QueryConfig, Transport,
+};
+
+// how long to wait for a query to finish when the window is full (or the rate
+// limiter is empty) before looking again
+const TICK: Duration = Duration::from_millis(1);
+
+/// Keeps up to `window` queries in flight as tasks on a single threaded tokio
+/// runtime, starting a new one as each is answered or times out. Each query
+/// has a socket (or TCP connection) of its own, so it waits on nothing but its
+/// answer; a window of thousands needs the open file limit raised to match.
+///
+/// Unlike the `concurrent` engine, retries, TCP and falling back to it when
+/// an answer is truncated are all supported, as each task does what a Client
+/// would for its query. SOCKS5 proxies are not.
+pub fn perform_queries(qc: &mut QueryConfig, reporter: &mut Reporter, window: usize) {
+    let rt = match runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!("could not start the async runtime: {}", e);
+            return;
+        }
+    };
+
+    rt.block_on(run(qc, reporter, window));
+}
+
+async fn run(qc: &mut QueryConfig, reporter: &mut Reporter, window: usize) {
+    let mut rng = rand::thread_rng();
+    let mut trace = qc
+        .trace
+        .as_ref()
+        .map(|trace| trace::Buffer::new(trace.clone(), qc.nameserver));
+    let mut tasks = JoinSet::new();
+    let mut next_id: u16 = 0;
+    let mut sending = true;
+
+    while sending || !tasks.is_empty() {
+        reporter.tick();
+        if qc.finished.load(Ordering::Relaxed) {
+            sending = false;
+        }
+
+        while sending && tasks.len() < window {
+            if let Some(limiter) = &qc.limiter {
+                if !limiter.try_acquire() {
+                    break;
+                }
+            }
+
+            if let Some(budget) = &qc.budget {
+                if !budget.take() {
+                    sending = false;
+                    break;
+                }
+            }
+
+            let (name, record_type) = qc.names.next(&mut rng);
+            let record_type = record_type.unwrap_or_else(|| qc.qtypes.sample(&mut rng));
+            *reporter
+                .details()
+                .queries_by_type
+                .entry(record_type)
+                .or_default() += 1;
+
+            // every query has a socket of its own, so ids only need to differ
+            // between the retransmissions on one
+            next_id = next_id.wrapping_add(1);
+            tasks.spawn(query(
+                qc.nameserver,
+                qc.client_options,
+                next_id,
+                name,
+                record_type,
+            ));
+        }
+
+        if tasks.is_empty() {
+            if sending {
+                tokio::time::sleep(TICK).await;
+            }
+            continue;
+        }
+
+        let outcome = match timeout(TICK, tasks.join_next()).await {
+            Ok(Some(Ok(outcome))) => outcome,
+            Ok(Some(Err(e))) => {
+                error!("query task failed: {}", e);
+                continue;
+            }
+            Ok(None) | Err(_) => continue,
+        };
+
+        if let Some(trace) = &mut trace {
+            trace.record(
+                outcome.sent,
+                &outcome.name,
+                outcome.record_type,
+                &outcome.res,
+                outcome.latency,
+            );
+        }
+
+        let details = reporter.details();
+        let success = details.record(
+            outcome.record_type,
+            &outcome.res,
+            outcome.latency.as_nanos(),
+            qc.expect_nxdomain,
+            qc.expected.as_deref(),
+        );
+        if !success {
+            qc.failure_log.failed(
+                qc.nameserver,
+                &outcome.name,
+                outcome.record_type,
+                outcome.latency,
+                || failurelog::cause(&outcome.res),
+            );
+        }
+        details.bytes_sent += outcome.traffic.bytes_sent;
+        details.bytes_received += outcome.traffic.bytes_received;
+        details.mismatches += outcome.traffic.mismatches;
+
+        if let Some(budget) = &qc.budget {
+            budget.complete(1);
+        }
+    }
+}
+
+// what a query task hands back to be recorded
+struct Outcome {
+    sent: Instant,
+    name: Name,
+    record_type: RecordType,
+    res: Result<wire::Exchange, wire::QueryError>,
+    latency: Duration,
+    traffic: Traffic,
+}
+
+// everything a query sent and received, as a Client would count it
+#[derive(Default)]
+struct Traffic {
+    bytes_sent: u64,
+    bytes_received: u64,
+    mismatches: Mismatches,
+}
+
+async fn query(
+    nameserver: SocketAddr,
+    options: wire::ClientOptions,
+    id: u16,
+    name: Name,
+    record_type: RecordType,
+) -> Outcome {
+    let mut sent = Instant::now();
+    let mut traffic = Traffic::default();
+    let res = match wire::build_query(id, name.clone(), record_type, &options) {
+        Ok(packet) => match options.transport {
+            // the clock starts once the socket is open, as a Client opens
+            // its socket before the first query and keeps it between them
+            Transport::Udp => match udp_socket(nameserver, &options) {
+                Ok(socket) => {
+                    sent = Instant::now();
+                    exchange_udp(
+                        &socket,
+                        &options,
+                        id,
+                        &packet,
+                        &name,
+                        record_type,
+                        &mut traffic,
+                    )
+                    .await
+                }
+                Err(e) => Err(e.into()),
+            },
+            Transport::Tcp => exchange_tcp(nameserver, &options, id, &packet, &mut traffic)
+                .await
+                .map(|(message, size, connect_time)| wire::Exchange {
+                    message,
+                    size,
+                    attempts: 1,
+                    connect_time: Some(connect_time),
+                    truncated: false,
+                    tcp_fallback: false,
+                }),
+        },
+        Err(e) => Err(e.into()),
+    };
+
+    // this includes opening the query's TCP connection, if it needed one;
+    // RunDetails::record takes the exchange's connect_time back out, as it
+    // does for a Client's, so the latencies compare with the other engines
+    Outcome {
+        sent,
+        name,
+        record_type,
+        res,
+        latency: sent.elapsed(),
+        traffic,
+    }
+}
+
+fn udp_socket(nameserver: SocketAddr, options: &wire::ClientOptions) -> io::Result<UdpSocket> {
+    let socket = wire::udp_socket(nameserver, options)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+// sends `packet` on `socket`, which is connected to the nameserver, falling
+// back to TCP if the answer is truncated
+async fn exchange_udp(
+    socket: &UdpSocket,
+    options: &wire::ClientOptions,
+    id: u16,
+    packet: &[u8],
+    name: &Name,
+    record_type: RecordType,
+    traffic: &mut Traffic,
+) -> Result<wire::Exchange, wire::QueryError> {
+    let mut attempts = 0;
+    let (message, size) = loop {
+        attempts += 1;
+        socket.send(packet).await?;
+        traffic.bytes_sent += packet.len() as u64;
+
+        match timeout(
+            options.timeout,
+            recv_udp(socket, id, name, record_type, traffic),
+        )
+        .await
+        {
+            Ok(res) => break res?,
+            Err(_) if attempts <= options.retries => continue,
+            Err(_) => return Err(wire::QueryError::Timeout),
+        }
+    };
+
+    if !message.truncated() || !options.tcp_fallback {
+        return Ok(wire::Exchange {
+            truncated: message.truncated(),
+            message,
+            size,
+            attempts,
+            connect_time: None,
+            tcp_fallback: false,
+        });
+    }
+
+    let (message, size, connect_time) =
+        exchange_tcp(socket.peer_addr()?, options, id, packet, traffic).await?;
+    Ok(wire::Exchange {
+        message,
+        size,
+        attempts,
+        connect_time: Some(connect_time),
+        truncated: true,
+        tcp_fallback: true,
+    })
+}
+
+// reads from `socket` until the answer to query `id` arrives, skipping
+// anything else
+async fn recv_udp(
+    socket: &UdpSocket,
+    id: u16,
+    name: &Name,
+    record_type: RecordType,
+    traffic: &mut Traffic,
+) -> Result<(Message, usize), wire::QueryError> {
+    loop {
+        // the buffer only lives for as long as it takes to read a datagram
+        // that has already arrived, rather than for as long as every query
+        // in the window waits
+        socket.readable().await?;
+        let mut buf = vec![0u8; wire::MAX_UDP_SIZE];
+        let len = match socket.try_recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e.into()),
+        };
+        traffic.bytes_received += len as u64;
+
+        let message = match Message::from_vec(&buf[..len]) {
+            Ok(message) if message.message_type() == MessageType::Response => message,
+            _ => {
+                traffic.mismatches.malformed += 1;
+                continue;
+            }
+        };
+
+        if message.id() != id {
+            traffic.mismatches.unsolicited += 1;
+        } else if !mismatch::answers(&message, name, record_type) {
+            traffic.mismatches.wrong_question += 1;
+        } else {
+            return Ok((message, len));
+        }
+    }
+}
+
+// sends `packet`, query `id`, over a new TCP connection, returning the answer, its size and
+// how long connecting took
+async fn exchange_tcp(
+    nameserver: SocketAddr,
+    options: &wire::ClientOptions,
+    id: u16,
+    packet: &[u8],
+    traffic: &mut Traffic,
+) -> Result<(Message, usize, Duration), wire::QueryError> {
+    let start = Instant::now();
+    let socket = wire::tcp_socket(nameserver, nameserver, options)?;
+    socket.set_nonblocking(true)?;
+    let socket = TcpSocket::from_std_stream(socket.into());
+    let mut stream = timeout(options.timeout, socket.connect(nameserver))
+        .await
+        .map_err(|_| wire::QueryError::Timeout)??;
+    stream.set_nodelay(true)?;
+    let connect_time = start.elapsed();
+
+    timeout(
+        options.timeout,
+        roundtrip_tcp(&mut stream, id, packet, traffic),
+    )
+    .await
+    .map_err(|_| wire::QueryError::Timeout)?
+    .map(|(message, size)| (message, size, connect_time))
+}
+
+async fn roundtrip_tcp(
+    stream: &mut TcpStream,
+    id: u16,
+    packet: &[u8],
+    traffic: &mut Traffic,
+) -> Result<(Message, usize), wire::QueryError> {
+    let mut framed = Vec::with_capacity(packet.len() + 2);
+    framed.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    framed.extend_from_slice(packet);
+    stream.write_all(&framed).await?;
+    traffic.bytes_sent += framed.len() as u64;
+
+    loop {
+        let len = stream.read_u16().await?;
+        let mut buf = vec![0u8; len as usize];
+        stream.read_exact(&mut buf).await?;
+        traffic.bytes_received += 2 + buf.len() as u64;
+
+        let message = Message::from_vec(&buf)?;
+        if message.id() == id {
+            return Ok((message, buf.len()));
+        }
+    }
+}
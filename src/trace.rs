@@ -0,0 +1,233 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Write},
+    net::SocketAddr,
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use trust_dns_resolver::proto::rr::{Name, RecordType};
+
+use crate::{rcode_name, wire};
+
+// how much a worker collects before handing it over to be written, so that
+// the workers rarely contend for the file
+const BUFFER_SIZE: usize = 64 * 1024;
+
+const HEADER: &str = "timestamp,nameserver,name,type,response,latency_ns\n";
+
+#[derive(Debug)]
+enum Sink {
+    File(File),
+    // a compressor writing to the file, fed through its stdin
+    Compressor(ChildStdin, Child),
+}
+
+/// Trace records every query sent as a line of CSV: when it was sent, where
+/// to, what was asked, what came back and how long it took. A path ending in
+/// `.gz` or `.zst` is compressed with the `gzip` or `zstd` command, which
+/// must be installed.
+#[derive(Debug)]
+pub struct Trace {
+    sink: Mutex<Option<Sink>>,
+    error: Mutex<Option<io::Error>>,
+    // the wall clock time at `started`, to turn instants into timestamps
+    epoch: SystemTime,
+    started: Instant,
+}
+
+impl Trace {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let compressor = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Some("gzip"),
+            Some("zst") => Some("zstd"),
+            _ => None,
+        };
+
+        let mut sink = match compressor {
+            Some(command) => {
+                let spawned = Command::new(command)
+                    .arg("-c")
+                    .stdin(Stdio::piped())
+                    .stdout(file)
+                    .spawn();
+                let mut child = match spawned {
+                    Ok(child) => child,
+                    Err(e) => {
+                        // don't leave an empty trace behind to be mistaken
+                        // for one of a run without queries
+                        let _ = std::fs::remove_file(path);
+                        return Err(match e.kind() {
+                            io::ErrorKind::NotFound => io::Error::new(
+                                e.kind(),
+                                format!(
+                                    "compressing it needs the {} command, which is not installed",
+                                    command
+                                ),
+                            ),
+                            _ => io::Error::new(
+                                e.kind(),
+                                format!("could not run {}: {}", command, e),
+                            ),
+                        });
+                    }
+                };
+                Sink::Compressor(child.stdin.take().unwrap(), child)
+            }
+            None => Sink::File(file),
+        };
+        sink.write_all(HEADER.as_bytes())?;
+
+        Ok(Self {
+            sink: Mutex::new(Some(sink)),
+            error: Mutex::new(None),
+            epoch: SystemTime::now(),
+            started: Instant::now(),
+        })
+    }
+
+    // writes out lines from a worker, keeping the first error for finish
+    fn write(&self, lines: &str) {
+        if let Some(sink) = self.sink.lock().unwrap().as_mut() {
+            if let Err(e) = sink.write_all(lines.as_bytes()) {
+                self.error.lock().unwrap().get_or_insert(e);
+            }
+        }
+    }
+
+    /// Flushes the trace, once every worker is done with it, and waits for
+    /// any compressor to finish.
+    pub fn finish(&self) -> io::Result<()> {
+        if let Some(e) = self.error.lock().unwrap().take() {
+            return Err(e);
+        }
+
+        match self.sink.lock().unwrap().take() {
+            Some(Sink::File(mut file)) => file.flush(),
+            Some(Sink::Compressor(stdin, mut child)) => {
+                drop(stdin);
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!(
+                        "compressor exited with {}",
+                        status
+                    )));
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::File(file) => file.write(buf),
+            Sink::Compressor(stdin, _) => stdin.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.flush(),
+            Sink::Compressor(stdin, _) => stdin.flush(),
+        }
+    }
+}
+
+/// Buffer collects one worker's lines of the trace, and writes them out in
+/// large pieces as it goes and when it is dropped.
+pub struct Buffer {
+    trace: Arc<Trace>,
+    nameserver: SocketAddr,
+    lines: String,
+}
+
+impl Buffer {
+    pub fn new(trace: Arc<Trace>, nameserver: SocketAddr) -> Self {
+        Self {
+            trace,
+            nameserver,
+            lines: String::with_capacity(BUFFER_SIZE),
+        }
+    }
+
+    /// Records a `record_type` query for `name`, sent at `sent`, that ended
+    /// with `res` after `latency`.
+    pub fn record(
+        &mut self,
+        sent: Instant,
+        name: &Name,
+        record_type: RecordType,
+        res: &Result<wire::Exchange, wire::QueryError>,
+        latency: Duration,
+    ) {
+        let response = match res {
+            Ok(exchange) => rcode_name(exchange.message.response_code().into()),
+            Err(wire::QueryError::Timeout) => "TIMEOUT".to_string(),
+            Err(wire::QueryError::Io(_)) => "NETWORK_ERROR".to_string(),
+            Err(wire::QueryError::Proto(_)) => "PROTOCOL_ERROR".to_string(),
+        };
+        self.line(sent, name, record_type, &response, latency);
+    }
+
+    /// Records a `record_type` query for `name`, sent at `sent`, that was
+    /// never answered.
+    pub fn timed_out(&mut self, sent: Instant, name: &Name, record_type: RecordType) {
+        let latency = sent.elapsed();
+        self.line(sent, name, record_type, "TIMEOUT", latency);
+    }
+
+    fn line(
+        &mut self,
+        sent: Instant,
+        name: &Name,
+        record_type: RecordType,
+        response: &str,
+        latency: Duration,
+    ) {
+        let timestamp = (self.trace.epoch + sent.saturating_duration_since(self.trace.started))
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        writeln!(
+            self.lines,
+            "{}.{:06},{},{},{},{},{}",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            self.nameserver,
+            quote(name.to_string()),
+            record_type,
+            response,
+            latency.as_nanos()
+        )
+        .unwrap();
+
+        if self.lines.len() >= BUFFER_SIZE {
+            self.trace.write(&self.lines);
+            self.lines.clear();
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if !self.lines.is_empty() {
+            self.trace.write(&self.lines);
+        }
+    }
+}
+
+// quotes a CSV field if it needs it; names from templates can hold anything
+fn quote(field: String) -> String {
+    if !field.contains([',', '"', '\n']) {
+        return field;
+    }
+
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    io::{self, Write},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{RunDetails, LATENCY_PERCENTILES};
+
+// how many seconds of history the sparklines cover
+const HISTORY: usize = 60;
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// moves the cursor home and clears the screen below it
+const CLEAR: &str = "\x1b[H\x1b[J";
+
+/// Dashboard redraws a summary of the run so far on the terminal every
+/// second, in place of the per-second lines.
+pub struct Dashboard {
+    nameservers: Vec<SocketAddr>,
+    rates: VecDeque<u64>,
+    latencies: VecDeque<Duration>,
+    started: Instant,
+}
+
+impl Dashboard {
+    pub fn new(nameservers: Vec<SocketAddr>) -> Self {
+        Self {
+            nameservers,
+            rates: VecDeque::with_capacity(HISTORY),
+            latencies: VecDeque::with_capacity(HISTORY),
+            started: Instant::now(),
+        }
+    }
+
+    /// Adds the last second's results to the history, and redraws the screen
+    /// with `totals`, the results for the stage so far, and `per_target`, the
+    /// same for each nameserver.
+    pub fn draw(&mut self, interval: &RunDetails, totals: &RunDetails, per_target: &[RunDetails]) {
+        if self.rates.len() == HISTORY {
+            self.rates.pop_front();
+            self.latencies.pop_front();
+        }
+        self.rates.push_back(interval.successes);
+        self.latencies.push_back(interval.mean_latency());
+
+        let mut screen = String::from(CLEAR);
+        writeln!(
+            screen,
+            "nsbench: {} nameserver(s), {}s elapsed\n",
+            self.nameservers.len(),
+            self.started.elapsed().as_secs()
+        )
+        .unwrap();
+
+        writeln!(
+            screen,
+            "QPS          {:>10}   {}",
+            interval.successes,
+            sparkline(self.rates.iter().map(|rate| *rate as f64))
+        )
+        .unwrap();
+        writeln!(
+            screen,
+            "Avg latency  {:>10}   {}",
+            format!("{:.2?}", interval.mean_latency()),
+            sparkline(self.latencies.iter().map(|latency| latency.as_secs_f64()))
+        )
+        .unwrap();
+        writeln!(
+            screen,
+            "Success rate {:>9.2}%   {} failures{}\n",
+            rate(totals),
+            totals.failures,
+            causes(totals)
+        )
+        .unwrap();
+
+        write!(screen, "{:<24}", "Latency").unwrap();
+        for (label, _) in LATENCY_PERCENTILES {
+            write!(screen, "{:>10}", label).unwrap();
+        }
+        writeln!(screen, "{:>10}", "max").unwrap();
+        self.percentiles(&mut screen, "all", totals);
+        if self.nameservers.len() > 1 {
+            for (nameserver, details) in self.nameservers.iter().zip(per_target) {
+                self.percentiles(&mut screen, &nameserver.to_string(), details);
+            }
+        }
+
+        let mut stderr = io::stderr();
+        let _ = stderr.write_all(screen.as_bytes());
+        let _ = stderr.flush();
+    }
+
+    fn percentiles(&self, screen: &mut String, label: &str, details: &RunDetails) {
+        write!(screen, "{:<24}", label).unwrap();
+        for (_, percentile) in LATENCY_PERCENTILES {
+            write!(
+                screen,
+                "{:>10}",
+                format!(
+                    "{:.2?}",
+                    Duration::from_nanos(details.latencies.percentile(*percentile))
+                )
+            )
+            .unwrap();
+        }
+        writeln!(
+            screen,
+            "{:>10}",
+            format!("{:.2?}", Duration::from_nanos(details.latencies.max()))
+        )
+        .unwrap();
+    }
+}
+
+// the success rate, or 100% before anything has been sent
+fn rate(details: &RunDetails) -> f64 {
+    if details.successes + details.failures == 0 {
+        return 100.0;
+    }
+
+    details.success_rate()
+}
+
+fn causes(details: &RunDetails) -> String {
+    if details.failures == 0 {
+        return String::new();
+    }
+
+    format!(" ({})", details.failure_causes)
+}
+
+// draws `values` as a row of bars, scaled to the largest of them
+fn sparkline(values: impl Iterator<Item = f64> + Clone) -> String {
+    let max = values.clone().fold(0.0, f64::max);
+
+    values
+        .map(|value| {
+            if max <= 0.0 {
+                return SPARKS[0];
+            }
+
+            let level = (value / max * (SPARKS.len() - 1) as f64).round() as usize;
+            SPARKS[level.min(SPARKS.len() - 1)]
+        })
+        .collect()
+}
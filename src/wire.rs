@@ -0,0 +1,477 @@
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use ipnet::IpNet;
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use trust_dns_resolver::{
+    proto::{
+        error::ProtoError,
+        op::{Edns, Message, MessageType, OpCode, Query},
+        rr::{
+            rdata::opt::{EdnsCode, EdnsOption},
+            RecordType,
+        },
+    },
+    Name,
+};
+
+use crate::{
+    mismatch::{self, TimedOut},
+    socks::{self, Proxy},
+    Mismatches, Transport,
+};
+
+// largest datagram we will accept, regardless of the EDNS payload size offered
+pub const MAX_UDP_SIZE: usize = 65535;
+
+#[derive(Debug)]
+pub enum QueryError {
+    Timeout,
+    Io(io::Error),
+    Proto(ProtoError),
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Timeout => write!(f, "timed out"),
+            QueryError::Io(e) => write!(f, "network error: {}", e),
+            QueryError::Proto(e) => write!(f, "protocol error: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for QueryError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => QueryError::Timeout,
+            _ => QueryError::Io(e),
+        }
+    }
+}
+
+impl From<ProtoError> for QueryError {
+    fn from(e: ProtoError) -> Self {
+        QueryError::Proto(e)
+    }
+}
+
+/// The result of a single exchange with the nameserver. `truncated` is set when
+/// the UDP response came back with TC=1, and `tcp_fallback` when the query was
+/// then retried over TCP; `message` is whichever response was kept and `size`
+/// its length on the wire, without the TCP length prefix. `attempts` counts
+/// the UDP transmissions it took to get an answer, and `connect_time` is how
+/// long it spent opening a TCP connection (and any proxy handshake), if it had
+/// to open one.
+#[derive(Debug)]
+pub struct Exchange {
+    pub message: Message,
+    pub size: usize,
+    pub attempts: u32,
+    pub connect_time: Option<Duration>,
+    pub truncated: bool,
+    pub tcp_fallback: bool,
+}
+
+/// Encodes a query for `name`, honoring the EDNS setting in `options`.
+pub fn build_query(
+    id: u16,
+    name: Name,
+    record_type: RecordType,
+    options: &ClientOptions,
+) -> Result<Vec<u8>, ProtoError> {
+    let mut msg = Message::new();
+    msg.set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name, record_type));
+
+    if let Some(payload) = options.edns_payload {
+        let mut edns = Edns::new();
+        edns.set_max_payload(payload)
+            .set_version(0)
+            .set_dnssec_ok(options.dnssec);
+        if let Some(subnet) = &options.ecs {
+            edns.options_mut().insert(client_subnet(subnet));
+        }
+        msg.set_edns(edns);
+    }
+
+    msg.to_vec()
+}
+
+// the EDNS Client Subnet option (RFC 7871) for `subnet`: its address family,
+// source prefix length and a scope prefix length of 0, then only as many
+// bytes of the address as the prefix covers
+fn client_subnet(subnet: &IpNet) -> EdnsOption {
+    let (family, address) = match subnet.trunc().addr() {
+        IpAddr::V4(addr) => (1u16, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (2u16, addr.octets().to_vec()),
+    };
+    let prefix = subnet.prefix_len();
+
+    let mut data = family.to_be_bytes().to_vec();
+    data.extend([prefix, 0]);
+    data.extend(&address[..(prefix as usize).div_ceil(8)]);
+    EdnsOption::Unknown(u16::from(EdnsCode::Subnet), data)
+}
+
+/// Returns a UDP socket connected to `nameserver`, bound according to
+/// `options`.
+pub fn udp_socket(nameserver: SocketAddr, options: &ClientOptions) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(local_addr(nameserver, options))?;
+    if let Some(ttl) = options.ip_ttl {
+        set_ip_ttl(SockRef::from(&socket), nameserver, ttl)?;
+    }
+    if let Some(dscp) = options.dscp {
+        set_dscp(SockRef::from(&socket), nameserver, dscp)?;
+    }
+    socket.connect(nameserver)?;
+    Ok(socket)
+}
+
+/// Returns an unconnected TCP socket for reaching `remote`, which is either
+/// `nameserver` or the proxy in front of it, bound and marked according to
+/// `options`.
+pub fn tcp_socket(
+    remote: SocketAddr,
+    nameserver: SocketAddr,
+    options: &ClientOptions,
+) -> io::Result<Socket> {
+    let socket = Socket::new(
+        Domain::for_address(remote),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if options.bind.is_some() {
+        socket.bind(&local_addr(nameserver, options).into())?;
+    }
+    if let Some(ttl) = options.ip_ttl {
+        set_ip_ttl(SockRef::from(&socket), remote, ttl)?;
+    }
+    if let Some(dscp) = options.dscp {
+        set_dscp(SockRef::from(&socket), remote, dscp)?;
+    }
+    Ok(socket)
+}
+
+// sets the TTL, or hop limit over IPv6, of the packets `socket` sends to
+// `remote`
+fn set_ip_ttl(socket: SockRef<'_>, remote: SocketAddr, ttl: u32) -> io::Result<()> {
+    if remote.is_ipv4() {
+        socket.set_ttl(ttl)
+    } else {
+        socket.set_unicast_hops_v6(ttl)
+    }
+}
+
+// marks the packets `socket` sends to `remote` with the DiffServ codepoint
+// `dscp`, the upper six bits of the IPv4 TOS or IPv6 traffic class
+fn set_dscp(socket: SockRef<'_>, remote: SocketAddr, dscp: u8) -> io::Result<()> {
+    let tos = u32::from(dscp) << 2;
+    if remote.is_ipv4() {
+        socket.set_tos(tos)
+    } else {
+        set_tclass_v6(socket, tos)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_tclass_v6(socket: SockRef<'_>, tclass: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let tclass = tclass as libc::c_int;
+    // SAFETY: IPV6_TCLASS takes an int, and is given one along with its size.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &tclass as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_tclass_v6(_socket: SockRef<'_>, _tclass: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "marking IPv6 packets with a DSCP is only supported on Linux",
+    ))
+}
+
+fn local_addr(nameserver: SocketAddr, options: &ClientOptions) -> SocketAddr {
+    match options.bind {
+        Some(ip) => SocketAddr::new(ip, 0),
+        None if nameserver.is_ipv4() => "0.0.0.0:0".parse().unwrap(),
+        None => "[::]:0".parse().unwrap(),
+    }
+}
+
+/// ClientOptions controls how a Client talks to its nameserver.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientOptions {
+    pub transport: Transport,
+    pub timeout: Duration,
+    /// How many times a UDP query that times out is sent again before giving
+    /// up, waiting the full timeout each time.
+    pub retries: u32,
+    pub tcp_fallback: bool,
+    pub bind: Option<IpAddr>,
+    /// When set, the TTL (hop limit over IPv6) of every packet sent, rather
+    /// than the system's default.
+    pub ip_ttl: Option<u32>,
+    /// When set, the DiffServ codepoint every packet sent is marked with.
+    pub dscp: Option<u8>,
+    pub proxy: Option<Proxy>,
+    /// When set, queries carry an EDNS OPT record advertising this UDP
+    /// payload size.
+    pub edns_payload: Option<u16>,
+    /// Whether to set the DO bit in the EDNS OPT record, asking for DNSSEC
+    /// records and validation; it has no effect without `edns_payload`.
+    pub dnssec: bool,
+    /// When set, queries carry an EDNS Client Subnet option (RFC 7871) for
+    /// this subnet, as a resolver forwarding for a client in it would; it
+    /// has no effect without `edns_payload`.
+    pub ecs: Option<IpNet>,
+}
+
+/// Client is a minimal synchronous DNS client. Unlike the trust-dns Resolver it
+/// does no caching, retrying or response massaging, so what we measure is the
+/// nameserver and nothing else.
+pub struct Client {
+    nameserver: SocketAddr,
+    options: ClientOptions,
+    next_id: u16,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+    // how long the current query spent connecting
+    connect_time: Option<Duration>,
+    // bytes written and read since the last take_traffic, including TCP
+    // length prefixes, retransmissions and responses that were thrown away
+    bytes_sent: u64,
+    bytes_received: u64,
+    // UDP packets thrown away since the last take_mismatches
+    mismatches: Mismatches,
+    timed_out: TimedOut,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    pub fn new(nameserver: SocketAddr, options: ClientOptions) -> Self {
+        Self {
+            nameserver,
+            options,
+            next_id: 0,
+            udp: None,
+            tcp: None,
+            connect_time: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            mismatches: Mismatches::default(),
+            timed_out: TimedOut::new(),
+            buf: vec![0u8; MAX_UDP_SIZE],
+        }
+    }
+
+    pub fn query(&mut self, name: Name, record_type: RecordType) -> Result<Exchange, QueryError> {
+        self.next_id = self.next_id.wrapping_add(1);
+        let packet = build_query(self.next_id, name.clone(), record_type, &self.options)?;
+        self.connect_time = None;
+
+        match self.options.transport {
+            Transport::Tcp => {
+                let (message, size) = self.exchange_tcp(&packet)?;
+                Ok(Exchange {
+                    message,
+                    size,
+                    attempts: 1,
+                    connect_time: self.connect_time,
+                    truncated: false,
+                    tcp_fallback: false,
+                })
+            }
+            Transport::Udp => {
+                let mut attempts = 0;
+                let (message, size) = loop {
+                    attempts += 1;
+                    match self.exchange_udp(&packet, &name, record_type) {
+                        Err(QueryError::Timeout) if attempts <= self.options.retries => continue,
+                        Err(QueryError::Timeout) => {
+                            self.timed_out.insert(self.next_id);
+                            return Err(QueryError::Timeout);
+                        }
+                        res => break res?,
+                    }
+                };
+
+                if !message.truncated() {
+                    return Ok(Exchange {
+                        message,
+                        size,
+                        attempts,
+                        connect_time: None,
+                        truncated: false,
+                        tcp_fallback: false,
+                    });
+                }
+
+                if !self.options.tcp_fallback {
+                    return Ok(Exchange {
+                        message,
+                        size,
+                        attempts,
+                        connect_time: None,
+                        truncated: true,
+                        tcp_fallback: false,
+                    });
+                }
+
+                let (message, size) = self.exchange_tcp(&packet)?;
+                Ok(Exchange {
+                    message,
+                    size,
+                    attempts,
+                    connect_time: self.connect_time,
+                    truncated: true,
+                    tcp_fallback: true,
+                })
+            }
+        }
+    }
+
+    /// Returns the bytes sent and received since the last call.
+    pub fn take_traffic(&mut self) -> (u64, u64) {
+        let traffic = (self.bytes_sent, self.bytes_received);
+        self.bytes_sent = 0;
+        self.bytes_received = 0;
+        traffic
+    }
+
+    /// Returns the UDP packets thrown away since the last call.
+    pub fn take_mismatches(&mut self) -> Mismatches {
+        std::mem::take(&mut self.mismatches)
+    }
+
+    fn exchange_udp(
+        &mut self,
+        packet: &[u8],
+        name: &Name,
+        record_type: RecordType,
+    ) -> Result<(Message, usize), QueryError> {
+        if self.udp.is_none() {
+            self.udp = Some(udp_socket(self.nameserver, &self.options)?);
+        }
+
+        let socket = self.udp.as_ref().unwrap();
+        socket.send(packet)?;
+        self.bytes_sent += packet.len() as u64;
+
+        let deadline = Instant::now() + self.options.timeout;
+
+        // responses to earlier, timed out queries may still be in flight; skip
+        // anything that doesn't carry the id and question we just sent.
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(QueryError::Timeout);
+            }
+
+            socket.set_read_timeout(Some(remaining))?;
+            let len = socket.recv(&mut self.buf)?;
+            self.bytes_received += len as u64;
+
+            let message = match Message::from_vec(&self.buf[..len]) {
+                Ok(message) if message.message_type() == MessageType::Response => message,
+                _ => {
+                    self.mismatches.malformed += 1;
+                    continue;
+                }
+            };
+
+            if message.id() != self.next_id {
+                if self.timed_out.take(message.id()) {
+                    self.mismatches.late += 1;
+                } else {
+                    self.mismatches.unsolicited += 1;
+                }
+            } else if !mismatch::answers(&message, name, record_type) {
+                self.mismatches.wrong_question += 1;
+            } else {
+                return Ok((message, len));
+            }
+        }
+    }
+
+    fn exchange_tcp(&mut self, packet: &[u8]) -> Result<(Message, usize), QueryError> {
+        let res = self.try_exchange_tcp(packet);
+        if res.is_err() {
+            // the stream may be left mid-message; start over on the next query
+            self.tcp = None;
+        }
+
+        res
+    }
+
+    fn connect_tcp(&self) -> Result<TcpStream, QueryError> {
+        // with a proxy configured, the TCP connection is made to the proxy and
+        // the nameserver is reached through it.
+        let remote = match self.options.proxy {
+            Some(Proxy(addr)) => addr,
+            None => self.nameserver,
+        };
+
+        let socket = tcp_socket(remote, self.nameserver, &self.options)?;
+        socket.connect_timeout(&remote.into(), self.options.timeout)?;
+        socket.set_nodelay(true)?;
+
+        let mut stream: TcpStream = socket.into();
+        stream.set_read_timeout(Some(self.options.timeout))?;
+        stream.set_write_timeout(Some(self.options.timeout))?;
+
+        if self.options.proxy.is_some() {
+            socks::handshake(&mut stream, self.nameserver)?;
+        }
+
+        Ok(stream)
+    }
+
+    fn try_exchange_tcp(&mut self, packet: &[u8]) -> Result<(Message, usize), QueryError> {
+        if self.tcp.is_none() {
+            let start = Instant::now();
+            self.tcp = Some(self.connect_tcp()?);
+            self.connect_time = Some(start.elapsed());
+        }
+
+        let stream = self.tcp.as_mut().unwrap();
+
+        let mut framed = Vec::with_capacity(packet.len() + 2);
+        framed.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+        framed.extend_from_slice(packet);
+        stream.write_all(&framed)?;
+        self.bytes_sent += framed.len() as u64;
+
+        loop {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len)?;
+            let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut buf)?;
+            self.bytes_received += 2 + buf.len() as u64;
+
+            let message = Message::from_vec(&buf)?;
+            if message.id() == self.next_id {
+                return Ok((message, buf.len()));
+            }
+        }
+    }
+}
@@ -0,0 +1,895 @@
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use ipnet::IpNet;
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use trust_dns_resolver::{proto::rr::RecordType, Name};
+
+use crate::source::{self, WorkloadSource};
+
+pub(crate) const RANDOM_LABEL_LEN: usize = 12;
+
+// how many previously queried names a generator remembers for repeats
+const HISTORY_SIZE: usize = 10_000;
+
+/// Names is the set of names queried during a run: either a single host given
+/// on the command line or a list loaded from a file, which workers cycle
+/// through. List entries may carry their own record type, as in a dnsperf
+/// datafile; entries without one use the run's qtype mix. RandomPrefix puts a
+/// fresh random label in front of the name for every query, so no two queries
+/// can be answered from cache. PtrRange walks the reverse (in-addr.arpa or
+/// ip6.arpa) names of every address in a network. Template renders a
+/// NameTemplate for every query. Custom builds each worker a WorkloadSource
+/// of its own making.
+#[derive(Debug, Clone)]
+pub enum Names {
+    Single(Name),
+    List(Arc<Vec<(Name, Option<RecordType>)>>),
+    RandomPrefix(Name),
+    PtrRange(IpNet),
+    Template(Arc<NameTemplate>),
+    Custom(source::Factory),
+}
+
+impl Names {
+    /// Loads one name per line from `path`, skipping blank lines and `#`
+    /// comments. With `shuffle`, the order is randomized once for the run.
+    pub fn from_file(path: &Path, shuffle: bool) -> Result<Self, String> {
+        Self::load(path, shuffle, |line| Ok((Name::from_str(line)?, None)))
+    }
+
+    /// Loads a dnsperf-style datafile from `path`: one `name type` pair per
+    /// line, with blank lines and lines starting with `#` or `;` ignored. The
+    /// type may be left off, in which case the qtype mix is used.
+    pub fn from_datafile(path: &Path, shuffle: bool) -> Result<Self, String> {
+        Self::load(path, shuffle, |line| {
+            let mut fields = line.split_whitespace();
+            let name = Name::from_str(fields.next().unwrap())?;
+            let record_type = fields.next().map(crate::parse_record_type).transpose()?;
+
+            if let Some(extra) = fields.next() {
+                return Err(format!("unexpected field '{}'", extra).into());
+            }
+
+            Ok((name, record_type))
+        })
+    }
+
+    /// Queries every (owner, type) pair found in a zone file.
+    pub fn from_zone_file(path: &Path, shuffle: bool) -> Result<Self, String> {
+        let mut names = crate::zone::parse(path)?
+            .into_iter()
+            .map(|(name, record_type)| (name, Some(record_type)))
+            .collect::<Vec<_>>();
+
+        if names.is_empty() {
+            return Err(format!("{} contains no records", path.display()));
+        }
+
+        if shuffle {
+            names.shuffle(&mut rand::thread_rng());
+        }
+
+        Ok(Names::List(Arc::new(names)))
+    }
+
+    fn load<F>(path: &Path, shuffle: bool, parse_line: F) -> Result<Self, String>
+    where
+        F: Fn(&str) -> Result<(Name, Option<RecordType>), Box<dyn std::error::Error>>,
+    {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        let mut names = Vec::new();
+        for (lineno, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            names.push(parse_line(line).map_err(|e| {
+                format!(
+                    "{}:{}: invalid entry '{}': {}",
+                    path.display(),
+                    lineno + 1,
+                    line,
+                    e
+                )
+            })?);
+        }
+
+        if names.is_empty() {
+            return Err(format!("{} contains no names", path.display()));
+        }
+
+        if shuffle {
+            names.shuffle(&mut rand::thread_rng());
+        }
+
+        Ok(Names::List(Arc::new(names)))
+    }
+
+    /// Queries `<random label>.<suffix>`, a new label every time.
+    pub fn random_prefix(suffix: Name) -> Result<Self, String> {
+        random_label(&mut rand::thread_rng(), RANDOM_LABEL_LEN)
+            .append_domain(&suffix)
+            .map_err(|e| format!("cannot prefix {}: {}", suffix, e))?;
+
+        Ok(Names::RandomPrefix(suffix))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Names::Single(_) | Names::RandomPrefix(_) | Names::Template(_) | Names::Custom(_) => 1,
+            Names::List(names) => names.len(),
+            Names::PtrRange(net) => usize::try_from(net_size(net)).unwrap_or(usize::MAX),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a generator for worker number `worker`. Round-robin generators
+    /// start `offset` entries into the list.
+    ///
+    /// With a `stride` above one, a round-robin generator only visits every
+    /// `stride`th entry, so that a group of workers can split a list between
+    /// them instead of each walking all of it.
+    pub fn generator(
+        &self,
+        worker: usize,
+        offset: usize,
+        stride: usize,
+        sampler: Sampler,
+    ) -> NameGenerator {
+        let source: Box<dyn WorkloadSource> = match self {
+            Names::Single(name) => Box::new(source::Single(name.clone())),
+            Names::List(names) => {
+                Box::new(source::List::new(names.clone(), sampler, offset, stride))
+            }
+            Names::RandomPrefix(suffix) => Box::new(source::RandomPrefix(suffix.clone())),
+            Names::PtrRange(net) => Box::new(source::PtrRange::new(*net, offset, stride)),
+            Names::Template(template) => Box::new(source::Template::new(
+                template.clone(),
+                worker,
+                offset,
+                stride,
+            )),
+            Names::Custom(factory) => factory.build(worker),
+        };
+
+        NameGenerator {
+            source,
+            seq: offset,
+            stride,
+            repeat_ratio: 0.0,
+            history: Vec::new(),
+        }
+    }
+
+    /// Queries the DNS queries found in a packet capture. The capture time of
+    /// each query, relative to the first, is returned alongside for pacing.
+    pub fn from_pcap(path: &Path) -> Result<(Self, Vec<Duration>), String> {
+        let captured = crate::pcap::read_queries(path)?;
+        if captured.is_empty() {
+            return Err(format!("{} contains no DNS queries", path.display()));
+        }
+
+        let offsets = captured.iter().map(|query| query.offset).collect();
+        let names = captured
+            .into_iter()
+            .map(|query| (query.name, Some(query.record_type)))
+            .collect();
+
+        Ok((Names::List(Arc::new(names)), offsets))
+    }
+}
+
+/// Pacing replays a list of queries on the schedule they were captured on,
+/// sped up or slowed down by `speed`. When the end of the list is reached it
+/// starts over, shifted by the length of the capture.
+#[derive(Debug, Clone)]
+pub struct Pacing {
+    offsets: Arc<Vec<Duration>>,
+    span: Duration,
+    speed: f64,
+}
+
+impl Pacing {
+    pub fn new(offsets: Vec<Duration>, speed: f64) -> Self {
+        // leave the average gap between the last query and the first one of
+        // the next pass, so they don't go out together.
+        let last = *offsets.last().unwrap();
+        let span = last + last / offsets.len() as u32;
+
+        Self {
+            offsets: Arc::new(offsets),
+            span,
+            speed,
+        }
+    }
+
+    /// Returns when the `seq`th query is due, relative to the start of the run.
+    pub fn due(&self, seq: usize) -> Duration {
+        let passes = (seq / self.offsets.len()) as u32;
+        (self.span * passes + self.offsets[seq % self.offsets.len()]).div_f64(self.speed)
+    }
+}
+
+/// NameDistribution is how entries are picked from a name list: in order,
+/// uniformly at random, or following a Zipf popularity curve with exponent
+/// `s`, where the first entry in the list is the most popular.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameDistribution {
+    RoundRobin,
+    Uniform,
+    Zipf(f64),
+}
+
+impl NameDistribution {
+    /// Prepares a sampler over a list of `len` names.
+    pub fn sampler(&self, len: usize) -> Sampler {
+        match self {
+            NameDistribution::RoundRobin => Sampler::RoundRobin,
+            NameDistribution::Uniform => Sampler::Uniform,
+            NameDistribution::Zipf(s) => {
+                let mut total = 0.0;
+                let cdf = (1..=len)
+                    .map(|rank| {
+                        total += 1.0 / (rank as f64).powf(*s);
+                        total
+                    })
+                    .collect::<Vec<f64>>();
+
+                Sampler::Zipf(Arc::new(cdf.iter().map(|c| c / total).collect()))
+            }
+        }
+    }
+}
+
+impl FromStr for NameDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "round-robin" => Ok(NameDistribution::RoundRobin),
+            None if s == "uniform" => Ok(NameDistribution::Uniform),
+            Some(("zipf", exponent)) => match exponent.parse::<f64>() {
+                Ok(exponent) if exponent > 0.0 => Ok(NameDistribution::Zipf(exponent)),
+                _ => Err(format!("invalid zipf exponent '{}'", exponent)),
+            },
+            _ => Err(format!(
+                "invalid distribution '{}': expected round-robin, uniform or zipf:<s>",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for NameDistribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameDistribution::RoundRobin => write!(f, "round-robin"),
+            NameDistribution::Uniform => write!(f, "uniform"),
+            NameDistribution::Zipf(s) => write!(f, "zipf:{}", s),
+        }
+    }
+}
+
+/// Sampler is a NameDistribution prepared for a specific list; the Zipf CDF is
+/// computed once and shared between workers.
+#[derive(Debug, Clone)]
+pub enum Sampler {
+    RoundRobin,
+    Uniform,
+    Zipf(Arc<Vec<f64>>),
+}
+
+impl Sampler {
+    pub(crate) fn index<R: Rng + ?Sized>(&self, rng: &mut R, seq: usize, len: usize) -> usize {
+        match self {
+            Sampler::RoundRobin => seq % len,
+            Sampler::Uniform => rng.gen_range(0..len),
+            Sampler::Zipf(cdf) => {
+                let p = rng.gen::<f64>();
+                cdf.partition_point(|c| *c < p).min(len - 1)
+            }
+        }
+    }
+}
+
+/// NameGenerator produces the names a single worker queries, from its
+/// WorkloadSource, repeating some it has already produced if asked to.
+#[derive(Debug)]
+pub struct NameGenerator {
+    source: Box<dyn WorkloadSource>,
+    // how many entries the source has produced, counted the way its position
+    // in a list is
+    seq: usize,
+    stride: usize,
+    repeat_ratio: f64,
+    history: Vec<(Name, Option<RecordType>)>,
+}
+
+impl NameGenerator {
+    /// Makes `ratio` of the generated queries (0.0 to 1.0) repeat a name this
+    /// generator has already produced, picked at random from the last
+    /// HISTORY_SIZE fresh ones, to dial in a cache hit rate.
+    pub fn with_repeat_ratio(mut self, ratio: f64) -> Self {
+        self.repeat_ratio = ratio;
+        self
+    }
+
+    /// Returns the sequence number of the entry the next call to `next` will
+    /// produce.
+    pub fn position(&self) -> usize {
+        self.seq
+    }
+
+    /// Returns the next name to query, along with its record type if the entry
+    /// specified one.
+    pub fn next<R: Rng>(&mut self, rng: &mut R) -> (Name, Option<RecordType>) {
+        if self.repeat_ratio > 0.0 && !self.history.is_empty() && rng.gen_bool(self.repeat_ratio) {
+            return self.history[rng.gen_range(0..self.history.len())].clone();
+        }
+
+        let fresh = self.fresh(rng);
+
+        if self.repeat_ratio > 0.0 {
+            if self.history.len() < HISTORY_SIZE {
+                self.history.push(fresh.clone());
+            } else {
+                let slot = self.seq / self.stride % HISTORY_SIZE;
+                self.history[slot] = fresh.clone();
+            }
+        }
+
+        fresh
+    }
+
+    fn fresh<R: Rng>(&mut self, rng: &mut R) -> (Name, Option<RecordType>) {
+        self.seq = self.seq.wrapping_add(self.stride);
+        self.source.next_query(rng)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Seq,
+    Thread,
+    Rand(usize),
+}
+
+/// NameTemplate is a name with placeholders, like
+/// `{seq}-{thread}.{rand:8}.bench.example.com`. `{seq}` is replaced by a
+/// per-worker query counter, `{thread}` by the worker number and `{rand:N}` by
+/// N random letters and digits.
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl NameTemplate {
+    /// Renders the template. Placeholder values are always valid label
+    /// characters and the template is checked for length when parsed, so
+    /// rendering cannot fail unless the counters grow past what was checked;
+    /// in that case the name is cut back to the root.
+    pub fn render<R: Rng + ?Sized>(&self, rng: &mut R, seq: usize, worker: usize) -> Name {
+        let mut name = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => name.push_str(literal),
+                Segment::Seq => name.push_str(&seq.to_string()),
+                Segment::Thread => name.push_str(&worker.to_string()),
+                Segment::Rand(len) => name.push_str(&random_string(rng, *len)),
+            }
+        }
+
+        Name::from_str(&name).unwrap_or_else(|_| Name::root())
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut rest = s;
+
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                segments.push(Segment::Literal(rest[..open].to_string()));
+            }
+
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in '{}'", s))?
+                + open;
+
+            segments.push(match &rest[open + 1..close] {
+                "seq" => Segment::Seq,
+                "thread" => Segment::Thread,
+                placeholder => match placeholder.strip_prefix("rand:").map(str::parse::<usize>) {
+                    Some(Ok(len)) if (1..=63).contains(&len) => Segment::Rand(len),
+                    _ => return Err(format!("unknown placeholder {{{}}}", placeholder)),
+                },
+            });
+
+            rest = &rest[close + 1..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        let template = Self {
+            template: s.to_string(),
+            segments,
+        };
+
+        // render with the widest counters we could see to make sure the
+        // result is a valid name.
+        let widest = template
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Seq | Segment::Thread => usize::MAX.to_string(),
+                Segment::Rand(len) => "a".repeat(*len),
+            })
+            .collect::<String>();
+        Name::from_str(&widest).map_err(|e| format!("invalid template '{}': {}", s, e))?;
+
+        Ok(template)
+    }
+}
+
+impl std::fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+pub(crate) fn net_size(net: &IpNet) -> u128 {
+    let host_bits = (net.max_prefix_len() - net.prefix_len()) as u32;
+    // a /0 v6 network has 2^128 addresses; close enough.
+    1u128.checked_shl(host_bits).unwrap_or(u128::MAX)
+}
+
+pub(crate) fn nth_address(net: &IpNet, n: u128) -> IpAddr {
+    match net {
+        IpNet::V4(net) => IpAddr::V4(Ipv4Addr::from(u32::from(net.network()) + n as u32)),
+        IpNet::V6(net) => IpAddr::V6(Ipv6Addr::from(u128::from(net.network()) + n)),
+    }
+}
+
+fn random_string<R: Rng + ?Sized>(rng: &mut R, len: usize) -> String {
+    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+    (0..len)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+pub(crate) fn random_label<R: Rng + ?Sized>(rng: &mut R, len: usize) -> Name {
+    Name::from_labels(vec![random_string(rng, len)]).unwrap()
+}
+
+/// QtypeMix is a weighted set of record types, given on the command line as
+/// `A:60,AAAA:30,HTTPS:10`. Each query samples its type from the mix.
+#[derive(Debug, Clone)]
+pub struct QtypeMix {
+    types: Vec<(RecordType, u32)>,
+    index: WeightedIndex<u32>,
+}
+
+impl QtypeMix {
+    pub fn single(record_type: RecordType) -> Self {
+        Self {
+            types: vec![(record_type, 1)],
+            index: WeightedIndex::new([1]).unwrap(),
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> RecordType {
+        if self.types.len() == 1 {
+            return self.types[0].0;
+        }
+
+        self.types[self.index.sample(rng)].0
+    }
+
+    pub fn contains(&self, record_type: RecordType) -> bool {
+        self.types.iter().any(|(t, _)| *t == record_type)
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.types.len() == 1
+    }
+}
+
+impl FromStr for QtypeMix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut types = Vec::new();
+
+        for entry in s.split(',') {
+            let (record_type, weight) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("invalid mix entry '{}': expected TYPE:WEIGHT", entry))?;
+
+            let record_type = crate::parse_record_type(record_type.trim())?;
+            let weight = weight
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| format!("invalid weight in '{}': {}", entry, e))?;
+
+            types.push((record_type, weight));
+        }
+
+        let index = WeightedIndex::new(types.iter().map(|(_, weight)| *weight))
+            .map_err(|e| format!("invalid mix '{}': {}", s, e))?;
+
+        Ok(Self { types, index })
+    }
+}
+
+impl std::fmt::Display for QtypeMix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_single() {
+            return write!(f, "{}", self.types[0].0);
+        }
+
+        let entries = self
+            .types
+            .iter()
+            .map(|(record_type, weight)| format!("{}:{}", record_type, weight))
+            .collect::<Vec<String>>();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn parses_qtype_mixes() {
+        let cases = [
+            ("A:60,AAAA:30,HTTPS:10", "A:60,AAAA:30,HTTPS:10"),
+            ("a:1, mx : 3", "A:1,MX:3"),
+            ("TXT:5", "TXT"),
+        ];
+
+        for (s, shown) in cases {
+            assert_eq!(s.parse::<QtypeMix>().unwrap().to_string(), shown, "{}", s);
+        }
+
+        let mix: QtypeMix = "A:60,AAAA:30".parse().unwrap();
+        assert!(!mix.is_single());
+        assert!(mix.contains(RecordType::AAAA));
+        assert!(!mix.contains(RecordType::MX));
+    }
+
+    #[test]
+    fn rejects_bad_qtype_mixes() {
+        let cases = [
+            ("A", "invalid mix entry 'A': expected TYPE:WEIGHT"),
+            ("A:60,", "invalid mix entry '': expected TYPE:WEIGHT"),
+            (
+                "A:x",
+                "invalid weight in 'A:x': invalid digit found in string",
+            ),
+            (
+                "A:-1",
+                "invalid weight in 'A:-1': invalid digit found in string",
+            ),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(s.parse::<QtypeMix>().unwrap_err(), err, "{}", s);
+        }
+
+        assert!("NOTATYPE:1".parse::<QtypeMix>().is_err());
+        assert!("A:0,AAAA:0"
+            .parse::<QtypeMix>()
+            .unwrap_err()
+            .starts_with("invalid mix 'A:0,AAAA:0': "));
+    }
+
+    #[test]
+    fn samples_qtypes_by_weight() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mix: QtypeMix = "A:60,AAAA:30,HTTPS:10,MX:0".parse().unwrap();
+
+        let mut counts = std::collections::HashMap::new();
+        for _ in 0..100_000 {
+            *counts.entry(mix.sample(&mut rng)).or_insert(0u32) += 1;
+        }
+
+        for (record_type, share) in [
+            (RecordType::A, 0.6),
+            (RecordType::AAAA, 0.3),
+            (RecordType::HTTPS, 0.1),
+        ] {
+            let seen = counts[&record_type] as f64 / 100_000.0;
+            assert!((seen - share).abs() < 0.01, "{} {}", record_type, seen);
+        }
+        assert!(!counts.contains_key(&RecordType::MX));
+
+        let single = QtypeMix::single(RecordType::NS);
+        assert!((0..100).all(|_| single.sample(&mut rng) == RecordType::NS));
+    }
+
+    #[test]
+    fn parses_distributions() {
+        let cases = [
+            ("round-robin", NameDistribution::RoundRobin),
+            ("uniform", NameDistribution::Uniform),
+            ("zipf:1.2", NameDistribution::Zipf(1.2)),
+            ("zipf:0.5", NameDistribution::Zipf(0.5)),
+        ];
+
+        for (s, distribution) in cases {
+            assert_eq!(s.parse(), Ok(distribution), "{}", s);
+            assert_eq!(distribution.to_string(), s);
+        }
+
+        let cases = [
+            ("zipf:0", "invalid zipf exponent '0'"),
+            ("zipf:-1", "invalid zipf exponent '-1'"),
+            ("zipf:x", "invalid zipf exponent 'x'"),
+            (
+                "zipf",
+                "invalid distribution 'zipf': expected round-robin, uniform or zipf:<s>",
+            ),
+            (
+                "uniform:1",
+                "invalid distribution 'uniform:1': expected round-robin, uniform or zipf:<s>",
+            ),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(s.parse::<NameDistribution>(), Err(err.to_string()), "{}", s);
+        }
+    }
+
+    #[test]
+    fn zipf_cdf_is_normalized() {
+        let cdf = match NameDistribution::Zipf(1.0).sampler(4) {
+            Sampler::Zipf(cdf) => cdf,
+            sampler => panic!("{:?}", sampler),
+        };
+
+        // 1, 1/2, 1/3 and 1/4, over their sum of 25/12
+        let expected = [12.0 / 25.0, 18.0 / 25.0, 22.0 / 25.0, 1.0];
+        for (c, expected) in cdf.iter().zip(expected) {
+            assert!((c - expected).abs() < 1e-12, "{:?}", cdf);
+        }
+    }
+
+    #[test]
+    fn samples_names_by_distribution() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let sampler = NameDistribution::RoundRobin.sampler(3);
+        let picked: Vec<usize> = (0..6).map(|seq| sampler.index(&mut rng, seq, 3)).collect();
+        assert_eq!(picked, [0, 1, 2, 0, 1, 2]);
+
+        let sampler = NameDistribution::Uniform.sampler(10);
+        let mut counts = [0u32; 10];
+        for seq in 0..100_000 {
+            counts[sampler.index(&mut rng, seq, 10)] += 1;
+        }
+        assert!(
+            counts.iter().all(|count| (9_000..11_000).contains(count)),
+            "{:?}",
+            counts
+        );
+
+        // with s = 1 over 100 names, the first is drawn 1 / H(100) of the
+        // time, about 19.3%, the second half as often and so on
+        let sampler = NameDistribution::Zipf(1.0).sampler(100);
+        let mut counts = [0u32; 100];
+        for seq in 0..100_000 {
+            counts[sampler.index(&mut rng, seq, 100)] += 1;
+        }
+        let harmonic: f64 = (1..=100).map(|rank| 1.0 / rank as f64).sum();
+        for rank in [1, 2, 10, 100] {
+            let seen = counts[rank - 1] as f64 / 100_000.0;
+            let expected = 1.0 / rank as f64 / harmonic;
+            assert!(
+                (seen - expected).abs() < expected * 0.1 + 0.001,
+                "rank {}: {} vs {}",
+                rank,
+                seen,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn renders_name_templates() {
+        let cases = [
+            ("www.example.com", "www.example.com"),
+            ("{seq}.example.com", "42.example.com"),
+            ("w{thread}-{seq}.example.com", "w3-42.example.com"),
+            ("{thread}{seq}", "342"),
+        ];
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for (s, expected) in cases {
+            let template: NameTemplate = s.parse().unwrap();
+            assert_eq!(template.to_string(), s);
+            assert_eq!(
+                template.render(&mut rng, 42, 3),
+                Name::from_str(expected).unwrap(),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn renders_random_labels() {
+        let template: NameTemplate = "{rand:8}.{seq}.example.com".parse().unwrap();
+
+        let render = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            template.render(&mut rng, 7, 0).to_string()
+        };
+        assert_eq!(render(1), render(1));
+        assert_ne!(render(1), render(2));
+
+        let name = render(1);
+        let (label, rest) = name.split_once('.').unwrap();
+        assert_eq!(rest, "7.example.com");
+        assert_eq!(label.len(), 8);
+        assert!(label
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()));
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        let cases = [
+            (
+                "{seq.example.com",
+                "unclosed placeholder in '{seq.example.com'",
+            ),
+            ("{}.example.com", "unknown placeholder {}"),
+            ("{rand}.example.com", "unknown placeholder {rand}"),
+            ("{rand:0}.example.com", "unknown placeholder {rand:0}"),
+            ("{rand:64}.example.com", "unknown placeholder {rand:64}"),
+            ("{Seq}.example.com", "unknown placeholder {Seq}"),
+        ];
+
+        for (s, err) in cases {
+            assert_eq!(s.parse::<NameTemplate>().unwrap_err(), err, "{}", s);
+        }
+
+        // fits with a short counter, but not with the widest one
+        let s = format!("{}{{seq}}.example.com", "a".repeat(50));
+        let err = s.parse::<NameTemplate>().unwrap_err();
+        assert!(
+            err.starts_with(&format!("invalid template '{}': ", s)),
+            "{}",
+            err
+        );
+    }
+
+    fn numbered(len: usize) -> Names {
+        Names::List(Arc::new(
+            (0..len)
+                .map(|i| {
+                    (
+                        Name::from_str(&format!("n{}.example.com", i)).unwrap(),
+                        None,
+                    )
+                })
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn repeats_names_by_ratio() {
+        let names = numbered(1000);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        // without repeats, a round-robin generator walks the list in order
+        let mut generator = names.generator(0, 0, 1, Sampler::RoundRobin);
+        for i in 0..1000 {
+            let (name, _) = generator.next(&mut rng);
+            assert_eq!(name.to_string(), format!("n{}.example.com", i));
+        }
+
+        // with every query a repeat, only the first name is ever fresh
+        let mut generator = names
+            .generator(0, 0, 1, Sampler::RoundRobin)
+            .with_repeat_ratio(1.0);
+        for _ in 0..100 {
+            assert_eq!(generator.next(&mut rng).0.to_string(), "n0.example.com");
+        }
+        assert_eq!(generator.position(), 1);
+
+        // repeats come from names already produced, and only fresh names
+        // move the generator along the list
+        let mut generator = names
+            .generator(0, 0, 1, Sampler::RoundRobin)
+            .with_repeat_ratio(0.25);
+        let mut seen = HashSet::new();
+        let mut repeats = 0;
+        for _ in 0..10_000 {
+            let position = generator.position();
+            let (name, _) = generator.next(&mut rng);
+            if generator.position() == position {
+                assert!(seen.contains(&name), "{}", name);
+                repeats += 1;
+            } else {
+                assert_eq!(name, names_at(&names, position % 1000));
+                seen.insert(name);
+            }
+        }
+        assert!((2_300..2_700).contains(&repeats), "{}", repeats);
+    }
+
+    #[test]
+    fn repeats_only_recent_names() {
+        let names = numbered(HISTORY_SIZE * 3);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut generator = names
+            .generator(0, 0, 1, Sampler::RoundRobin)
+            .with_repeat_ratio(0.5);
+
+        let mut fresh = 0;
+        while fresh < HISTORY_SIZE * 2 {
+            let position = generator.position();
+            let _ = generator.next(&mut rng);
+            if generator.position() != position {
+                fresh += 1;
+            }
+        }
+
+        // once the history is full, each fresh name replaces the oldest, so
+        // only the last HISTORY_SIZE are ever repeated
+        for _ in 0..10_000 {
+            let position = generator.position();
+            let (name, _) = generator.next(&mut rng);
+            if generator.position() == position {
+                // the list is walked in order, so a name's position in it is
+                // when it was produced
+                assert!(names_index(&name) >= fresh - HISTORY_SIZE, "{}", name);
+            } else {
+                fresh += 1;
+            }
+        }
+    }
+
+    fn names_index(name: &Name) -> usize {
+        name.to_string()
+            .strip_prefix('n')
+            .and_then(|name| name.split('.').next())
+            .and_then(|i| i.parse().ok())
+            .unwrap()
+    }
+
+    fn names_at(names: &Names, i: usize) -> Name {
+        match names {
+            Names::List(names) => names[i].0.clone(),
+            _ => unreachable!(),
+        }
+    }
+}
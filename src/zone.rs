@@ -0,0 +1,253 @@
+use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+use trust_dns_resolver::{proto::rr::RecordType, Name};
+
+const CLASSES: &[&str] = &["IN", "CH", "HS", "CS"];
+
+/// Reads a BIND-style zone file and returns every distinct (owner, type) pair
+/// in it, in file order. Only as much of the format is understood as is needed
+/// to find owners and types: `$ORIGIN`, `$TTL`, `@`, relative names, inherited
+/// owners, comments and parenthesized multi-line records. `$INCLUDE` and
+/// `$GENERATE` are rejected.
+pub fn parse(path: &Path) -> Result<Vec<(Name, RecordType)>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+    parse_records(&contents)
+        .map_err(|(lineno, msg)| format!("{}:{}: {}", path.display(), lineno, msg))
+}
+
+// finds the records in the zone in `contents`, failing with the line and what
+// is wrong with it
+fn parse_records(contents: &str) -> Result<Vec<(Name, RecordType)>, (usize, String)> {
+    let mut origin = Name::root();
+    let mut owner: Option<Name> = None;
+    let mut seen = BTreeSet::new();
+    let mut records = Vec::new();
+
+    for (lineno, line) in logical_lines(contents) {
+        let err = |msg: String| (lineno, msg);
+
+        let inherits_owner = line.starts_with(|c: char| c.is_whitespace());
+        let mut tokens = line.split_whitespace().peekable();
+
+        match tokens.peek() {
+            None => continue,
+            Some(&"$ORIGIN") => {
+                tokens.next();
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| err("$ORIGIN requires a name".into()))?;
+                origin = absolute(name, &origin).map_err(err)?;
+                continue;
+            }
+            Some(&"$TTL") => continue,
+            Some(directive) if directive.starts_with('$') => {
+                return Err(err(format!("unsupported directive {}", directive)))
+            }
+            _ => {}
+        }
+
+        if !inherits_owner {
+            owner = Some(absolute(tokens.next().unwrap(), &origin).map_err(err)?);
+        }
+
+        let owner = owner
+            .clone()
+            .ok_or_else(|| err("record has no owner name".into()))?;
+
+        // skip the optional TTL and class, in either order, to get to the type
+        let record_type = tokens
+            .find(|token| {
+                !token.starts_with(|c: char| c.is_ascii_digit())
+                    && !CLASSES.contains(&token.to_uppercase().as_str())
+            })
+            .ok_or_else(|| err("record has no type".into()))?;
+        let record_type =
+            RecordType::from_str(&record_type.to_uppercase()).map_err(|e| err(e.to_string()))?;
+
+        if seen.insert((owner.clone(), record_type)) {
+            records.push((owner, record_type));
+        }
+    }
+
+    Ok(records)
+}
+
+fn absolute(name: &str, origin: &Name) -> Result<Name, String> {
+    if name == "@" {
+        return Ok(origin.clone());
+    }
+
+    Name::parse(name, Some(origin)).map_err(|e| format!("invalid name '{}': {}", name, e))
+}
+
+/// Strips comments and joins parenthesized records onto one line, returning
+/// each logical line with the number of the physical line it started on.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut depth = 0;
+
+    for (lineno, line) in contents.lines().enumerate() {
+        if depth == 0 {
+            start = lineno + 1;
+        }
+
+        let mut quoted = false;
+        for c in line.chars() {
+            match c {
+                '"' => quoted = !quoted,
+                ';' if !quoted => break,
+                '(' if !quoted => {
+                    depth += 1;
+                    current.push(' ');
+                    continue;
+                }
+                ')' if !quoted => {
+                    depth -= 1;
+                    current.push(' ');
+                    continue;
+                }
+                _ => {}
+            }
+
+            current.push(c);
+        }
+
+        if depth > 0 {
+            current.push(' ');
+        } else {
+            lines.push((start, std::mem::take(&mut current)));
+        }
+    }
+
+    if !current.is_empty() {
+        lines.push((start, current));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(contents: &str) -> Vec<(String, RecordType)> {
+        parse_records(contents)
+            .unwrap()
+            .into_iter()
+            .map(|(name, record_type)| (name.to_string(), record_type))
+            .collect()
+    }
+
+    fn record(name: &str, record_type: RecordType) -> (String, RecordType) {
+        (name.to_string(), record_type)
+    }
+
+    #[test]
+    fn names_are_relative_to_the_origin() {
+        let zone = "\
+$ORIGIN example.com.
+$TTL 3600
+@       IN SOA ns1 hostmaster (
+                2024010101 ; serial
+                3600 900 604800 300 )
+        IN NS  ns1
+ns1     IN A   192.0.2.1
+www 300 IN A   192.0.2.2
+www IN 300 AAAA 2001:db8::2
+mail.example.net. MX 10 mx
+$ORIGIN sub.example.com.
+host    AAAA   2001:db8::3
+        TXT    \"v=spf1 ; not a comment\"
+@       A      192.0.2.4
+";
+
+        assert_eq!(
+            records(zone),
+            vec![
+                record("example.com.", RecordType::SOA),
+                record("example.com.", RecordType::NS),
+                record("ns1.example.com.", RecordType::A),
+                record("www.example.com.", RecordType::A),
+                record("www.example.com.", RecordType::AAAA),
+                record("mail.example.net.", RecordType::MX),
+                record("host.sub.example.com.", RecordType::AAAA),
+                record("host.sub.example.com.", RecordType::TXT),
+                record("sub.example.com.", RecordType::A),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_relative_origin_is_relative_to_the_last() {
+        let zone = "\
+$ORIGIN example.com.
+$ORIGIN sub
+www A 192.0.2.1
+$ORIGIN @
+mail A 192.0.2.2
+";
+
+        assert_eq!(
+            records(zone),
+            vec![
+                record("www.sub.example.com.", RecordType::A),
+                record("mail.sub.example.com.", RecordType::A),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_are_relative_to_the_root_without_an_origin() {
+        assert_eq!(
+            records("www.example.org A 192.0.2.1\n@ NS ns1.example.org.\n"),
+            vec![
+                record("www.example.org.", RecordType::A),
+                record(".", RecordType::NS),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_records_are_listed_once() {
+        assert_eq!(
+            records("www.example. A 192.0.2.1\nWWW.example. a 192.0.2.2\n\tA 192.0.2.3\n"),
+            vec![record("www.example.", RecordType::A)]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let cases = [
+            ("$ORIGIN\n", 1, "$ORIGIN requires a name"),
+            (
+                "\n$INCLUDE other.zone\n",
+                2,
+                "unsupported directive $INCLUDE",
+            ),
+            ("  A 192.0.2.1\n", 1, "record has no owner name"),
+            ("www.example. 300 IN\n", 1, "record has no type"),
+            (
+                "@ SOA ns1 hostmaster (\n 1 2 3 4 5 )\nwww 300 IN ; no type\n",
+                3,
+                "record has no type",
+            ),
+        ];
+
+        for (zone, lineno, msg) in cases {
+            assert_eq!(
+                parse_records(zone).unwrap_err(),
+                (lineno, msg.to_string()),
+                "{:?}",
+                zone
+            );
+        }
+
+        let (lineno, msg) = parse_records("www.example. NOTATYPE 1\n").unwrap_err();
+        assert_eq!(lineno, 1);
+        assert!(msg.contains("NOTATYPE"), "{}", msg);
+    }
+}